@@ -0,0 +1,2 @@
+ALTER TABLE persons ADD COLUMN uuid BINARY(16) NULL;
+CREATE UNIQUE INDEX persons_uuid ON persons (uuid);
@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS users (
+    username VARCHAR(64) PRIMARY KEY,
+    password_hash VARCHAR(255) NOT NULL,
+    roles VARCHAR(255) NOT NULL,
+    created_at TIMESTAMP(6) NOT NULL
+);
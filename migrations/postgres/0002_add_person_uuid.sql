@@ -0,0 +1,2 @@
+ALTER TABLE persons ADD COLUMN IF NOT EXISTS uuid UUID;
+CREATE UNIQUE INDEX IF NOT EXISTS persons_uuid ON persons (uuid);
@@ -0,0 +1 @@
+ALTER TABLE persons ADD COLUMN deleted_at TEXT;
@@ -0,0 +1,103 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use axum::{
+    body::HttpBody,
+    extract::{MatchedPath, Request, State},
+    middleware::Next,
+    response::Response,
+};
+use crate::config::Config;
+use crate::errors::ConfigError;
+use crate::proxy::Client;
+use crate::request_id::REQUEST_ID_HEADER;
+
+const DEFAULT_SLOW_REQUEST_MS: u64 = 500;
+
+/// Logs one line per request under the `access_log` target, with the client
+/// address, method, path, status, duration in milliseconds, request id and body size in bytes.
+/// The size is missing for streamed bodies, whose length is unknown up front.
+pub async fn log(request: Request, next: Next) -> Response {
+    let started = Instant::now();
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let request_id = request_id(&request);
+    let client = request.extensions().get::<Client>().map(|client| client.ip.to_string());
+    let response = next.run(request).await;
+    tracing::info!(
+        target: "access_log",
+        client,
+        method = %method,
+        path,
+        status = response.status().as_u16(),
+        duration_ms = started.elapsed().as_micros() as f64 / 1000.0,
+        request_id,
+        bytes = response.body().size_hint().exact(),
+        "{} {} {}",
+        method,
+        path,
+        response.status().as_u16(),
+    );
+    response
+}
+
+fn request_id(request: &Request) -> Option<String> {
+    request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+}
+
+pub struct SlowRequests {
+    threshold: Option<Duration>,
+}
+
+impl SlowRequests {
+    /// Warns about requests taking longer than `SLOW_REQUEST_MS` (500), or
+    /// never when it is 0.
+    pub fn from_config(config: &Config) -> Result<Self, ConfigError> {
+        let millis = match config.var("SLOW_REQUEST_MS") {
+            Some(value) => value.parse().map_err(|_| {
+                ConfigError::Invalid(format!("SLOW_REQUEST_MS must be a number, got {}", value))
+            })?,
+            None => DEFAULT_SLOW_REQUEST_MS,
+        };
+        let threshold = (millis > 0).then(|| Duration::from_millis(millis));
+        Ok(SlowRequests { threshold })
+    }
+}
+
+/// Logs a warning with the route, duration and request id of requests slower
+/// than the threshold, to spot latency regressions.
+pub async fn warn_slow(
+    State(slow): State<Arc<SlowRequests>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(threshold) = slow.threshold else {
+        return next.run(request).await;
+    };
+    let started = Instant::now();
+    let method = request.method().clone();
+    let route = match request.extensions().get::<MatchedPath>() {
+        Some(path) => path.as_str().to_string(),
+        None => request.uri().path().to_string(),
+    };
+    let request_id = request_id(&request);
+    let response = next.run(request).await;
+    let elapsed = started.elapsed();
+    if elapsed > threshold {
+        tracing::warn!(
+            method = %method,
+            route,
+            duration_ms = elapsed.as_millis() as u64,
+            threshold_ms = threshold.as_millis() as u64,
+            request_id,
+            "Slow request {} {} took {}ms",
+            method,
+            route,
+            elapsed.as_millis(),
+        );
+    }
+    response
+}
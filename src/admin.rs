@@ -0,0 +1,318 @@
+use std::path::Path;
+use std::sync::Arc;
+use axum::{
+    extract::{Path as UrlPath, State},
+    http::StatusCode,
+    routing::{delete, get, post, put},
+    Json, Router,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use crate::audit::{AuditEntry, AuditQuery};
+use crate::errors::HttpAppError;
+use crate::extract::{JsonBody, QueryParams};
+use crate::flags::{Flag, FlagState};
+use crate::login_guard::LoginStats;
+use crate::maintenance::{MaintenanceRequest, MaintenanceStatus, DEFAULT_RETRY_AFTER_SECS};
+use crate::person::Person;
+use crate::repository::PersonRepository;
+use crate::routes::AppState;
+
+/// Version of the backup format written by `/admin/export`.
+pub const BACKUP_SCHEMA_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+pub struct Backup {
+    pub schema_version: u32,
+    pub exported_at: DateTime<Utc>,
+    pub app_version: String,
+    pub count: usize,
+    pub persons: Vec<Person>,
+}
+
+impl Backup {
+    pub async fn create(repository: &dyn PersonRepository) -> Result<Self, HttpAppError> {
+        let persons = repository.list().await?;
+        Ok(Backup {
+            schema_version: BACKUP_SCHEMA_VERSION,
+            exported_at: Utc::now(),
+            app_version: env!("CARGO_PKG_VERSION").to_string(),
+            count: persons.len(),
+            persons,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportMode {
+    #[default]
+    Replace,
+    Merge,
+}
+
+#[derive(Deserialize)]
+pub struct ImportParams {
+    #[serde(default)]
+    pub mode: ImportMode,
+}
+
+#[derive(Serialize)]
+pub struct ImportSummary {
+    pub mode: ImportMode,
+    pub created: usize,
+    pub updated: usize,
+}
+
+#[derive(Deserialize)]
+pub struct RestoreRequest {
+    pub snapshot: String,
+}
+
+#[derive(Serialize)]
+pub struct SnapshotInfo {
+    pub snapshot: String,
+    pub count: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LogFilter {
+    pub filter: String,
+}
+
+#[derive(Serialize)]
+pub struct PurgeSummary {
+    pub purged: usize,
+}
+
+pub fn create_admin_routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/admin/snapshot", post(snapshot))
+        .route("/admin/restore", post(restore))
+        .route("/admin/export", get(export))
+        .route("/admin/import", post(import))
+        .route("/admin/purge", post(purge))
+        .route("/admin/audit", get(audit))
+        .route("/admin/lockouts", get(lockouts))
+        .route("/admin/loglevel", get(log_level))
+        .route("/admin/loglevel", put(set_log_level))
+        .route("/admin/maintenance", get(maintenance))
+        .route("/admin/maintenance", put(start_maintenance))
+        .route("/admin/maintenance", delete(end_maintenance))
+        .route("/admin/flags", get(flags))
+        .route("/admin/flags/:name", put(set_flag))
+        .route("/admin/flags/:name", delete(clear_flag))
+}
+
+async fn snapshot(
+    State(state): State<Arc<AppState>>,
+) -> Result<(StatusCode, Json<SnapshotInfo>), HttpAppError> {
+    let persons = state.person_repository.list().await?;
+    let name = format!("persons-{}.json", Utc::now().format("%Y%m%dT%H%M%S%.3fZ"));
+    let contents = serde_json::to_vec_pretty(&persons)
+        .map_err(|e| HttpAppError::StorageError(e.to_string()))?;
+    fs::create_dir_all(&state.settings().snapshot_dir).await?;
+    fs::write(state.settings().snapshot_dir.join(&name), contents).await?;
+    tracing::info!("Wrote snapshot {} with {} persons", name, persons.len());
+    let info = SnapshotInfo {
+        snapshot: name,
+        count: persons.len(),
+    };
+    Ok((StatusCode::CREATED, Json(info)))
+}
+
+async fn restore(
+    State(state): State<Arc<AppState>>,
+    JsonBody(request): JsonBody<RestoreRequest>,
+) -> Result<Json<SnapshotInfo>, HttpAppError> {
+    let name = request.snapshot;
+    if Path::new(&name).file_name() != Some(name.as_ref()) {
+        return Err(HttpAppError::BadRequest("Invalid snapshot name".to_string()));
+    }
+    let contents = match fs::read(state.settings().snapshot_dir.join(&name)).await {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Err(HttpAppError::NotFound),
+        Err(e) => return Err(e.into()),
+    };
+    let persons: Vec<Person> = serde_json::from_slice(&contents)
+        .map_err(|e| HttpAppError::BadRequest(format!("Invalid snapshot: {}", e)))?;
+    let count = persons.len();
+    state.person_repository.replace_all(persons).await?;
+    tracing::info!("Restored snapshot {} with {} persons", name, count);
+    Ok(Json(SnapshotInfo {
+        snapshot: name,
+        count,
+    }))
+}
+
+async fn export(State(state): State<Arc<AppState>>) -> Result<Json<Backup>, HttpAppError> {
+    Ok(Json(Backup::create(state.person_repository.as_ref()).await?))
+}
+
+async fn import(
+    QueryParams(params): QueryParams<ImportParams>,
+    State(state): State<Arc<AppState>>,
+    JsonBody(backup): JsonBody<Backup>,
+) -> Result<Json<ImportSummary>, HttpAppError> {
+    if backup.schema_version > BACKUP_SCHEMA_VERSION {
+        return Err(HttpAppError::BadRequest(format!(
+            "Unsupported backup schema version {}",
+            backup.schema_version
+        )));
+    }
+    let mut summary = ImportSummary {
+        mode: params.mode,
+        created: 0,
+        updated: 0,
+    };
+    match params.mode {
+        ImportMode::Replace => {
+            summary.created = backup.persons.len();
+            state.person_repository.replace_all(backup.persons).await?;
+        }
+        ImportMode::Merge => {
+            for person in backup.persons {
+                let (id, deleted_at) = (person.id, person.deleted_at);
+                let person = Person { version: 0, ..person };
+                if state.person_repository.upsert(person).await? {
+                    summary.created += 1;
+                } else {
+                    state.person_repository.set_deleted(id, deleted_at).await?;
+                    summary.updated += 1;
+                }
+            }
+        }
+    }
+    tracing::info!(
+        "Imported backup from {} ({:?}): {} created, {} updated",
+        backup.exported_at,
+        summary.mode,
+        summary.created,
+        summary.updated
+    );
+    Ok(Json(summary))
+}
+
+/// Removes soft deleted persons for good.
+async fn purge(State(state): State<Arc<AppState>>) -> Result<Json<PurgeSummary>, HttpAppError> {
+    let mut purged = 0;
+    for person in state.person_repository.list().await? {
+        if person.deleted_at.is_some() {
+            state.person_repository.purge(person.id).await?;
+            purged += 1;
+        }
+    }
+    tracing::info!("Purged {} soft deleted persons", purged);
+    Ok(Json(PurgeSummary { purged }))
+}
+
+/// The audit entries of one person and a time range, `until` excluded.
+async fn audit(
+    State(state): State<Arc<AppState>>,
+    QueryParams(query): QueryParams<AuditQuery>,
+) -> Result<Json<Vec<AuditEntry>>, HttpAppError> {
+    Ok(Json(state.audit.entries(&query)?))
+}
+
+/// Failed login counters and the accounts and addresses locked out right now.
+async fn lockouts(State(state): State<Arc<AppState>>) -> Json<LoginStats> {
+    Json(state.login_guard.stats())
+}
+
+async fn log_level(State(state): State<Arc<AppState>>) -> Result<Json<LogFilter>, HttpAppError> {
+    Ok(Json(LogFilter { filter: state.log_level.current()? }))
+}
+
+/// Takes effect at once for every request, until the next change or restart.
+async fn set_log_level(
+    State(state): State<Arc<AppState>>,
+    JsonBody(request): JsonBody<LogFilter>,
+) -> Result<Json<LogFilter>, HttpAppError> {
+    state.log_level.set(&request.filter)?;
+    Ok(Json(LogFilter { filter: state.log_level.current()? }))
+}
+
+async fn maintenance(State(state): State<Arc<AppState>>) -> Json<MaintenanceStatus> {
+    Json(state.maintenance.status())
+}
+
+/// Lasts until `DELETE` or a restart; `MAINTENANCE` starts the service in it.
+async fn start_maintenance(
+    State(state): State<Arc<AppState>>,
+    JsonBody(request): JsonBody<MaintenanceRequest>,
+) -> Json<MaintenanceStatus> {
+    state.maintenance.start(request.retry_after.unwrap_or(DEFAULT_RETRY_AFTER_SECS));
+    Json(state.maintenance.status())
+}
+
+async fn end_maintenance(State(state): State<Arc<AppState>>) -> Json<MaintenanceStatus> {
+    state.maintenance.end();
+    Json(state.maintenance.status())
+}
+
+async fn flags(State(state): State<Arc<AppState>>) -> Json<Vec<FlagState>> {
+    Json(state.flags.list())
+}
+
+/// Overrides the configured value of a flag until `DELETE` or a restart.
+async fn set_flag(
+    State(state): State<Arc<AppState>>,
+    UrlPath(name): UrlPath<String>,
+    JsonBody(flag): JsonBody<Flag>,
+) -> Result<Json<FlagState>, HttpAppError> {
+    Ok(Json(state.flags.set_override(&name, flag)?))
+}
+
+async fn clear_flag(
+    State(state): State<Arc<AppState>>,
+    UrlPath(name): UrlPath<String>,
+) -> Result<Json<FlagState>, HttpAppError> {
+    Ok(Json(state.flags.clear_override(&name)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::{self, Body};
+    use axum::http::{header, Request};
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn merge_imports_update_deleted_persons() {
+        let state = Arc::new(AppState::for_tests());
+        let repository = &state.person_repository;
+        repository.delete(1, 0).await.unwrap();
+        let mut persons = repository.list().await.unwrap();
+        persons[0].age = 44;
+        persons[0].deleted_at = None;
+        persons[1].deleted_at = Some(Utc::now());
+        persons.push(Person { id: 3, name: "Peach".to_string(), ..persons[1].clone() });
+        let backup = Backup {
+            schema_version: BACKUP_SCHEMA_VERSION,
+            exported_at: Utc::now(),
+            app_version: "0.0.0".to_string(),
+            count: persons.len(),
+            persons,
+        };
+
+        let request = Request::post("/admin/import?mode=merge")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(serde_json::to_vec(&backup).unwrap()))
+            .unwrap();
+        let router = create_admin_routes().with_state(state.clone());
+        let response = router.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let summary: Value =
+            serde_json::from_slice(&body::to_bytes(response.into_body(), usize::MAX).await.unwrap())
+                .unwrap();
+        assert_eq!(summary, json!({ "mode": "merge", "created": 1, "updated": 2 }));
+
+        let mario = repository.get(1).await.unwrap();
+        assert_eq!((mario.age, mario.deleted_at), (44, None));
+        assert!(repository.get(2).await.unwrap().deleted_at.is_some());
+        let peach = repository.get(3).await.unwrap();
+        assert_eq!((peach.name.as_str(), peach.deleted_at.is_some()), ("Peach", true));
+    }
+}
@@ -0,0 +1,73 @@
+use std::collections::HashMap;
+use std::fs;
+use sha2::{Digest, Sha256};
+use crate::auth::Role;
+use crate::config::Config;
+use crate::errors::AuthError;
+
+pub struct ApiKey {
+    pub label: String,
+    pub roles: Vec<Role>,
+}
+
+/// API keys by their SHA-256 digest, so a lookup takes the same time whatever
+/// part of a guessed key happens to match.
+pub struct ApiKeys {
+    keys: HashMap<[u8; 32], ApiKey>,
+}
+
+impl ApiKeys {
+    /// Reads `label:key` entries from `API_KEYS`, separated by commas, and from
+    /// the lines of `API_KEYS_FILE`; `None` when neither is set. An entry may
+    /// end in `:role+role`, and grants `writer` without one.
+    pub fn from_config(config: &Config) -> Result<Option<Self>, AuthError> {
+        let mut entries = Vec::new();
+        if let Some(keys) = config.var("API_KEYS") {
+            entries.extend(keys.split(',').map(str::to_string));
+        }
+        if let Some(path) = config.var("API_KEYS_FILE") {
+            entries.extend(fs::read_to_string(path)?.lines().map(str::to_string));
+        }
+        let mut keys = HashMap::new();
+        for entry in entries.iter().map(|entry| entry.trim()) {
+            if entry.is_empty() || entry.starts_with('#') {
+                continue;
+            }
+            let mut parts = entry.splitn(3, ':');
+            let (Some(label), Some(key)) = (parts.next(), parts.next()) else {
+                return Err(AuthError::Config(format!(
+                    "API key entries are written as label:key, got '{}'",
+                    label_hint(entry)
+                )));
+            };
+            let roles = match parts.next() {
+                Some(roles) => roles
+                    .split('+')
+                    .map(|role| role.trim().parse())
+                    .collect::<Result<_, _>>()
+                    .map_err(AuthError::Config)?,
+                None => vec![Role::Writer],
+            };
+            let label = label.trim().to_string();
+            keys.insert(digest(key.trim()), ApiKey { label, roles });
+        }
+        if keys.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(ApiKeys { keys }))
+    }
+
+    /// The entry of `key`, if it is a known key.
+    pub fn get(&self, key: &str) -> Option<&ApiKey> {
+        self.keys.get(&digest(key))
+    }
+}
+
+pub fn digest(key: &str) -> [u8; 32] {
+    Sha256::digest(key.as_bytes()).into()
+}
+
+/// The start of a malformed entry, short enough not to leak a whole key.
+fn label_hint(entry: &str) -> String {
+    entry.chars().take(4).chain("...".chars()).collect()
+}
@@ -0,0 +1,247 @@
+use std::cell::RefCell;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::Method,
+    middleware::Next,
+    response::Response,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use crate::errors::{HttpAppError, StorageError};
+use crate::history;
+use crate::person::Person;
+use crate::routes::AppState;
+
+/// One mutating request, or one person it changed when it changed several.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub at: DateTime<Utc>,
+    pub actor: String,
+    pub method: String,
+    /// The route template, such as `/api/v1/person/:id`.
+    pub route: String,
+    pub status: u16,
+    pub entity_id: Option<u32>,
+    pub before: Option<Person>,
+    pub after: Option<Person>,
+    /// Security events the request set off, such as a lockout.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub events: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct AuditQuery {
+    pub entity_id: Option<u32>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+struct Mutation {
+    id: u32,
+    before: Option<Person>,
+    after: Option<Person>,
+}
+
+#[derive(Default)]
+struct Pending {
+    actor: Option<String>,
+    mutations: Vec<Mutation>,
+    events: Vec<String>,
+}
+
+tokio::task_local! {
+    static PENDING: RefCell<Pending>;
+}
+
+/// Notes a change to person `id` for the audit entry of the current request.
+/// Changes made outside a request, by background jobs, are not audited.
+pub fn collect(id: u32, before: Option<&Person>, after: Option<&Person>) {
+    let _ = PENDING.try_with(|pending| {
+        pending.borrow_mut().mutations.push(Mutation {
+            id,
+            before: before.cloned(),
+            after: after.cloned(),
+        })
+    });
+}
+
+/// Names the authenticated principal of the current request.
+pub fn set_actor(actor: &str) {
+    let _ = PENDING.try_with(|pending| pending.borrow_mut().actor = Some(actor.to_string()));
+}
+
+/// Notes a security event, which gets the current request audited even if it
+/// only reads.
+pub fn note_event(event: String) {
+    let _ = PENDING.try_with(|pending| pending.borrow_mut().events.push(event));
+}
+
+/// Audit entries, kept in memory and appended to `AUDIT_LOG_FILE` when set.
+/// Entries are never changed or removed.
+#[derive(Default)]
+pub struct AuditLog {
+    entries: RwLock<Vec<AuditEntry>>,
+    log: Option<Mutex<File>>,
+}
+
+impl AuditLog {
+    /// Loads the entries already in the file at `path` and appends to it.
+    pub async fn open(path: impl AsRef<Path>) -> Result<Self, StorageError> {
+        let path = path.as_ref();
+        let entries = match fs::read_to_string(path).await {
+            Ok(contents) => contents
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .filter_map(|line| match serde_json::from_str(line) {
+                    Ok(entry) => Some(entry),
+                    Err(e) => {
+                        tracing::warn!("Skipping unreadable audit entry: {}", e);
+                        None
+                    }
+                })
+                .collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        tracing::info!("Loaded {} audit entries from {}", entries.len(), path.display());
+        let log = OpenOptions::new().create(true).append(true).open(path).await?;
+        Ok(AuditLog {
+            entries: RwLock::new(entries),
+            log: Some(Mutex::new(log)),
+        })
+    }
+
+    async fn append(&self, entries: Vec<AuditEntry>) -> Result<(), HttpAppError> {
+        if let Some(log) = &self.log {
+            let mut lines = Vec::new();
+            for entry in &entries {
+                serde_json::to_writer(&mut lines, entry)
+                    .map_err(|e| HttpAppError::StorageError(e.to_string()))?;
+                lines.push(b'\n');
+            }
+            let mut log = log.lock().await;
+            log.write_all(&lines).await?;
+            log.sync_data().await?;
+        }
+        self.entries.write()?.extend(entries);
+        Ok(())
+    }
+
+    /// The entries matching `query`, oldest first.
+    pub fn entries(&self, query: &AuditQuery) -> Result<Vec<AuditEntry>, HttpAppError> {
+        let entries = self.entries.read()?;
+        Ok(entries
+            .iter()
+            .filter(|entry| query.entity_id.map_or(true, |id| entry.entity_id == Some(id)))
+            .filter(|entry| query.since.map_or(true, |since| entry.at >= since))
+            .filter(|entry| query.until.map_or(true, |until| entry.at < until))
+            .cloned()
+            .collect())
+    }
+}
+
+/// Audits every `POST`, `PUT`, `PATCH` and `DELETE`, successful or not, with
+/// one entry per person it changed, and any request that set off an event.
+pub async fn record(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let mutating = !matches!(*request.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+    let method = request.method().to_string();
+    let route = match request.extensions().get::<MatchedPath>() {
+        Some(path) => path.as_str().to_string(),
+        None => request.uri().path().to_string(),
+    };
+    let actor = history::current_actor();
+    let pending = RefCell::new(Pending::default());
+    let (response, pending) = PENDING
+        .scope(pending, async {
+            let response = next.run(request).await;
+            (response, PENDING.with(|pending| pending.take()))
+        })
+        .await;
+    if !mutating && pending.events.is_empty() {
+        return response;
+    }
+
+    let status = response.status().as_u16();
+    let entry = |entity_id, before, after| AuditEntry {
+        at: Utc::now(),
+        actor: pending.actor.clone().unwrap_or_else(|| actor.clone()),
+        method: method.clone(),
+        route: route.clone(),
+        status,
+        entity_id,
+        before,
+        after,
+        events: pending.events.clone(),
+    };
+    let entries = if pending.mutations.is_empty() {
+        vec![entry(None, None, None)]
+    } else {
+        pending
+            .mutations
+            .into_iter()
+            .map(|mutation| entry(Some(mutation.id), mutation.before, mutation.after))
+            .collect()
+    };
+    if let Err(e) = state.audit.append(entries).await {
+        tracing::error!("Could not write the audit log: {}", e);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::{header, StatusCode};
+    use axum::middleware;
+    use tower::ServiceExt;
+    use crate::history::HistoryRepository;
+    use crate::person::create_person_collection;
+    use crate::repository::MemoryRepository;
+    use crate::routes::create_routes;
+
+    #[tokio::test]
+    async fn mutations_are_audited_once_with_both_sides() {
+        let mut state = AppState::for_tests();
+        let memory = MemoryRepository::new(create_person_collection());
+        state.person_repository =
+            Box::new(HistoryRepository::new(Box::new(memory), state.history.clone()));
+        let state = Arc::new(state);
+        let router = create_routes()
+            .with_state(state.clone())
+            .layer(middleware::from_fn_with_state(state.clone(), record));
+        let request = Request::builder()
+            .method(Method::PATCH)
+            .uri("/api/v1/person/1")
+            .header(header::IF_MATCH, "\"v1\"")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(r#"{"age": 44}"#))
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let request = Request::builder().uri("/api/v1/person/1").body(Body::empty()).unwrap();
+        router.oneshot(request).await.unwrap();
+
+        let query = AuditQuery { entity_id: None, since: None, until: None };
+        let entries = state.audit.entries(&query).unwrap();
+        assert_eq!(entries.len(), 1);
+        let entry = &entries[0];
+        assert_eq!((entry.method.as_str(), entry.status), ("PATCH", 200));
+        assert_eq!(entry.entity_id, Some(1));
+        let before = entry.before.as_ref().unwrap();
+        let after = entry.after.as_ref().unwrap();
+        assert_eq!((before.age, before.version), (43, 1));
+        assert_eq!((after.age, after.version), (44, 2));
+        assert_eq!(after.name, before.name);
+    }
+}
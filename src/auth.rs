@@ -0,0 +1,480 @@
+use std::fmt;
+use std::fs;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap, Method},
+    middleware::Next,
+    response::Response,
+    Extension,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use jsonwebtoken::jwk::{AlgorithmParameters, JwkSet};
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tracing::Instrument;
+use crate::api_keys;
+use crate::audit;
+use crate::config::Config;
+use crate::errors::{AuthError, HttpAppError};
+use crate::history;
+use crate::proxy::Client;
+use crate::routes::AppState;
+use crate::tls::ClientIdentity;
+use crate::users;
+
+pub const API_KEY_HEADER: &str = "X-Api-Key";
+
+/// Seconds of clock skew tolerated for `exp` and `nbf`.
+const DEFAULT_LEEWAY_SECS: u64 = 60;
+
+/// The claims of a verified token.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Claims {
+    pub sub: String,
+    pub exp: u64,
+    #[serde(default)]
+    pub roles: Vec<String>,
+}
+
+struct VerifyingKey {
+    kid: Option<String>,
+    algorithm: Algorithm,
+    key: DecodingKey,
+}
+
+/// Verifies bearer tokens against an HS256 secret, an RS256 public key and the
+/// keys of a JWKS document, whichever are configured.
+pub struct JwtVerifier {
+    keys: Vec<VerifyingKey>,
+    validation: Validation,
+}
+
+impl JwtVerifier {
+    /// Reads `JWT_SECRET`, `JWT_PUBLIC_KEY_FILE` and `JWT_JWKS_FILE`; `None`
+    /// when none is set and authentication is off.
+    pub fn from_config(config: &Config) -> Result<Option<Self>, AuthError> {
+        let mut keys = Vec::new();
+        if let Some(secret) = config.var("JWT_SECRET") {
+            keys.push(VerifyingKey {
+                kid: None,
+                algorithm: Algorithm::HS256,
+                key: DecodingKey::from_secret(secret.as_bytes()),
+            });
+        }
+        if let Some(path) = config.var("JWT_PUBLIC_KEY_FILE") {
+            keys.push(VerifyingKey {
+                kid: None,
+                algorithm: Algorithm::RS256,
+                key: DecodingKey::from_rsa_pem(&fs::read(path)?)?,
+            });
+        }
+        if let Some(path) = config.var("JWT_JWKS_FILE") {
+            let jwks: JwkSet = serde_json::from_slice(&fs::read(path)?)?;
+            keys.extend(jwks_keys(&jwks)?);
+        }
+        if keys.is_empty() {
+            return Ok(None);
+        }
+
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.leeway = leeway(config)?;
+        let mut required = vec!["exp"];
+        if let Some(issuer) = config.var("JWT_ISSUER") {
+            validation.set_issuer(&[issuer]);
+            required.push("iss");
+        }
+        match config.var("JWT_AUDIENCE") {
+            Some(audience) => {
+                validation.set_audience(&[audience]);
+                required.push("aud");
+            }
+            None => validation.validate_aud = false,
+        }
+        validation.set_required_spec_claims(&required);
+        Ok(Some(JwtVerifier { keys, validation }))
+    }
+
+    /// Trusts the keys of `jwks` for tokens that `issuer` made for `audience`.
+    pub fn for_issuer(
+        config: &Config,
+        jwks: &JwkSet,
+        issuer: &str,
+        audience: &str,
+    ) -> Result<Self, AuthError> {
+        let mut validation = Validation::new(Algorithm::RS256);
+        validation.leeway = leeway(config)?;
+        validation.set_issuer(&[issuer]);
+        validation.set_audience(&[audience]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud"]);
+        Ok(JwtVerifier {
+            keys: jwks_keys(jwks)?,
+            validation,
+        })
+    }
+
+    pub fn verify<C: DeserializeOwned>(&self, token: &str) -> Result<C, HttpAppError> {
+        let header = decode_header(token).map_err(invalid_token)?;
+        let key = self
+            .keys
+            .iter()
+            .find(|key| {
+                key.algorithm == header.alg
+                    && (key.kid.is_none() || header.kid.is_none() || key.kid == header.kid)
+            })
+            .ok_or_else(|| HttpAppError::Unauthorized("no key for this token".to_string()))?;
+        let mut validation = self.validation.clone();
+        validation.algorithms = vec![key.algorithm];
+        decode::<C>(token, &key.key, &validation)
+            .map(|data| data.claims)
+            .map_err(invalid_token)
+    }
+}
+
+fn leeway(config: &Config) -> Result<u64, AuthError> {
+    match config.var("JWT_LEEWAY_SECS") {
+        Some(value) => value.parse().map_err(|_| {
+            AuthError::Config(format!("JWT_LEEWAY_SECS must be a number of seconds, got {}", value))
+        }),
+        None => Ok(DEFAULT_LEEWAY_SECS),
+    }
+}
+
+/// The keys of a JWKS document, skipping those of an algorithm we do not know.
+fn jwks_keys(jwks: &JwkSet) -> Result<Vec<VerifyingKey>, AuthError> {
+    let mut keys = Vec::new();
+    for jwk in &jwks.keys {
+        let algorithm = match (jwk.common.key_algorithm, &jwk.algorithm) {
+            (Some(algorithm), _) => match algorithm.to_string().parse() {
+                Ok(algorithm) => algorithm,
+                Err(_) => continue,
+            },
+            (None, AlgorithmParameters::RSA(_)) => Algorithm::RS256,
+            (None, AlgorithmParameters::EllipticCurve(_)) => Algorithm::ES256,
+            (None, AlgorithmParameters::OctetKeyPair(_)) => Algorithm::EdDSA,
+            (None, AlgorithmParameters::OctetKey(_)) => Algorithm::HS256,
+        };
+        keys.push(VerifyingKey {
+            kid: jwk.common.key_id.clone(),
+            algorithm,
+            key: DecodingKey::from_jwk(jwk)?,
+        });
+    }
+    Ok(keys)
+}
+
+fn invalid_token(error: jsonwebtoken::errors::Error) -> HttpAppError {
+    HttpAppError::Unauthorized(format!("invalid token: {}", error))
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = value.split_once(' ')?;
+    scheme.eq_ignore_ascii_case("bearer").then(|| token.trim())
+}
+
+fn basic_credentials(headers: &HeaderMap) -> Option<(String, String)> {
+    let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, encoded) = value.split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+    let decoded = String::from_utf8(STANDARD.decode(encoded.trim()).ok()?).ok()?;
+    let (user, password) = decoded.split_once(':')?;
+    Some((user.to_string(), password.to_string()))
+}
+
+/// The `ADMIN_USER` and `ADMIN_PASSWORD` that unlock `/admin/*` over HTTP Basic auth.
+pub struct AdminCredentials {
+    user: String,
+    password: [u8; 32],
+}
+
+impl AdminCredentials {
+    /// `None` when neither variable is set and the admin routes stay open.
+    pub fn from_config(config: &Config) -> Result<Option<Self>, AuthError> {
+        match (config.var("ADMIN_USER"), config.var("ADMIN_PASSWORD")) {
+            (Some(user), Some(password)) => Ok(Some(AdminCredentials {
+                user: user.to_string(),
+                password: api_keys::digest(password),
+            })),
+            (None, None) => Ok(None),
+            _ => Err(AuthError::Config(
+                "ADMIN_USER and ADMIN_PASSWORD must be set together".to_string(),
+            )),
+        }
+    }
+
+    pub fn user(&self) -> &str {
+        &self.user
+    }
+
+    pub fn accepts(&self, user: &str, password: &str) -> bool {
+        user == self.user && api_keys::digest(password) == self.password
+    }
+}
+
+/// What a principal may do. Each role includes the ones before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Reader,
+    Writer,
+    Admin,
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "reader" => Ok(Role::Reader),
+            "writer" => Ok(Role::Writer),
+            "admin" => Ok(Role::Admin),
+            _ => Err(format!("Unknown role {}", value)),
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Role::Reader => "reader",
+            Role::Writer => "writer",
+            Role::Admin => "admin",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Who made a request, attached to it as an extension once authenticated.
+#[derive(Debug, Clone)]
+pub struct Principal {
+    pub name: String,
+    pub roles: Vec<Role>,
+}
+
+impl Principal {
+    pub fn has(&self, role: Role) -> bool {
+        self.roles.iter().any(|granted| *granted >= role)
+    }
+}
+
+/// Requires an API key or a valid bearer token for every request that changes
+/// data, once keys are configured. Reads stay open but are still attributed.
+/// A client certificate identifies requests that carry no other credentials.
+pub async fn authenticate(
+    State(state): State<Arc<AppState>>,
+    Extension(remote): Extension<Client>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, HttpAppError> {
+    let open = state.jwt.is_none() && state.api_keys.is_none() && state.oidc.is_none();
+    let client = request.extensions().get::<ClientIdentity>();
+    if open && state.admin.is_none() && state.sessions.is_none() && client.is_none() {
+        return Ok(next.run(request).await);
+    }
+    let client = client.map(|client| Principal {
+        name: client.name().to_string(),
+        roles: client.roles.clone(),
+    });
+    let principal = match principal(&state, request.headers(), remote.ip).await?.or(client) {
+        Some(principal) => principal,
+        None if open || is_read(request.method()) => return Ok(next.run(request).await),
+        None => {
+            return Err(HttpAppError::Unauthorized(format!(
+                "an {} header or a bearer token is required",
+                API_KEY_HEADER
+            )))
+        }
+    };
+    let span = tracing::info_span!("principal", name = %principal.name, roles = ?principal.roles);
+    let actor = principal.name.clone();
+    audit::set_actor(&actor);
+    request.extensions_mut().insert(principal);
+    Ok(history::as_actor(actor, next.run(request)).instrument(span).await)
+}
+
+async fn principal(
+    state: &AppState,
+    headers: &HeaderMap,
+    ip: IpAddr,
+) -> Result<Option<Principal>, HttpAppError> {
+    if let Some(key) = headers.get(API_KEY_HEADER) {
+        let key = state
+            .api_keys
+            .as_ref()
+            .zip(key.to_str().ok())
+            .and_then(|(keys, key)| keys.get(key))
+            .ok_or_else(|| HttpAppError::Forbidden("unknown API key".to_string()))?;
+        return Ok(Some(Principal {
+            name: key.label.clone(),
+            roles: key.roles.clone(),
+        }));
+    }
+    if let (Some(admin), Some((user, password))) = (&state.admin, basic_credentials(headers)) {
+        state.login_guard.check(&user, ip)?;
+        if !admin.accepts(&user, &password) {
+            state.login_guard.failed(&user, ip);
+            return Err(HttpAppError::AdminUnauthorized);
+        }
+        state.login_guard.succeeded(&user);
+        return Ok(Some(Principal {
+            name: admin.user().to_string(),
+            roles: vec![Role::Admin],
+        }));
+    }
+    if let Some(sessions) = &state.sessions {
+        if let Some(principal) = sessions.principal(headers).await? {
+            return Ok(Some(principal));
+        }
+    }
+    let Some(token) = bearer_token(headers) else {
+        return Ok(None);
+    };
+    let mut principal = Err(HttpAppError::Unauthorized(
+        "bearer tokens are not accepted".to_string(),
+    ));
+    if let Some(verifier) = &state.jwt {
+        principal = verifier.verify::<Claims>(token).map(|claims| Principal {
+            name: claims.sub,
+            roles: claims.roles.iter().filter_map(|role| role.parse().ok()).collect(),
+        });
+    }
+    if let (Err(_), Some(provider)) = (&principal, &state.oidc) {
+        principal = provider.principal(token);
+    }
+    principal.map(Some)
+}
+
+/// The principal of a username and password, checked against the admin
+/// credentials first and the user accounts after.
+pub async fn check_login(
+    state: &AppState,
+    username: &str,
+    password: &str,
+    ip: IpAddr,
+) -> Result<Principal, HttpAppError> {
+    state.login_guard.check(username, ip)?;
+    if let Some(admin) = state.admin.as_ref().filter(|admin| admin.accepts(username, password)) {
+        state.login_guard.succeeded(username);
+        return Ok(Principal {
+            name: admin.user().to_string(),
+            roles: vec![Role::Admin],
+        });
+    }
+    let Some(user) = users::check_credentials(state.users.as_ref(), username, password).await?
+    else {
+        state.login_guard.failed(username, ip);
+        return Err(HttpAppError::Unauthorized("invalid username or password".to_string()));
+    };
+    state.login_guard.succeeded(username);
+    Ok(Principal {
+        name: user.username,
+        roles: user.roles,
+    })
+}
+
+/// Requires the `role` of an authenticated principal, for routes layered with
+/// `middleware::from_fn_with_state(role, authorize)`. Anonymous requests have
+/// already been turned away by `authenticate` unless they only read.
+pub async fn authorize(
+    State(role): State<Role>,
+    request: Request,
+    next: Next,
+) -> Result<Response, HttpAppError> {
+    if let Some(principal) = request.extensions().get::<Principal>() {
+        if !principal.has(role) {
+            return Err(HttpAppError::Forbidden(format!("the {} role is required", role)));
+        }
+    }
+    Ok(next.run(request).await)
+}
+
+/// Guards the admin routes, reads included, with the admin role once any
+/// credentials are configured, challenging for HTTP Basic auth when admin
+/// credentials are set.
+pub async fn require_admin(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, HttpAppError> {
+    if request.extensions().get::<Principal>().is_none() {
+        if state.admin.is_some() {
+            return Err(HttpAppError::AdminUnauthorized);
+        }
+        if state.jwt.is_some() || state.api_keys.is_some() || state.oidc.is_some() {
+            return Err(HttpAppError::Unauthorized(format!("the {} role is required", Role::Admin)));
+        }
+    }
+    authorize(State(Role::Admin), request, next).await
+}
+
+fn is_read(method: &Method) -> bool {
+    matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{encode, get_current_timestamp, EncodingKey, Header};
+    use serde_json::json;
+
+    fn verifier(settings: &[(&str, &str)]) -> JwtVerifier {
+        let mut settings = settings.to_vec();
+        settings.push(("JWT_SECRET", "secret"));
+        JwtVerifier::from_config(&Config::from_overrides(&settings)).unwrap().unwrap()
+    }
+
+    fn token(secret: &str, claims: serde_json::Value) -> String {
+        encode(&Header::default(), &claims, &EncodingKey::from_secret(secret.as_bytes())).unwrap()
+    }
+
+    fn expiring_in(secs: i64) -> u64 {
+        get_current_timestamp().saturating_add_signed(secs)
+    }
+
+    #[test]
+    fn tokens_need_the_secret_and_an_unexpired_exp() {
+        let verifier = verifier(&[]);
+        let claims = json!({"sub": "ann", "exp": expiring_in(60), "roles": ["writer"]});
+        let valid = token("secret", claims);
+        let claims: Claims = verifier.verify(&valid).unwrap();
+        assert_eq!((claims.sub.as_str(), claims.roles), ("ann", vec!["writer".to_string()]));
+
+        let forged = token("other", json!({"sub": "ann", "exp": expiring_in(60)}));
+        let expired = token("secret", json!({"sub": "ann", "exp": expiring_in(-120)}));
+        let no_exp = token("secret", json!({"sub": "ann"}));
+        for token in [forged, expired, no_exp, "not a token".to_string()] {
+            let result = verifier.verify::<Claims>(&token);
+            assert!(matches!(result, Err(HttpAppError::Unauthorized(_))), "{}", token);
+        }
+    }
+
+    #[test]
+    fn leeway_allows_for_clock_skew() {
+        let recently = token("secret", json!({"sub": "ann", "exp": expiring_in(-30)}));
+        assert!(verifier(&[]).verify::<Claims>(&recently).is_ok());
+        assert!(verifier(&[("JWT_LEEWAY_SECS", "0")]).verify::<Claims>(&recently).is_err());
+        let config = Config::from_overrides(&[("JWT_SECRET", "s"), ("JWT_LEEWAY_SECS", "a")]);
+        assert!(JwtVerifier::from_config(&config).is_err());
+    }
+
+    #[test]
+    fn issuer_and_audience_are_checked_when_set() {
+        let verifier = verifier(&[("JWT_ISSUER", "https://issuer"), ("JWT_AUDIENCE", "persons")]);
+        let exp = expiring_in(60);
+        let valid = json!({"sub": "ann", "exp": exp, "iss": "https://issuer", "aud": "persons"});
+        assert!(verifier.verify::<Claims>(&token("secret", valid)).is_ok());
+        for claims in [
+            json!({"sub": "ann", "exp": exp, "iss": "https://other", "aud": "persons"}),
+            json!({"sub": "ann", "exp": exp, "iss": "https://issuer", "aud": "other"}),
+            json!({"sub": "ann", "exp": exp, "aud": "persons"}),
+        ] {
+            assert!(verifier.verify::<Claims>(&token("secret", claims)).is_err());
+        }
+    }
+}
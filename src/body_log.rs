@@ -0,0 +1,162 @@
+use std::sync::Arc;
+use axum::{
+    body::{Body, Bytes, HttpBody},
+    extract::{Request, State},
+    http::{header, HeaderMap},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde_json::Value;
+use crate::config::Config;
+use crate::errors::{ConfigError, HttpAppError};
+
+const DEFAULT_MAX_BYTES: usize = 4096;
+
+/// Largest response buffered to be logged; bigger and streamed ones are only
+/// described, so that exports keep streaming.
+const MAX_BUFFERED_RESPONSE: u64 = 1024 * 1024;
+
+/// Fields whose name contains one of these, ignoring case, are logged as
+/// `[REDACTED]`.
+const SENSITIVE_FIELDS: &[&str] = &["password", "secret", "token", "api_key", "credential"];
+
+const REDACTED: &str = "[REDACTED]";
+
+pub struct BodyLogging {
+    max_bytes: Option<usize>,
+}
+
+impl BodyLogging {
+    /// Logs request and response bodies when `LOG_BODIES` is true, each cut
+    /// to `LOG_BODY_MAX_BYTES` (4096). Meant for debugging client
+    /// integrations, not for production traffic.
+    pub fn from_config(config: &Config) -> Result<Self, ConfigError> {
+        let enabled = config.enabled("LOG_BODIES");
+        if !enabled {
+            return Ok(BodyLogging { max_bytes: None });
+        }
+        let max_bytes = match config.var("LOG_BODY_MAX_BYTES") {
+            Some(value) => value.parse().map_err(|_| {
+                ConfigError::Invalid(format!(
+                    "LOG_BODY_MAX_BYTES must be a number, got {}",
+                    value
+                ))
+            })?,
+            None => DEFAULT_MAX_BYTES,
+        };
+        tracing::warn!("Logging request and response bodies up to {} bytes", max_bytes);
+        Ok(BodyLogging { max_bytes: Some(max_bytes) })
+    }
+}
+
+/// Logs the bodies with passwords, secrets and tokens redacted from JSON and
+/// form data.
+pub async fn log(
+    State(logging): State<Arc<BodyLogging>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(max_bytes) = logging.max_bytes else {
+        return next.run(request).await;
+    };
+    let (parts, body) = request.into_parts();
+    let bytes = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(_) => return HttpAppError::PayloadTooLarge.into_response(),
+    };
+    if !bytes.is_empty() {
+        tracing::info!(
+            target: "body_log",
+            body = describe(&parts.headers, &bytes, max_bytes),
+            "Request body"
+        );
+    }
+    let response = next.run(Request::from_parts(parts, Body::from(bytes))).await;
+
+    let size = response.body().size_hint().exact();
+    if !size.is_some_and(|size| size <= MAX_BUFFERED_RESPONSE) {
+        tracing::info!(target: "body_log", "Response body not logged, streamed or too large");
+        return response;
+    }
+    let (parts, body) = response.into_parts();
+    let bytes = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(e) => return HttpAppError::StorageError(e.to_string()).into_response(),
+    };
+    if !bytes.is_empty() {
+        tracing::info!(
+            target: "body_log",
+            body = describe(&parts.headers, &bytes, max_bytes),
+            "Response body"
+        );
+    }
+    Response::from_parts(parts, Body::from(bytes))
+}
+
+fn describe(headers: &HeaderMap, bytes: &Bytes, max_bytes: usize) -> String {
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("");
+    let text = if content_type.starts_with("application/x-www-form-urlencoded") {
+        redact_form(bytes)
+    } else if let Ok(mut json) = serde_json::from_slice::<Value>(bytes) {
+        redact_json(&mut json);
+        json.to_string()
+    } else {
+        match std::str::from_utf8(bytes) {
+            Ok(text) => text.to_string(),
+            Err(_) => return format!("<{} bytes of binary data>", bytes.len()),
+        }
+    };
+    truncate(text, max_bytes)
+}
+
+fn is_sensitive(field: &str) -> bool {
+    let field = field.to_ascii_lowercase();
+    SENSITIVE_FIELDS.iter().any(|sensitive| field.contains(sensitive))
+}
+
+fn redact_json(value: &mut Value) {
+    match value {
+        Value::Object(fields) => {
+            for (field, value) in fields.iter_mut() {
+                if is_sensitive(field) {
+                    *value = Value::String(REDACTED.to_string());
+                } else {
+                    redact_json(value);
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(redact_json),
+        _ => {}
+    }
+}
+
+fn redact_form(bytes: &[u8]) -> String {
+    let fields: Vec<(String, String)> = serde_urlencoded::from_bytes(bytes).unwrap_or_default();
+    let fields: Vec<(String, String)> = fields
+        .into_iter()
+        .map(|(field, value)| {
+            if is_sensitive(&field) {
+                (field, REDACTED.to_string())
+            } else {
+                (field, value)
+            }
+        })
+        .collect();
+    serde_urlencoded::to_string(fields).unwrap_or_default()
+}
+
+fn truncate(mut text: String, max_bytes: usize) -> String {
+    if text.len() <= max_bytes {
+        return text;
+    }
+    let total = text.len();
+    let mut end = max_bytes;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    text.truncate(end);
+    format!("{}... ({} bytes in all)", text, total)
+}
@@ -0,0 +1,401 @@
+use std::sync::Arc;
+use axum::{
+    extract::State,
+    http::StatusCode,
+    middleware,
+    routing::{delete, post, put},
+    Router,
+};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+use crate::auth::{self, Role};
+use crate::errors::{ErrorBody, HttpAppError};
+use crate::extract::Body;
+use crate::flags::{Bulk, Enabled};
+use crate::listing::PersonFilter;
+use crate::negotiation::{Format, Negotiated};
+use crate::person::{IdStrategy, NewPerson, Person};
+use crate::routes::AppState;
+use crate::validation::{FieldError, Validate};
+
+pub const MAX_BULK_ITEMS: usize = 1000;
+
+#[derive(Deserialize)]
+#[serde(from = "BulkFields")]
+pub struct BulkPerson {
+    pub id: Option<u32>,
+    pub person: NewPerson,
+}
+
+/// A `BulkPerson` as sent, flat since XML does not support `flatten`.
+#[derive(Deserialize, ToSchema)]
+#[schema(as = BulkPerson)]
+struct BulkFields {
+    /// Not accepted; the server assigns the ids.
+    id: Option<u32>,
+    name: String,
+    age: u8,
+    date: NaiveDate,
+}
+
+impl From<BulkFields> for BulkPerson {
+    fn from(fields: BulkFields) -> Self {
+        BulkPerson {
+            id: fields.id,
+            person: NewPerson {
+                uuid: None,
+                name: fields.name,
+                age: fields.age,
+                date: fields.date,
+            },
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct BulkItemResult {
+    pub index: usize,
+    pub status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub person: Option<Person>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl BulkItemResult {
+    pub fn from_result(
+        index: usize,
+        status: StatusCode,
+        result: Result<Person, HttpAppError>,
+    ) -> Self {
+        match result {
+            Ok(person) => BulkItemResult {
+                index,
+                status: status.as_u16(),
+                person: Some(person),
+                error: None,
+            },
+            Err(e) => BulkItemResult {
+                index,
+                status: e.status_code().as_u16(),
+                person: None,
+                error: Some(e.to_string()),
+            },
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct BulkResponse {
+    pub succeeded: usize,
+    pub failed: usize,
+    pub results: Vec<BulkItemResult>,
+}
+
+impl BulkResponse {
+    pub fn new(results: Vec<BulkItemResult>) -> Self {
+        let succeeded = results.iter().filter(|result| result.error.is_none()).count();
+        BulkResponse {
+            succeeded,
+            failed: results.len() - succeeded,
+            results,
+        }
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct BulkDeleteRequest {
+    pub ids: Option<Vec<u32>>,
+    pub filter: Option<PersonFilter>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct BulkDeleteResponse {
+    pub deleted: u64,
+}
+
+pub fn create_bulk_routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/persons/bulk", post(bulk_create))
+        .route("/persons/bulk", put(bulk_update))
+        .route("/persons", delete(bulk_delete))
+        .route_layer(middleware::from_fn_with_state(Role::Writer, auth::authorize))
+}
+
+pub fn check_size<T>(items: &[T]) -> Result<(), HttpAppError> {
+    if items.is_empty() || items.len() > MAX_BULK_ITEMS {
+        return Err(HttpAppError::BadRequest(format!(
+            "A bulk request takes between 1 and {} items",
+            MAX_BULK_ITEMS
+        )));
+    }
+    Ok(())
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/persons/bulk",
+    tag = "persons",
+    summary = "Create many persons",
+    request_body = Vec<BulkFields>,
+    security((), ("api_key" = []), ("bearer" = [])),
+    responses((status = 200, body = BulkResponse), (status = 400, body = ErrorBody))
+)]
+async fn bulk_create(
+    _: Enabled<Bulk>,
+    format: Format,
+    State(state): State<Arc<AppState>>,
+    Body(items): Body<Vec<BulkPerson>>,
+) -> Result<Negotiated<BulkResponse>, HttpAppError> {
+    check_size(&items)?;
+    let mut results = Vec::with_capacity(items.len());
+    for (index, item) in items.into_iter().enumerate() {
+        let result = create_one(&state, item).await;
+        results.push(BulkItemResult::from_result(index, StatusCode::CREATED, result));
+    }
+    Ok(Negotiated(format, BulkResponse::new(results)))
+}
+
+/// Ids are the server's to assign, as for a single create, so an entry that
+/// brings its own is rejected rather than taking the id or dropping it.
+pub async fn create_one(state: &AppState, item: BulkPerson) -> Result<Person, HttpAppError> {
+    if item.id.is_some() {
+        return Err(HttpAppError::Validation(vec![FieldError::new(
+            "id",
+            "is assigned by the server",
+        )]));
+    }
+    let mut person = item.person;
+    person.validate()?;
+    if state.settings().id_strategy == IdStrategy::Uuid {
+        person.uuid = Some(Uuid::new_v4());
+    }
+    state.person_repository.create(person).await
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/persons/bulk",
+    tag = "persons",
+    summary = "Replace many persons",
+    request_body = Vec<Person>,
+    security((), ("api_key" = []), ("bearer" = [])),
+    responses((status = 200, body = BulkResponse), (status = 400, body = ErrorBody))
+)]
+async fn bulk_update(
+    _: Enabled<Bulk>,
+    format: Format,
+    State(state): State<Arc<AppState>>,
+    Body(items): Body<Vec<Person>>,
+) -> Result<Negotiated<BulkResponse>, HttpAppError> {
+    check_size(&items)?;
+    let mut results = Vec::with_capacity(items.len());
+    for (index, person) in items.into_iter().enumerate() {
+        let result = update_one(&state, person).await;
+        results.push(BulkItemResult::from_result(index, StatusCode::OK, result));
+    }
+    Ok(Negotiated(format, BulkResponse::new(results)))
+}
+
+async fn update_one(state: &AppState, person: Person) -> Result<Person, HttpAppError> {
+    person.validate()?;
+    if person.version == 0 {
+        return Err(HttpAppError::PreconditionRequired);
+    }
+    let id = person.id;
+    state.person_repository.update(person).await?;
+    state.person_repository.get(id).await
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/persons",
+    tag = "persons",
+    summary = "Delete many persons",
+    description = "Deletes the persons with the given `ids`, or those that match `filter`.",
+    request_body = BulkDeleteRequest,
+    security((), ("api_key" = []), ("bearer" = [])),
+    responses((status = 200, body = BulkDeleteResponse), (status = 400, body = ErrorBody))
+)]
+async fn bulk_delete(
+    _: Enabled<Bulk>,
+    format: Format,
+    State(state): State<Arc<AppState>>,
+    Body(request): Body<BulkDeleteRequest>,
+) -> Result<Negotiated<BulkDeleteResponse>, HttpAppError> {
+    let deleted = match (request.ids, request.filter) {
+        (Some(ids), None) => {
+            check_size(&ids)?;
+            state.person_repository.delete_many(&ids).await?
+        }
+        (None, Some(filter)) if !filter.is_empty() => {
+            filter.validate()?;
+            state.person_repository.delete_matching(&filter).await?
+        }
+        _ => {
+            return Err(HttpAppError::BadRequest(
+                "Provide either ids or a non-empty filter".to_string(),
+            ))
+        }
+    };
+    tracing::info!("Bulk deleted {} persons", deleted);
+    Ok(Negotiated(format, BulkDeleteResponse { deleted }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use axum::body::{self, Body};
+    use axum::http::{header, Method, Request};
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+    use crate::flags::{Flag, Flags};
+    use crate::person::create_person_collection;
+
+    fn state() -> Arc<AppState> {
+        let mut state = AppState::for_tests();
+        state.flags = Flags::new(BTreeMap::from([("bulk".to_string(), Flag::Enabled(true))]));
+        Arc::new(state)
+    }
+
+    async fn send(
+        state: &Arc<AppState>,
+        method: Method,
+        uri: &str,
+        body: Value,
+    ) -> (StatusCode, Value) {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let router = create_bulk_routes().with_state(state.clone());
+        let response = router.oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    fn statuses(response: &Value) -> Vec<u64> {
+        let results = response["results"].as_array().unwrap();
+        results.iter().map(|result| result["status"].as_u64().unwrap()).collect()
+    }
+
+    fn peach(name: &str) -> Value {
+        json!({ "name": name, "age": 30, "date": "1985-09-13" })
+    }
+
+    #[tokio::test]
+    async fn bulk_creates_report_each_item() {
+        let state = state();
+        let items = json!([peach("Peach"), peach(""), peach("Daisy")]);
+        let (status, response) = send(&state, Method::POST, "/persons/bulk", items).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!((&response["succeeded"], &response["failed"]), (&json!(2), &json!(1)));
+        assert_eq!(statuses(&response), [201, 422, 201]);
+        assert_eq!(response["results"][0]["person"]["id"], 3);
+        assert_eq!(response["results"][2]["person"]["id"], 4);
+        assert_eq!(state.person_repository.list().await.unwrap().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn bulk_creates_reject_client_ids() {
+        let state = state();
+        let mut item = peach("Peach");
+        item["id"] = json!(9);
+        let (status, response) = send(&state, Method::POST, "/persons/bulk", json!([item])).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(response["results"][0]["status"], 422);
+        assert!(response["results"][0]["error"].as_str().unwrap().contains("id"));
+        assert!(matches!(state.person_repository.get(9).await, Err(HttpAppError::NotFound)));
+        assert_eq!(state.person_repository.list().await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn bulk_requests_are_bounded() {
+        assert!(check_size(&[0; MAX_BULK_ITEMS]).is_ok());
+        assert!(check_size(&[0; MAX_BULK_ITEMS + 1]).is_err());
+        assert!(check_size::<u32>(&[]).is_err());
+
+        let state = state();
+        let (status, _) = send(&state, Method::POST, "/persons/bulk", json!([])).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let items = vec![peach("Peach"); MAX_BULK_ITEMS + 1];
+        let (status, _) = send(&state, Method::POST, "/persons/bulk", json!(items)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(state.person_repository.list().await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn bulk_deletes_take_ids() {
+        let state = state();
+        let request = json!({ "ids": [1, 9] });
+        let (status, response) = send(&state, Method::DELETE, "/persons", request).await;
+        assert_eq!((status, response), (StatusCode::OK, json!({ "deleted": 1 })));
+        assert!(state.person_repository.get(1).await.unwrap().deleted_at.is_some());
+        assert!(state.person_repository.get(2).await.unwrap().deleted_at.is_none());
+
+        let (_, response) = send(&state, Method::DELETE, "/persons", json!({ "ids": [1] })).await;
+        assert_eq!(response, json!({ "deleted": 0 }));
+    }
+
+    #[tokio::test]
+    async fn bulk_deletes_take_a_filter() {
+        let state = state();
+        let request = json!({ "filter": { "max_age": 42 } });
+        let (status, response) = send(&state, Method::DELETE, "/persons", request).await;
+        assert_eq!((status, response), (StatusCode::OK, json!({ "deleted": 1 })));
+        assert!(state.person_repository.get(1).await.unwrap().deleted_at.is_none());
+        assert!(state.person_repository.get(2).await.unwrap().deleted_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn bulk_deletes_need_ids_or_a_filter() {
+        let state = state();
+        let too_many: Vec<u32> = (1..=MAX_BULK_ITEMS as u32 + 1).collect();
+        for request in [
+            json!({}),
+            json!({ "filter": {} }),
+            json!({ "ids": [] }),
+            json!({ "ids": too_many }),
+            json!({ "ids": [1], "filter": { "max_age": 42 } }),
+        ] {
+            let (status, _) = send(&state, Method::DELETE, "/persons", request.clone()).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{}", request);
+        }
+        let request = json!({ "filter": { "date_from": "2000-01-01", "date_to": "1990-01-01" } });
+        let (status, _) = send(&state, Method::DELETE, "/persons", request).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        let persons = state.person_repository.list().await.unwrap();
+        assert!(persons.iter().all(|person| person.deleted_at.is_none()));
+    }
+
+    #[tokio::test]
+    async fn bulk_updates_check_each_version() {
+        let state = state();
+        let person = |id: u32, age: u8, version: u32| {
+            let mut person = json!(create_person_collection()[(id as usize - 1) % 2]);
+            person["id"] = json!(id);
+            person["age"] = json!(age);
+            person["version"] = json!(version);
+            person
+        };
+        let items = json!([person(1, 44, 1), person(1, 45, 1), person(2, 42, 0), person(9, 1, 1)]);
+        let (status, response) = send(&state, Method::PUT, "/persons/bulk", items).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(statuses(&response), [200, 412, 428, 404]);
+        assert_eq!(response["results"][0]["person"]["version"], 2);
+        let mario = state.person_repository.get(1).await.unwrap();
+        let luigi = state.person_repository.get(2).await.unwrap();
+        assert_eq!((mario.age, luigi.age), (44, 41));
+
+        let mut nameless = person(1, 45, 2);
+        nameless["name"] = json!("");
+        let (_, response) = send(&state, Method::PUT, "/persons/bulk", json!([nameless])).await;
+        assert_eq!(statuses(&response), [422]);
+    }
+}
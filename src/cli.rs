@@ -0,0 +1,118 @@
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::str::FromStr;
+use clap::{Args, Parser, Subcommand};
+use crate::config::Config;
+use crate::errors::ConfigError;
+
+pub const DEFAULT_HOST: IpAddr = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
+pub const DEFAULT_PORT: u16 = 8080;
+
+/// REST API for persons. Settings come from environment variables and the
+/// configuration file; the options given here win over both.
+#[derive(Parser)]
+#[command(version, about, args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    /// TOML or YAML configuration file, instead of CONFIG_FILE
+    #[arg(long, global = true, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Log filter such as `debug` or `info,sqlx=warn`, instead of LOG_LEVEL
+    #[arg(long, global = true, value_name = "FILTER")]
+    pub log_level: Option<String>,
+
+    /// Threads that run requests, instead of WORKER_THREADS [default: one per core]
+    #[arg(long, global = true, value_name = "N")]
+    pub worker_threads: Option<usize>,
+
+    /// Threads for blocking work, instead of MAX_BLOCKING_THREADS [default: 512]
+    #[arg(long, global = true, value_name = "N")]
+    pub max_blocking_threads: Option<usize>,
+
+    /// Same as the migrate command, kept for older deployments
+    #[arg(long, hide = true)]
+    pub migrate_only: bool,
+
+    #[command(flatten)]
+    pub serve: ServeArgs,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Serve the API; the default without a command
+    Serve(ServeArgs),
+    /// Apply the schema migrations and exit
+    Migrate,
+    /// Load persons from a JSON or CSV file, keeping the ones that exist
+    Seed {
+        file: PathBuf,
+    },
+    /// Write a backup of every person to a file, as /admin/export does
+    Export {
+        file: PathBuf,
+    },
+}
+
+#[derive(Args)]
+pub struct ServeArgs {
+    /// Address to listen on, instead of HOST [default: 0.0.0.0]
+    #[arg(long)]
+    pub host: Option<IpAddr>,
+
+    /// Port to listen on, instead of PORT [default: 8080]; 0 picks a free one
+    #[arg(long)]
+    pub port: Option<u16>,
+}
+
+impl ServeArgs {
+    /// The address given on the command line, else the one from `HOST` and
+    /// `PORT`, which the configuration file may set as well.
+    pub fn addr(&self, config: &Config) -> Result<SocketAddr, ConfigError> {
+        let host = match self.host {
+            Some(host) => host,
+            None => parse_or(config, "HOST", DEFAULT_HOST)?,
+        };
+        let port = match self.port {
+            Some(port) => port,
+            None => parse_or(config, "PORT", DEFAULT_PORT)?,
+        };
+        Ok(SocketAddr::new(host, port))
+    }
+}
+
+fn parse_or<T: FromStr>(config: &Config, name: &str, default: T) -> Result<T, ConfigError> {
+    match config.var(name) {
+        Some(value) => value
+            .parse()
+            .map_err(|_| ConfigError::Invalid(format!("Invalid {} '{}'", name, value))),
+        None => Ok(default),
+    }
+}
+
+impl Cli {
+    /// `--config`, `--log-level` and the thread counts, by the setting they
+    /// stand in for, and the command to run.
+    pub fn apply(self) -> (Command, BTreeMap<String, String>) {
+        let mut overrides = BTreeMap::new();
+        if let Some(config) = &self.config {
+            overrides.insert("CONFIG_FILE".to_string(), config.display().to_string());
+        }
+        if let Some(log_level) = self.log_level {
+            overrides.insert("LOG_LEVEL".to_string(), log_level);
+        }
+        if let Some(worker_threads) = self.worker_threads {
+            overrides.insert("WORKER_THREADS".to_string(), worker_threads.to_string());
+        }
+        if let Some(max_blocking_threads) = self.max_blocking_threads {
+            overrides.insert("MAX_BLOCKING_THREADS".to_string(), max_blocking_threads.to_string());
+        }
+        if self.migrate_only {
+            return (Command::Migrate, overrides);
+        }
+        (self.command.unwrap_or(Command::Serve(self.serve)), overrides)
+    }
+}
@@ -0,0 +1,56 @@
+use tower_http::compression::{
+    predicate::{And, NotForContentType, Predicate as _, SizeAbove},
+    CompressionLayer,
+};
+use crate::config::Config;
+use crate::errors::ConfigError;
+
+const DEFAULT_ALGORITHMS: &str = "gzip,br,zstd";
+
+/// Smaller bodies gain little and cost a round of the compressor each.
+pub const DEFAULT_MIN_BYTES: u16 = 1024;
+
+pub type Predicate =
+    And<And<And<SizeAbove, NotForContentType>, NotForContentType>, NotForContentType>;
+
+/// Compresses responses of at least `COMPRESSION_MIN_BYTES` (1024) with the
+/// `COMPRESSION` algorithm the client accepts, out of `gzip`, `br` and `zstd`
+/// by default, or none with `COMPRESSION=none`. Images, gRPC and event
+/// streams are left alone.
+pub fn layer_from_config(config: &Config) -> Result<CompressionLayer<Predicate>, ConfigError> {
+    let algorithms = config.var("COMPRESSION").unwrap_or(DEFAULT_ALGORITHMS).to_string();
+    let (mut gzip, mut br, mut zstd) = (false, false, false);
+    for algorithm in algorithms.split(',').map(str::trim) {
+        match algorithm.to_ascii_lowercase().as_str() {
+            "gzip" => gzip = true,
+            "br" => br = true,
+            "zstd" => zstd = true,
+            "none" | "" => {}
+            other => {
+                return Err(ConfigError::Invalid(format!(
+                    "Unknown COMPRESSION algorithm '{}', expected gzip, br, zstd or none",
+                    other
+                )))
+            }
+        }
+    }
+    let min_bytes = match config.var("COMPRESSION_MIN_BYTES") {
+        Some(value) => value.parse().map_err(|_| {
+            ConfigError::Invalid(format!(
+                "COMPRESSION_MIN_BYTES must be a number up to {}, got {}",
+                u16::MAX,
+                value
+            ))
+        })?,
+        None => DEFAULT_MIN_BYTES,
+    };
+    let predicate = SizeAbove::new(min_bytes)
+        .and(NotForContentType::GRPC)
+        .and(NotForContentType::IMAGES)
+        .and(NotForContentType::SSE);
+    Ok(CompressionLayer::new()
+        .gzip(gzip)
+        .br(br)
+        .zstd(zstd)
+        .compress_when(predicate))
+}
@@ -0,0 +1,393 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use figment::providers::{Format, Serialized, Toml, Yaml};
+use figment::value::{Dict, Value as FigmentValue};
+use figment::Figment;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+use crate::auth::Role;
+use crate::errors::ConfigError;
+use crate::person::IdStrategy;
+
+/// Settings that hold credentials, which may be mounted as files instead,
+/// as Docker and Kubernetes secrets are. `API_KEYS_FILE` is read by the API
+/// keys themselves, one entry per line.
+const SECRETS: &[&str] = &[
+    "ADMIN_PASSWORD",
+    "DATABASE_URL",
+    "JWT_SECRET",
+    "MONGODB_URL",
+    "OIDC_CLIENT_SECRET",
+    "REDIS_URL",
+    "SENTRY_DSN",
+    "SESSION_SECRET",
+    "SIGNING_SECRETS",
+];
+
+/// Every setting by the name of its environment variable: those of
+/// `CONFIG_FILE`, overridden by the environment, overridden by the command
+/// line. The environment is read once; the file again on every `reload`.
+#[derive(Clone, Default)]
+pub struct Config {
+    variables: BTreeMap<String, String>,
+    environment: BTreeMap<String, String>,
+    overrides: BTreeMap<String, String>,
+    file: Option<PathBuf>,
+    secret_files: Vec<(String, String)>,
+}
+
+impl Config {
+    /// Reads the environment and the file it or `overrides` name in
+    /// `CONFIG_FILE`.
+    pub fn load(overrides: BTreeMap<String, String>) -> Result<Self, ConfigError> {
+        Config::build(env::vars().collect(), overrides)
+    }
+
+    /// Just the given settings, without the environment.
+    #[cfg(test)]
+    pub fn from_overrides(overrides: &[(&str, &str)]) -> Self {
+        let overrides = overrides
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        Config::build(BTreeMap::new(), overrides).expect("no file to read")
+    }
+
+    /// Reads `CONFIG_FILE` again, keeping the environment and the command line
+    /// of the startup; an unreadable file leaves this configuration as it was.
+    pub fn reload(&self) -> Result<Self, ConfigError> {
+        Config::build(self.environment.clone(), self.overrides.clone())
+    }
+
+    /// Keys of the file are the variable names in any case; keys of nested
+    /// tables are joined with `_`, so `[jwt] secret` sets `JWT_SECRET`, and
+    /// arrays become comma separated lists. A variable of the environment,
+    /// itself or through its `_FILE` twin, wins over the file.
+    fn build(
+        environment: BTreeMap<String, String>,
+        overrides: BTreeMap<String, String>,
+    ) -> Result<Self, ConfigError> {
+        let file = overrides
+            .get("CONFIG_FILE")
+            .or_else(|| environment.get("CONFIG_FILE"))
+            .map(PathBuf::from);
+        let mut variables = match &file {
+            Some(path) => read_file(path)?,
+            None => BTreeMap::new(),
+        };
+        variables.retain(|name, _| {
+            let secret = name.strip_suffix("_FILE").filter(|name| SECRETS.contains(name));
+            !environment.contains_key(name)
+                && !environment.contains_key(&format!("{}_FILE", name))
+                && !secret.is_some_and(|secret| environment.contains_key(secret))
+        });
+        variables.extend(environment.clone());
+        let secret_files = read_secret_files(&mut variables)?;
+        variables.extend(overrides.clone());
+        Ok(Config {
+            variables,
+            environment,
+            overrides,
+            file,
+            secret_files,
+        })
+    }
+
+    pub fn file(&self) -> Option<&Path> {
+        self.file.as_deref()
+    }
+
+    /// The secrets read from files, and the files, for the startup log.
+    pub fn secret_files(&self) -> &[(String, String)] {
+        &self.secret_files
+    }
+
+    pub fn var(&self, name: &str) -> Option<&str> {
+        self.variables.get(name).map(String::as_str)
+    }
+
+    /// Whether `name` is set to `true`.
+    pub fn enabled(&self, name: &str) -> bool {
+        self.var(name) == Some("true")
+    }
+
+    pub fn vars(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.variables.iter().map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    /// The variables of `fields`, in lower case, for figment to extract. Like
+    /// figment's own environment provider, values that look like numbers or
+    /// booleans become those.
+    fn provider(&self, fields: &[&str]) -> Serialized<Dict> {
+        let values = fields
+            .iter()
+            .filter_map(|field| {
+                let value = self.var(&field.to_ascii_uppercase())?;
+                let value: FigmentValue = value.parse().expect("parsing a value is infallible");
+                Some((field.to_string(), value))
+            })
+            .collect();
+        Serialized::defaults(values)
+    }
+
+    /// The settings of `T` in `fields`, over its defaults.
+    fn extract<T>(&self, fields: &[&str]) -> Result<T, ConfigError>
+    where
+        T: Default + Serialize + for<'de> Deserialize<'de>,
+    {
+        Figment::from(Serialized::defaults(T::default()))
+            .merge(self.provider(fields))
+            .extract()
+            .map_err(|e| match e.path.as_slice() {
+                [] => ConfigError::Invalid(e.kind.to_string()),
+                path => ConfigError::Invalid(format!(
+                    "{}: {}",
+                    path.join("_").to_ascii_uppercase(),
+                    e.kind
+                )),
+            })
+    }
+}
+
+fn read_file(path: &Path) -> Result<BTreeMap<String, String>, ConfigError> {
+    let display = path.display();
+    let contents = fs::read_to_string(path).map_err(|e| {
+        ConfigError::Invalid(format!("Could not read CONFIG_FILE {}: {}", display, e))
+    })?;
+    let figment = match path.extension().and_then(|extension| extension.to_str()) {
+        Some("toml") => Figment::from(Toml::string(&contents)),
+        Some("yaml" | "yml") => Figment::from(Yaml::string(&contents)),
+        _ => {
+            return Err(ConfigError::Invalid(format!(
+                "CONFIG_FILE must end in .toml, .yaml or .yml, got {}",
+                display
+            )))
+        }
+    };
+    let values: Value = figment
+        .extract()
+        .map_err(|e| ConfigError::Invalid(format!("Invalid CONFIG_FILE {}: {}", display, e)))?;
+    let mut variables = Vec::new();
+    flatten(String::new(), values, &mut variables);
+    Ok(variables
+        .into_iter()
+        .map(|(name, value)| (name.to_ascii_uppercase(), value))
+        .collect())
+}
+
+/// Sets each secret `NAME` from the file named by `NAME_FILE`, without its
+/// trailing newline, so that the rest of the service reads it like any other
+/// setting.
+fn read_secret_files(
+    variables: &mut BTreeMap<String, String>,
+) -> Result<Vec<(String, String)>, ConfigError> {
+    let mut read = Vec::new();
+    for name in SECRETS {
+        let file_name = format!("{}_FILE", name);
+        let Some(path) = variables.get(&file_name) else {
+            continue;
+        };
+        if variables.contains_key(*name) {
+            return Err(ConfigError::Invalid(format!(
+                "{} and {} cannot both be set",
+                name, file_name
+            )));
+        }
+        let secret = fs::read_to_string(path).map_err(|e| {
+            ConfigError::Invalid(format!("Could not read {} {}: {}", file_name, path, e))
+        })?;
+        read.push((name.to_string(), path.clone()));
+        variables.insert(name.to_string(), secret.trim_end_matches(['\r', '\n']).to_string());
+    }
+    Ok(read)
+}
+
+/// The settings `main` reads itself rather than through a `from_config`
+/// constructor, from the variable of the same name in upper case.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    #[serde(deserialize_with = "text")]
+    pub greeting_text: String,
+    pub id_strategy: IdStrategy,
+    pub strict_json: bool,
+    pub unique_names: bool,
+    pub max_body_bytes: usize,
+    pub snapshot_dir: PathBuf,
+    pub seed_file: Option<PathBuf>,
+    pub audit_log_file: Option<PathBuf>,
+    pub user_registration: bool,
+    pub user_registration_role: Role,
+    pub graphiql: bool,
+}
+
+const SETTINGS: &[&str] = &[
+    "greeting_text",
+    "id_strategy",
+    "strict_json",
+    "unique_names",
+    "max_body_bytes",
+    "snapshot_dir",
+    "seed_file",
+    "audit_log_file",
+    "user_registration",
+    "user_registration_role",
+    "graphiql",
+];
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            greeting_text: "Hi!".to_string(),
+            id_strategy: IdStrategy::Sequential,
+            strict_json: false,
+            unique_names: false,
+            max_body_bytes: 1024 * 1024,
+            snapshot_dir: PathBuf::from("snapshots"),
+            seed_file: None,
+            audit_log_file: None,
+            user_registration: false,
+            user_registration_role: Role::Reader,
+            graphiql: false,
+        }
+    }
+}
+
+impl Settings {
+    pub fn load(config: &Config) -> Result<Self, ConfigError> {
+        config.extract(SETTINGS)
+    }
+
+    pub fn registration_role(&self) -> Option<Role> {
+        self.user_registration.then_some(self.user_registration_role)
+    }
+}
+
+/// How the listeners treat connections, instead of the hyper defaults, from
+/// the variable of the same name in upper case. Read once at startup.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServerSettings {
+    pub keep_alive: bool,
+    pub header_read_timeout_secs: u64,
+    pub max_connections: Option<usize>,
+}
+
+const SERVER_SETTINGS: &[&str] = &["keep_alive", "header_read_timeout_secs", "max_connections"];
+
+impl Default for ServerSettings {
+    fn default() -> Self {
+        ServerSettings {
+            keep_alive: true,
+            header_read_timeout_secs: 30,
+            max_connections: None,
+        }
+    }
+}
+
+impl ServerSettings {
+    pub fn load(config: &Config) -> Result<Self, ConfigError> {
+        let settings: Self = config.extract(SERVER_SETTINGS)?;
+        if settings.max_connections == Some(0) {
+            return Err(ConfigError::Invalid("MAX_CONNECTIONS must be positive".to_string()));
+        }
+        Ok(settings)
+    }
+
+    /// How long a client has to send the headers of a request; `None` when
+    /// `HEADER_READ_TIMEOUT_SECS` is 0. On a kept-alive connection the wait for
+    /// the next request counts as well, so this is its idle timeout too.
+    pub fn header_read_timeout(&self) -> Option<Duration> {
+        let secs = self.header_read_timeout_secs;
+        (secs > 0).then(|| Duration::from_secs(secs))
+    }
+}
+
+fn flatten(name: String, value: Value, variables: &mut Vec<(String, String)>) {
+    let value = match value {
+        Value::Object(fields) => {
+            for (field, value) in fields {
+                let field = if name.is_empty() { field } else { format!("{}_{}", name, field) };
+                flatten(field, value, variables);
+            }
+            return;
+        }
+        Value::Array(values) => values.iter().map(scalar).collect::<Vec<_>>().join(","),
+        Value::Null => return,
+        value => scalar(&value),
+    };
+    variables.push((name, value));
+}
+
+/// Values that look like numbers or booleans are parsed as such, so free text
+/// settings accept those too.
+fn text<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    Ok(scalar(&Value::deserialize(deserializer)?))
+}
+
+fn scalar(value: &Value) -> String {
+    match value {
+        Value::String(value) => value.clone(),
+        value => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variables(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn settings_come_from_the_variables() {
+        let config = Config::from_overrides(&[("GREETING_TEXT", "42"), ("UNIQUE_NAMES", "true")]);
+        let settings = Settings::load(&config).unwrap();
+        assert_eq!(settings.greeting_text, "42");
+        assert!(settings.unique_names);
+        assert_eq!(settings.max_body_bytes, Settings::default().max_body_bytes);
+
+        let config = Config::from_overrides(&[("MAX_BODY_BYTES", "lots")]);
+        let e = Settings::load(&config).unwrap_err().to_string();
+        assert!(e.contains("MAX_BODY_BYTES"), "{}", e);
+    }
+
+    #[test]
+    fn the_environment_wins_over_the_file_and_the_command_line_over_both() {
+        let path = env::temp_dir().join(format!("config-{}.toml", std::process::id()));
+        fs::write(&path, "greeting_text = \"file\"\ngraphiql = true\n[jwt]\nsecret = \"s\"\n")
+            .unwrap();
+        let file = path.to_str().unwrap();
+        let environment = variables(&[("CONFIG_FILE", file), ("GREETING_TEXT", "environment")]);
+        let config = Config::build(environment, variables(&[("LOG_LEVEL", "debug")])).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(config.var("GREETING_TEXT"), Some("environment"));
+        assert_eq!(config.var("JWT_SECRET"), Some("s"));
+        assert_eq!(config.var("LOG_LEVEL"), Some("debug"));
+        assert!(config.enabled("GRAPHIQL"));
+        assert!(config.reload().is_err(), "the file is gone");
+    }
+
+    #[test]
+    fn secrets_are_read_from_files() {
+        let path = env::temp_dir().join(format!("secret-{}", std::process::id()));
+        fs::write(&path, "hunter2\n").unwrap();
+        let file = path.to_str().unwrap();
+        let config = Config::build(variables(&[("JWT_SECRET_FILE", file)]), BTreeMap::new());
+        let both = Config::build(
+            variables(&[("JWT_SECRET_FILE", file), ("JWT_SECRET", "inline")]),
+            BTreeMap::new(),
+        );
+        fs::remove_file(&path).unwrap();
+
+        let config = config.unwrap();
+        assert_eq!(config.var("JWT_SECRET"), Some("hunter2"));
+        assert_eq!(config.secret_files(), [("JWT_SECRET".to_string(), file.to_string())]);
+        assert!(both.is_err());
+    }
+}
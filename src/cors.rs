@@ -0,0 +1,96 @@
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use axum::{
+    extract::{Request, State},
+    http::{HeaderName, HeaderValue, Method},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use tower::{Layer, ServiceExt};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use crate::config::Config;
+use crate::errors::ConfigError;
+use crate::routes::AppState;
+
+const DEFAULT_METHODS: &str = "GET,POST,PUT,PATCH,DELETE";
+const DEFAULT_HEADERS: &str =
+    "authorization,content-type,if-match,if-none-match,x-api-key,x-request-id";
+const DEFAULT_EXPOSED_HEADERS: &str = "etag,link,location,retry-after,x-request-id";
+
+/// Reads `CORS_ALLOWED_ORIGINS` (a list or `*`), `CORS_ALLOWED_METHODS`,
+/// `CORS_ALLOWED_HEADERS`, `CORS_EXPOSED_HEADERS`, `CORS_ALLOW_CREDENTIALS` and
+/// `CORS_MAX_AGE_SECS`; `None` when no origin is allowed.
+pub fn layer_from_config(config: &Config) -> Result<Option<CorsLayer>, ConfigError> {
+    let Some(origins) = config.var("CORS_ALLOWED_ORIGINS") else {
+        return Ok(None);
+    };
+    let credentials = config.enabled("CORS_ALLOW_CREDENTIALS");
+    let origins = if origins.trim() == "*" {
+        if credentials {
+            return Err(ConfigError::Invalid(
+                "CORS_ALLOW_CREDENTIALS needs explicit CORS_ALLOWED_ORIGINS, not *".to_string(),
+            ));
+        }
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(parse_list::<HeaderValue>("CORS_ALLOWED_ORIGINS", origins)?)
+    };
+
+    let list = |name: &str, default: &str| config.var(name).unwrap_or(default).to_string();
+    let methods: Vec<Method> =
+        parse_list("CORS_ALLOWED_METHODS", &list("CORS_ALLOWED_METHODS", DEFAULT_METHODS))?;
+    let headers: Vec<HeaderName> =
+        parse_list("CORS_ALLOWED_HEADERS", &list("CORS_ALLOWED_HEADERS", DEFAULT_HEADERS))?;
+    let exposed: Vec<HeaderName> = parse_list(
+        "CORS_EXPOSED_HEADERS",
+        &list("CORS_EXPOSED_HEADERS", DEFAULT_EXPOSED_HEADERS),
+    )?;
+    let mut layer = CorsLayer::new()
+        .allow_origin(origins)
+        .allow_methods(methods)
+        .allow_headers(headers)
+        .expose_headers(exposed)
+        .allow_credentials(credentials);
+    if let Some(max_age) = config.var("CORS_MAX_AGE_SECS") {
+        let max_age = max_age.parse().map_err(|_| {
+            ConfigError::Invalid(format!("CORS_MAX_AGE_SECS must be a number, got {}", max_age))
+        })?;
+        layer = layer.max_age(Duration::from_secs(max_age));
+    }
+    Ok(Some(layer))
+}
+
+/// The CORS policy in force, which a configuration reload may replace.
+pub struct Cors {
+    layer: RwLock<Option<CorsLayer>>,
+}
+
+impl Cors {
+    pub fn new(layer: Option<CorsLayer>) -> Self {
+        Cors { layer: RwLock::new(layer) }
+    }
+
+    pub fn set(&self, layer: Option<CorsLayer>) {
+        *self.layer.write().unwrap_or_else(|e| e.into_inner()) = layer;
+    }
+}
+
+/// Answers preflight requests and adds the CORS headers, as `CorsLayer` does.
+pub async fn apply(State(state): State<Arc<AppState>>, request: Request, next: Next) -> Response {
+    let layer = state.cors.layer.read().unwrap_or_else(|e| e.into_inner()).clone();
+    match layer {
+        Some(layer) => layer.layer(next).oneshot(request).await.into_response(),
+        None => next.run(request).await,
+    }
+}
+
+fn parse_list<T: std::str::FromStr>(name: &str, list: &str) -> Result<Vec<T>, ConfigError> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(|item| {
+            item.parse()
+                .map_err(|_| ConfigError::Invalid(format!("Invalid {} entry '{}'", name, item)))
+        })
+        .collect()
+}
@@ -0,0 +1,315 @@
+use std::sync::Arc;
+use axum::{
+    body::Body,
+    extract::State,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    middleware,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use chrono::NaiveDate;
+use futures::{stream, Stream, StreamExt};
+use serde::Deserialize;
+use crate::auth::{self, Role};
+use crate::bulk::{self, BulkItemResult, BulkPerson, BulkResponse};
+use crate::errors::HttpAppError;
+use crate::extract::QueryParams;
+use crate::listing::{ListQuery, PersonFilter, Sort};
+use crate::pagination::PageRequest;
+use crate::person::{NewPerson, Person};
+use crate::routes::AppState;
+use crate::validation::Validate;
+
+const CSV_HEADER: [&str; 6] = ["id", "uuid", "name", "age", "date", "deleted_at"];
+const EXPORT_CHUNK: u64 = 500;
+
+pub fn create_csv_routes() -> Router<Arc<AppState>> {
+    let writer = middleware::from_fn_with_state(Role::Writer, auth::authorize);
+    Router::new()
+        .route("/persons/export.csv", get(export_csv))
+        .route("/persons/import", post(import_csv).route_layer(writer))
+}
+
+/// A CSV row; other columns such as the `id` and `version` of an export are
+/// ignored, and the persons get new ids.
+#[derive(Deserialize)]
+struct ImportRow {
+    name: String,
+    age: u8,
+    date: NaiveDate,
+}
+
+impl From<ImportRow> for BulkPerson {
+    fn from(row: ImportRow) -> Self {
+        BulkPerson {
+            id: None,
+            person: NewPerson {
+                uuid: None,
+                name: row.name,
+                age: row.age,
+                date: row.date,
+            },
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/persons/export.csv",
+    tag = "persons",
+    summary = "Export the matching persons as CSV",
+    params(PersonFilter, Sort),
+    responses((status = 200, content_type = "text/csv", body = String))
+)]
+async fn export_csv(
+    QueryParams(filter): QueryParams<PersonFilter>,
+    QueryParams(sort): QueryParams<Sort>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Response, HttpAppError> {
+    filter.validate()?;
+    let mut response = stream_csv(state, filter, sort)?;
+    response.headers_mut().insert(
+        header::CONTENT_DISPOSITION,
+        HeaderValue::from_static("attachment; filename=\"persons.csv\""),
+    );
+    Ok(response)
+}
+
+/// The matching persons as CSV, in the order of `sort`, written as they are
+/// read so that the list is never held in memory at once.
+pub fn stream_csv(
+    state: Arc<AppState>,
+    filter: PersonFilter,
+    sort: Sort,
+) -> Result<Response, HttpAppError> {
+    let header = encode_rows(&[], true)?;
+    let body = stream::once(async { Ok(header) }).chain(export_chunks(state, filter, sort));
+    let headers = [(header::CONTENT_TYPE, "text/csv; charset=utf-8")];
+    Ok((headers, Body::from_stream(body)).into_response())
+}
+
+/// Where the next chunk starts: after an id in the default order, which stays
+/// right while persons are added, or else at a page of the sorted list.
+enum Chunk {
+    After(Option<u32>),
+    Page(u64),
+}
+
+fn export_chunks(
+    state: Arc<AppState>,
+    filter: PersonFilter,
+    sort: Sort,
+) -> impl Stream<Item = Result<Vec<u8>, HttpAppError>> {
+    let first = if sort.is_default() { Chunk::After(None) } else { Chunk::Page(1) };
+    stream::try_unfold(Some(first), move |chunk: Option<Chunk>| {
+        let state = state.clone();
+        let filter = filter.clone();
+        async move {
+            let Some(chunk) = chunk else {
+                return Ok(None);
+            };
+            let (persons, next) = match chunk {
+                Chunk::After(after) => {
+                    let persons = state
+                        .person_repository
+                        .list_after(&filter, after, EXPORT_CHUNK)
+                        .await?;
+                    let next = persons.last().map(|last| Chunk::After(Some(last.id)));
+                    (persons, next)
+                }
+                Chunk::Page(page) => {
+                    let page_request = PageRequest { page, per_page: EXPORT_CHUNK };
+                    let query = ListQuery { filter, sort, page: page_request };
+                    let persons = state.person_repository.list_page(&query).await?.items;
+                    (persons, Some(Chunk::Page(page + 1)))
+                }
+            };
+            let next = next.filter(|_| persons.len() as u64 == EXPORT_CHUNK);
+            Ok(Some((encode_rows(&persons, false)?, next)))
+        }
+    })
+}
+
+pub fn is_csv(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("text/csv"))
+}
+
+/// Parses CSV rows for import; a row that does not parse becomes a 400 error.
+pub fn parse_rows(body: &str) -> Vec<Result<BulkPerson, HttpAppError>> {
+    csv::Reader::from_reader(body.as_bytes())
+        .deserialize::<ImportRow>()
+        .map(|row| {
+            row.map(BulkPerson::from)
+                .map_err(|e| HttpAppError::BadRequest(e.to_string()))
+        })
+        .collect()
+}
+
+async fn import_csv(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    body: String,
+) -> Result<Json<BulkResponse>, HttpAppError> {
+    if !is_csv(&headers) {
+        return Err(HttpAppError::UnsupportedMediaType("expected text/csv".to_string()));
+    }
+    let rows = parse_rows(&body);
+    bulk::check_size(&rows)?;
+    let mut results = Vec::with_capacity(rows.len());
+    for (index, row) in rows.into_iter().enumerate() {
+        let result = match row {
+            Ok(row) => bulk::create_one(&state, row).await,
+            Err(e) => Err(e),
+        };
+        results.push(BulkItemResult::from_result(index, StatusCode::CREATED, result));
+    }
+    Ok(Json(BulkResponse::new(results)))
+}
+
+fn encode_rows(persons: &[Person], with_header: bool) -> Result<Vec<u8>, HttpAppError> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    if with_header {
+        writer.write_record(CSV_HEADER).map_err(csv_error)?;
+    }
+    for person in persons {
+        writer
+            .write_record([
+                person.id.to_string(),
+                person.uuid.map(|uuid| uuid.to_string()).unwrap_or_default(),
+                person.name.clone(),
+                person.age.to_string(),
+                person.date.to_string(),
+                person.deleted_at.map(|at| at.to_rfc3339()).unwrap_or_default(),
+            ])
+            .map_err(csv_error)?;
+    }
+    writer
+        .into_inner()
+        .map_err(|e| HttpAppError::StorageError(e.to_string()))
+}
+
+fn csv_error(error: csv::Error) -> HttpAppError {
+    HttpAppError::StorageError(error.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body;
+    use axum::http::Request;
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+    use crate::person::create_person_collection;
+    use crate::repository::MemoryRepository;
+
+    async fn export(state: &Arc<AppState>, query: &str) -> String {
+        let request = Request::get(format!("/persons/export.csv{}", query))
+            .body(Body::empty())
+            .unwrap();
+        let router = create_csv_routes().with_state(state.clone());
+        let response = router.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/csv; charset=utf-8");
+        let body = body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn exports_have_a_header_and_quote_fields() {
+        let state = Arc::new(AppState::for_tests());
+        let mut peach = create_person_collection()[0].clone();
+        peach.id = 3;
+        peach.name = "Peach, \"Princess\"".to_string();
+        state.person_repository.insert(peach).await.unwrap();
+        state.person_repository.delete(2, 0).await.unwrap();
+
+        let csv = export(&state, "").await;
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], "id,uuid,name,age,date,deleted_at");
+        assert_eq!(lines[1], "1,,Mario,43,1981-02-21,");
+        assert_eq!(lines[2], "3,,\"Peach, \"\"Princess\"\"\",43,1981-02-21,");
+        assert_eq!(lines.len(), 3);
+
+        let csv = export(&state, "?sort=age&order=asc&include_deleted=true").await;
+        let ids: Vec<&str> = csv.lines().skip(1).map(|line| &line[..1]).collect();
+        assert_eq!(ids, ["2", "1", "3"]);
+    }
+
+    #[tokio::test]
+    async fn exports_continue_past_a_chunk() {
+        let persons = (1..=EXPORT_CHUNK as u32 * 2 + 1)
+            .map(|id| Person { id, ..create_person_collection()[0].clone() })
+            .collect();
+        let mut state = AppState::for_tests();
+        state.person_repository = Box::new(MemoryRepository::new(persons));
+        let state = Arc::new(state);
+        for query in ["", "?sort=name"] {
+            let csv = export(&state, query).await;
+            assert_eq!(csv.lines().count() as u64, 1 + EXPORT_CHUNK * 2 + 1, "{}", query);
+        }
+    }
+
+    async fn import(state: &Arc<AppState>, content_type: &str, csv: &str) -> (StatusCode, Value) {
+        let request = Request::post("/persons/import")
+            .header(header::CONTENT_TYPE, content_type)
+            .body(Body::from(csv.to_string()))
+            .unwrap();
+        let router = create_csv_routes().with_state(state.clone());
+        let response = router.oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn imports_report_each_row() {
+        let state = Arc::new(AppState::for_tests());
+        let csv = "name,age,date\n\"Peach, \"\"Princess\"\"\",30,1985-09-13\nDaisy,old,1989-04-21\n\
+                   ,20,1990-01-01\nToad,25\n";
+        let (status, response) = import(&state, "text/csv", csv).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!((&response["succeeded"], &response["failed"]), (&json!(1), &json!(3)));
+        let results = response["results"].as_array().unwrap();
+        let statuses: Vec<&Value> = results.iter().map(|result| &result["status"]).collect();
+        assert_eq!(statuses, [&json!(201), &json!(400), &json!(422), &json!(400)]);
+        assert_eq!(state.person_repository.get(3).await.unwrap().name, "Peach, \"Princess\"");
+        assert_eq!(state.person_repository.list().await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn imports_need_csv_with_rows() {
+        let state = Arc::new(AppState::for_tests());
+        let csv = "name,age,date\nToad,25,1990-01-01\n";
+        let (status, _) = import(&state, "application/json", csv).await;
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        let (status, _) = import(&state, "text/csv", "name,age,date\n").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (_, response) = import(&state, "text/csv", "age,date\n25,1990-01-01\n").await;
+        assert_eq!(response["results"][0]["status"], 400);
+        assert_eq!(state.person_repository.list().await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn exports_import_as_new_persons() {
+        let state = Arc::new(AppState::for_tests());
+        let csv = export(&state, "").await;
+        let (_, response) = import(&state, "text/csv; charset=utf-8", &csv).await;
+        assert_eq!(response["succeeded"], 2);
+
+        let persons = state.person_repository.list().await.unwrap();
+        let copies: Vec<_> = persons[2..]
+            .iter()
+            .map(|person| (person.id, person.name.as_str(), person.age, person.date))
+            .collect();
+        let originals = create_person_collection();
+        let expected: Vec<_> = originals
+            .iter()
+            .map(|person| (person.id + 2, person.name.as_str(), person.age, person.date))
+            .collect();
+        assert_eq!(copies, expected);
+    }
+}
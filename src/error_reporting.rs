@@ -0,0 +1,99 @@
+use axum::{
+    extract::{MatchedPath, Request},
+    http::header,
+    middleware::Next,
+    response::Response,
+};
+use sentry::{protocol, types::Dsn, ClientInitGuard, ClientOptions};
+use crate::auth::API_KEY_HEADER;
+use crate::config::Config;
+use crate::errors::{ConfigError, HttpAppError};
+use crate::proxy::{Client, Scheme};
+use crate::request_id::REQUEST_ID_HEADER;
+use crate::signatures::SIGNATURE_HEADER;
+
+/// Headers left out of the request context sent along with an event.
+const REDACTED_HEADERS: &[&str] = &[
+    "authorization",
+    "cookie",
+    API_KEY_HEADER,
+    SIGNATURE_HEADER,
+];
+
+/// Starts reporting panics and server errors to the Sentry project of
+/// `SENTRY_DSN`, with the crate version and git commit as the release.
+/// `SENTRY_ENVIRONMENT` names the environment. Without a DSN nothing is sent.
+/// Events are flushed when the guard is dropped.
+pub fn init(config: &Config) -> Result<Option<ClientInitGuard>, ConfigError> {
+    let Some(dsn) = config.var("SENTRY_DSN") else {
+        return Ok(None);
+    };
+    let dsn: Dsn = dsn
+        .parse()
+        .map_err(|e| ConfigError::Invalid(format!("SENTRY_DSN is not a valid DSN: {}", e)))?;
+    let git_sha = env!("BUILD_GIT_SHA");
+    let release = format!(
+        "{}@{}+{}",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        &git_sha[..git_sha.len().min(7)]
+    );
+    Ok(Some(sentry::init(ClientOptions {
+        dsn: Some(dsn),
+        release: Some(release.into()),
+        ..Default::default()
+    })))
+}
+
+/// Attaches the request, its route and request id to whatever the request
+/// reports. Runs inside the hub `NewSentryLayer` gives each request.
+pub async fn add_context(request: Request, next: Next) -> Response {
+    let host = request
+        .headers()
+        .get(header::HOST)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("localhost");
+    let headers = request
+        .headers()
+        .iter()
+        .filter(|(name, _)| {
+            !REDACTED_HEADERS.iter().any(|redacted| name.as_str().eq_ignore_ascii_case(redacted))
+        })
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+        .collect();
+    let scheme = request.extensions().get::<Client>().map_or(Scheme::Http, |client| client.scheme);
+    let context = protocol::Request {
+        method: Some(request.method().to_string()),
+        url: format!("{}://{}{}", scheme, host, request.uri().path()).parse().ok(),
+        headers,
+        ..Default::default()
+    };
+    let route = request.extensions().get::<MatchedPath>().map(|path| path.as_str().to_string());
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    sentry::configure_scope(|scope| {
+        scope.add_event_processor(move |mut event| {
+            event.request.get_or_insert_with(|| context.clone());
+            Some(event)
+        });
+        if let Some(route) = route {
+            scope.set_tag("route", route);
+        }
+        if let Some(request_id) = request_id {
+            scope.set_tag("request_id", request_id);
+        }
+    });
+    next.run(request).await
+}
+
+/// Reports errors the client is not to blame for, other than maintenance or
+/// overload turning requests away; no-op without `SENTRY_DSN`.
+pub fn report(error: &HttpAppError) {
+    let turned_away = matches!(error, HttpAppError::Maintenance(_) | HttpAppError::Overloaded);
+    if error.status_code().is_server_error() && !turned_away {
+        sentry::capture_error(error);
+    }
+}
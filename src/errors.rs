@@ -1,29 +1,213 @@
 use std::sync::{PoisonError, RwLockReadGuard, RwLockWriteGuard};
 use axum::{
-    http::StatusCode,
+    http::{header, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
+use metrics::counter;
+use serde::Serialize;
 use thiserror::Error;
+use tower::{load_shed::error::Overloaded, timeout::error::Elapsed, BoxError};
+use utoipa::ToSchema;
+use crate::error_reporting;
+use crate::request_id;
+use crate::sql::NAME_INDEX;
+use crate::validation::FieldError;
 
 #[derive(Debug, Error)]
 pub enum HttpAppError {
+    #[error("Bad request: {0}")]
+    BadRequest(String),
     #[error("An element with the same ID already exists")]
     Conflict,
+    #[error("A person with the same name already exists")]
+    DuplicateName,
+    #[error("The username is already taken")]
+    UsernameTaken,
     #[error("Not found")]
     NotFound,
+    #[error("The person is not deleted")]
+    NotDeleted,
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+    #[error("Unauthorized: admin credentials are required")]
+    AdminUnauthorized,
+    #[error("Invalid signature: {0}")]
+    InvalidSignature(String),
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+    #[error("The person was modified by someone else")]
+    PreconditionFailed,
+    #[error("An If-Match header or a version is required")]
+    PreconditionRequired,
+    #[error("Not acceptable: {0}")]
+    NotAcceptable(String),
+    #[error("Unsupported media type: {0}")]
+    UnsupportedMediaType(String),
+    #[error("The request body is too large")]
+    PayloadTooLarge,
+    #[error("Too many requests, retry in {0}s")]
+    TooManyRequests(u64),
+    #[error("The request took too long")]
+    Timeout,
+    #[error("The service is overloaded, retry later")]
+    Overloaded,
+    #[error("Down for maintenance, retry in {0}s")]
+    Maintenance(u64),
+    #[error("Validation failed: {}", describe(.0))]
+    Validation(Vec<FieldError>),
+    #[error("Identity provider error {0}")]
+    IdentityProvider(String),
     #[error("Poison error {0}")]
     LockError(String),
+    #[error("Database error {0}")]
+    DatabaseError(String),
+    #[error("Storage error {0}")]
+    StorageError(String),
 }
 
-impl IntoResponse for HttpAppError {
-    fn into_response(self) -> Response {
-        let status = match self {
+#[derive(Debug, Error)]
+pub enum StorageError {
+    #[error("{0}")]
+    Config(String),
+    #[error("Database error {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("I/O error {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid JSON data {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Sled error {0}")]
+    Sled(#[from] sled::Error),
+    #[error("Redis error {0}")]
+    Redis(#[from] redis::RedisError),
+    #[error("MongoDB error {0}")]
+    Mongo(#[from] mongodb::error::Error),
+}
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("{0}")]
+    Invalid(String),
+}
+
+#[derive(Debug, Error)]
+pub enum AuthError {
+    #[error("{0}")]
+    Config(String),
+    #[error("I/O error {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid key {0}")]
+    Key(#[from] jsonwebtoken::errors::Error),
+    #[error("Invalid JWKS {0}")]
+    Jwks(#[from] serde_json::Error),
+    #[error("Identity provider error {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("Session store error {0}")]
+    SessionStore(#[from] redis::RedisError),
+}
+
+impl HttpAppError {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            HttpAppError::BadRequest(_) => StatusCode::BAD_REQUEST,
             HttpAppError::Conflict => StatusCode::CONFLICT,
+            HttpAppError::DuplicateName => StatusCode::CONFLICT,
+            HttpAppError::UsernameTaken => StatusCode::CONFLICT,
             HttpAppError::NotFound => StatusCode::NOT_FOUND,
+            HttpAppError::NotDeleted => StatusCode::CONFLICT,
+            HttpAppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            HttpAppError::AdminUnauthorized => StatusCode::UNAUTHORIZED,
+            HttpAppError::InvalidSignature(_) => StatusCode::UNAUTHORIZED,
+            HttpAppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            HttpAppError::PreconditionFailed => StatusCode::PRECONDITION_FAILED,
+            HttpAppError::PreconditionRequired => StatusCode::PRECONDITION_REQUIRED,
+            HttpAppError::NotAcceptable(_) => StatusCode::NOT_ACCEPTABLE,
+            HttpAppError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            HttpAppError::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            HttpAppError::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            HttpAppError::Timeout => StatusCode::GATEWAY_TIMEOUT,
+            HttpAppError::Overloaded => StatusCode::SERVICE_UNAVAILABLE,
+            HttpAppError::Maintenance(_) => StatusCode::SERVICE_UNAVAILABLE,
+            HttpAppError::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            HttpAppError::IdentityProvider(_) => StatusCode::BAD_GATEWAY,
             HttpAppError::LockError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            HttpAppError::DatabaseError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            HttpAppError::StorageError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ErrorBody {
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ValidationBody<'a> {
+    message: &'static str,
+    errors: &'a [FieldError],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+}
+
+fn describe(errors: &[FieldError]) -> String {
+    let errors: Vec<String> = errors
+        .iter()
+        .map(|error| format!("{} {}", error.field, error.message))
+        .collect();
+    errors.join(", ")
+}
+
+/// Error bodies carry the request id, to find the failure in the server logs.
+impl IntoResponse for HttpAppError {
+    fn into_response(self) -> Response {
+        error_reporting::report(&self);
+        let request_id = request_id::current();
+        if let HttpAppError::Validation(errors) = &self {
+            let body = ValidationBody { message: "Validation failed", errors, request_id };
+            return (self.status_code(), Json(body)).into_response();
+        }
+        let body = Json(ErrorBody { message: self.to_string(), request_id });
+        if let HttpAppError::TooManyRequests(retry_after) | HttpAppError::Maintenance(retry_after) =
+            &self
+        {
+            let retry_after = [(header::RETRY_AFTER, retry_after.to_string())];
+            return (self.status_code(), retry_after, body).into_response();
+        }
+        let challenge = match &self {
+            HttpAppError::Unauthorized(_) => Some("Bearer"),
+            HttpAppError::AdminUnauthorized => Some("Basic realm=\"admin\", charset=\"UTF-8\""),
+            _ => None,
         };
-        (status, Json(self.to_string())).into_response()
+        if let Some(challenge) = challenge {
+            let challenge = [(header::WWW_AUTHENTICATE, challenge)];
+            return (self.status_code(), challenge, body).into_response();
+        }
+        (self.status_code(), body).into_response()
+    }
+}
+
+/// Replaces the plain-text 413 of the body limit layer with the app's JSON error.
+pub async fn payload_too_large(response: Response) -> Response {
+    if response.status() == StatusCode::PAYLOAD_TOO_LARGE {
+        return HttpAppError::PayloadTooLarge.into_response();
+    }
+    response
+}
+
+/// Turns the errors of the timeout and load shedding layers into the app's
+/// JSON errors.
+pub async fn from_layers(error: BoxError) -> HttpAppError {
+    if error.is::<Elapsed>() {
+        tracing::warn!("Request timed out");
+        HttpAppError::Timeout
+    } else if error.is::<Overloaded>() {
+        counter!("http_requests_shed_total").increment(1);
+        HttpAppError::Overloaded
+    } else {
+        HttpAppError::StorageError(error.to_string())
     }
 }
 
@@ -38,3 +222,59 @@ impl<T> From<PoisonError<RwLockWriteGuard<'_, T>>> for HttpAppError {
         HttpAppError::LockError("Write Lock was poisoned".to_string())
     }
 }
+
+impl From<reqwest::Error> for HttpAppError {
+    fn from(error: reqwest::Error) -> Self {
+        HttpAppError::IdentityProvider(error.to_string())
+    }
+}
+
+impl From<sqlx::Error> for HttpAppError {
+    fn from(error: sqlx::Error) -> Self {
+        match error {
+            sqlx::Error::RowNotFound => HttpAppError::NotFound,
+            sqlx::Error::Database(e) if e.is_unique_violation() => {
+                if e.message().contains(NAME_INDEX) {
+                    HttpAppError::DuplicateName
+                } else {
+                    HttpAppError::Conflict
+                }
+            }
+            e => HttpAppError::DatabaseError(e.to_string()),
+        }
+    }
+}
+
+impl From<std::io::Error> for HttpAppError {
+    fn from(error: std::io::Error) -> Self {
+        HttpAppError::StorageError(error.to_string())
+    }
+}
+
+impl From<sled::Error> for HttpAppError {
+    fn from(error: sled::Error) -> Self {
+        HttpAppError::StorageError(error.to_string())
+    }
+}
+
+impl From<redis::RedisError> for HttpAppError {
+    fn from(error: redis::RedisError) -> Self {
+        HttpAppError::StorageError(error.to_string())
+    }
+}
+
+impl From<mongodb::error::Error> for HttpAppError {
+    fn from(error: mongodb::error::Error) -> Self {
+        use mongodb::error::{ErrorKind, WriteFailure};
+        match *error.kind {
+            ErrorKind::Write(WriteFailure::WriteError(ref e)) if e.code == 11000 => {
+                if e.message.contains(NAME_INDEX) {
+                    HttpAppError::DuplicateName
+                } else {
+                    HttpAppError::Conflict
+                }
+            }
+            _ => HttpAppError::DatabaseError(error.to_string()),
+        }
+    }
+}
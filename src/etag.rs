@@ -0,0 +1,80 @@
+use axum::http::{header, HeaderMap, HeaderValue};
+use crate::errors::HttpAppError;
+
+/// The entity tag of a person version; partial representations get a weak tag.
+pub fn for_version(version: u32, weak: bool) -> String {
+    let prefix = if weak { "W/" } else { "" };
+    format!("{}\"v{}\"", prefix, version)
+}
+
+/// Whether `If-None-Match` lists `etag`; weak tags compare equal to strong ones here.
+pub fn none_match(headers: &HeaderMap, etag: &str) -> bool {
+    let etag = etag.trim_start_matches("W/");
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+/// The version named by `If-Match`, with `*` as 0 to match any version.
+pub fn if_match(headers: &HeaderMap) -> Result<Option<u32>, HttpAppError> {
+    let Some(value) = headers.get(header::IF_MATCH) else {
+        return Ok(None);
+    };
+    let value = value.to_str().unwrap_or_default().trim();
+    if value == "*" {
+        return Ok(Some(0));
+    }
+    value
+        .strip_prefix("\"v")
+        .and_then(|tag| tag.strip_suffix('"'))
+        .and_then(|version| version.parse().ok())
+        .map(Some)
+        .ok_or(HttpAppError::PreconditionFailed)
+}
+
+pub fn header_value(etag: &str) -> HeaderValue {
+    HeaderValue::from_str(etag).expect("entity tags are ASCII")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::person::create_person_collection;
+
+    fn headers(name: header::HeaderName, value: &'static str) -> HeaderMap {
+        HeaderMap::from_iter([(name, HeaderValue::from_static(value))])
+    }
+
+    #[test]
+    fn if_match_names_a_version() {
+        assert_eq!(if_match(&HeaderMap::new()).unwrap(), None);
+        assert_eq!(if_match(&headers(header::IF_MATCH, "\"v3\"")).unwrap(), Some(3));
+        assert_eq!(if_match(&headers(header::IF_MATCH, "*")).unwrap(), Some(0));
+        for value in ["v3", "\"3\"", "W/\"v3\"", "\"vx\""] {
+            let result = if_match(&headers(header::IF_MATCH, value));
+            assert!(matches!(result, Err(HttpAppError::PreconditionFailed)), "{}", value);
+        }
+    }
+
+    #[test]
+    fn none_match_ignores_weakness() {
+        let headers = headers(header::IF_NONE_MATCH, "\"v1\", W/\"v2\"");
+        assert!(none_match(&headers, &for_version(1, true)));
+        assert!(none_match(&headers, &for_version(2, false)));
+        assert!(!none_match(&headers, &for_version(3, false)));
+        assert!(none_match(&self::headers(header::IF_NONE_MATCH, "*"), "\"v9\""));
+    }
+
+    #[test]
+    fn versions_are_checked_unless_zero() {
+        let person = &create_person_collection()[0];
+        assert!(person.check_version(0).is_ok());
+        assert!(person.check_version(person.version).is_ok());
+        let stale = person.check_version(person.version + 1);
+        assert!(matches!(stale, Err(HttpAppError::PreconditionFailed)));
+    }
+}
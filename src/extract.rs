@@ -0,0 +1,250 @@
+use std::sync::Arc;
+use async_trait::async_trait;
+use axum::{
+    body::Bytes,
+    extract::{FromRequest, FromRequestParts, Request},
+    http::{header, request::Parts, HeaderMap, StatusCode},
+};
+use quick_xml::DeError;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::error::Category;
+use crate::errors::HttpAppError;
+use crate::negotiation::{self, Format};
+use crate::protobuf::FromProtobuf;
+use crate::routes::AppState;
+use crate::strict::StrictBody;
+use crate::validation::{FieldError, Validate};
+
+/// Like `axum::Json`, but rejects bodies with the app's JSON errors: 415 without a
+/// JSON content type, 400 for malformed JSON and 422 naming the offending field.
+/// With `STRICT_JSON` on, unknown fields are rejected too.
+pub struct JsonBody<T>(pub T);
+
+#[async_trait]
+impl<T: StrictBody> FromRequest<Arc<AppState>> for JsonBody<T> {
+    type Rejection = HttpAppError;
+
+    async fn from_request(
+        request: Request,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        if !is_json(request.headers()) {
+            return Err(HttpAppError::UnsupportedMediaType(
+                "expected application/json".to_string(),
+            ));
+        }
+        let bytes = body_bytes(request, state).await?;
+        parse(&bytes, state.settings().strict_json).map(JsonBody)
+    }
+}
+
+async fn body_bytes(request: Request, state: &Arc<AppState>) -> Result<Bytes, HttpAppError> {
+    Bytes::from_request(request, state)
+        .await
+        .map_err(|e| match e.status() {
+            StatusCode::PAYLOAD_TOO_LARGE => HttpAppError::PayloadTooLarge,
+            _ => HttpAppError::BadRequest(e.body_text()),
+        })
+}
+
+/// A `JsonBody` that is also validated, so handlers only see acceptable data.
+pub struct ValidatedJson<T>(pub T);
+
+#[async_trait]
+impl<T: StrictBody + Validate> FromRequest<Arc<AppState>> for ValidatedJson<T> {
+    type Rejection = HttpAppError;
+
+    async fn from_request(
+        request: Request,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        let JsonBody(value) = JsonBody::<T>::from_request(request, state).await?;
+        value.validate()?;
+        Ok(ValidatedJson(value))
+    }
+}
+
+/// Like `JsonBody`, but also takes XML, MessagePack and, for the bodies with
+/// a message, Protobuf, by the content type, for the endpoints that answer in
+/// those formats too.
+pub struct Body<T>(pub T);
+
+#[async_trait]
+impl<T: StrictBody + FromProtobuf> FromRequest<Arc<AppState>> for Body<T> {
+    type Rejection = HttpAppError;
+
+    async fn from_request(
+        request: Request,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        let Some(format) = Format::from_content_type(request.headers()) else {
+            return Err(HttpAppError::UnsupportedMediaType(format!(
+                "expected {}",
+                negotiation::SUPPORTED
+            )));
+        };
+        let bytes = body_bytes(request, state).await?;
+        if let (Format::Protobuf, Some(value)) = (format, T::from_protobuf(&bytes)) {
+            return value.map(Body);
+        }
+        let value = if state.settings().strict_json {
+            decode::<T::Strict>(format, &bytes, T::LIST).map(T::from_strict)?
+        } else {
+            decode(format, &bytes, T::LIST)?
+        };
+        Ok(Body(value))
+    }
+}
+
+/// A `Body` that is also validated, like `ValidatedJson`.
+pub struct ValidatedBody<T>(pub T);
+
+#[async_trait]
+impl<T: StrictBody + FromProtobuf + Validate> FromRequest<Arc<AppState>> for ValidatedBody<T> {
+    type Rejection = HttpAppError;
+
+    async fn from_request(
+        request: Request,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        let Body(value) = Body::<T>::from_request(request, state).await?;
+        value.validate()?;
+        Ok(ValidatedBody(value))
+    }
+}
+
+/// Like `axum::extract::Query`, but names the offending parameter in a 400 answer.
+pub struct QueryParams<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for QueryParams<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = HttpAppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let query = parts.uri.query().unwrap_or_default();
+        let deserializer =
+            serde_urlencoded::Deserializer::new(form_urlencoded::parse(query.as_bytes()));
+        serde_path_to_error::deserialize(deserializer).map(QueryParams).map_err(|e| {
+            let path = e.path().to_string();
+            let reason = e.into_inner();
+            if path == "." {
+                HttpAppError::BadRequest(format!("Invalid query: {}", reason))
+            } else {
+                HttpAppError::BadRequest(format!("Invalid query parameter {}: {}", path, reason))
+            }
+        })
+    }
+}
+
+fn is_json(headers: &HeaderMap) -> bool {
+    let Some(content_type) = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+    else {
+        return false;
+    };
+    let mime = content_type.split(';').next().unwrap_or_default().trim();
+    mime.eq_ignore_ascii_case("application/json") || mime.ends_with("+json")
+}
+
+/// Deserializes a request body, through its strict twin when `strict` is set.
+pub fn parse<T: StrictBody>(bytes: &[u8], strict: bool) -> Result<T, HttpAppError> {
+    if strict {
+        parse_json::<T::Strict>(bytes).map(T::from_strict)
+    } else {
+        parse_json(bytes)
+    }
+}
+
+/// Deserializes a JSON document, reporting where in it deserialization failed.
+fn parse_json<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, HttpAppError> {
+    let mut deserializer = serde_json::Deserializer::from_slice(bytes);
+    serde_path_to_error::deserialize(&mut deserializer).map_err(|e| {
+        let path = e.path().to_string();
+        let inner = e.into_inner();
+        if inner.classify() != Category::Data {
+            return HttpAppError::BadRequest(format!("Invalid JSON: {}", inner));
+        }
+        let message = inner.to_string();
+        field_error(path, message.split(" at line ").next().unwrap_or_default())
+    })
+}
+
+fn decode<T: DeserializeOwned>(
+    format: Format,
+    bytes: &[u8],
+    list: bool,
+) -> Result<T, HttpAppError> {
+    match format {
+        Format::Json => parse_json(bytes),
+        Format::Msgpack => parse_msgpack(bytes),
+        Format::Protobuf => Err(HttpAppError::UnsupportedMediaType(
+            "this body has no Protobuf message".to_string(),
+        )),
+        Format::Xml if list => parse_xml::<XmlList<T>>(bytes).map(|list| list.items),
+        Format::Xml => parse_xml(bytes),
+    }
+}
+
+/// Deserializes a MessagePack document, a map with the field names as keys.
+fn parse_msgpack<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, HttpAppError> {
+    let mut deserializer = rmp_serde::Deserializer::new(bytes).with_human_readable();
+    serde_path_to_error::deserialize(&mut deserializer).map_err(|e| {
+        let path = e.path().to_string();
+        match e.into_inner() {
+            rmp_serde::decode::Error::Syntax(reason) => field_error(path, &reason),
+            inner @ rmp_serde::decode::Error::TypeMismatch(_) => {
+                field_error(path, &inner.to_string())
+            }
+            inner => HttpAppError::BadRequest(format!("Invalid MessagePack: {}", inner)),
+        }
+    })
+}
+
+/// The items of a list in XML, the children of the root element whatever
+/// their names, such as `<persons><person>…</person></persons>`.
+#[derive(Deserialize)]
+struct XmlList<T> {
+    #[serde(rename = "$value")]
+    items: T,
+}
+
+/// Deserializes an XML document, whose root element may have any name.
+fn parse_xml<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, HttpAppError> {
+    let xml = std::str::from_utf8(bytes)
+        .map_err(|e| HttpAppError::BadRequest(format!("Invalid XML: {}", e)))?;
+    let mut deserializer = quick_xml::de::Deserializer::from_str(xml);
+    serde_path_to_error::deserialize(&mut deserializer).map_err(|e| {
+        let path = match e.path().to_string().strip_prefix("$value") {
+            Some("") => ".".to_string(),
+            Some(path) => path.to_string(),
+            None => e.path().to_string(),
+        };
+        match e.into_inner() {
+            DeError::Custom(reason) if reason == "missing field `$value`" => {
+                field_error(path, "expected at least one item")
+            }
+            DeError::Custom(reason) => field_error(path, &reason),
+            inner => HttpAppError::BadRequest(format!("Invalid XML: {}", inner)),
+        }
+    })
+}
+
+/// Names the field a body failed on, `body` for the body as a whole.
+fn field_error(path: String, reason: &str) -> HttpAppError {
+    let missing = reason
+        .strip_prefix("missing field `")
+        .and_then(|field| field.strip_suffix('`'));
+    let field = match (missing, path.as_str()) {
+        (Some(field), ".") => field.to_string(),
+        (Some(field), _) => format!("{}.{}", path, field),
+        (None, ".") => "body".to_string(),
+        (None, _) => path,
+    };
+    HttpAppError::Validation(vec![FieldError::new(field, reason)])
+}
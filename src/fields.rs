@@ -0,0 +1,110 @@
+use std::str::FromStr;
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+use serde::Deserialize;
+use utoipa::IntoParams;
+use crate::errors::HttpAppError;
+use crate::person::Person;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PersonField {
+    Id,
+    Uuid,
+    Name,
+    Age,
+    Date,
+    DeletedAt,
+    Version,
+}
+
+impl FromStr for PersonField {
+    type Err = HttpAppError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "id" => Ok(PersonField::Id),
+            "uuid" => Ok(PersonField::Uuid),
+            "name" => Ok(PersonField::Name),
+            "age" => Ok(PersonField::Age),
+            "date" => Ok(PersonField::Date),
+            "deleted_at" => Ok(PersonField::DeletedAt),
+            "version" => Ok(PersonField::Version),
+            other => Err(HttpAppError::BadRequest(format!("Unknown field '{}'", other))),
+        }
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct FieldsParams {
+    pub fields: Option<String>,
+}
+
+/// The fields selected with `?fields=`; `None` selects every field.
+#[derive(Debug, Clone, Default)]
+pub struct FieldSet(Option<Vec<PersonField>>);
+
+impl FieldSet {
+    pub fn is_all(&self) -> bool {
+        self.0.is_none()
+    }
+
+    pub fn contains(&self, field: PersonField) -> bool {
+        self.0.as_ref().map_or(true, |fields| fields.contains(&field))
+    }
+}
+
+impl TryFrom<FieldsParams> for FieldSet {
+    type Error = HttpAppError;
+
+    fn try_from(params: FieldsParams) -> Result<Self, Self::Error> {
+        let Some(fields) = params.fields else {
+            return Ok(FieldSet(None));
+        };
+        let fields = fields
+            .split(',')
+            .map(str::trim)
+            .filter(|field| !field.is_empty())
+            .map(PersonField::from_str)
+            .collect::<Result<Vec<_>, _>>()?;
+        if fields.is_empty() {
+            return Err(HttpAppError::BadRequest("fields must not be empty".to_string()));
+        }
+        Ok(FieldSet(Some(fields)))
+    }
+}
+
+/// Serializes a person with only the fields of a `FieldSet`.
+pub struct Sparse<'a> {
+    pub person: Person,
+    pub fields: &'a FieldSet,
+}
+
+impl Serialize for Sparse<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let person = &self.person;
+        let mut state = serializer.serialize_struct("Person", 7)?;
+        if self.fields.contains(PersonField::Id) {
+            state.serialize_field("id", &person.id)?;
+        }
+        if let (true, Some(uuid)) = (self.fields.contains(PersonField::Uuid), person.uuid) {
+            state.serialize_field("uuid", &uuid)?;
+        }
+        if self.fields.contains(PersonField::Name) {
+            state.serialize_field("name", &person.name)?;
+        }
+        if self.fields.contains(PersonField::Age) {
+            state.serialize_field("age", &person.age)?;
+        }
+        if self.fields.contains(PersonField::Date) {
+            state.serialize_field("date", &person.date)?;
+        }
+        let selected = self.fields.contains(PersonField::DeletedAt);
+        if let (true, Some(deleted_at)) = (selected, person.deleted_at) {
+            state.serialize_field("deleted_at", &deleted_at)?;
+        }
+        if self.fields.contains(PersonField::Version) {
+            state.serialize_field("version", &person.version)?;
+        }
+        state.end()
+    }
+}
@@ -0,0 +1,219 @@
+use std::collections::BTreeMap;
+use std::marker::PhantomData;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use async_trait::async_trait;
+use axum::{extract::FromRequestParts, http::request::Parts};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::auth::Principal;
+use crate::config::Config;
+use crate::errors::{ConfigError, HttpAppError};
+use crate::proxy::Client;
+use crate::routes::AppState;
+
+const PREFIX: &str = "FLAG_";
+
+/// Whether a feature is on, for everyone or for a share of the callers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Flag {
+    Enabled(bool),
+    Percentage(u8),
+}
+
+impl FromStr for Flag {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let flag = match value.to_ascii_lowercase().as_str() {
+            "true" => Flag::Enabled(true),
+            "false" => Flag::Enabled(false),
+            other => Flag::Percentage(
+                other
+                    .trim_end_matches('%')
+                    .parse()
+                    .map_err(|_| format!("expected true, false or a percentage, got {}", value))?,
+            ),
+        };
+        flag.validate()?;
+        Ok(flag)
+    }
+}
+
+impl Flag {
+    fn validate(&self) -> Result<(), String> {
+        match self {
+            Flag::Percentage(percentage) if *percentage > 100 => {
+                Err(format!("a percentage is at most 100, got {}", percentage))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// A percentage flag is on for the callers whose key, hashed together
+    /// with the flag name, falls below it, so that a caller keeps seeing the
+    /// same thing and each flag picks different callers.
+    pub fn is_on_for(&self, name: &str, key: &str) -> bool {
+        match *self {
+            Flag::Enabled(enabled) => enabled,
+            Flag::Percentage(percentage) => {
+                let digest = Sha256::new().chain_update(name).chain_update(":").chain_update(key);
+                let digest = digest.finalize();
+                let bucket = u16::from_be_bytes([digest[0], digest[1]]) % 100;
+                bucket < u16::from(percentage)
+            }
+        }
+    }
+}
+
+/// Flags the service checks. They are on unless configured otherwise, so that
+/// an endpoint can be switched off without a release.
+pub trait Gated {
+    const NAME: &'static str;
+}
+
+/// `GET /api/v1/persons/search`.
+pub struct Search;
+
+impl Gated for Search {
+    const NAME: &'static str = "search";
+}
+
+/// The bulk create, update and delete endpoints.
+pub struct Bulk;
+
+impl Gated for Bulk {
+    const NAME: &'static str = "bulk";
+}
+
+const KNOWN: &[&str] = &[Search::NAME, Bulk::NAME];
+
+/// The flags set as `FLAG_<NAME>`, e.g. `FLAG_SEARCH=false` or
+/// `FLAG_BULK=25%`, or in a `[flag]` table of the configuration file.
+pub fn from_config(config: &Config) -> Result<BTreeMap<String, Flag>, ConfigError> {
+    config
+        .vars()
+        .filter_map(|(variable, value)| {
+            let name = variable.strip_prefix(PREFIX)?.to_ascii_lowercase();
+            Some(
+                value
+                    .parse()
+                    .map(|flag| (name, flag))
+                    .map_err(|e| ConfigError::Invalid(format!("Invalid {}: {}", variable, e))),
+            )
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Source {
+    Default,
+    Config,
+    Override,
+}
+
+#[derive(Serialize)]
+pub struct FlagState {
+    pub name: String,
+    #[serde(flatten)]
+    pub flag: Flag,
+    pub source: Source,
+}
+
+/// The configured flags and the overrides set through `/admin/flags`, which
+/// last until the service restarts.
+pub struct Flags {
+    configured: RwLock<BTreeMap<String, Flag>>,
+    overrides: RwLock<BTreeMap<String, Flag>>,
+}
+
+impl Flags {
+    pub fn new(configured: BTreeMap<String, Flag>) -> Self {
+        Flags {
+            configured: RwLock::new(configured),
+            overrides: RwLock::new(BTreeMap::new()),
+        }
+    }
+
+    /// Replaces the configured flags on reload; overrides stay in force.
+    pub fn set_configured(&self, configured: BTreeMap<String, Flag>) {
+        *self.configured.write().unwrap_or_else(|e| e.into_inner()) = configured;
+    }
+
+    pub fn get(&self, name: &str) -> Option<FlagState> {
+        let overrides = self.overrides.read().unwrap_or_else(|e| e.into_inner());
+        let configured = self.configured.read().unwrap_or_else(|e| e.into_inner());
+        let (flag, source) = if let Some(flag) = overrides.get(name) {
+            (*flag, Source::Override)
+        } else if let Some(flag) = configured.get(name) {
+            (*flag, Source::Config)
+        } else if KNOWN.contains(&name) {
+            (Flag::Enabled(true), Source::Default)
+        } else {
+            return None;
+        };
+        Some(FlagState { name: name.to_string(), flag, source })
+    }
+
+    pub fn list(&self) -> Vec<FlagState> {
+        let mut names: Vec<String> = KNOWN.iter().map(|name| name.to_string()).collect();
+        names.extend(self.configured.read().unwrap_or_else(|e| e.into_inner()).keys().cloned());
+        names.extend(self.overrides.read().unwrap_or_else(|e| e.into_inner()).keys().cloned());
+        names.sort();
+        names.dedup();
+        names.iter().filter_map(|name| self.get(name)).collect()
+    }
+
+    pub fn set_override(&self, name: &str, flag: Flag) -> Result<FlagState, HttpAppError> {
+        flag.validate().map_err(HttpAppError::BadRequest)?;
+        if self.get(name).is_none() {
+            return Err(HttpAppError::NotFound);
+        }
+        self.overrides
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(name.to_string(), flag);
+        tracing::warn!("Feature flag {} overridden to {:?}", name, flag);
+        self.get(name).ok_or(HttpAppError::NotFound)
+    }
+
+    pub fn clear_override(&self, name: &str) -> Result<FlagState, HttpAppError> {
+        let cleared = self.overrides.write().unwrap_or_else(|e| e.into_inner()).remove(name);
+        if cleared.is_some() {
+            tracing::warn!("Feature flag {} back to its configured value", name);
+        }
+        self.get(name).ok_or(HttpAppError::NotFound)
+    }
+
+    pub fn is_on_for(&self, name: &str, key: &str) -> bool {
+        self.get(name).is_some_and(|state| state.flag.is_on_for(name, key))
+    }
+}
+
+/// Answers 404, as if the route did not exist, unless the flag `F` is on for
+/// the caller, identified by its principal or else its address.
+pub struct Enabled<F>(PhantomData<F>);
+
+#[async_trait]
+impl<F: Gated> FromRequestParts<Arc<AppState>> for Enabled<F> {
+    type Rejection = HttpAppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        let key = match parts.extensions.get::<Principal>() {
+            Some(principal) => principal.name.clone(),
+            None => match parts.extensions.get::<Client>() {
+                Some(client) => client.ip.to_string(),
+                None => String::new(),
+            },
+        };
+        if !state.flags.is_on_for(F::NAME, &key) {
+            return Err(HttpAppError::NotFound);
+        }
+        Ok(Enabled(PhantomData))
+    }
+}
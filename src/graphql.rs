@@ -0,0 +1,420 @@
+use std::sync::Arc;
+use async_graphql::http::GraphiQLSource;
+use async_graphql::{
+    Context, EmptySubscription, Enum, ErrorExtensions, InputObject, Object, Schema, SimpleObject,
+};
+use axum::{
+    body::Bytes,
+    extract::{RawQuery, State},
+    http::{header, HeaderValue},
+    response::{Html, IntoResponse, Response},
+    routing::get,
+    Extension, Json, Router,
+};
+use chrono::{DateTime, NaiveDate, Utc};
+use uuid::Uuid;
+use crate::auth::{Principal, Role};
+use crate::errors::HttpAppError;
+use crate::listing::{ListQuery, PersonFilter, Sort, SortField, SortOrder};
+use crate::pagination::{PageParams, PageRequest};
+use crate::person::{IdStrategy, NewPerson, Person, PersonPatch};
+use crate::routes::AppState;
+use crate::validation::{FieldError, Validate};
+
+/// GraphiQL loads React and itself from unpkg and runs an inline script.
+const GRAPHIQL_CSP: &str = "default-src 'none'; script-src 'unsafe-inline' https://unpkg.com; \
+                            style-src 'unsafe-inline' https://unpkg.com; \
+                            font-src data: https://unpkg.com; img-src data: 'self'; \
+                            connect-src 'self'; base-uri 'none'; frame-ancestors 'none'";
+
+const PATH: &str = "/graphql";
+
+type PersonSchema = Schema<Query, Mutation, EmptySubscription>;
+
+/// Whether `request` is for `/graphql`, which request signing covers as it
+/// does the REST API.
+pub fn is_call(request: &axum::extract::Request) -> bool {
+    request.uri().path() == PATH
+}
+
+/// Whether `request` may carry a mutation, that is a `POST` to `/graphql`,
+/// which maintenance holds off as it does the writes of the REST API.
+pub fn may_change(request: &axum::extract::Request) -> bool {
+    is_call(request) && request.method() == axum::http::Method::POST
+}
+
+/// `/graphql`, over the same repository as the REST routes. Queries may be
+/// sent with GET or POST, mutations only with POST so that they are
+/// authenticated like any other change. With `GRAPHIQL=true`, for
+/// development, a GET without a query opens GraphiQL.
+pub fn create_graphql_routes() -> Router<Arc<AppState>> {
+    let schema: PersonSchema = Schema::new(Query, Mutation, EmptySubscription);
+    Router::new()
+        .route(PATH, get(graphql_get).post(graphql_post))
+        .layer(Extension(schema))
+}
+
+/// Who sent a request, and whether it may change anything.
+struct Caller {
+    principal: Option<Principal>,
+    read_only: bool,
+}
+
+async fn execute(
+    schema: &PersonSchema,
+    state: Arc<AppState>,
+    principal: Option<Extension<Principal>>,
+    request: async_graphql::Request,
+    read_only: bool,
+) -> Json<async_graphql::Response> {
+    let caller = Caller { principal: principal.map(|Extension(principal)| principal), read_only };
+    Json(schema.execute(request.data(state).data(caller)).await)
+}
+
+async fn graphql_get(
+    Extension(schema): Extension<PersonSchema>,
+    State(state): State<Arc<AppState>>,
+    principal: Option<Extension<Principal>>,
+    RawQuery(query): RawQuery,
+) -> Result<Response, HttpAppError> {
+    let Some(query) = query.filter(|query| !query.is_empty()) else {
+        if !state.settings().graphiql {
+            return Err(HttpAppError::BadRequest("a query is required".to_string()));
+        }
+        let csp = [(header::CONTENT_SECURITY_POLICY, HeaderValue::from_static(GRAPHIQL_CSP))];
+        let page = GraphiQLSource::build().endpoint(PATH).finish();
+        return Ok((csp, Html(page)).into_response());
+    };
+    let request = async_graphql::http::parse_query_string(&query)
+        .map_err(|e| HttpAppError::BadRequest(e.to_string()))?;
+    Ok(execute(&schema, state, principal, request, true).await.into_response())
+}
+
+async fn graphql_post(
+    Extension(schema): Extension<PersonSchema>,
+    State(state): State<Arc<AppState>>,
+    principal: Option<Extension<Principal>>,
+    body: Bytes,
+) -> Result<Json<async_graphql::Response>, HttpAppError> {
+    let request = serde_json::from_slice(&body)
+        .map_err(|e| HttpAppError::BadRequest(format!("Invalid GraphQL request: {}", e)))?;
+    Ok(execute(&schema, state, principal, request, false).await)
+}
+
+/// The message of `error`, with its HTTP status as the `status` extension.
+fn error(error: HttpAppError) -> async_graphql::Error {
+    let status = error.status_code().as_u16();
+    async_graphql::Error::new(error.to_string()).extend_with(|_, extensions| {
+        extensions.set("status", status);
+    })
+}
+
+fn invalid(field: &str, message: impl Into<String>) -> async_graphql::Error {
+    error(HttpAppError::Validation(vec![FieldError::new(field, message)]))
+}
+
+fn state<'a>(ctx: &Context<'a>) -> &'a Arc<AppState> {
+    ctx.data_unchecked::<Arc<AppState>>()
+}
+
+/// Mutations need POST and, once credentials are configured, the writer role.
+fn require_writer(ctx: &Context<'_>) -> async_graphql::Result<()> {
+    let caller = ctx.data_unchecked::<Caller>();
+    if caller.read_only {
+        return Err(error(HttpAppError::BadRequest("mutations must be sent with POST".to_string())));
+    }
+    match &caller.principal {
+        Some(principal) if !principal.has(Role::Writer) => Err(error(HttpAppError::Forbidden(
+            format!("the {} role is required", Role::Writer),
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// A change must name the version it was made against; unlike `If-Match`,
+/// an argument cannot be left out, so `0` would skip the check.
+fn require_version(version: u32) -> async_graphql::Result<()> {
+    if version == 0 {
+        return Err(error(HttpAppError::PreconditionRequired));
+    }
+    Ok(())
+}
+
+struct PersonObject(Person);
+
+#[Object(name = "Person")]
+impl PersonObject {
+    async fn id(&self) -> u32 {
+        self.0.id
+    }
+
+    async fn uuid(&self) -> Option<Uuid> {
+        self.0.uuid
+    }
+
+    async fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    async fn age(&self) -> u8 {
+        self.0.age
+    }
+
+    async fn date(&self) -> NaiveDate {
+        self.0.date
+    }
+
+    async fn deleted_at(&self) -> Option<DateTime<Utc>> {
+        self.0.deleted_at
+    }
+
+    async fn version(&self) -> u32 {
+        self.0.version
+    }
+}
+
+#[derive(SimpleObject)]
+struct PersonPage {
+    items: Vec<PersonObject>,
+    total: u64,
+    page: u64,
+    per_page: u64,
+}
+
+#[derive(Enum, Clone, Copy, Default, PartialEq, Eq)]
+#[graphql(remote = "SortField")]
+enum PersonSort {
+    #[default]
+    Id,
+    Name,
+    Age,
+    Date,
+}
+
+#[derive(Enum, Clone, Copy, Default, PartialEq, Eq)]
+#[graphql(remote = "SortOrder")]
+enum Order {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// The filters of `GET /api/v1/persons`, `filter` being an expression such
+/// as `age >= 18 and name contains "a"`.
+#[derive(InputObject, Default)]
+struct PersonFilterInput {
+    name: Option<String>,
+    min_age: Option<u8>,
+    max_age: Option<u8>,
+    date_from: Option<NaiveDate>,
+    date_to: Option<NaiveDate>,
+    year: Option<i32>,
+    filter: Option<String>,
+    #[graphql(default)]
+    include_deleted: bool,
+}
+
+impl PersonFilterInput {
+    fn into_filter(self) -> async_graphql::Result<PersonFilter> {
+        let expr = match self.filter {
+            Some(filter) => Some(filter.parse().map_err(|e: String| invalid("filter", e))?),
+            None => None,
+        };
+        let filter = PersonFilter {
+            name: self.name,
+            min_age: self.min_age,
+            max_age: self.max_age,
+            date_from: self.date_from,
+            date_to: self.date_to,
+            year: self.year,
+            expr,
+            include_deleted: self.include_deleted,
+        };
+        filter.validate().map_err(error)?;
+        Ok(filter)
+    }
+}
+
+#[derive(InputObject)]
+struct PersonInput {
+    name: String,
+    age: u8,
+    date: NaiveDate,
+}
+
+#[derive(InputObject)]
+struct PersonPatchInput {
+    name: Option<String>,
+    age: Option<u8>,
+    date: Option<NaiveDate>,
+}
+
+struct Query;
+
+#[Object]
+impl Query {
+    async fn person(
+        &self,
+        ctx: &Context<'_>,
+        id: u32,
+    ) -> async_graphql::Result<Option<PersonObject>> {
+        match state(ctx).person_repository.get(id).await {
+            Ok(person) if person.deleted_at.is_some() => Ok(None),
+            Ok(person) => Ok(Some(PersonObject(person))),
+            Err(HttpAppError::NotFound) => Ok(None),
+            Err(e) => Err(error(e)),
+        }
+    }
+
+    /// A page of the persons, paged and sorted like `GET /api/v1/persons`.
+    async fn persons(
+        &self,
+        ctx: &Context<'_>,
+        filter: Option<PersonFilterInput>,
+        #[graphql(default)] sort: PersonSort,
+        #[graphql(default)] order: Order,
+        page: Option<u64>,
+        per_page: Option<u64>,
+    ) -> async_graphql::Result<PersonPage> {
+        let filter = filter.unwrap_or_default().into_filter()?;
+        let params = PageParams { page, per_page, after: None, limit: None };
+        let page = PageRequest::try_from(params).map_err(error)?;
+        let sort = Sort { sort: sort.into(), order: order.into() };
+        let query = ListQuery { filter, sort, page };
+        let persons = state(ctx).person_repository.list_page(&query).await.map_err(error)?;
+        Ok(PersonPage {
+            items: persons.items.into_iter().map(PersonObject).collect(),
+            total: persons.total,
+            page: page.page,
+            per_page: page.per_page,
+        })
+    }
+
+    async fn count(
+        &self,
+        ctx: &Context<'_>,
+        filter: Option<PersonFilterInput>,
+    ) -> async_graphql::Result<u64> {
+        let filter = filter.unwrap_or_default().into_filter()?;
+        state(ctx).person_repository.count(&filter).await.map_err(error)
+    }
+}
+
+struct Mutation;
+
+#[Object]
+impl Mutation {
+    async fn create_person(
+        &self,
+        ctx: &Context<'_>,
+        input: PersonInput,
+    ) -> async_graphql::Result<PersonObject> {
+        require_writer(ctx)?;
+        let state = state(ctx);
+        let mut person = NewPerson {
+            uuid: None,
+            name: input.name,
+            age: input.age,
+            date: input.date,
+        };
+        person.validate().map_err(error)?;
+        if state.settings().id_strategy == IdStrategy::Uuid {
+            person.uuid = Some(Uuid::new_v4());
+        }
+        let person = state.person_repository.create(person).await.map_err(error)?;
+        Ok(PersonObject(person))
+    }
+
+    /// Changes the given fields of the person at `version`, like `PATCH`.
+    async fn update_person(
+        &self,
+        ctx: &Context<'_>,
+        id: u32,
+        version: u32,
+        input: PersonPatchInput,
+    ) -> async_graphql::Result<PersonObject> {
+        require_writer(ctx)?;
+        require_version(version)?;
+        let patch = PersonPatch {
+            name: input.name,
+            age: input.age,
+            date: input.date,
+            version: Some(version),
+        };
+        patch.validate().map_err(error)?;
+        let person = state(ctx).person_repository.patch(id, patch).await.map_err(error)?;
+        Ok(PersonObject(person))
+    }
+
+    /// Soft-deletes the person at `version`, like `DELETE`.
+    async fn delete_person(
+        &self,
+        ctx: &Context<'_>,
+        id: u32,
+        version: u32,
+    ) -> async_graphql::Result<bool> {
+        require_writer(ctx)?;
+        require_version(version)?;
+        state(ctx).person_repository.delete(id, version).await.map_err(error)?;
+        Ok(true)
+    }
+
+    async fn restore_person(
+        &self,
+        ctx: &Context<'_>,
+        id: u32,
+    ) -> async_graphql::Result<PersonObject> {
+        require_writer(ctx)?;
+        let person = state(ctx).person_repository.restore(id).await.map_err(error)?;
+        Ok(PersonObject(person))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::{self, Body};
+    use axum::http::Request;
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+
+    async fn post(state: &Arc<AppState>, query: &str) -> Value {
+        let request = Request::post(PATH)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(json!({ "query": query }).to_string()))
+            .unwrap();
+        let router = create_graphql_routes().with_state(state.clone());
+        let response = router.oneshot(request).await.unwrap();
+        serde_json::from_slice(&body::to_bytes(response.into_body(), usize::MAX).await.unwrap())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn changes_must_name_a_version() {
+        let state = Arc::new(AppState::for_tests());
+        for mutation in [
+            "mutation { updatePerson(id: 1, version: 0, input: {age: 44}) { age } }",
+            "mutation { deletePerson(id: 1, version: 0) }",
+        ] {
+            let response = post(&state, mutation).await;
+            assert_eq!(response["errors"][0]["extensions"]["status"], 428, "{}", mutation);
+        }
+        let person = state.person_repository.get(1).await.unwrap();
+        assert_eq!((person.age, person.version, person.deleted_at), (43, 1, None));
+
+        let mutation = "mutation { updatePerson(id: 1, version: 1, input: {age: 44}) { version } }";
+        let response = post(&state, mutation).await;
+        assert_eq!(response["data"]["updatePerson"]["version"], 2);
+    }
+
+    #[tokio::test]
+    async fn deleted_persons_are_null() {
+        let state = Arc::new(AppState::for_tests());
+        let response = post(&state, "mutation { deletePerson(id: 1, version: 1) }").await;
+        assert_eq!(response["data"]["deletePerson"], true);
+
+        let query = "{ person(id: 1) { name } other: person(id: 2) { name } }";
+        let response = post(&state, query).await;
+        assert_eq!(response["data"], json!({ "person": null, "other": { "name": "Luigi" } }));
+    }
+}
@@ -0,0 +1,365 @@
+use std::sync::Arc;
+use axum::{
+    body::{self, Body},
+    http::{header, HeaderValue, StatusCode},
+    middleware::Next,
+    Router,
+};
+use tonic::{Code, Request, Response, Status};
+use uuid::Uuid;
+use crate::auth::{Principal, Role};
+use crate::config::Config;
+use crate::errors::{ConfigError, HttpAppError};
+use crate::listing::PersonFilter;
+use crate::pagination::{CursorRequest, PageParams};
+use crate::person::{IdStrategy, NewPerson, Person};
+use crate::protobuf::proto;
+use crate::protobuf::proto::person_service_server::{PersonService, PersonServiceServer};
+use crate::routes::AppState;
+use crate::validation::{FieldError, Validate};
+
+/// The schema of `proto/persons.proto`, for reflection.
+const FILE_DESCRIPTOR_SET: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/persons.bin"));
+
+const SERVICE_PATH: &str = "/persons.v1.PersonService/";
+
+/// Error bodies are short; a longer one keeps the HTTP reason as the message.
+const MAX_ERROR_BYTES: usize = 64 * 1024;
+
+/// Reads `GRPC_PORT`, where `PersonService` is served next to the REST API on
+/// the same host; `None` to serve no gRPC.
+pub fn port_from_config(config: &Config) -> Result<Option<u16>, ConfigError> {
+    match config.var("GRPC_PORT") {
+        Some(port) => port.parse().map(Some).map_err(|_| {
+            ConfigError::Invalid(format!("GRPC_PORT must be a port number, got {}", port))
+        }),
+        None => Ok(None),
+    }
+}
+
+/// `PersonService` and the reflection service, which lets tools such as
+/// grpcurl list and call the methods without a copy of the schema.
+pub fn create_grpc_routes(state: Arc<AppState>) -> Router<Arc<AppState>> {
+    let reflection = tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(FILE_DESCRIPTOR_SET)
+        .build_v1()
+        .expect("invalid file descriptor set");
+    let services = tonic::service::Routes::new(PersonServiceServer::new(Persons { state }))
+        .add_service(reflection)
+        .into_axum_router();
+    Router::new().fallback_service(services)
+}
+
+/// Whether `request` calls `PersonService`, which the rate limits cover as
+/// they do the REST API.
+pub fn is_call(request: &axum::extract::Request) -> bool {
+    request.uri().path().starts_with(SERVICE_PATH)
+}
+
+/// Whether `request` is a gRPC call that changes persons, which maintenance
+/// holds off as it does the writes of the REST API.
+pub fn is_change(request: &axum::extract::Request) -> bool {
+    let Some(method) = request.uri().path().strip_prefix(SERVICE_PATH) else {
+        return false;
+    };
+    matches!(method, "CreatePerson" | "UpdatePerson" | "DeletePerson")
+}
+
+/// Turns the JSON errors of the HTTP layers in front of the service, such as
+/// authentication, rate limiting and maintenance, into the gRPC status a
+/// client expects, keeping their message and headers like `Retry-After`.
+pub async fn http_errors_to_status(
+    request: axum::extract::Request,
+    next: Next,
+) -> axum::response::Response {
+    let grpc = request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|value| value.as_bytes().starts_with(b"application/grpc"));
+    let response = next.run(request).await;
+    if !grpc || response.status() == StatusCode::OK {
+        return response;
+    }
+    let (parts, body) = response.into_parts();
+    let message = body::to_bytes(body, MAX_ERROR_BYTES)
+        .await
+        .ok()
+        .and_then(|bytes| serde_json::from_slice::<serde_json::Value>(&bytes).ok())
+        .and_then(|error| error["message"].as_str().map(str::to_string))
+        .unwrap_or_else(|| parts.status.canonical_reason().unwrap_or_default().to_string());
+    let mut headers = parts.headers;
+    headers.remove(header::CONTENT_LENGTH);
+    headers.remove(header::CONTENT_ENCODING);
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("application/grpc"));
+    if let Err(e) = Status::new(code(parts.status), message).add_header(&mut headers) {
+        tracing::error!("Could not encode a gRPC status: {}", e);
+    }
+    let mut response = axum::response::Response::new(Body::empty());
+    *response.headers_mut() = headers;
+    response
+}
+
+fn code(status: StatusCode) -> Code {
+    match status {
+        StatusCode::BAD_REQUEST | StatusCode::UNPROCESSABLE_ENTITY => Code::InvalidArgument,
+        StatusCode::UNAUTHORIZED => Code::Unauthenticated,
+        StatusCode::FORBIDDEN => Code::PermissionDenied,
+        StatusCode::NOT_FOUND => Code::NotFound,
+        StatusCode::CONFLICT => Code::AlreadyExists,
+        StatusCode::PRECONDITION_FAILED | StatusCode::PRECONDITION_REQUIRED => {
+            Code::FailedPrecondition
+        }
+        StatusCode::PAYLOAD_TOO_LARGE | StatusCode::TOO_MANY_REQUESTS => Code::ResourceExhausted,
+        StatusCode::GATEWAY_TIMEOUT => Code::DeadlineExceeded,
+        StatusCode::SERVICE_UNAVAILABLE => Code::Unavailable,
+        _ => Code::Internal,
+    }
+}
+
+impl From<HttpAppError> for Status {
+    fn from(error: HttpAppError) -> Self {
+        Status::new(code(error.status_code()), error.to_string())
+    }
+}
+
+/// Changes need the writer role once credentials are configured, as on the
+/// REST routes. Every call is a POST, so `authenticate` has already turned
+/// anonymous ones away then.
+fn require_writer<T>(request: &Request<T>) -> Result<(), HttpAppError> {
+    match request.extensions().get::<Principal>() {
+        Some(principal) if !principal.has(Role::Writer) => Err(HttpAppError::Forbidden(
+            format!("the {} role is required", Role::Writer),
+        )),
+        _ => Ok(()),
+    }
+}
+
+struct Persons {
+    state: Arc<AppState>,
+}
+
+#[tonic::async_trait]
+impl PersonService for Persons {
+    async fn get_person(
+        &self,
+        request: Request<proto::GetPersonRequest>,
+    ) -> Result<Response<proto::Person>, Status> {
+        let person = self.state.person_repository.get(request.into_inner().id).await?;
+        if person.deleted_at.is_some() {
+            return Err(HttpAppError::NotFound.into());
+        }
+        Ok(Response::new(proto::Person::from(&person)))
+    }
+
+    async fn list_persons(
+        &self,
+        request: Request<proto::ListPersonsRequest>,
+    ) -> Result<Response<proto::PersonList>, Status> {
+        let request = request.into_inner();
+        let expr = match request.filter {
+            Some(filter) => Some(filter.parse().map_err(|e: String| {
+                HttpAppError::Validation(vec![FieldError::new("filter", e)])
+            })?),
+            None => None,
+        };
+        let filter = PersonFilter { expr, ..PersonFilter::default() };
+        filter.validate()?;
+        let params = PageParams {
+            page: None,
+            per_page: None,
+            after: request.after,
+            limit: request.limit,
+        };
+        let page = CursorRequest::try_from(params)?
+            .fetch(self.state.person_repository.as_ref(), &filter)
+            .await?;
+        Ok(Response::new(proto::PersonList {
+            persons: page.items.iter().map(proto::Person::from).collect(),
+            next_cursor: page.next_cursor,
+        }))
+    }
+
+    async fn create_person(
+        &self,
+        request: Request<proto::NewPerson>,
+    ) -> Result<Response<proto::Person>, Status> {
+        require_writer(&request)?;
+        let mut person = NewPerson::try_from(request.into_inner())?;
+        person.validate()?;
+        if self.state.settings().id_strategy == IdStrategy::Uuid {
+            person.uuid = Some(Uuid::new_v4());
+        }
+        let person = self.state.person_repository.create(person).await?;
+        Ok(Response::new(proto::Person::from(&person)))
+    }
+
+    async fn update_person(
+        &self,
+        request: Request<proto::Person>,
+    ) -> Result<Response<proto::Person>, Status> {
+        require_writer(&request)?;
+        let person = Person::try_from(request.into_inner())?;
+        person.validate()?;
+        if person.version == 0 {
+            return Err(HttpAppError::PreconditionRequired.into());
+        }
+        let id = person.id;
+        self.state.person_repository.update(person).await?;
+        let person = self.state.person_repository.get(id).await?;
+        Ok(Response::new(proto::Person::from(&person)))
+    }
+
+    async fn delete_person(
+        &self,
+        request: Request<proto::DeletePersonRequest>,
+    ) -> Result<Response<proto::DeletePersonResponse>, Status> {
+        require_writer(&request)?;
+        let proto::DeletePersonRequest { id, version } = request.into_inner();
+        if version == 0 {
+            return Err(HttpAppError::PreconditionRequired.into());
+        }
+        self.state.person_repository.delete(id, version).await?;
+        Ok(Response::new(proto::DeletePersonResponse {}))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{middleware, routing::post};
+    use tower::ServiceExt;
+
+    fn service() -> Persons {
+        Persons { state: Arc::new(AppState::for_tests()) }
+    }
+
+    fn peach() -> proto::NewPerson {
+        proto::NewPerson { name: "Peach".to_string(), age: 30, date: "1985-09-13".to_string() }
+    }
+
+    fn delete(id: u32, version: u32) -> Request<proto::DeletePersonRequest> {
+        Request::new(proto::DeletePersonRequest { id, version })
+    }
+
+    async fn get(service: &Persons, id: u32) -> Result<proto::Person, Status> {
+        let request = Request::new(proto::GetPersonRequest { id });
+        service.get_person(request).await.map(Response::into_inner)
+    }
+
+    #[tokio::test]
+    async fn persons_are_created_changed_and_deleted_at_their_version() {
+        let service = service();
+        let created = service.create_person(Request::new(peach())).await.unwrap().into_inner();
+        assert_eq!((created.id, created.version), (3, 1));
+        assert_eq!(get(&service, 3).await.unwrap(), created);
+
+        let changed = proto::Person { age: 31, ..created.clone() };
+        let updated = service.update_person(Request::new(changed.clone())).await.unwrap();
+        assert_eq!((updated.get_ref().age, updated.get_ref().version), (31, 2));
+        let stale = service.update_person(Request::new(changed)).await.unwrap_err();
+        assert_eq!(stale.code(), Code::FailedPrecondition);
+        let stale = service.delete_person(delete(3, 1)).await.unwrap_err();
+        assert_eq!(stale.code(), Code::FailedPrecondition);
+
+        service.delete_person(delete(3, 2)).await.unwrap();
+        assert_eq!(get(&service, 3).await.unwrap_err().code(), Code::NotFound);
+        assert_eq!(service.delete_person(delete(3, 3)).await.unwrap_err().code(), Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn changes_must_name_a_version() {
+        let service = service();
+        let mario = get(&service, 1).await.unwrap();
+        let unversioned = proto::Person { age: 44, version: 0, ..mario };
+        let status = service.update_person(Request::new(unversioned)).await.unwrap_err();
+        assert_eq!(status.code(), Code::FailedPrecondition);
+        let status = service.delete_person(delete(1, 0)).await.unwrap_err();
+        assert_eq!(status.code(), Code::FailedPrecondition);
+        let mario = get(&service, 1).await.unwrap();
+        assert_eq!((mario.age, mario.version), (43, 1));
+    }
+
+    #[tokio::test]
+    async fn deleted_persons_are_not_found_or_listed() {
+        let service = service();
+        service.delete_person(delete(1, 1)).await.unwrap();
+        assert_eq!(get(&service, 1).await.unwrap_err().code(), Code::NotFound);
+        let request = proto::ListPersonsRequest { after: None, limit: None, filter: None };
+        let list = service.list_persons(Request::new(request)).await.unwrap().into_inner();
+        let ids: Vec<u32> = list.persons.iter().map(|person| person.id).collect();
+        assert_eq!(ids, [2]);
+    }
+
+    #[tokio::test]
+    async fn changes_need_the_writer_role() {
+        let service = service();
+        let mut request = Request::new(peach());
+        let reader = Principal { name: "ann".to_string(), roles: vec![Role::Reader] };
+        request.extensions_mut().insert(reader);
+        let status = service.create_person(request).await.unwrap_err();
+        assert_eq!(status.code(), Code::PermissionDenied);
+        assert!(get(&service, 3).await.is_err());
+    }
+
+    #[test]
+    fn calls_are_told_by_their_path() {
+        let request = |method: &str| {
+            let uri = format!("{}{}", SERVICE_PATH, method);
+            axum::extract::Request::post(uri).body(Body::empty()).unwrap()
+        };
+        assert!(is_call(&request("GetPerson")) && !is_change(&request("GetPerson")));
+        assert!(is_call(&request("ListPersons")) && !is_change(&request("ListPersons")));
+        for method in ["CreatePerson", "UpdatePerson", "DeletePerson"] {
+            assert!(is_call(&request(method)) && is_change(&request(method)), "{}", method);
+        }
+        let other = axum::extract::Request::post("/api/v1/person").body(Body::empty()).unwrap();
+        assert!(!is_call(&other) && !is_change(&other));
+    }
+
+    #[test]
+    fn errors_become_statuses() {
+        let cases = [
+            (HttpAppError::Validation(vec![]), Code::InvalidArgument),
+            (HttpAppError::Unauthorized("no".to_string()), Code::Unauthenticated),
+            (HttpAppError::Forbidden("no".to_string()), Code::PermissionDenied),
+            (HttpAppError::NotFound, Code::NotFound),
+            (HttpAppError::Conflict, Code::AlreadyExists),
+            (HttpAppError::PreconditionFailed, Code::FailedPrecondition),
+            (HttpAppError::PreconditionRequired, Code::FailedPrecondition),
+            (HttpAppError::TooManyRequests(1), Code::ResourceExhausted),
+            (HttpAppError::PayloadTooLarge, Code::ResourceExhausted),
+            (HttpAppError::Timeout, Code::DeadlineExceeded),
+            (HttpAppError::Maintenance(1), Code::Unavailable),
+            (HttpAppError::StorageError("disk".to_string()), Code::Internal),
+        ];
+        for (error, code) in cases {
+            let message = error.to_string();
+            let status = Status::from(error);
+            assert_eq!((status.code(), status.message()), (code, message.as_str()));
+        }
+    }
+
+    #[tokio::test]
+    async fn http_errors_reach_grpc_clients_as_statuses() {
+        let router = Router::new()
+            .route("/call", post(|| async { Err::<(), _>(HttpAppError::TooManyRequests(7)) }))
+            .layer(middleware::from_fn(http_errors_to_status));
+        let request = |content_type: &str| {
+            axum::extract::Request::post("/call")
+                .header(header::CONTENT_TYPE, content_type)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let response = router.clone().oneshot(request("application/grpc")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let status = Status::from_header_map(response.headers()).unwrap();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+        assert_eq!(status.message(), HttpAppError::TooManyRequests(7).to_string());
+        assert_eq!(response.headers()[header::RETRY_AFTER], "7");
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/grpc");
+
+        let response = router.oneshot(request("application/json")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+}
@@ -0,0 +1,144 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use serde::Serialize;
+use crate::listing::PersonFilter;
+use crate::routes::AppState;
+
+/// How long the storage may take to answer a readiness probe.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Whether the service still takes new requests; flipped when it starts
+/// shutting down so that load balancers stop sending traffic first.
+#[derive(Default)]
+pub struct Readiness {
+    shutting_down: AtomicBool,
+}
+
+impl Readiness {
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::Relaxed)
+    }
+
+    pub fn shut_down(&self) {
+        self.shutting_down.store(true, Ordering::Relaxed);
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Ok,
+    Degraded,
+    Unavailable,
+}
+
+#[derive(Serialize)]
+pub struct Check {
+    pub status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Check {
+    fn ok() -> Self {
+        Check { status: Status::Ok, error: None }
+    }
+
+    fn degraded(error: String) -> Self {
+        Check { status: Status::Degraded, error: Some(error) }
+    }
+
+    fn unavailable(error: String) -> Self {
+        Check { status: Status::Unavailable, error: Some(error) }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Health {
+    pub status: Status,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub checks: BTreeMap<&'static str, Check>,
+}
+
+impl IntoResponse for Health {
+    fn into_response(self) -> Response {
+        let status = match self.status {
+            Status::Ok | Status::Degraded => StatusCode::OK,
+            Status::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        };
+        (status, Json(self)).into_response()
+    }
+}
+
+pub fn create_health_routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/healthz", get(liveness))
+        .route("/readyz", get(readiness))
+}
+
+/// Answers as long as the process serves requests at all.
+async fn liveness() -> Health {
+    Health { status: Status::Ok, checks: BTreeMap::new() }
+}
+
+/// Checks that the storage answers, its schema is up to date and the service
+/// is not shutting down. Maintenance only degrades it: reads still work, so
+/// the instance stays in the load balancer.
+async fn readiness(State(state): State<Arc<AppState>>) -> Health {
+    let repository = &state.person_repository;
+    let storage = check(repository.count(&PersonFilter::default()), |_| Ok(())).await;
+    let migrations = check(repository.pending_migrations(), |pending| match pending {
+        0 => Ok(()),
+        pending => Err(format!("{} migrations pending", pending)),
+    })
+    .await;
+    let shutdown = if state.readiness.is_shutting_down() {
+        Check::unavailable("Shutting down".to_string())
+    } else {
+        Check::ok()
+    };
+    let maintenance = match state.maintenance.retry_after() {
+        Some(_) => Check::degraded("Down for maintenance, only reads are served".to_string()),
+        None => Check::ok(),
+    };
+    let checks = BTreeMap::from([
+        ("storage", storage),
+        ("migrations", migrations),
+        ("shutdown", shutdown),
+        ("maintenance", maintenance),
+    ]);
+    let status = if checks.values().any(|check| matches!(check.status, Status::Unavailable)) {
+        Status::Unavailable
+    } else if checks.values().any(|check| matches!(check.status, Status::Degraded)) {
+        Status::Degraded
+    } else {
+        Status::Ok
+    };
+    Health { status, checks }
+}
+
+async fn check<T, E: Display>(
+    future: impl Future<Output = Result<T, E>>,
+    verdict: impl FnOnce(T) -> Result<(), String>,
+) -> Check {
+    let result = match tokio::time::timeout(CHECK_TIMEOUT, future).await {
+        Ok(Ok(value)) => verdict(value),
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(_) => Err(format!("No answer within {}s", CHECK_TIMEOUT.as_secs())),
+    };
+    match result {
+        Ok(()) => Check::ok(),
+        Err(error) => Check::unavailable(error),
+    }
+}
@@ -0,0 +1,352 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, RwLock};
+use async_trait::async_trait;
+use axum::{extract::Request, middleware::Next, response::Response};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::migrate::MigrateError;
+use utoipa::ToSchema;
+use uuid::Uuid;
+use crate::audit;
+use crate::errors::HttpAppError;
+use crate::listing::{ListQuery, PersonFilter};
+use crate::pagination::Page;
+use crate::person::{NewPerson, Person};
+use crate::repository::PersonRepository;
+use crate::search::SearchHit;
+
+const ANONYMOUS_ACTOR: &str = "anonymous";
+const SYSTEM_ACTOR: &str = "system";
+
+tokio::task_local! {
+    static ACTOR: String;
+}
+
+/// Runs the request as the anonymous actor, until `authenticate` names the
+/// principal instead. Clients cannot pick the actor themselves.
+pub async fn track_actor(request: Request, next: Next) -> Response {
+    ACTOR.scope(ANONYMOUS_ACTOR.to_string(), next.run(request)).await
+}
+
+/// Runs `future` as `actor`, e.g. for background work started by a request.
+pub async fn as_actor<F: std::future::Future>(actor: String, future: F) -> F::Output {
+    ACTOR.scope(actor, future).await
+}
+
+pub fn current_actor() -> String {
+    ACTOR
+        .try_with(Clone::clone)
+        .unwrap_or_else(|_| SYSTEM_ACTOR.to_string())
+}
+
+#[derive(Clone, Copy, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Create,
+    Update,
+    Delete,
+    Restore,
+    Purge,
+    Replace,
+}
+
+#[derive(Clone, Serialize, ToSchema)]
+pub struct Change {
+    pub from: Option<Value>,
+    pub to: Option<Value>,
+}
+
+#[derive(Clone, Serialize, ToSchema)]
+pub struct Revision {
+    pub revision: usize,
+    pub action: Action,
+    pub actor: String,
+    pub at: DateTime<Utc>,
+    pub changes: BTreeMap<String, Change>,
+}
+
+#[derive(Default)]
+pub struct History {
+    revisions: RwLock<HashMap<u32, Vec<Revision>>>,
+}
+
+impl History {
+    pub fn revisions(&self, id: u32) -> Result<Vec<Revision>, HttpAppError> {
+        let revisions = self.revisions.read()?;
+        Ok(revisions.get(&id).cloned().unwrap_or_default())
+    }
+
+    fn record(
+        &self,
+        id: u32,
+        action: Action,
+        before: Option<&Person>,
+        after: Option<&Person>,
+    ) -> Result<(), HttpAppError> {
+        audit::collect(id, before, after);
+        let changes = diff(fields(before)?, fields(after)?);
+        let mut revisions = self.revisions.write()?;
+        let person_revisions = revisions.entry(id).or_default();
+        person_revisions.push(Revision {
+            revision: person_revisions.len() + 1,
+            action,
+            actor: current_actor(),
+            at: Utc::now(),
+            changes,
+        });
+        Ok(())
+    }
+}
+
+fn fields(person: Option<&Person>) -> Result<serde_json::Map<String, Value>, HttpAppError> {
+    match person.map(serde_json::to_value).transpose() {
+        Ok(Some(Value::Object(map))) => Ok(map),
+        Ok(_) => Ok(serde_json::Map::new()),
+        Err(e) => Err(HttpAppError::StorageError(e.to_string())),
+    }
+}
+
+fn diff(
+    mut before: serde_json::Map<String, Value>,
+    mut after: serde_json::Map<String, Value>,
+) -> BTreeMap<String, Change> {
+    let mut keys: Vec<String> = before.keys().chain(after.keys()).cloned().collect();
+    keys.sort();
+    keys.dedup();
+    keys.into_iter()
+        .filter_map(|key| {
+            let from = before.remove(&key);
+            let to = after.remove(&key);
+            (from != to).then_some((key, Change { from, to }))
+        })
+        .collect()
+}
+
+/// Records a revision for every mutation going through the inner repository.
+pub struct HistoryRepository {
+    inner: Box<dyn PersonRepository>,
+    history: Arc<History>,
+}
+
+impl HistoryRepository {
+    pub fn new(inner: Box<dyn PersonRepository>, history: Arc<History>) -> Self {
+        HistoryRepository { inner, history }
+    }
+}
+
+#[async_trait]
+impl PersonRepository for HistoryRepository {
+    async fn migrate(&self) -> Result<(), MigrateError> {
+        self.inner.migrate().await
+    }
+
+    async fn pending_migrations(&self) -> Result<usize, MigrateError> {
+        self.inner.pending_migrations().await
+    }
+
+    async fn enforce_unique_names(&self, enabled: bool) -> Result<bool, HttpAppError> {
+        self.inner.enforce_unique_names(enabled).await
+    }
+
+    async fn close(&self) -> Result<(), HttpAppError> {
+        self.inner.close().await
+    }
+
+    async fn list(&self) -> Result<Vec<Person>, HttpAppError> {
+        self.inner.list().await
+    }
+
+    async fn list_page(&self, query: &ListQuery) -> Result<Page<Person>, HttpAppError> {
+        self.inner.list_page(query).await
+    }
+
+    async fn count(&self, filter: &PersonFilter) -> Result<u64, HttpAppError> {
+        self.inner.count(filter).await
+    }
+
+    async fn list_after(
+        &self,
+        filter: &PersonFilter,
+        after: Option<u32>,
+        limit: u64,
+    ) -> Result<Vec<Person>, HttpAppError> {
+        self.inner.list_after(filter, after, limit).await
+    }
+
+    async fn search(
+        &self,
+        query: &str,
+        fuzzy: bool,
+        limit: usize,
+    ) -> Result<Vec<SearchHit>, HttpAppError> {
+        self.inner.search(query, fuzzy, limit).await
+    }
+
+    async fn get(&self, id: u32) -> Result<Person, HttpAppError> {
+        self.inner.get(id).await
+    }
+
+    async fn get_by_uuid(&self, uuid: Uuid) -> Result<Person, HttpAppError> {
+        self.inner.get_by_uuid(uuid).await
+    }
+
+    async fn insert(&self, person: Person) -> Result<(), HttpAppError> {
+        self.inner.insert(person.clone()).await?;
+        self.history.record(person.id, Action::Create, None, Some(&person))
+    }
+
+    async fn next_id(&self) -> Result<u32, HttpAppError> {
+        self.inner.next_id().await
+    }
+
+    async fn create(&self, person: NewPerson) -> Result<Person, HttpAppError> {
+        let created = self.inner.create(person).await?;
+        self.history.record(created.id, Action::Create, None, Some(&created))?;
+        Ok(created)
+    }
+
+    async fn update(&self, person: Person) -> Result<(), HttpAppError> {
+        let before = self.inner.get(person.id).await?;
+        self.inner.update(person).await?;
+        let after = self.inner.get(before.id).await?;
+        self.history.record(before.id, Action::Update, Some(&before), Some(&after))
+    }
+
+    async fn upsert(&self, person: Person) -> Result<bool, HttpAppError> {
+        let id = person.id;
+        let before = match self.inner.get(id).await {
+            Ok(before) => Some(before),
+            Err(HttpAppError::NotFound) => None,
+            Err(e) => return Err(e),
+        };
+        let created = self.inner.upsert(person).await?;
+        let after = self.inner.get(id).await?;
+        let action = if created { Action::Create } else { Action::Update };
+        self.history.record(id, action, before.as_ref(), Some(&after))?;
+        Ok(created)
+    }
+
+    async fn delete(&self, id: u32, version: u32) -> Result<(), HttpAppError> {
+        let before = self.inner.get(id).await?;
+        self.inner.delete(id, version).await?;
+        let after = self.inner.get(id).await?;
+        self.history.record(id, Action::Delete, Some(&before), Some(&after))
+    }
+
+    async fn set_deleted(
+        &self,
+        id: u32,
+        deleted_at: Option<DateTime<Utc>>,
+    ) -> Result<(), HttpAppError> {
+        let before = self.inner.get(id).await?;
+        self.inner.set_deleted(id, deleted_at).await?;
+        let after = self.inner.get(id).await?;
+        let action = if deleted_at.is_some() { Action::Delete } else { Action::Restore };
+        self.history.record(id, action, Some(&before), Some(&after))
+    }
+
+    async fn purge(&self, id: u32) -> Result<(), HttpAppError> {
+        let before = self.inner.get(id).await?;
+        self.inner.purge(id).await?;
+        self.history.record(id, Action::Purge, Some(&before), None)
+    }
+
+    async fn replace_all(&self, persons: Vec<Person>) -> Result<(), HttpAppError> {
+        let mut before: HashMap<u32, Person> = self
+            .inner
+            .list()
+            .await?
+            .into_iter()
+            .map(|person| (person.id, person))
+            .collect();
+        self.inner.replace_all(persons.clone()).await?;
+        for person in &persons {
+            let old = before.remove(&person.id);
+            self.history.record(person.id, Action::Replace, old.as_ref(), Some(person))?;
+        }
+        for (id, old) in before {
+            self.history.record(id, Action::Purge, Some(&old), None)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use crate::person::create_person_collection;
+    use crate::repository::MemoryRepository;
+
+    fn repository() -> (HistoryRepository, Arc<History>) {
+        let history = Arc::new(History::default());
+        let memory = MemoryRepository::new(create_person_collection());
+        (HistoryRepository::new(Box::new(memory), history.clone()), history)
+    }
+
+    #[test]
+    fn diffs_name_only_the_changed_fields() {
+        let mario = create_person_collection().remove(0);
+        let older = Person { age: 44, version: 2, ..mario.clone() };
+        let changes = diff(fields(Some(&mario)).unwrap(), fields(Some(&older)).unwrap());
+        let names: Vec<&str> = changes.keys().map(String::as_str).collect();
+        assert_eq!(names, ["age", "version"]);
+        assert_eq!(changes["age"].from, Some(json!(43)));
+        assert_eq!(changes["age"].to, Some(json!(44)));
+
+        let created = diff(fields(None).unwrap(), fields(Some(&mario)).unwrap());
+        assert_eq!(created["name"].from, None);
+        assert_eq!(created["name"].to, Some(json!("Mario")));
+    }
+
+    #[tokio::test]
+    async fn revisions_count_up_per_person() {
+        let (repository, history) = repository();
+        let mut mario = repository.get(1).await.unwrap();
+        mario.age = 44;
+        repository.update(mario).await.unwrap();
+        repository.delete(1, 2).await.unwrap();
+        repository.restore(1).await.unwrap();
+        repository.delete(2, 1).await.unwrap();
+
+        let revisions = history.revisions(1).unwrap();
+        let numbers: Vec<usize> = revisions.iter().map(|revision| revision.revision).collect();
+        assert_eq!(numbers, [1, 2, 3]);
+        assert!(matches!(revisions[0].action, Action::Update));
+        assert!(matches!(revisions[1].action, Action::Delete));
+        assert!(matches!(revisions[2].action, Action::Restore));
+        assert_eq!(history.revisions(2).unwrap()[0].revision, 1);
+        assert!(history.revisions(3).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn revisions_name_the_actor() {
+        let (repository, history) = repository();
+        repository.delete(1, 0).await.unwrap();
+        as_actor("alice".to_string(), repository.restore(1)).await.unwrap();
+
+        let actors: Vec<String> =
+            history.revisions(1).unwrap().into_iter().map(|revision| revision.actor).collect();
+        assert_eq!(actors, [SYSTEM_ACTOR, "alice"]);
+    }
+
+    #[tokio::test]
+    async fn upserts_are_recorded_once() {
+        let (repository, history) = repository();
+        repository.delete(1, 0).await.unwrap();
+        let mario = Person { age: 44, deleted_at: None, ..repository.get(1).await.unwrap() };
+        assert!(!repository.upsert(mario.clone()).await.unwrap());
+        assert!(repository.upsert(Person { id: 3, ..mario }).await.unwrap());
+
+        let revisions = history.revisions(1).unwrap();
+        assert_eq!(revisions.len(), 2);
+        assert!(matches!(revisions[1].action, Action::Update));
+        let restored = &revisions[1].changes["deleted_at"];
+        assert!(restored.from.is_some() && restored.to.is_none());
+        assert_eq!(revisions[1].changes["age"].to, Some(json!(44)));
+        let created = history.revisions(3).unwrap();
+        assert_eq!(created.len(), 1);
+        assert!(matches!(created[0].action, Action::Create));
+    }
+}
@@ -0,0 +1,134 @@
+use std::net::IpAddr;
+use std::sync::Arc;
+use axum::{
+    extract::{Request, State},
+    Extension,
+    middleware::Next,
+    response::Response,
+};
+use ipnet::IpNet;
+use crate::config::Config;
+use crate::errors::{ConfigError, HttpAppError};
+use crate::proxy::{self, Client};
+use crate::routes::AppState;
+
+/// Which client addresses may use the service at all.
+pub struct IpFilter {
+    allow: Vec<IpNet>,
+    deny: Vec<IpNet>,
+}
+
+impl IpFilter {
+    /// Reads the CIDR ranges of `IP_ALLOWLIST` and `IP_DENYLIST`; `None` when
+    /// neither is set.
+    pub fn from_config(config: &Config) -> Result<Option<Self>, ConfigError> {
+        let nets = |name| match config.var(name) {
+            Some(value) => proxy::parse_nets(name, value),
+            None => Ok(Vec::new()),
+        };
+        let (allow, deny) = (nets("IP_ALLOWLIST")?, nets("IP_DENYLIST")?);
+        if allow.is_empty() && deny.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(IpFilter { allow, deny }))
+    }
+
+    /// Denied ranges win, so a single address can be cut out of an allowed one.
+    fn allows(&self, ip: IpAddr) -> bool {
+        let listed = |nets: &[IpNet]| nets.iter().any(|net| net.contains(&ip));
+        !listed(&self.deny) && (self.allow.is_empty() || listed(&self.allow))
+    }
+}
+
+/// Rejects clients outside the allowlist or inside the denylist, found behind
+/// the trusted proxies, before any other work is done for them.
+pub async fn filter(
+    State(state): State<Arc<AppState>>,
+    Extension(client): Extension<Client>,
+    request: Request,
+    next: Next,
+) -> Result<Response, HttpAppError> {
+    if let Some(ip_filter) = &state.ip_filter {
+        if !ip_filter.allows(client.ip) {
+            tracing::warn!("Rejected {} by the IP filter", client.ip);
+            return Err(HttpAppError::Forbidden(format!("{} is not allowed", client.ip)));
+        }
+    }
+    Ok(next.run(request).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+    use axum::body::Body;
+    use axum::extract::ConnectInfo;
+    use axum::http::StatusCode;
+    use axum::{middleware, routing::get, Router};
+    use tower::ServiceExt;
+    use crate::proxy::{TrustedProxies, FORWARDED_FOR_HEADER};
+
+    fn listing(settings: &[(&str, &str)]) -> IpFilter {
+        IpFilter::from_config(&Config::from_overrides(settings)).unwrap().unwrap()
+    }
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn allowlists_admit_only_their_ranges() {
+        let ip_filter = listing(&[("IP_ALLOWLIST", "10.0.0.0/8, 2001:db8::/32")]);
+        assert!(ip_filter.allows(ip("10.1.2.3")));
+        assert!(ip_filter.allows(ip("2001:db8::1")));
+        assert!(!ip_filter.allows(ip("11.0.0.1")));
+        assert!(!ip_filter.allows(ip("2001:db9::1")));
+    }
+
+    #[test]
+    fn denylists_win_over_allowlists() {
+        let ip_filter = listing(&[("IP_DENYLIST", "192.0.2.0/24")]);
+        assert!(!ip_filter.allows(ip("192.0.2.200")));
+        assert!(ip_filter.allows(ip("198.51.100.1")));
+
+        let ip_filter = listing(&[("IP_ALLOWLIST", "10.0.0.0/8"), ("IP_DENYLIST", "10.0.0.7")]);
+        assert!(!ip_filter.allows(ip("10.0.0.7")));
+        assert!(ip_filter.allows(ip("10.0.0.8")));
+    }
+
+    #[test]
+    fn filters_need_a_list() {
+        assert!(IpFilter::from_config(&Config::default()).unwrap().is_none());
+        let config = Config::from_overrides(&[("IP_ALLOWLIST", "10.0.0.0/40")]);
+        assert!(IpFilter::from_config(&config).is_err());
+    }
+
+    #[tokio::test]
+    async fn clients_are_filtered_behind_trusted_proxies() {
+        let config = Config::from_overrides(&[("TRUSTED_PROXIES", "10.0.0.1")]);
+        let mut state = AppState::for_tests();
+        state.ip_filter = Some(listing(&[("IP_DENYLIST", "203.0.113.0/24")]));
+        state.trusted_proxies = TrustedProxies::from_config(&config).unwrap();
+        let state = Arc::new(state);
+        let router = Router::new()
+            .route("/", get(|| async { "ok" }))
+            .layer(middleware::from_fn_with_state(state.clone(), filter))
+            .layer(middleware::from_fn_with_state(state.clone(), proxy::resolve))
+            .with_state(state);
+        let status = |peer: &str, forwarded_for: &str| {
+            let mut request = Request::get("/")
+                .header(FORWARDED_FOR_HEADER, forwarded_for)
+                .body(Body::empty())
+                .unwrap();
+            let peer: SocketAddr = format!("{}:40000", peer).parse().unwrap();
+            request.extensions_mut().insert(ConnectInfo(peer));
+            let router = router.clone();
+            async move { router.oneshot(request).await.unwrap().status() }
+        };
+
+        assert_eq!(status("10.0.0.1", "203.0.113.9").await, StatusCode::FORBIDDEN);
+        assert_eq!(status("10.0.0.1", "203.0.113.9, 198.51.100.1").await, StatusCode::OK);
+        assert_eq!(status("203.0.113.9", "198.51.100.1").await, StatusCode::FORBIDDEN);
+        assert_eq!(status("198.51.100.1", "203.0.113.9").await, StatusCode::OK);
+    }
+}
@@ -0,0 +1,261 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use axum::{
+    extract::{Path, State},
+    http::{header, HeaderMap, StatusCode},
+    middleware,
+    response::IntoResponse,
+    routing::{get, post},
+    Router,
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use uuid::Uuid;
+use crate::auth::{self, Role};
+use crate::bulk::{self, BulkPerson};
+use crate::csv_io;
+use crate::errors::HttpAppError;
+use crate::extract;
+use crate::history;
+use crate::links;
+use crate::negotiation::{Format, Negotiated};
+use crate::routes::AppState;
+
+pub const MAX_IMPORT_ROWS: usize = 100_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Completed,
+}
+
+#[derive(Clone, Serialize)]
+pub struct RowError {
+    pub index: usize,
+    pub status: u16,
+    pub error: String,
+}
+
+#[derive(Clone, Serialize)]
+pub struct ImportJob {
+    pub id: Uuid,
+    pub status: JobStatus,
+    pub total: usize,
+    pub processed: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub errors: Vec<RowError>,
+    pub created_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+/// Import jobs by id; jobs live in memory and are lost on restart.
+#[derive(Default)]
+pub struct ImportJobs {
+    jobs: RwLock<HashMap<Uuid, ImportJob>>,
+}
+
+impl ImportJobs {
+    pub fn get(&self, id: Uuid) -> Result<ImportJob, HttpAppError> {
+        let jobs = self.jobs.read()?;
+        jobs.get(&id).cloned().ok_or(HttpAppError::NotFound)
+    }
+
+    fn enqueue(&self, total: usize) -> Result<ImportJob, HttpAppError> {
+        let job = ImportJob {
+            id: Uuid::new_v4(),
+            status: JobStatus::Queued,
+            total,
+            processed: 0,
+            succeeded: 0,
+            failed: 0,
+            errors: Vec::new(),
+            created_at: Utc::now(),
+            finished_at: None,
+        };
+        self.jobs.write()?.insert(job.id, job.clone());
+        Ok(job)
+    }
+
+    fn update(&self, id: Uuid, change: impl FnOnce(&mut ImportJob)) -> Result<(), HttpAppError> {
+        let mut jobs = self.jobs.write()?;
+        if let Some(job) = jobs.get_mut(&id) {
+            change(job);
+        }
+        Ok(())
+    }
+}
+
+pub fn create_job_routes() -> Router<Arc<AppState>> {
+    let writer = middleware::from_fn_with_state(Role::Writer, auth::authorize);
+    Router::new()
+        .route("/imports", post(submit_import).route_layer(writer))
+        .route("/imports/:job_id", get(import_status))
+}
+
+/// Takes a JSON array of persons, or CSV rows when sent as `text/csv`.
+async fn submit_import(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    format: Format,
+    body: String,
+) -> Result<impl IntoResponse, HttpAppError> {
+    let rows = if csv_io::is_csv(&headers) {
+        csv_io::parse_rows(&body)
+    } else {
+        let strict = state.settings().strict_json;
+        let persons: Vec<BulkPerson> = extract::parse(body.as_bytes(), strict)?;
+        persons.into_iter().map(Ok).collect()
+    };
+    if rows.is_empty() || rows.len() > MAX_IMPORT_ROWS {
+        return Err(HttpAppError::BadRequest(format!(
+            "An import takes between 1 and {} rows",
+            MAX_IMPORT_ROWS
+        )));
+    }
+    let job = state.import_jobs.enqueue(rows.len())?;
+    let actor = history::current_actor();
+    tokio::spawn(history::as_actor(actor, run_import(state, job.id, rows)));
+    let location = [(header::LOCATION, format!("{}/imports/{}", links::API_PREFIX, job.id))];
+    Ok((StatusCode::ACCEPTED, location, Negotiated(format, job)))
+}
+
+async fn import_status(
+    State(state): State<Arc<AppState>>,
+    Path(job_id): Path<Uuid>,
+    format: Format,
+) -> Result<Negotiated<ImportJob>, HttpAppError> {
+    Ok(Negotiated(format, state.import_jobs.get(job_id)?))
+}
+
+async fn run_import(
+    state: Arc<AppState>,
+    id: Uuid,
+    rows: Vec<Result<BulkPerson, HttpAppError>>,
+) {
+    let jobs = &state.import_jobs;
+    if let Err(e) = jobs.update(id, |job| job.status = JobStatus::Running) {
+        tracing::error!("Import job {} could not start: {}", id, e);
+        return;
+    }
+    for (index, row) in rows.into_iter().enumerate() {
+        let result = match row {
+            Ok(row) => bulk::create_one(&state, row).await,
+            Err(e) => Err(e),
+        };
+        let progress = jobs.update(id, |job| {
+            job.processed += 1;
+            match result {
+                Ok(_) => job.succeeded += 1,
+                Err(e) => {
+                    job.failed += 1;
+                    job.errors.push(RowError {
+                        index,
+                        status: e.status_code().as_u16(),
+                        error: e.to_string(),
+                    });
+                }
+            }
+        });
+        if let Err(e) = progress {
+            tracing::error!("Import job {} stopped at row {}: {}", id, index, e);
+            return;
+        }
+    }
+    let finished = jobs.update(id, |job| {
+        job.status = JobStatus::Completed;
+        job.finished_at = Some(Utc::now());
+    });
+    match finished {
+        Ok(()) => tracing::info!("Import job {} completed", id),
+        Err(e) => tracing::error!("Import job {} could not finish: {}", id, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::{self, Body};
+    use axum::http::Request;
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+    use crate::person::NewPerson;
+
+    async fn send(state: &Arc<AppState>, request: Request<Body>) -> (StatusCode, HeaderMap, Value) {
+        let router = create_job_routes().with_state(state.clone());
+        let response = router.oneshot(request).await.unwrap();
+        let (status, headers) = (response.status(), response.headers().clone());
+        let body = body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, headers, serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
+
+    fn submit(body: Value) -> Request<Body> {
+        Request::post("/imports")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    fn row(name: &str) -> Result<BulkPerson, HttpAppError> {
+        let date = chrono::NaiveDate::from_ymd_opt(1985, 9, 13).unwrap();
+        let person = NewPerson { uuid: None, name: name.to_string(), age: 30, date };
+        Ok(BulkPerson { id: None, person })
+    }
+
+    #[tokio::test]
+    async fn jobs_run_every_row_and_record_failures() {
+        let state = Arc::new(AppState::for_tests());
+        let job = state.import_jobs.enqueue(3).unwrap();
+        assert_eq!(state.import_jobs.get(job.id).unwrap().status, JobStatus::Queued);
+
+        let unparsed = Err(HttpAppError::BadRequest("bad row".to_string()));
+        let rows = vec![row("Peach"), unparsed, row("")];
+        run_import(state.clone(), job.id, rows).await;
+        let job = state.import_jobs.get(job.id).unwrap();
+        assert_eq!(job.status, JobStatus::Completed);
+        assert_eq!((job.total, job.processed, job.succeeded, job.failed), (3, 3, 1, 2));
+        let errors: Vec<(usize, u16)> = job.errors.iter().map(|e| (e.index, e.status)).collect();
+        assert_eq!(errors, [(1, 400), (2, 422)]);
+        assert!(job.finished_at.is_some_and(|finished_at| finished_at >= job.created_at));
+        assert_eq!(state.person_repository.get(3).await.unwrap().name, "Peach");
+    }
+
+    #[tokio::test]
+    async fn submitted_jobs_are_queued_then_completed() {
+        let state = Arc::new(AppState::for_tests());
+        let persons = json!([{ "name": "Peach", "age": 30, "date": "1985-09-13" }]);
+        let (status, headers, job) = send(&state, submit(persons)).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert_eq!(job["status"], "queued");
+        let uri = format!("/imports/{}", job["id"].as_str().unwrap());
+        let location = headers[header::LOCATION].to_str().unwrap();
+        assert_eq!(location, format!("{}{}", links::API_PREFIX, uri));
+
+        let mut job = Value::Null;
+        for _ in 0..1000 {
+            let status;
+            (status, _, job) = send(&state, Request::get(&uri).body(Body::empty()).unwrap()).await;
+            assert_eq!(status, StatusCode::OK);
+            if job["status"] == "completed" {
+                break;
+            }
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(job["status"], "completed");
+        assert_eq!((&job["succeeded"], &job["failed"]), (&json!(1), &json!(0)));
+    }
+
+    #[tokio::test]
+    async fn unknown_jobs_are_not_found() {
+        let state = Arc::new(AppState::for_tests());
+        let uri = format!("/imports/{}", Uuid::new_v4());
+        let (status, _, _) = send(&state, Request::get(uri).body(Body::empty()).unwrap()).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(matches!(state.import_jobs.get(Uuid::new_v4()), Err(HttpAppError::NotFound)));
+        let (status, _, _) = send(&state, submit(json!([]))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,141 @@
+use std::future::Future;
+use std::io;
+use std::path::{Path, PathBuf};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use crate::errors::{HttpAppError, StorageError};
+use crate::person::Person;
+use crate::repository::{MemoryRepository, PersonRepository};
+
+pub struct JsonFileRepository {
+    path: PathBuf,
+    memory: MemoryRepository,
+    write_lock: Mutex<()>,
+}
+
+impl JsonFileRepository {
+    pub async fn open(path: impl Into<PathBuf>) -> Result<Self, StorageError> {
+        let path = path.into();
+        let persons = match fs::read(&path).await {
+            Ok(contents) => serde_json::from_slice(&contents)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(JsonFileRepository {
+            path,
+            memory: MemoryRepository::new(persons),
+            write_lock: Mutex::new(()),
+        })
+    }
+
+    /// Runs `change` against the persons in memory and writes them out, putting
+    /// the previous persons back when the write fails.
+    async fn persist<T>(
+        &self,
+        change: impl Future<Output = Result<T, HttpAppError>>,
+    ) -> Result<T, HttpAppError> {
+        let _guard = self.write_lock.lock().await;
+        let before = self.memory.list().await?;
+        let changed = change.await?;
+        if let Err(e) = self.write(&self.memory.list().await?).await {
+            self.memory.replace_all(before).await?;
+            return Err(e);
+        }
+        Ok(changed)
+    }
+
+    async fn write(&self, persons: &[Person]) -> Result<(), HttpAppError> {
+        let contents = serde_json::to_vec_pretty(persons)
+            .map_err(|e| HttpAppError::StorageError(e.to_string()))?;
+        write_atomically(&self.path, &contents).await?;
+        Ok(())
+    }
+}
+
+async fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    let temp_path = PathBuf::from(temp_path);
+
+    let mut file = fs::File::create(&temp_path).await?;
+    file.write_all(contents).await?;
+    file.sync_all().await?;
+    drop(file);
+    fs::rename(&temp_path, path).await?;
+    // The rename itself only survives a crash once the directory is synced.
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    fs::File::open(directory).await?.sync_all().await
+}
+
+#[async_trait]
+impl PersonRepository for JsonFileRepository {
+    async fn list(&self) -> Result<Vec<Person>, HttpAppError> {
+        self.memory.list().await
+    }
+
+    async fn get(&self, id: u32) -> Result<Person, HttpAppError> {
+        self.memory.get(id).await
+    }
+
+    async fn insert(&self, person: Person) -> Result<(), HttpAppError> {
+        self.persist(self.memory.insert(person)).await
+    }
+
+    async fn update(&self, person: Person) -> Result<(), HttpAppError> {
+        self.persist(self.memory.update(person)).await
+    }
+
+    async fn delete(&self, id: u32, version: u32) -> Result<(), HttpAppError> {
+        self.persist(self.memory.delete(id, version)).await
+    }
+
+    async fn set_deleted(
+        &self,
+        id: u32,
+        deleted_at: Option<DateTime<Utc>>,
+    ) -> Result<(), HttpAppError> {
+        self.persist(self.memory.set_deleted(id, deleted_at)).await
+    }
+
+    async fn purge(&self, id: u32) -> Result<(), HttpAppError> {
+        self.persist(self.memory.purge(id)).await
+    }
+
+    async fn replace_all(&self, persons: Vec<Person>) -> Result<(), HttpAppError> {
+        self.persist(self.memory.replace_all(persons)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::person::create_person_collection;
+
+    #[tokio::test]
+    async fn failed_writes_leave_the_persons_unchanged() {
+        let directory = std::env::temp_dir().join(format!("json-file-{}", std::process::id()));
+        fs::create_dir_all(&directory).await.unwrap();
+        let path = directory.join("persons.json");
+        let repository = JsonFileRepository::open(&path).await.unwrap();
+        let mario = create_person_collection().remove(0);
+        repository.insert(mario.clone()).await.unwrap();
+        let stored: Vec<Person> = serde_json::from_slice(&fs::read(&path).await.unwrap()).unwrap();
+        assert_eq!(stored.len(), 1);
+
+        fs::remove_dir_all(&directory).await.unwrap();
+        let renamed = Person { name: "Mario Mario".to_string(), ..mario.clone() };
+        let failed = repository.update(renamed).await;
+        assert!(matches!(failed, Err(HttpAppError::StorageError(_))));
+        let failed = repository.insert(Person { id: 2, ..mario.clone() }).await;
+        assert!(matches!(failed, Err(HttpAppError::StorageError(_))));
+        let persons = repository.list().await.unwrap();
+        assert_eq!(persons.len(), 1);
+        assert_eq!((persons[0].name.as_str(), persons[0].version), ("Mario", 1));
+    }
+}
@@ -0,0 +1,128 @@
+use std::collections::BTreeMap;
+use serde::Serialize;
+use utoipa::ToSchema;
+use crate::fields::{FieldSet, Sparse};
+use crate::person::Person;
+
+pub const API_PREFIX: &str = "/api/v1";
+pub const PERSONS: &str = "/persons";
+pub const PERSON: &str = "/person";
+pub const PERSON_BY_ID: &str = "/person/:id";
+
+#[derive(Serialize, ToSchema)]
+pub struct Link {
+    pub href: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub method: Option<&'static str>,
+}
+
+impl Link {
+    fn new(href: String, method: Option<&'static str>) -> Self {
+        Link { href, method }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Linked<T> {
+    #[serde(flatten)]
+    pub item: T,
+    #[serde(rename = "_links")]
+    pub links: BTreeMap<&'static str, Link>,
+}
+
+/// Expands a route template such as `/person/:id` below the API prefix.
+pub fn href(template: &str, id: Option<u32>) -> String {
+    let path = match id {
+        Some(id) => template.replace(":id", &id.to_string()),
+        None => template.to_string(),
+    };
+    format!("{}{}", API_PREFIX, path)
+}
+
+fn person_links(id: u32) -> BTreeMap<&'static str, Link> {
+    let id = Some(id);
+    BTreeMap::from([
+        ("self", Link::new(href(PERSON_BY_ID, id), None)),
+        ("collection", Link::new(href(PERSONS, None), None)),
+        ("update", Link::new(href(PERSON, None), Some("PUT"))),
+        ("delete", Link::new(href(PERSON_BY_ID, id), Some("DELETE"))),
+    ])
+}
+
+pub fn person(person: Person) -> Linked<Person> {
+    Linked { links: person_links(person.id), item: person }
+}
+
+pub fn sparse(person: Person, fields: &FieldSet) -> Linked<Sparse<'_>> {
+    Linked { links: person_links(person.id), item: Sparse { person, fields } }
+}
+
+pub fn persons(persons: Vec<Person>, fields: &FieldSet) -> Vec<Linked<Sparse<'_>>> {
+    persons.into_iter().map(|person| sparse(person, fields)).collect()
+}
+
+/// Rebuilds the list URL from the request query, replacing `params`.
+pub fn list_href(query: Option<&str>, params: &[(&str, String)]) -> String {
+    let mut pairs: Vec<String> = query
+        .unwrap_or_default()
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .filter(|pair| {
+            let key = pair.split('=').next().unwrap_or_default();
+            params.iter().all(|(name, _)| *name != key)
+        })
+        .map(str::to_string)
+        .collect();
+    pairs.extend(params.iter().map(|(name, value)| format!("{}={}", name, value)));
+    let path = href(PERSONS, None);
+    if pairs.is_empty() {
+        path
+    } else {
+        format!("{}?{}", path, pairs.join("&"))
+    }
+}
+
+/// Value of a `Link` header with first, prev, next and last pages.
+pub fn page_header(query: Option<&str>, page: u64, per_page: u64, total: u64) -> String {
+    let last = total.div_ceil(per_page).max(1);
+    let mut links = vec![(1, "first")];
+    if page > 1 {
+        links.push((page.min(last.saturating_add(1)) - 1, "prev"));
+    }
+    if page < last {
+        links.push((page.saturating_add(1), "next"));
+    }
+    links.push((last, "last"));
+    links
+        .into_iter()
+        .map(|(page, rel)| {
+            let href = list_href(query, &[("page", page.to_string())]);
+            format!("<{}>; rel=\"{}\"", href, rel)
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn page_header_links_neighbours() {
+        let header = page_header(Some("per_page=10&page=2"), 2, 10, 35);
+        assert_eq!(
+            header,
+            "</api/v1/persons?per_page=10&page=1>; rel=\"first\", \
+             </api/v1/persons?per_page=10&page=1>; rel=\"prev\", \
+             </api/v1/persons?per_page=10&page=3>; rel=\"next\", \
+             </api/v1/persons?per_page=10&page=4>; rel=\"last\""
+        );
+    }
+
+    #[test]
+    fn page_header_past_the_end() {
+        let header = page_header(None, u64::MAX, 10, 5);
+        assert!(header.contains("page=1>; rel=\"prev\""));
+        assert!(!header.contains("rel=\"next\""));
+    }
+}
@@ -0,0 +1,264 @@
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use axum::{
+    extract::{ConnectInfo, Request},
+    Extension, Router,
+};
+use hyper::body::Incoming;
+use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
+use hyper_util::server::{conn::auto, graceful::GracefulShutdown};
+use hyper_util::service::TowerToHyperService;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Semaphore;
+use tokio::time;
+use tower::ServiceExt;
+use crate::config::{Config, ServerSettings};
+use crate::errors::ConfigError;
+use crate::proxy::Scheme;
+use crate::tls::TlsSettings;
+
+/// The peer address of connections over the Unix socket, so that the rate
+/// limit, IP filter and trusted proxies see the proxy in front on loopback.
+pub const UNIX_PEER: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+
+/// How long to wait after a failed accept, e.g. when out of file descriptors.
+const ACCEPT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// A Unix domain socket to serve on instead of TCP.
+pub struct UnixSocket {
+    path: PathBuf,
+    mode: Option<u32>,
+}
+
+impl UnixSocket {
+    /// Reads `UNIX_SOCKET_PATH` and `UNIX_SOCKET_MODE`, the octal permissions
+    /// of the socket file such as `660`; `None` without a path.
+    pub fn from_config(config: &Config) -> Result<Option<Self>, ConfigError> {
+        let Some(path) = config.var("UNIX_SOCKET_PATH") else {
+            return Ok(None);
+        };
+        if cfg!(not(unix)) {
+            return Err(ConfigError::Invalid("UNIX_SOCKET_PATH needs a Unix system".to_string()));
+        }
+        if config.var("TLS_CERT_FILE").is_some() {
+            return Err(ConfigError::Invalid(
+                "UNIX_SOCKET_PATH serves plain HTTP, without TLS_CERT_FILE".to_string(),
+            ));
+        }
+        let mode = match config.var("UNIX_SOCKET_MODE") {
+            Some(mode) => Some(u32::from_str_radix(mode, 8).map_err(|_| {
+                ConfigError::Invalid(format!("UNIX_SOCKET_MODE must be octal, got {}", mode))
+            })?),
+            None => None,
+        };
+        Ok(Some(UnixSocket { path: path.into(), mode }))
+    }
+
+    /// Replaces a socket file left behind by a previous run, but not one that
+    /// another process still listens on.
+    #[cfg(unix)]
+    fn bind(&self) -> io::Result<tokio::net::UnixListener> {
+        use std::fs::{self, Permissions};
+        use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+        use std::os::unix::net::UnixStream;
+
+        let stale = fs::symlink_metadata(&self.path)
+            .is_ok_and(|metadata| metadata.file_type().is_socket());
+        if stale {
+            if UnixStream::connect(&self.path).is_ok() {
+                return Err(io::ErrorKind::AddrInUse.into());
+            }
+            fs::remove_file(&self.path)?;
+        }
+        let listener = tokio::net::UnixListener::bind(&self.path)?;
+        if let Some(mode) = self.mode {
+            fs::set_permissions(&self.path, Permissions::from_mode(mode))?;
+        }
+        Ok(listener)
+    }
+}
+
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener, PathBuf),
+}
+
+impl Listener {
+    /// Binds the Unix socket when there is one, else `addr`. Port 0 picks a
+    /// free port.
+    pub async fn bind(addr: SocketAddr, unix_socket: Option<UnixSocket>) -> io::Result<Self> {
+        let (target, bound) = match unix_socket {
+            #[cfg(unix)]
+            Some(socket) => (
+                socket.path.display().to_string(),
+                socket.bind().map(|listener| Listener::Unix(listener, socket.path)),
+            ),
+            _ => (addr.to_string(), TcpListener::bind(addr).await.map(Listener::Tcp)),
+        };
+        bound.map_err(|e| {
+            let message = match e.kind() {
+                io::ErrorKind::AddrInUse => format!("{}: already in use", target),
+                _ => format!("{}: {}", target, e),
+            };
+            io::Error::new(e.kind(), message)
+        })
+    }
+
+    /// Serves `app` until `shutdown` resolves and the open connections are done.
+    /// TLS negotiates HTTP/2 or HTTP/1.1; the cleartext listeners speak
+    /// HTTP/1.1, and HTTP/2 with prior knowledge when `h2c` is set.
+    pub async fn serve(
+        self,
+        app: Router,
+        tls: Option<TlsSettings>,
+        h2c: bool,
+        settings: ServerSettings,
+        shutdown: impl Future<Output = ()> + Send + 'static,
+    ) -> io::Result<()> {
+        match self {
+            Listener::Tcp(listener) => {
+                let addr = listener.local_addr()?;
+                match tls {
+                    Some(tls) => {
+                        tracing::info!("Server running on https://{}", addr);
+                        let app = app
+                            .layer(Extension(Scheme::Https))
+                            .into_make_service_with_connect_info::<SocketAddr>();
+                        tls.serve(listener, app, settings, shutdown).await
+                    }
+                    None => {
+                        tracing::info!("Server running on http://{}", addr);
+                        serve_connections(listener, app, h2c, settings, shutdown).await
+                    }
+                }
+            }
+            #[cfg(unix)]
+            Listener::Unix(listener, path) => {
+                tracing::info!("Server running on unix:{}", path.display());
+                let served = serve_connections(listener, app, h2c, settings, shutdown).await;
+                std::fs::remove_file(&path)?;
+                served
+            }
+        }
+    }
+}
+
+/// Reads `ADMIN_ADDR`, e.g. `127.0.0.1:9090`, where `/admin` and `/metrics`
+/// are served instead of on the public listener, so that they can stay
+/// internal; `None` to serve them with the rest.
+pub fn admin_addr_from_config(config: &Config) -> Result<Option<SocketAddr>, ConfigError> {
+    match config.var("ADMIN_ADDR") {
+        Some(addr) => addr.parse().map(Some).map_err(|_| {
+            ConfigError::Invalid(format!("ADMIN_ADDR must be a host and port, got {}", addr))
+        }),
+        None => Ok(None),
+    }
+}
+
+/// Reads `H2C`; `true` lets cleartext clients speak HTTP/2 without the TLS
+/// handshake, as gRPC clients and load balancers do.
+pub fn h2c_from_config(config: &Config) -> bool {
+    config.enabled("H2C")
+}
+
+/// Applies the keep-alive and header read timeout settings, which hyper only
+/// has for HTTP/1.
+pub fn configure(builder: &mut auto::Builder<TokioExecutor>, settings: &ServerSettings) {
+    builder
+        .http1()
+        .keep_alive(settings.keep_alive)
+        .timer(TokioTimer::new())
+        .header_read_timeout(settings.header_read_timeout());
+}
+
+trait Accept {
+    type Stream: AsyncRead + AsyncWrite + Unpin + Send + 'static;
+
+    async fn accept_peer(&self) -> io::Result<(Self::Stream, SocketAddr)>;
+}
+
+impl Accept for TcpListener {
+    type Stream = TcpStream;
+
+    async fn accept_peer(&self) -> io::Result<(TcpStream, SocketAddr)> {
+        self.accept().await
+    }
+}
+
+#[cfg(unix)]
+impl Accept for tokio::net::UnixListener {
+    type Stream = tokio::net::UnixStream;
+
+    async fn accept_peer(&self) -> io::Result<(tokio::net::UnixStream, SocketAddr)> {
+        let (stream, _) = self.accept().await?;
+        Ok((stream, UNIX_PEER))
+    }
+}
+
+/// What `axum::serve` does, with the HTTP versions under our control: each
+/// connection gets the peer address as `ConnectInfo`, and on shutdown the
+/// listener closes while open connections finish their requests. Past
+/// `max_connections` no more are accepted until one closes.
+async fn serve_connections(
+    listener: impl Accept,
+    app: Router,
+    h2c: bool,
+    settings: ServerSettings,
+    shutdown: impl Future<Output = ()>,
+) -> io::Result<()> {
+    // `serve_connection_with_upgrades` would ignore `http1_only`, and no
+    // route upgrades its connection.
+    let mut builder = auto::Builder::new(TokioExecutor::new());
+    configure(&mut builder, &settings);
+    let builder = if h2c { builder } else { builder.http1_only() };
+    let permits = settings.max_connections.map(|max| Arc::new(Semaphore::new(max)));
+    let graceful = GracefulShutdown::new();
+    tokio::pin!(shutdown);
+    loop {
+        let permit = match &permits {
+            Some(permits) => {
+                if permits.available_permits() == 0 {
+                    tracing::warn!("Connection limit reached, waiting for one to close");
+                }
+                tokio::select! {
+                    permit = permits.clone().acquire_owned() => permit.ok(),
+                    () = &mut shutdown => break,
+                }
+            }
+            None => None,
+        };
+        let (stream, peer) = tokio::select! {
+            accepted = listener.accept_peer() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    tracing::warn!("Could not accept a connection: {}", e);
+                    time::sleep(ACCEPT_BACKOFF).await;
+                    continue;
+                }
+            },
+            () = &mut shutdown => break,
+        };
+        let service = app.clone().map_request(move |mut request: Request<Incoming>| {
+            request.extensions_mut().insert(ConnectInfo(peer));
+            request
+        });
+        let connection = builder
+            .serve_connection(TokioIo::new(stream), TowerToHyperService::new(service))
+            .into_owned();
+        let connection = graceful.watch(connection);
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                tracing::debug!("Connection closed: {}", e);
+            }
+            drop(permit);
+        });
+    }
+    graceful.shutdown().await;
+    Ok(())
+}
@@ -0,0 +1,125 @@
+use std::cmp::Ordering;
+use chrono::{Datelike, NaiveDate};
+use serde::Deserialize;
+use utoipa::{IntoParams, ToSchema};
+use crate::pagination::PageRequest;
+use crate::person::Person;
+use crate::query::Expr;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SortField {
+    #[default]
+    Id,
+    Name,
+    Age,
+    Date,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct Sort {
+    #[serde(default)]
+    pub sort: SortField,
+    #[serde(default)]
+    pub order: SortOrder,
+}
+
+impl Sort {
+    pub fn is_default(&self) -> bool {
+        self.sort == SortField::Id && self.order == SortOrder::Asc
+    }
+
+    pub fn compare(&self, a: &Person, b: &Person) -> Ordering {
+        let ordering = match self.sort {
+            SortField::Id => a.id.cmp(&b.id),
+            SortField::Name => a.name.cmp(&b.name),
+            SortField::Age => a.age.cmp(&b.age),
+            SortField::Date => a.date.cmp(&b.date),
+        }
+        .then(a.id.cmp(&b.id));
+        match self.order {
+            SortOrder::Asc => ordering,
+            SortOrder::Desc => ordering.reverse(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, IntoParams, ToSchema)]
+#[into_params(parameter_in = Query)]
+pub struct PersonFilter {
+    pub name: Option<String>,
+    pub min_age: Option<u8>,
+    pub max_age: Option<u8>,
+    pub date_from: Option<NaiveDate>,
+    pub date_to: Option<NaiveDate>,
+    pub year: Option<i32>,
+    /// An expression such as `age >= 18 and name contains "a"`.
+    #[serde(default, rename = "filter")]
+    #[param(value_type = Option<String>)]
+    #[schema(value_type = Option<String>)]
+    pub expr: Option<Expr>,
+    #[serde(default)]
+    pub include_deleted: bool,
+}
+
+impl PersonFilter {
+    pub fn is_empty(&self) -> bool {
+        self.name.is_none()
+            && self.min_age.is_none()
+            && self.max_age.is_none()
+            && self.date_from.is_none()
+            && self.date_to.is_none()
+            && self.year.is_none()
+            && self.expr.is_none()
+    }
+
+    /// The first and last day of `year`, if it is set and representable.
+    pub fn year_range(&self) -> Option<(NaiveDate, NaiveDate)> {
+        let year = self.year?;
+        Some((NaiveDate::from_ymd_opt(year, 1, 1)?, NaiveDate::from_ymd_opt(year, 12, 31)?))
+    }
+
+    pub fn matches(&self, person: &Person) -> bool {
+        if !self.include_deleted && person.deleted_at.is_some() {
+            return false;
+        }
+        if let Some(name) = &self.name {
+            if !person.name.to_lowercase().contains(&name.to_lowercase()) {
+                return false;
+            }
+        }
+        self.min_age.map_or(true, |min_age| person.age >= min_age)
+            && self.max_age.map_or(true, |max_age| person.age <= max_age)
+            && self.date_from.map_or(true, |date_from| person.date >= date_from)
+            && self.date_to.map_or(true, |date_to| person.date <= date_to)
+            && self.year.map_or(true, |year| person.date.year() == year)
+            && self.expr.as_ref().map_or(true, |expr| expr.matches(person))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ListQuery {
+    pub filter: PersonFilter,
+    pub sort: Sort,
+    pub page: PageRequest,
+}
+
+impl ListQuery {
+    pub fn apply(&self, mut persons: Vec<Person>) -> Vec<Person> {
+        persons.sort_by(|a, b| self.sort.compare(a, b));
+        persons
+            .into_iter()
+            .skip(self.page.offset() as usize)
+            .take(self.page.per_page as usize)
+            .collect()
+    }
+}
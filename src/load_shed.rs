@@ -0,0 +1,18 @@
+use crate::config::Config;
+use crate::errors::ConfigError;
+
+/// How many requests each listener handles at once, `MAX_CONCURRENT_REQUESTS`,
+/// or no limit without it. Over the limit requests are shed with 503 at once
+/// rather than queued, so that an overloaded instance recovers quickly.
+pub fn from_config(config: &Config) -> Result<Option<usize>, ConfigError> {
+    let Some(value) = config.var("MAX_CONCURRENT_REQUESTS") else {
+        return Ok(None);
+    };
+    match value.parse() {
+        Ok(0) | Err(_) => Err(ConfigError::Invalid(format!(
+            "MAX_CONCURRENT_REQUESTS must be a positive number, got {}",
+            value
+        ))),
+        Ok(max) => Ok(Some(max)),
+    }
+}
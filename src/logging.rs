@@ -0,0 +1,97 @@
+use std::str::FromStr;
+use tracing::Subscriber;
+use tracing_subscriber::{fmt, registry::LookupSpan, reload, EnvFilter, Layer, Registry};
+use crate::config::Config;
+use crate::errors::{ConfigError, HttpAppError};
+
+pub const DEFAULT_LOG_FILTER: &str = "info";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = ConfigError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            other => Err(ConfigError::Invalid(format!(
+                "Unknown LOG_FORMAT '{}', expected text or json",
+                other
+            ))),
+        }
+    }
+}
+
+impl LogFormat {
+    pub fn from_config(config: &Config) -> Result<Self, ConfigError> {
+        match config.var("LOG_FORMAT") {
+            Some(value) => value.parse(),
+            None => Ok(LogFormat::default()),
+        }
+    }
+
+    /// The layer writing log lines to stdout. In JSON each line is an object
+    /// with the timestamp, level, target and fields of the event, the fields of
+    /// its innermost span, such as the request id, under `span` and the names
+    /// and fields of every enclosing span under `spans`.
+    pub fn layer<S>(self) -> Box<dyn Layer<S> + Send + Sync>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        match self {
+            LogFormat::Text => fmt::layer().boxed(),
+            LogFormat::Json => fmt::layer()
+                .json()
+                .with_current_span(true)
+                .with_span_list(true)
+                .boxed(),
+        }
+    }
+}
+
+/// The filter from `LOG_LEVEL`, in `RUST_LOG` syntax such as
+/// `info,axum_app::auth=debug`.
+pub fn filter_from_config(config: &Config) -> Result<EnvFilter, ConfigError> {
+    let filter = config.var("LOG_LEVEL").unwrap_or(DEFAULT_LOG_FILTER).to_string();
+    EnvFilter::try_new(&filter)
+        .map_err(|e| ConfigError::Invalid(format!("Invalid LOG_LEVEL '{}': {}", filter, e)))
+}
+
+/// Changes the log filter of the running service, e.g. to debug an incident.
+pub struct LogLevel {
+    handle: reload::Handle<EnvFilter, Registry>,
+}
+
+impl LogLevel {
+    pub fn new(handle: reload::Handle<EnvFilter, Registry>) -> Self {
+        LogLevel { handle }
+    }
+
+    pub fn current(&self) -> Result<String, HttpAppError> {
+        self.handle
+            .with_current(|filter| filter.to_string())
+            .map_err(|e| HttpAppError::LockError(e.to_string()))
+    }
+
+    pub fn set(&self, filter: &str) -> Result<(), HttpAppError> {
+        let parsed = EnvFilter::try_new(filter).map_err(|e| {
+            HttpAppError::BadRequest(format!("Invalid log filter '{}': {}", filter, e))
+        })?;
+        self.replace(parsed)
+    }
+
+    pub fn replace(&self, filter: EnvFilter) -> Result<(), HttpAppError> {
+        let description = filter.to_string();
+        self.handle
+            .reload(filter)
+            .map_err(|e| HttpAppError::LockError(e.to_string()))?;
+        tracing::warn!("Log filter changed to {}", description);
+        Ok(())
+    }
+}
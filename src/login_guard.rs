@@ -0,0 +1,313 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use crate::audit;
+use crate::config::Config;
+use crate::errors::{ConfigError, HttpAppError};
+
+/// Failure counters kept before stale ones are dropped.
+const MAX_TRACKED: usize = 10_000;
+
+const DEFAULT_MAX_FAILURES: u32 = 5;
+const DEFAULT_MAX_FAILURES_PER_IP: u32 = 20;
+const DEFAULT_LOCKOUT_SECS: u64 = 30;
+const DEFAULT_MAX_LOCKOUT_SECS: u64 = 3600;
+/// The longest lockout that may be configured, a year.
+const LOCKOUT_LIMIT_SECS: u64 = 366 * 24 * 3600;
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Account(String),
+    Ip(IpAddr),
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Key::Account(username) => write!(f, "account {}", username),
+            Key::Ip(ip) => write!(f, "address {}", ip),
+        }
+    }
+}
+
+struct Failures {
+    count: u32,
+    last: Instant,
+    locked_until: Option<Instant>,
+}
+
+#[derive(Serialize)]
+pub struct Lockout {
+    pub locked: String,
+    pub failures: u32,
+    pub until: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub struct LoginStats {
+    pub failed_logins_total: u64,
+    pub lockouts_total: u64,
+    pub lockouts: Vec<Lockout>,
+}
+
+/// Counts failed logins per account and per client address. Past a limit,
+/// each further failure locks the account or address out for twice as long,
+/// from `LOGIN_LOCKOUT_SECS` up to `LOGIN_MAX_LOCKOUT_SECS`. A quiet period of
+/// the longest lockout forgets the failures.
+pub struct LoginGuard {
+    max_failures: u32,
+    max_failures_per_ip: u32,
+    lockout: Duration,
+    max_lockout: Duration,
+    failures: Mutex<HashMap<Key, Failures>>,
+    failed_logins_total: AtomicU64,
+    lockouts_total: AtomicU64,
+}
+
+impl LoginGuard {
+    /// Reads `LOGIN_MAX_FAILURES` per account (default 5),
+    /// `LOGIN_MAX_FAILURES_PER_IP` (default 20), `LOGIN_LOCKOUT_SECS` (default
+    /// 30) and `LOGIN_MAX_LOCKOUT_SECS` (default an hour), both at most a
+    /// year. A limit of 0 turns that check off.
+    pub fn from_config(config: &Config) -> Result<Self, ConfigError> {
+        Ok(LoginGuard {
+            max_failures: number(config, "LOGIN_MAX_FAILURES", DEFAULT_MAX_FAILURES)?,
+            max_failures_per_ip: number(
+                config,
+                "LOGIN_MAX_FAILURES_PER_IP",
+                DEFAULT_MAX_FAILURES_PER_IP,
+            )?,
+            lockout: lockout(config, "LOGIN_LOCKOUT_SECS", DEFAULT_LOCKOUT_SECS)?,
+            max_lockout: lockout(config, "LOGIN_MAX_LOCKOUT_SECS", DEFAULT_MAX_LOCKOUT_SECS)?,
+            failures: Mutex::new(HashMap::new()),
+            failed_logins_total: AtomicU64::new(0),
+            lockouts_total: AtomicU64::new(0),
+        })
+    }
+
+    fn keys(username: &str, ip: IpAddr) -> [Key; 2] {
+        [Key::Account(username.to_string()), Key::Ip(ip)]
+    }
+
+    /// Turns the attempt away while the account or the address is locked out,
+    /// before the password is even checked.
+    pub fn check(&self, username: &str, ip: IpAddr) -> Result<(), HttpAppError> {
+        let now = Instant::now();
+        let failures = self.failures.lock().unwrap_or_else(|e| e.into_inner());
+        let retry_after = Self::keys(username, ip)
+            .iter()
+            .filter_map(|key| failures.get(key)?.locked_until)
+            .filter(|until| *until > now)
+            .map(|until| (until - now).as_secs() + 1)
+            .max();
+        match retry_after {
+            Some(retry_after) => Err(HttpAppError::TooManyRequests(retry_after)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn failed(&self, username: &str, ip: IpAddr) {
+        self.failed_logins_total.fetch_add(1, Ordering::Relaxed);
+        let now = Instant::now();
+        let mut failures = self.failures.lock().unwrap_or_else(|e| e.into_inner());
+        if failures.len() >= MAX_TRACKED {
+            failures.retain(|_, failures| now - failures.last < self.max_lockout);
+        }
+        for key in Self::keys(username, ip) {
+            let limit = match key {
+                Key::Account(_) => self.max_failures,
+                Key::Ip(_) => self.max_failures_per_ip,
+            };
+            if limit == 0 {
+                continue;
+            }
+            let entry = failures.entry(key.clone()).or_insert(Failures {
+                count: 0,
+                last: now,
+                locked_until: None,
+            });
+            if now - entry.last >= self.max_lockout {
+                entry.count = 0;
+            }
+            entry.count += 1;
+            entry.last = now;
+            if entry.count < limit {
+                continue;
+            }
+            let doublings = (entry.count - limit).min(31);
+            let lockout = self.lockout.saturating_mul(1 << doublings).min(self.max_lockout);
+            let Some(until) = now.checked_add(lockout) else {
+                tracing::error!("Brute force protection cannot lock out {} that long", key);
+                continue;
+            };
+            entry.locked_until = Some(until);
+            self.lockouts_total.fetch_add(1, Ordering::Relaxed);
+            let event = format!(
+                "locked out {} for {}s after {} failed logins",
+                key,
+                lockout.as_secs(),
+                entry.count
+            );
+            tracing::warn!("Brute force protection {}", event);
+            audit::note_event(event);
+        }
+    }
+
+    /// Forgets the failures of the account, but not of the address, which
+    /// may be guessing at other accounts too.
+    pub fn succeeded(&self, username: &str) {
+        let mut failures = self.failures.lock().unwrap_or_else(|e| e.into_inner());
+        failures.remove(&Key::Account(username.to_string()));
+    }
+
+    pub fn stats(&self) -> LoginStats {
+        let (now, utc_now) = (Instant::now(), Utc::now());
+        let failures = self.failures.lock().unwrap_or_else(|e| e.into_inner());
+        let lockouts = failures
+            .iter()
+            .filter_map(|(key, failures)| {
+                let until = failures.locked_until.filter(|until| *until > now)?;
+                let remaining = chrono::Duration::from_std(until - now).unwrap_or_default();
+                Some(Lockout {
+                    locked: key.to_string(),
+                    failures: failures.count,
+                    until: utc_now + remaining,
+                })
+            })
+            .collect();
+        LoginStats {
+            failed_logins_total: self.failed_logins_total.load(Ordering::Relaxed),
+            lockouts_total: self.lockouts_total.load(Ordering::Relaxed),
+            lockouts,
+        }
+    }
+}
+
+fn lockout(config: &Config, name: &str, default: u64) -> Result<Duration, ConfigError> {
+    let secs = number(config, name, default)?;
+    if secs > LOCKOUT_LIMIT_SECS {
+        return Err(ConfigError::Invalid(format!(
+            "{} must be at most {} seconds, got {}",
+            name, LOCKOUT_LIMIT_SECS, secs
+        )));
+    }
+    Ok(Duration::from_secs(secs))
+}
+
+fn number<T: FromStr>(config: &Config, name: &str, default: T) -> Result<T, ConfigError> {
+    match config.var(name) {
+        Some(value) => value.parse().map_err(|_| {
+            ConfigError::Invalid(format!("{} must be a number, got {}", name, value))
+        }),
+        None => Ok(default),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ANN: &str = "ann";
+
+    fn guard(settings: &[(&str, &str)]) -> LoginGuard {
+        LoginGuard::from_config(&Config::from_overrides(settings)).unwrap()
+    }
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::from([192, 0, 2, last])
+    }
+
+    fn retry_after(guard: &LoginGuard, username: &str, ip: IpAddr) -> Option<u64> {
+        match guard.check(username, ip) {
+            Ok(()) => None,
+            Err(HttpAppError::TooManyRequests(retry_after)) => Some(retry_after),
+            Err(e) => panic!("unexpected {}", e),
+        }
+    }
+
+    /// Checks the `Retry-After` of a lockout, which rounds the time left up.
+    fn assert_locked_for(guard: &LoginGuard, username: &str, ip: IpAddr, secs: u64) {
+        let retry_after = retry_after(guard, username, ip);
+        let rounded = |retry: u64| retry == secs || retry == secs + 1;
+        assert!(retry_after.is_some_and(rounded), "{:?} for {}s", retry_after, secs);
+    }
+
+    #[test]
+    fn accounts_are_locked_out_after_the_limit() {
+        let guard = guard(&[("LOGIN_MAX_FAILURES", "3")]);
+        for _ in 0..2 {
+            guard.failed(ANN, ip(1));
+            assert_eq!(retry_after(&guard, ANN, ip(1)), None);
+        }
+        guard.failed(ANN, ip(2));
+        assert_locked_for(&guard, ANN, ip(3), 30);
+        assert_eq!(retry_after(&guard, "bob", ip(1)), None);
+
+        let stats = guard.stats();
+        assert_eq!((stats.failed_logins_total, stats.lockouts_total), (3, 1));
+        assert_eq!(stats.lockouts[0].locked, "account ann");
+        assert_eq!(stats.lockouts[0].failures, 3);
+    }
+
+    #[test]
+    fn lockouts_double_up_to_the_longest() {
+        let guard = guard(&[
+            ("LOGIN_MAX_FAILURES", "1"),
+            ("LOGIN_LOCKOUT_SECS", "30"),
+            ("LOGIN_MAX_LOCKOUT_SECS", "100"),
+        ]);
+        for secs in [30, 60, 100, 100] {
+            guard.failed(ANN, ip(1));
+            assert_locked_for(&guard, ANN, ip(1), secs);
+        }
+    }
+
+    #[test]
+    fn addresses_are_locked_out_across_accounts() {
+        let guard = guard(&[("LOGIN_MAX_FAILURES", "0"), ("LOGIN_MAX_FAILURES_PER_IP", "2")]);
+        guard.failed(ANN, ip(1));
+        guard.failed("bob", ip(1));
+        assert!(retry_after(&guard, "carol", ip(1)).is_some());
+        assert_eq!(retry_after(&guard, ANN, ip(2)), None);
+    }
+
+    #[test]
+    fn successful_logins_forget_the_account_failures() {
+        let guard = guard(&[("LOGIN_MAX_FAILURES", "2"), ("LOGIN_MAX_FAILURES_PER_IP", "3")]);
+        guard.failed(ANN, ip(1));
+        guard.succeeded(ANN);
+        guard.failed(ANN, ip(1));
+        assert_eq!(retry_after(&guard, ANN, ip(2)), None);
+
+        guard.failed(ANN, ip(1));
+        assert!(retry_after(&guard, ANN, ip(2)).is_some());
+        guard.succeeded(ANN);
+        assert_eq!(retry_after(&guard, ANN, ip(2)), None);
+        assert!(retry_after(&guard, ANN, ip(1)).is_some(), "the address stays locked out");
+    }
+
+    #[test]
+    fn lockouts_are_at_most_a_year() {
+        let too_long = (LOCKOUT_LIMIT_SECS + 1).to_string();
+        for name in ["LOGIN_LOCKOUT_SECS", "LOGIN_MAX_LOCKOUT_SECS"] {
+            for secs in [too_long.as_str(), "18446744073709551615", "-1"] {
+                let config = Config::from_overrides(&[(name, secs)]);
+                assert!(LoginGuard::from_config(&config).is_err(), "{}={}", name, secs);
+            }
+        }
+        let longest = LOCKOUT_LIMIT_SECS.to_string();
+        let guard = guard(&[
+            ("LOGIN_MAX_FAILURES", "1"),
+            ("LOGIN_LOCKOUT_SECS", &longest),
+            ("LOGIN_MAX_LOCKOUT_SECS", &longest),
+        ]);
+        guard.failed(ANN, ip(1));
+        assert_locked_for(&guard, ANN, ip(1), LOCKOUT_LIMIT_SECS);
+    }
+}
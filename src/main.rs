@@ -1,35 +1,707 @@
+mod access_log;
+mod admin;
+mod api_keys;
+mod audit;
+mod auth;
+mod body_log;
+mod bulk;
+mod cli;
+mod compression;
+mod config;
+mod cors;
+mod csv_io;
+mod error_reporting;
 mod errors;
+mod etag;
+mod extract;
+mod fields;
+mod flags;
+mod graphql;
+mod grpc;
+mod health;
+mod history;
+mod ip_filter;
+mod jobs;
+mod json_file;
+mod links;
+mod listener;
+mod listing;
+mod load_shed;
+mod logging;
+mod login_guard;
+mod maintenance;
+mod migrations;
+mod mongo;
+mod mysql;
+mod negotiation;
+mod oidc;
+mod openapi;
+mod otel;
+mod pagination;
 mod person;
+mod postgres;
+mod prometheus;
+mod protobuf;
+mod proxy;
+mod query;
+mod rate_limit;
+mod redis_store;
+mod reload;
+mod repository;
+mod request_id;
 mod routes;
+mod runtime;
+mod search;
+mod security_headers;
+mod seed;
+mod sessions;
+mod shutdown;
+mod signatures;
+mod sled_store;
+mod sql;
+mod sqlite;
+mod static_files;
+mod storage;
+mod strict;
+mod timeout;
+mod tls;
+mod tokens;
+mod unique;
+mod users;
+mod validation;
+mod version;
+mod wal;
 
+use std::net::SocketAddr;
 use std::sync::{Arc, RwLock};
-use std::env;
-use axum::Router;
-use tower_http::trace::TraceLayer;
+use std::time::Duration;
+use axum::{
+    error_handling::HandleErrorLayer,
+    extract::{DefaultBodyLimit, Request},
+    middleware, Router,
+};
+use clap::Parser;
+use futures::future;
+use sentry::integrations::tower::NewSentryLayer;
+use tower::{limit::GlobalConcurrencyLimitLayer, timeout::TimeoutLayer, ServiceBuilder};
+use tower_http::{
+    compression::CompressionLayer, limit::RequestBodyLimitLayer, trace::TraceLayer,
+};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use history::{History, HistoryRepository};
 use routes::AppState;
+use storage::StorageBackend;
+use unique::UniqueNameRepository;
 
-#[tokio::main]
-async fn main() {
-    tracing_subscriber::fmt()
-        .with_env_filter("info")
+fn main() {
+    let (command, overrides) = cli::Cli::parse().apply();
+
+    let config = match config::Config::load(overrides) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Invalid configuration: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let runtime_settings = match runtime::RuntimeSettings::from_config(&config) {
+        Ok(runtime_settings) => runtime_settings,
+        Err(e) => {
+            eprintln!("Invalid runtime settings: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let runtime = match runtime_settings.build() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            eprintln!("Failed to start the runtime: {}", e);
+            std::process::exit(1);
+        }
+    };
+    runtime.block_on(run(command, config, runtime_settings));
+}
+
+async fn run(
+    command: cli::Command,
+    config: config::Config,
+    runtime_settings: runtime::RuntimeSettings,
+) {
+    let tracer = match otel::tracer(&config) {
+        Ok(tracer) => tracer,
+        Err(e) => {
+            eprintln!("Invalid OpenTelemetry settings: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let log_format = match logging::LogFormat::from_config(&config) {
+        Ok(log_format) => log_format,
+        Err(e) => {
+            eprintln!("Invalid log format: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let log_filter = match logging::filter_from_config(&config) {
+        Ok(log_filter) => log_filter,
+        Err(e) => {
+            eprintln!("Invalid log level: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let (log_filter, log_level) = tracing_subscriber::reload::Layer::new(log_filter);
+    let traced = tracer.is_some();
+    tracing_subscriber::registry()
+        .with(log_filter)
+        .with(log_format.layer())
+        .with(tracer.map(|tracer| tracing_opentelemetry::layer().with_tracer(tracer)))
         .init();
 
-    let greeting_text = env::var("GREETING_TEXT").unwrap_or_else(|_| "Hi!".to_string());
+    if let Some(path) = config.file() {
+        tracing::info!("Read settings from {}", path.display());
+    }
+    for (name, path) in config.secret_files() {
+        tracing::info!("Read {} from {}", name, path);
+    }
+    runtime_settings.log();
+
+    let metrics = match prometheus::latency_buckets(&config) {
+        Ok(buckets) => prometheus::install(&buckets),
+        Err(e) => {
+            tracing::error!("Invalid metrics settings: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let _sentry = match error_reporting::init(&config) {
+        Ok(sentry) => sentry,
+        Err(e) => {
+            tracing::error!("Invalid Sentry settings: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let settings = match config::Settings::load(&config) {
+        Ok(settings) => settings,
+        Err(e) => {
+            tracing::error!("Invalid settings: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let jwt = match auth::JwtVerifier::from_config(&config) {
+        Ok(jwt) => jwt,
+        Err(e) => {
+            tracing::error!("Invalid JWT configuration: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let api_keys = match api_keys::ApiKeys::from_config(&config) {
+        Ok(api_keys) => api_keys,
+        Err(e) => {
+            tracing::error!("Invalid API keys: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let admin = match auth::AdminCredentials::from_config(&config) {
+        Ok(admin) => admin,
+        Err(e) => {
+            tracing::error!("Invalid admin credentials: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let oidc = match oidc::OidcProvider::discover(&config).await {
+        Ok(oidc) => oidc,
+        Err(e) => {
+            tracing::error!("Invalid OpenID Connect configuration: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let sessions = match sessions::Sessions::from_config(&config).await {
+        Ok(sessions) => sessions,
+        Err(e) => {
+            tracing::error!("Invalid session configuration: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let signing = match signatures::RequestSigning::from_config(&config) {
+        Ok(signing) => signing,
+        Err(e) => {
+            tracing::error!("Invalid request signing configuration: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let security_headers = match security_headers::SecurityHeaders::from_config(&config) {
+        Ok(security_headers) => Arc::new(security_headers),
+        Err(e) => {
+            tracing::error!("Invalid security headers: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let tokens = match tokens::TokenIssuer::from_config(&config) {
+        Ok(tokens) => tokens,
+        Err(e) => {
+            tracing::error!("Invalid token configuration: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let login_guard = match login_guard::LoginGuard::from_config(&config) {
+        Ok(login_guard) => login_guard,
+        Err(e) => {
+            tracing::error!("Invalid login lockout configuration: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let ip_filter = match ip_filter::IpFilter::from_config(&config) {
+        Ok(ip_filter) => ip_filter,
+        Err(e) => {
+            tracing::error!("Invalid IP filter: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let trusted_proxies = match proxy::TrustedProxies::from_config(&config) {
+        Ok(trusted_proxies) => trusted_proxies,
+        Err(e) => {
+            tracing::error!("Invalid trusted proxies: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let cors = match cors::layer_from_config(&config) {
+        Ok(cors) => cors::Cors::new(cors),
+        Err(e) => {
+            tracing::error!("Invalid CORS configuration: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let flags = match flags::from_config(&config) {
+        Ok(flags) => flags::Flags::new(flags),
+        Err(e) => {
+            tracing::error!("Invalid feature flags: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let maintenance = match maintenance::Maintenance::from_config(&config) {
+        Ok(maintenance) => maintenance,
+        Err(e) => {
+            tracing::error!("Invalid maintenance settings: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let server_settings = match config::ServerSettings::load(&config) {
+        Ok(server_settings) => server_settings,
+        Err(e) => {
+            tracing::error!("Invalid server settings: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let unix_socket = match listener::UnixSocket::from_config(&config) {
+        Ok(unix_socket) => unix_socket,
+        Err(e) => {
+            tracing::error!("Invalid Unix socket settings: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let tls = match tls::TlsSettings::from_config(&config) {
+        Ok(tls) => tls,
+        Err(e) => {
+            tracing::error!("Invalid TLS configuration: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let slow_requests = match access_log::SlowRequests::from_config(&config) {
+        Ok(slow_requests) => Arc::new(slow_requests),
+        Err(e) => {
+            tracing::error!("Invalid slow request threshold: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let request_timeout = match timeout::from_config(&config) {
+        Ok(request_timeout) => request_timeout,
+        Err(e) => {
+            tracing::error!("Invalid request timeout: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let compression = match compression::layer_from_config(&config) {
+        Ok(compression) => compression,
+        Err(e) => {
+            tracing::error!("Invalid compression settings: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let static_files = match static_files::StaticFiles::from_config(&config) {
+        Ok(static_files) => static_files,
+        Err(e) => {
+            tracing::error!("Invalid static file settings: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let max_concurrent_requests = match load_shed::from_config(&config) {
+        Ok(max_concurrent_requests) => max_concurrent_requests,
+        Err(e) => {
+            tracing::error!("Invalid concurrency limit: {}", e);
+            std::process::exit(1);
+        }
+    };
 
+    let body_logging = match body_log::BodyLogging::from_config(&config) {
+        Ok(body_logging) => Arc::new(body_logging),
+        Err(e) => {
+            tracing::error!("Invalid body logging settings: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let shutdown = match shutdown::Shutdown::from_config(&config) {
+        Ok(shutdown) => Arc::new(shutdown),
+        Err(e) => {
+            tracing::error!("Invalid shutdown settings: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let rate_limits = match rate_limit::RateLimits::from_config(&config) {
+        Ok(rate_limits) => rate_limits,
+        Err(e) => {
+            tracing::error!("Invalid rate limit settings: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let reloader = match reload::Reloader::from_config(&config) {
+        Ok(reloader) => reloader,
+        Err(e) => {
+            tracing::error!("Invalid reload settings: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let seed_persons = settings.seed_file.as_ref().map(|path| match seed::load(path) {
+        Ok(persons) => persons,
+        Err(e) => {
+            tracing::error!("Invalid seed file {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    });
+
+    let storage_backend = match StorageBackend::from_config(&config) {
+        Ok(storage_backend) => storage_backend,
+        Err(e) => {
+            tracing::error!("Invalid storage settings: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let repositories = match storage_backend.connect(&config).await {
+        Ok(repositories) => repositories,
+        Err(e) => {
+            tracing::error!("Could not open the {} storage: {}", storage_backend.name(), e);
+            std::process::exit(1);
+        }
+    };
+    let (person_repository, users) = (repositories.persons, repositories.users);
+
+    if let Err(e) = person_repository.migrate().await {
+        tracing::error!("Could not migrate the database: {}", e);
+        std::process::exit(1);
+    }
+    if matches!(command, cli::Command::Migrate) {
+        tracing::info!("Migrations finished, exiting");
+        return;
+    }
+
+    let unique_names = settings.unique_names;
+    let person_repository = match person_repository.enforce_unique_names(unique_names).await {
+        Ok(false) if unique_names => Box::new(UniqueNameRepository::new(person_repository)),
+        Ok(_) => person_repository,
+        Err(e) => {
+            tracing::error!("Could not enforce unique names: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    if let Some(persons) = seed_persons {
+        match seed::apply(person_repository.as_ref(), persons).await {
+            Ok(created) => tracing::info!("Seeded {} persons", created),
+            Err(e) => {
+                tracing::error!("Could not seed persons: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let serve = match command {
+        cli::Command::Serve(serve) => serve,
+        cli::Command::Migrate => unreachable!("migrate exits after the migrations"),
+        cli::Command::Seed { file } => {
+            let persons = match seed::load(&file) {
+                Ok(persons) => persons,
+                Err(e) => {
+                    tracing::error!("Invalid seed file {}: {}", file.display(), e);
+                    std::process::exit(1);
+                }
+            };
+            match seed::apply(person_repository.as_ref(), persons).await {
+                Ok(created) => tracing::info!("Seeded {} persons from {}", created, file.display()),
+                Err(e) => {
+                    tracing::error!("Could not seed persons from {}: {}", file.display(), e);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        cli::Command::Export { file } => {
+            let backup = match admin::Backup::create(person_repository.as_ref()).await {
+                Ok(backup) => backup,
+                Err(e) => {
+                    tracing::error!("Could not read the persons to export: {}", e);
+                    std::process::exit(1);
+                }
+            };
+            let contents = match serde_json::to_vec_pretty(&backup) {
+                Ok(contents) => contents,
+                Err(e) => {
+                    tracing::error!("Could not encode the backup: {}", e);
+                    std::process::exit(1);
+                }
+            };
+            if let Err(e) = tokio::fs::write(&file, contents).await {
+                tracing::error!("Could not write {}: {}", file.display(), e);
+                std::process::exit(1);
+            }
+            tracing::info!("Exported {} persons to {}", backup.count, file.display());
+            return;
+        }
+    };
+
+    let addr = match serve.addr(&config) {
+        Ok(addr) => addr,
+        Err(e) => {
+            tracing::error!("Invalid listen address: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let listener = bind(addr, unix_socket).await;
+    let tls_listener = match tls.as_ref().and_then(|tls| tls.port) {
+        Some(port) => Some(bind(SocketAddr::new(addr.ip(), port), None).await),
+        None => None,
+    };
+    let grpc_listener = match grpc::port_from_config(&config) {
+        Ok(Some(port)) => Some(bind(SocketAddr::new(addr.ip(), port), None).await),
+        Ok(None) => None,
+        Err(e) => {
+            tracing::error!("Invalid gRPC listener settings: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let admin_addr = match listener::admin_addr_from_config(&config) {
+        Ok(admin_addr) => admin_addr,
+        Err(e) => {
+            tracing::error!("Invalid admin listener settings: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let admin_listener = match admin_addr {
+        Some(admin_addr) => Some(bind(admin_addr, None).await),
+        None => None,
+    };
+
+    let audit = match &settings.audit_log_file {
+        Some(path) => match audit::AuditLog::open(path).await {
+            Ok(audit) => audit,
+            Err(e) => {
+                tracing::error!("Could not open the audit log {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        },
+        None => audit::AuditLog::default(),
+    };
+
+    let person_repository: Box<dyn repository::PersonRepository> = if traced {
+        Box::new(otel::TracedRepository::new(person_repository, storage_backend.name()))
+    } else {
+        person_repository
+    };
+
+    let history = Arc::new(History::default());
+    let person_repository = Box::new(HistoryRepository::new(person_repository, history.clone()));
+
+    let max_body_bytes = settings.max_body_bytes;
     let shared_state = Arc::new(AppState {
-        person_collection: RwLock::new(person::create_person_collection()),
-        greeting_text,
+        person_repository,
+        history,
+        audit,
+        metrics,
+        readiness: Default::default(),
+        log_level: logging::LogLevel::new(log_level),
+        maintenance,
+        import_jobs: Default::default(),
+        settings: RwLock::new(Arc::new(settings)),
+        jwt,
+        api_keys,
+        admin,
+        oidc,
+        cors,
+        flags,
+        ip_filter,
+        trusted_proxies,
+        login_guard,
+        rate_limiter: rate_limit::RateLimiter::new(rate_limits),
+        sessions,
+        signing,
+        tokens,
+        users,
     });
+    reloader.watch(shared_state.clone());
+
+    let admin_routes = admin::create_admin_routes()
+        .route_layer(middleware::from_fn_with_state(shared_state.clone(), auth::require_admin));
+    let authenticate = middleware::from_fn_with_state(shared_state.clone(), auth::authenticate);
+
+    let mut api = routes::create_routes();
+    if admin_listener.is_none() {
+        api = api.merge(admin_routes.clone());
+    }
+    let mut public = api
+        .layer(authenticate.clone())
+        .merge(health::create_health_routes())
+        .merge(oidc::create_oidc_routes())
+        .merge(openapi::create_docs_routes())
+        .merge(sessions::create_session_routes())
+        .merge(static_files::create_static_routes(static_files))
+        .merge(tokens::create_token_routes())
+        .merge(users::create_user_routes())
+        .merge(version::create_version_routes());
+    if admin_listener.is_none() {
+        public = public.merge(prometheus::create_metrics_routes());
+    }
+    let grpc = grpc::create_grpc_routes(shared_state.clone()).layer(authenticate.clone());
+    let admin = admin_routes
+        .layer(authenticate)
+        .merge(health::create_health_routes())
+        .merge(prometheus::create_metrics_routes())
+        .merge(version::create_version_routes());
+
+    let stack = Middleware {
+        state: shared_state.clone(),
+        slow_requests,
+        request_timeout,
+        max_concurrent_requests,
+        body_logging,
+        security_headers,
+        compression,
+        max_body_bytes,
+    };
+    let public = stack.apply(public);
+
+    tokio::spawn(shutdown.clone().listen(shared_state.clone()));
+    let h2c = listener::h2c_from_config(&config);
+    let serve_on = |listener: listener::Listener, app, tls| {
+        listener.serve(app, tls, h2c, server_settings, shutdown.requested())
+    };
+    let mut servers = Vec::new();
+    match tls_listener {
+        Some(tls_listener) => {
+            servers.push(serve_on(listener, public.clone(), None));
+            servers.push(serve_on(tls_listener, public, tls));
+        }
+        None => servers.push(serve_on(listener, public, tls)),
+    }
+    if let Some(grpc_listener) = grpc_listener {
+        // gRPC needs HTTP/2, which cleartext clients speak with prior knowledge.
+        let grpc = stack.apply(grpc).layer(middleware::from_fn(grpc::http_errors_to_status));
+        servers.push(grpc_listener.serve(grpc, None, true, server_settings, shutdown.requested()));
+    }
+    if let Some(admin_listener) = admin_listener {
+        servers.push(serve_on(admin_listener, stack.apply(admin), None));
+    }
+    let served = shutdown.drain(future::try_join_all(servers)).await;
+    if let Some(Err(e)) = served {
+        tracing::error!("Could not serve: {}", e);
+        std::process::exit(1);
+    }
+    shutdown::close(&shared_state).await;
+}
+
+async fn bind(addr: SocketAddr, unix_socket: Option<listener::UnixSocket>) -> listener::Listener {
+    match listener::Listener::bind(addr, unix_socket).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            tracing::error!("Could not listen on {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// The middleware every listener runs its routes through.
+struct Middleware {
+    state: Arc<AppState>,
+    slow_requests: Arc<access_log::SlowRequests>,
+    request_timeout: Option<Duration>,
+    max_concurrent_requests: Option<usize>,
+    body_logging: Arc<body_log::BodyLogging>,
+    security_headers: Arc<security_headers::SecurityHeaders>,
+    compression: CompressionLayer<compression::Predicate>,
+    max_body_bytes: usize,
+}
 
-    let app = Router::new()
-        .merge(routes::create_routes())
-        .layer(TraceLayer::new_for_http())
-        .with_state(shared_state);
-
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:8080")
-        .await
-        .unwrap();
-    
-    tracing::info!("Server running on http://0.0.0.0:8080");
-    axum::serve(listener, app).await.unwrap();
+impl Middleware {
+    fn apply(&self, routes: Router<Arc<AppState>>) -> Router {
+        let state = &self.state;
+        let trace = TraceLayer::new_for_http()
+            .make_span_with(otel::make_span)
+            .on_response(otel::record_status);
+        // One limit for all the routes of the listener; a plain concurrency
+        // limit layer would give each route its own.
+        let concurrency_limit = self.max_concurrent_requests.map(|max| {
+            ServiceBuilder::new().load_shed().layer(GlobalConcurrencyLimitLayer::new(max))
+        });
+        let overload = ServiceBuilder::new()
+            .layer(HandleErrorLayer::new(errors::from_layers))
+            .option_layer(concurrency_limit)
+            .option_layer(self.request_timeout.map(TimeoutLayer::new));
+        routes
+            .layer(overload)
+            .layer(middleware::from_fn_with_state(state.clone(), audit::record))
+            .layer(middleware::from_fn_with_state(state.clone(), maintenance::guard))
+            .layer(middleware::from_fn_with_state(state.clone(), signatures::verify))
+            .layer(middleware::from_fn_with_state(state.clone(), rate_limit::limit))
+            .layer(middleware::from_fn_with_state(state.clone(), ip_filter::filter))
+            .layer(middleware::from_fn_with_state(
+                self.slow_requests.clone(),
+                access_log::warn_slow,
+            ))
+            .layer(middleware::from_fn(prometheus::track))
+            .layer(middleware::from_fn(history::track_actor))
+            .layer(middleware::from_fn_with_state(self.body_logging.clone(), body_log::log))
+            .layer(DefaultBodyLimit::disable())
+            .layer(RequestBodyLimitLayer::new(self.max_body_bytes))
+            .layer(middleware::map_response(errors::payload_too_large))
+            .layer(self.compression.clone())
+            .layer(middleware::map_response_with_state(
+                self.security_headers.clone(),
+                security_headers::set_headers,
+            ))
+            .layer(middleware::from_fn_with_state(state.clone(), cors::apply))
+            .layer(middleware::from_fn(access_log::log))
+            .layer(trace)
+            .layer(middleware::from_fn(error_reporting::add_context))
+            .layer(middleware::from_fn(request_id::assign))
+            .layer(middleware::from_fn_with_state(state.clone(), proxy::resolve))
+            .layer(NewSentryLayer::<Request>::new_from_top())
+            .with_state(state.clone())
+    }
 }
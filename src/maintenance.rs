@@ -0,0 +1,147 @@
+use std::sync::{Arc, RwLock};
+use axum::{
+    extract::{Request, State},
+    http::Method,
+    middleware::Next,
+    response::Response,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use crate::config::Config;
+use crate::errors::{ConfigError, HttpAppError};
+use crate::graphql;
+use crate::grpc;
+use crate::routes::AppState;
+
+pub const DEFAULT_RETRY_AFTER_SECS: u64 = 60;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MaintenanceWindow {
+    pub since: DateTime<Utc>,
+    pub retry_after: u64,
+}
+
+#[derive(Serialize)]
+pub struct MaintenanceStatus {
+    pub enabled: bool,
+    #[serde(flatten)]
+    pub window: Option<MaintenanceWindow>,
+}
+
+#[derive(Deserialize)]
+pub struct MaintenanceRequest {
+    pub retry_after: Option<u64>,
+}
+
+/// While on, requests that change persons are turned away with 503 and
+/// reads keep working, e.g. during a migration.
+pub struct Maintenance {
+    window: RwLock<Option<MaintenanceWindow>>,
+}
+
+impl Maintenance {
+    /// Starts in maintenance when `MAINTENANCE` is true, asking clients to
+    /// retry after `MAINTENANCE_RETRY_AFTER_SECS` (60).
+    pub fn from_config(config: &Config) -> Result<Self, ConfigError> {
+        let maintenance = Maintenance { window: RwLock::new(None) };
+        if config.enabled("MAINTENANCE") {
+            let retry_after = match config.var("MAINTENANCE_RETRY_AFTER_SECS") {
+                Some(secs) => secs.parse().map_err(|_| {
+                    ConfigError::Invalid(format!(
+                        "MAINTENANCE_RETRY_AFTER_SECS must be a number, got {}",
+                        secs
+                    ))
+                })?,
+                None => DEFAULT_RETRY_AFTER_SECS,
+            };
+            maintenance.start(retry_after);
+        }
+        Ok(maintenance)
+    }
+
+    pub fn status(&self) -> MaintenanceStatus {
+        let window = self.window.read().unwrap_or_else(|e| e.into_inner()).clone();
+        MaintenanceStatus { enabled: window.is_some(), window }
+    }
+
+    /// Keeps the start of a window already open, so that only the retry
+    /// delay changes.
+    pub fn start(&self, retry_after: u64) {
+        let mut window = self.window.write().unwrap_or_else(|e| e.into_inner());
+        let since = window.as_ref().map_or_else(Utc::now, |window| window.since);
+        *window = Some(MaintenanceWindow { since, retry_after });
+        tracing::warn!("Maintenance mode on, mutations answer 503");
+    }
+
+    pub fn end(&self) {
+        if self.window.write().unwrap_or_else(|e| e.into_inner()).take().is_some() {
+            tracing::warn!("Maintenance mode off");
+        }
+    }
+
+    pub fn retry_after(&self) -> Option<u64> {
+        self.window
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .as_ref()
+            .map(|window| window.retry_after)
+    }
+}
+
+/// Rejects `POST`, `PUT`, `PATCH` and `DELETE` under `/api`, `POST` to
+/// `/graphql`, which queries can avoid by using `GET`, and the gRPC calls
+/// that change persons, during maintenance. The admin routes stay open, to
+/// end it.
+pub async fn guard(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, HttpAppError> {
+    let read = matches!(*request.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+    let change = (!read && request.uri().path().starts_with("/api"))
+        || graphql::may_change(&request)
+        || grpc::is_change(&request);
+    if change {
+        if let Some(retry_after) = state.maintenance.retry_after() {
+            return Err(HttpAppError::Maintenance(retry_after));
+        }
+    }
+    Ok(next.run(request).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::{header, StatusCode};
+    use axum::middleware;
+    use tower::ServiceExt;
+
+    async fn graphql(state: &Arc<AppState>, request: Request) -> StatusCode {
+        let router = graphql::create_graphql_routes()
+            .layer(middleware::from_fn_with_state(state.clone(), guard))
+            .with_state(state.clone());
+        router.oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn graphql_posts_wait_for_the_end() {
+        let state = Arc::new(AppState::for_tests());
+        state.maintenance.start(30);
+        let mutation = r#"{"query": "mutation { deletePerson(id: 1, version: 1) }"}"#;
+        let post = || {
+            Request::post("/graphql")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(mutation))
+                .unwrap()
+        };
+        assert_eq!(graphql(&state, post()).await, StatusCode::SERVICE_UNAVAILABLE);
+        let query = Request::get("/graphql?query=%7B%20count%20%7D").body(Body::empty()).unwrap();
+        assert_eq!(graphql(&state, query).await, StatusCode::OK);
+        assert!(state.person_repository.get(1).await.unwrap().deleted_at.is_none());
+
+        state.maintenance.end();
+        assert_eq!(graphql(&state, post()).await, StatusCode::OK);
+        assert!(state.person_repository.get(1).await.unwrap().deleted_at.is_some());
+    }
+}
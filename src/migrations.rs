@@ -0,0 +1,57 @@
+use std::collections::HashSet;
+use sqlx::migrate::{Migrate, MigrateError, Migrator};
+use sqlx::{Database, Pool};
+
+pub static SQLITE: Migrator = sqlx::migrate!("./migrations/sqlite");
+pub static POSTGRES: Migrator = sqlx::migrate!("./migrations/postgres");
+pub static MYSQL: Migrator = sqlx::migrate!("./migrations/mysql");
+
+async fn applied<DB>(pool: &Pool<DB>) -> Result<HashSet<i64>, MigrateError>
+where
+    DB: Database,
+    DB::Connection: Migrate,
+{
+    let mut conn = pool.acquire().await?;
+    conn.ensure_migrations_table().await?;
+    Ok(conn
+        .list_applied_migrations()
+        .await?
+        .into_iter()
+        .map(|migration| migration.version)
+        .collect())
+}
+
+pub async fn run<DB>(migrator: &Migrator, pool: &Pool<DB>) -> Result<(), MigrateError>
+where
+    DB: Database,
+    DB::Connection: Migrate,
+{
+    let applied = applied(pool).await?;
+
+    migrator.run(pool).await?;
+
+    let mut pending = 0;
+    for migration in migrator.iter() {
+        if !applied.contains(&migration.version) {
+            tracing::info!("Applied migration {} {}", migration.version, migration.description);
+            pending += 1;
+        }
+    }
+    if pending == 0 {
+        tracing::info!("Database schema is up to date");
+    }
+    Ok(())
+}
+
+pub async fn pending<DB>(migrator: &Migrator, pool: &Pool<DB>) -> Result<usize, MigrateError>
+where
+    DB: Database,
+    DB::Connection: Migrate,
+{
+    let applied = applied(pool).await?;
+    Ok(migrator
+        .iter()
+        .filter(|migration| !migration.migration_type.is_down_migration())
+        .filter(|migration| !applied.contains(&migration.version))
+        .count())
+}
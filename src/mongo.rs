@@ -0,0 +1,186 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::TryStreamExt;
+use mongodb::bson::{doc, Bson, Document};
+use mongodb::options::{Collation, CollationStrength, IndexOptions};
+use mongodb::{Client, Collection, IndexModel};
+use crate::errors::{HttpAppError, StorageError};
+use crate::person::Person;
+use crate::repository::{next_after, PersonRepository};
+use crate::sql::NAME_INDEX;
+
+pub struct MongoRepository {
+    collection: Collection<Document>,
+}
+
+impl MongoRepository {
+    pub async fn connect(url: &str, database: &str) -> Result<Self, StorageError> {
+        let client = Client::with_uri_str(url).await?;
+        let collection = client.database(database).collection("persons");
+        collection
+            .create_index(IndexModel::builder().keys(doc! { "name": 1 }).build())
+            .await?;
+        Ok(MongoRepository { collection })
+    }
+}
+
+fn id_filter(id: u32) -> Document {
+    doc! { "_id": i64::from(id) }
+}
+
+fn to_document(person: &Person) -> Result<Document, HttpAppError> {
+    let mut document = mongodb::bson::to_document(person)
+        .map_err(|e| HttpAppError::StorageError(e.to_string()))?;
+    let id = document.remove("id").unwrap_or(Bson::Null);
+    document.insert("_id", id);
+    Ok(document)
+}
+
+fn from_document(mut document: Document) -> Result<Person, HttpAppError> {
+    let id = document.remove("_id").unwrap_or(Bson::Null);
+    document.insert("id", id);
+    mongodb::bson::from_document(document).map_err(|e| HttpAppError::StorageError(e.to_string()))
+}
+
+#[async_trait]
+impl PersonRepository for MongoRepository {
+    /// The index compares names with a case-insensitive collation.
+    async fn enforce_unique_names(&self, enabled: bool) -> Result<bool, HttpAppError> {
+        let exists = self
+            .collection
+            .list_index_names()
+            .await?
+            .iter()
+            .any(|name| name == NAME_INDEX);
+        match (enabled, exists) {
+            (true, false) => {
+                let collation = Collation::builder()
+                    .locale("en")
+                    .strength(CollationStrength::Secondary)
+                    .build();
+                let options = IndexOptions::builder()
+                    .name(NAME_INDEX.to_string())
+                    .unique(true)
+                    .collation(collation)
+                    .build();
+                let index = IndexModel::builder()
+                    .keys(doc! { "name": 1 })
+                    .options(options)
+                    .build();
+                self.collection.create_index(index).await?;
+            }
+            (false, true) => self.collection.drop_index(NAME_INDEX).await?,
+            _ => {}
+        }
+        Ok(true)
+    }
+
+    async fn list(&self) -> Result<Vec<Person>, HttpAppError> {
+        let documents: Vec<Document> = self
+            .collection
+            .find(doc! {})
+            .sort(doc! { "_id": 1 })
+            .await?
+            .try_collect()
+            .await?;
+        documents.into_iter().map(from_document).collect()
+    }
+
+    async fn get(&self, id: u32) -> Result<Person, HttpAppError> {
+        match self.collection.find_one(id_filter(id)).await? {
+            Some(document) => from_document(document),
+            None => Err(HttpAppError::NotFound),
+        }
+    }
+
+    async fn next_id(&self) -> Result<u32, HttpAppError> {
+        let last = self
+            .collection
+            .find_one(doc! {})
+            .sort(doc! { "_id": -1 })
+            .await?;
+        let max_id = match last {
+            Some(document) => Some(from_document(document)?.id),
+            None => None,
+        };
+        next_after(max_id)
+    }
+
+    async fn insert(&self, person: Person) -> Result<(), HttpAppError> {
+        let person = Person {
+            version: person.version.max(1),
+            ..person
+        };
+        self.collection.insert_one(to_document(&person)?).await?;
+        Ok(())
+    }
+
+    async fn update(&self, person: Person) -> Result<(), HttpAppError> {
+        let date = mongodb::bson::to_bson(&person.date)
+            .map_err(|e| HttpAppError::StorageError(e.to_string()))?;
+        let changes = doc! {
+            "$set": { "name": person.name, "age": i32::from(person.age), "date": date },
+            "$inc": { "version": 1 }
+        };
+        let mut filter = id_filter(person.id);
+        filter.insert("deleted_at", Bson::Null);
+        if person.version != 0 {
+            filter.insert("version", i64::from(person.version));
+        }
+        let result = self.collection.update_one(filter, changes).await?;
+        if result.matched_count == 0 {
+            if self.get(person.id).await?.deleted_at.is_some() {
+                return Err(HttpAppError::NotFound);
+            }
+            return Err(HttpAppError::PreconditionFailed);
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, id: u32, version: u32) -> Result<(), HttpAppError> {
+        let deleted_at = mongodb::bson::to_bson(&Utc::now())
+            .map_err(|e| HttpAppError::StorageError(e.to_string()))?;
+        let changes = doc! { "$set": { "deleted_at": deleted_at }, "$inc": { "version": 1 } };
+        let mut filter = id_filter(id);
+        filter.insert("deleted_at", Bson::Null);
+        if version != 0 {
+            filter.insert("version", i64::from(version));
+        }
+        let result = self.collection.update_one(filter, changes).await?;
+        if result.matched_count == 0 {
+            if self.get(id).await?.deleted_at.is_some() {
+                return Err(HttpAppError::NotFound);
+            }
+            return Err(HttpAppError::PreconditionFailed);
+        }
+        Ok(())
+    }
+
+    async fn set_deleted(
+        &self,
+        id: u32,
+        deleted_at: Option<DateTime<Utc>>,
+    ) -> Result<(), HttpAppError> {
+        let changes = match deleted_at {
+            Some(deleted_at) => {
+                let deleted_at = mongodb::bson::to_bson(&deleted_at)
+                    .map_err(|e| HttpAppError::StorageError(e.to_string()))?;
+                doc! { "$set": { "deleted_at": deleted_at }, "$inc": { "version": 1 } }
+            }
+            None => doc! { "$unset": { "deleted_at": "" }, "$inc": { "version": 1 } },
+        };
+        let result = self.collection.update_one(id_filter(id), changes).await?;
+        if result.matched_count == 0 {
+            return Err(HttpAppError::NotFound);
+        }
+        Ok(())
+    }
+
+    async fn purge(&self, id: u32) -> Result<(), HttpAppError> {
+        let result = self.collection.delete_one(id_filter(id)).await?;
+        if result.deleted_count == 0 {
+            return Err(HttpAppError::NotFound);
+        }
+        Ok(())
+    }
+}
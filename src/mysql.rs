@@ -0,0 +1,76 @@
+use async_trait::async_trait;
+use sqlx::migrate::Migrator;
+use sqlx::mysql::{MySql, MySqlPool, MySqlPoolOptions, MySqlRow};
+use sqlx::{Error, FromRow, QueryBuilder};
+use crate::migrations;
+use crate::sql::{self, Dialect, SqlRepository};
+use crate::storage::PoolSettings;
+
+pub type MySqlRepository = SqlRepository<MySql>;
+
+#[async_trait]
+impl Dialect for MySql {
+    const INSERT_USER: (&'static str, &'static str) = ("INSERT IGNORE INTO", "");
+
+    fn migrator() -> &'static Migrator {
+        &migrations::MYSQL
+    }
+
+    async fn fetch_all<T>(pool: &MySqlPool, mut query: QueryBuilder<'_, MySql>) -> Result<Vec<T>, Error>
+    where
+        T: for<'r> FromRow<'r, MySqlRow> + Send + Unpin,
+    {
+        query.build_query_as().fetch_all(pool).await
+    }
+
+    async fn fetch_optional<T>(
+        pool: &MySqlPool,
+        mut query: QueryBuilder<'_, MySql>,
+    ) -> Result<Option<T>, Error>
+    where
+        T: for<'r> FromRow<'r, MySqlRow> + Send + Unpin,
+    {
+        query.build_query_as().fetch_optional(pool).await
+    }
+
+    async fn execute(pool: &MySqlPool, mut query: QueryBuilder<'_, MySql>) -> Result<u64, Error> {
+        Ok(query.build().execute(pool).await?.rows_affected())
+    }
+
+    /// MySQL has no `IF NOT EXISTS` for indexes, and its default collation
+    /// already compares names case-insensitively.
+    async fn enforce_unique_names(pool: &MySqlPool, enabled: bool) -> Result<(), Error> {
+        let (exists,): (i64,) = sqlx::query_as(
+            "SELECT COUNT(*) FROM information_schema.statistics \
+             WHERE table_schema = DATABASE() AND table_name = 'persons' AND index_name = ?",
+        )
+        .bind(sql::NAME_INDEX)
+        .fetch_one(pool)
+        .await?;
+        match (enabled, exists > 0) {
+            (true, false) => {
+                sqlx::query("CREATE UNIQUE INDEX persons_name_unique ON persons (name)")
+                    .execute(pool)
+                    .await?;
+            }
+            (false, true) => {
+                sqlx::query("DROP INDEX persons_name_unique ON persons").execute(pool).await?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+impl SqlRepository<MySql> {
+    pub async fn connect(url: &str, settings: PoolSettings) -> Result<Self, Error> {
+        let pool = MySqlPoolOptions::new()
+            .max_connections(settings.max_connections)
+            .min_connections(settings.min_connections)
+            .acquire_timeout(settings.acquire_timeout)
+            .idle_timeout(settings.idle_timeout)
+            .connect(url)
+            .await?;
+        Ok(SqlRepository::new(pool))
+    }
+}
@@ -0,0 +1,327 @@
+use std::collections::BTreeMap;
+use async_trait::async_trait;
+use axum::{
+    extract::FromRequestParts,
+    http::{header, request::Parts, HeaderMap, HeaderValue},
+    response::{IntoResponse, Response},
+    Json,
+};
+use quick_xml::SeError;
+use serde::ser::{SerializeStruct, Serializer};
+use serde::Serialize;
+use crate::bulk::{BulkDeleteResponse, BulkResponse};
+use crate::errors::HttpAppError;
+use crate::fields::Sparse;
+use crate::history::Revision;
+use crate::jobs::ImportJob;
+use crate::links::{Link, Linked};
+use crate::pagination::CursorPage;
+use crate::person::Person;
+use crate::protobuf::ToProtobuf;
+use crate::routes::PersonCount;
+use crate::search::SearchHit;
+
+pub const SUPPORTED: &str =
+    "application/json, application/msgpack, application/x-protobuf or application/xml";
+
+/// The representations a client can ask for with `Accept`, and send.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Msgpack,
+    Protobuf,
+    Xml,
+}
+
+impl Format {
+    fn from_media_type(media_type: &str) -> Option<Self> {
+        let media_type = media_type.to_ascii_lowercase();
+        match media_type.as_str() {
+            "*/*" | "application/*" | "application/json" => Some(Format::Json),
+            "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => {
+                Some(Format::Msgpack)
+            }
+            "application/x-protobuf" | "application/protobuf" => Some(Format::Protobuf),
+            "application/xml" | "text/xml" => Some(Format::Xml),
+            _ if media_type.ends_with("+json") => Some(Format::Json),
+            _ if media_type.ends_with("+xml") => Some(Format::Xml),
+            _ => None,
+        }
+    }
+
+    /// The supported type the client prefers, by quality and then by order;
+    /// JSON without an `Accept` header.
+    pub fn from_accept(headers: &HeaderMap) -> Result<Self, HttpAppError> {
+        preferred(headers, Format::from_media_type, SUPPORTED)
+    }
+
+    /// The format of a request body, by its `Content-Type`.
+    pub fn from_content_type(headers: &HeaderMap) -> Option<Self> {
+        let content_type = headers.get(header::CONTENT_TYPE)?.to_str().ok()?;
+        let media_type = content_type.split(';').next().unwrap_or_default().trim();
+        if media_type.contains('*') {
+            return None;
+        }
+        Format::from_media_type(media_type)
+    }
+
+    fn content_type(self) -> HeaderValue {
+        HeaderValue::from_static(match self {
+            Format::Json => "application/json",
+            Format::Msgpack => "application/msgpack",
+            Format::Protobuf => "application/x-protobuf",
+            Format::Xml => "application/xml; charset=utf-8",
+        })
+    }
+}
+
+/// The type in `Accept` that `from_media_type` knows, as for `*/*` when there
+/// is no `Accept` header.
+fn preferred<T>(
+    headers: &HeaderMap,
+    from_media_type: impl Fn(&str) -> Option<T>,
+    supported: &str,
+) -> Result<T, HttpAppError> {
+    let mut ranges: Vec<(&str, f32)> = headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|range| {
+            let mut params = range.split(';').map(str::trim);
+            let media_type = params.next().unwrap_or_default();
+            let quality = params
+                .find_map(|param| param.strip_prefix("q="))
+                .and_then(|quality| quality.parse().ok())
+                .unwrap_or(1.0);
+            (media_type, quality)
+        })
+        .filter(|(media_type, _)| !media_type.is_empty())
+        .collect();
+    if ranges.is_empty() {
+        ranges.push(("*/*", 1.0));
+    }
+    ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+    ranges
+        .into_iter()
+        .filter(|(_, quality)| *quality > 0.0)
+        .find_map(|(media_type, _)| from_media_type(media_type))
+        .ok_or_else(|| HttpAppError::NotAcceptable(format!("expected {}", supported)))
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Format {
+    type Rejection = HttpAppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Format::from_accept(&parts.headers)
+    }
+}
+
+/// The formats of the person list, which can also be CSV.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListFormat {
+    Csv,
+    Other(Format),
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ListFormat {
+    type Rejection = HttpAppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let from_media_type = |media_type: &str| {
+            if media_type.eq_ignore_ascii_case("text/csv") {
+                Some(ListFormat::Csv)
+            } else {
+                Format::from_media_type(media_type).map(ListFormat::Other)
+            }
+        };
+        preferred(&parts.headers, from_media_type, &format!("text/csv, {}", SUPPORTED))
+    }
+}
+
+/// A response body written in the format the client asked for. Error bodies
+/// stay JSON. In MessagePack, structs are maps with the field names and ids
+/// and dates are strings, as in JSON.
+pub struct Negotiated<T>(pub Format, pub T);
+
+impl<T: Serialize + ToXml + ToProtobuf> IntoResponse for Negotiated<T> {
+    fn into_response(self) -> Response {
+        let Negotiated(format, value) = self;
+        let vary = [(header::VARY, HeaderValue::from_static("accept"))];
+        let body = match format {
+            Format::Msgpack => {
+                let mut body = Vec::new();
+                let mut serializer = rmp_serde::Serializer::new(&mut body)
+                    .with_struct_map()
+                    .with_human_readable();
+                value.serialize(&mut serializer).map(|_| body).map_err(|e| e.to_string())
+            }
+            Format::Protobuf => match value.to_protobuf() {
+                Some(body) => Ok(body),
+                None => return (vary, Json(value)).into_response(),
+            },
+            Format::Xml => value.to_xml().map(String::into_bytes).map_err(|e| e.to_string()),
+            Format::Json => return (vary, Json(value)).into_response(),
+        };
+        match body {
+            Ok(body) => {
+                let content_type = [(header::CONTENT_TYPE, format.content_type())];
+                (vary, content_type, body).into_response()
+            }
+            Err(e) => HttpAppError::StorageError(format!("Could not write {:?}: {}", format, e))
+                .into_response(),
+        }
+    }
+}
+
+/// How a body is written as XML, which needs a name for the document element
+/// and for each element of a list.
+pub trait ToXml {
+    fn to_xml(&self) -> Result<String, SeError>;
+}
+
+fn element<T: Serialize>(name: &'static str, value: &T) -> Result<String, SeError> {
+    quick_xml::se::to_string_with_root(name, value)
+}
+
+/// `<persons><person>…</person><person>…</person></persons>`.
+struct List<'a, T> {
+    name: &'static str,
+    item: &'static str,
+    items: &'a [T],
+}
+
+impl<T: Serialize> Serialize for List<'_, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct(self.name, 1)?;
+        state.serialize_field(self.item, self.items)?;
+        state.end()
+    }
+}
+
+fn list<T: Serialize>(
+    name: &'static str,
+    item: &'static str,
+    items: &[T],
+) -> Result<String, SeError> {
+    element(name, &List { name, item, items })
+}
+
+impl ToXml for Linked<Person> {
+    fn to_xml(&self) -> Result<String, SeError> {
+        element("person", self)
+    }
+}
+
+impl ToXml for Linked<Sparse<'_>> {
+    fn to_xml(&self) -> Result<String, SeError> {
+        element("person", self)
+    }
+}
+
+impl ToXml for Vec<Linked<Sparse<'_>>> {
+    fn to_xml(&self) -> Result<String, SeError> {
+        list("persons", "person", self)
+    }
+}
+
+/// A cursor page as XML, with the persons named like in a plain list.
+#[derive(Serialize)]
+struct PersonsAfter<'a, T> {
+    person: &'a [T],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_cursor: &'a Option<String>,
+    #[serde(rename = "_links")]
+    links: &'a BTreeMap<&'static str, Link>,
+}
+
+impl ToXml for Linked<CursorPage<Linked<Sparse<'_>>>> {
+    fn to_xml(&self) -> Result<String, SeError> {
+        let page = PersonsAfter {
+            person: &self.item.items,
+            next_cursor: &self.item.next_cursor,
+            links: &self.links,
+        };
+        element("persons", &page)
+    }
+}
+
+impl ToXml for PersonCount {
+    fn to_xml(&self) -> Result<String, SeError> {
+        element("persons", self)
+    }
+}
+
+impl ToXml for Vec<SearchHit> {
+    fn to_xml(&self) -> Result<String, SeError> {
+        list("hits", "hit", self)
+    }
+}
+
+impl ToXml for Vec<Revision> {
+    fn to_xml(&self) -> Result<String, SeError> {
+        list("history", "revision", self)
+    }
+}
+
+impl ToXml for BulkResponse {
+    fn to_xml(&self) -> Result<String, SeError> {
+        element("bulk", self)
+    }
+}
+
+impl ToXml for BulkDeleteResponse {
+    fn to_xml(&self) -> Result<String, SeError> {
+        element("bulk", self)
+    }
+}
+
+impl ToXml for ImportJob {
+    fn to_xml(&self) -> Result<String, SeError> {
+        element("import", self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accepting(accept: &'static str) -> Result<Format, HttpAppError> {
+        let headers = HeaderMap::from_iter([(header::ACCEPT, HeaderValue::from_static(accept))]);
+        Format::from_accept(&headers)
+    }
+
+    #[test]
+    fn accept_picks_by_quality_then_order() {
+        assert_eq!(Format::from_accept(&HeaderMap::new()).unwrap(), Format::Json);
+        let msgpack = accepting("application/xml;q=0.5, application/msgpack").unwrap();
+        assert_eq!(msgpack, Format::Msgpack);
+        assert_eq!(accepting("text/html, application/problem+json").unwrap(), Format::Json);
+        assert_eq!(accepting("application/x-protobuf, application/xml").unwrap(), Format::Protobuf);
+        assert_eq!(accepting("text/html;q=0.9, text/xml;q=0.8").unwrap(), Format::Xml);
+    }
+
+    #[test]
+    fn accept_without_a_known_type_is_not_acceptable() {
+        for accept in ["text/html", "application/json;q=0", "image/*"] {
+            let result = accepting(accept);
+            assert!(matches!(result, Err(HttpAppError::NotAcceptable(_))), "{}", accept);
+        }
+    }
+
+    #[test]
+    fn content_type_names_one_format() {
+        let content_type = |value: &'static str| {
+            Format::from_content_type(&HeaderMap::from_iter([(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static(value),
+            )]))
+        };
+        assert_eq!(content_type("application/xml; charset=utf-8"), Some(Format::Xml));
+        assert_eq!(content_type("APPLICATION/JSON"), Some(Format::Json));
+        assert_eq!(content_type("*/*"), None);
+        assert_eq!(content_type("text/plain"), None);
+    }
+}
@@ -0,0 +1,274 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use axum::{
+    extract::State,
+    response::Redirect,
+    routing::get,
+    Json, Router,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::jwk::JwkSet;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+use crate::auth::{JwtVerifier, Principal, Role};
+use crate::config::Config;
+use crate::errors::{AuthError, HttpAppError};
+use crate::extract::QueryParams;
+use crate::routes::AppState;
+
+/// How long a user has to come back from the identity provider.
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(600);
+
+#[derive(Deserialize)]
+struct Discovery {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+struct PendingLogin {
+    nonce: String,
+    code_verifier: String,
+    started: Instant,
+}
+
+/// An OpenID Connect provider such as Keycloak or Auth0, found through its
+/// discovery document. Its ID tokens are accepted as bearer tokens.
+pub struct OidcProvider {
+    client_id: String,
+    client_secret: Option<String>,
+    redirect_url: String,
+    scopes: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    verifier: JwtVerifier,
+    roles_claim: String,
+    role_map: HashMap<String, Role>,
+    pending: RwLock<HashMap<String, PendingLogin>>,
+    http: reqwest::Client,
+}
+
+#[derive(Deserialize)]
+pub struct CallbackParams {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+    expires_in: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct IdClaims {
+    sub: String,
+    nonce: Option<String>,
+    #[serde(flatten)]
+    other: Map<String, Value>,
+}
+
+#[derive(Serialize)]
+pub struct LoginResponse {
+    pub token_type: &'static str,
+    pub access_token: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_in: Option<u64>,
+    pub name: String,
+    pub roles: Vec<Role>,
+}
+
+impl OidcProvider {
+    /// Reads `OIDC_ISSUER_URL` and friends and fetches the provider's
+    /// discovery document and keys; `None` when no issuer is set.
+    pub async fn discover(config: &Config) -> Result<Option<Self>, AuthError> {
+        let Some(issuer) = config.var("OIDC_ISSUER_URL") else {
+            return Ok(None);
+        };
+        let issuer = issuer.trim_end_matches('/').to_string();
+        let client_id = required(config, "OIDC_CLIENT_ID")?;
+        let redirect_url = required(config, "OIDC_REDIRECT_URL")?;
+        let role_map = match config.var("OIDC_ROLE_MAP") {
+            Some(map) => parse_role_map(map)?,
+            None => HashMap::new(),
+        };
+
+        let http = reqwest::Client::new();
+        let url = format!("{}/.well-known/openid-configuration", issuer);
+        let discovery: Discovery = fetch(&http, &url).await?;
+        if discovery.issuer.trim_end_matches('/') != issuer {
+            return Err(AuthError::Config(format!(
+                "the provider calls itself {}, not {}",
+                discovery.issuer, issuer
+            )));
+        }
+        let jwks: JwkSet = fetch(&http, &discovery.jwks_uri).await?;
+        tracing::info!("Using OpenID Connect provider {}", discovery.issuer);
+
+        Ok(Some(OidcProvider {
+            verifier: JwtVerifier::for_issuer(config, &jwks, &discovery.issuer, &client_id)?,
+            client_id,
+            client_secret: config.var("OIDC_CLIENT_SECRET").map(str::to_string),
+            redirect_url,
+            scopes: config.var("OIDC_SCOPES").unwrap_or("openid profile email").to_string(),
+            authorization_endpoint: discovery.authorization_endpoint,
+            token_endpoint: discovery.token_endpoint,
+            roles_claim: config.var("OIDC_ROLES_CLAIM").unwrap_or("roles").to_string(),
+            role_map,
+            pending: RwLock::new(HashMap::new()),
+            http,
+        }))
+    }
+
+    /// The principal of an ID token issued by this provider.
+    pub fn principal(&self, token: &str) -> Result<Principal, HttpAppError> {
+        let claims: IdClaims = self.verifier.verify(token)?;
+        Ok(Principal {
+            roles: self.roles(&claims.other),
+            name: claims.sub,
+        })
+    }
+
+    /// The app roles for the names found under the dotted `OIDC_ROLES_CLAIM`,
+    /// such as `realm_access.roles` for Keycloak, translated by `OIDC_ROLE_MAP`.
+    fn roles(&self, claims: &Map<String, Value>) -> Vec<Role> {
+        let mut path = self.roles_claim.split('.');
+        let first = path.next().and_then(|name| claims.get(name));
+        let names = match path.fold(first, |value, name| value.and_then(|value| value.get(name))) {
+            Some(Value::Array(names)) => names.iter().filter_map(Value::as_str).collect(),
+            Some(Value::String(names)) => names.split_whitespace().collect(),
+            _ => Vec::new(),
+        };
+        names
+            .into_iter()
+            .filter_map(|name| self.role_map.get(name).copied().or_else(|| name.parse().ok()))
+            .collect()
+    }
+}
+
+async fn fetch<T: DeserializeOwned>(http: &reqwest::Client, url: &str) -> Result<T, AuthError> {
+    Ok(http.get(url).send().await?.error_for_status()?.json().await?)
+}
+
+fn required(config: &Config, name: &str) -> Result<String, AuthError> {
+    config
+        .var(name)
+        .map(str::to_string)
+        .ok_or_else(|| AuthError::Config(format!("{} is required with OIDC_ISSUER_URL", name)))
+}
+
+/// Parses `provider-role=app-role` entries separated by commas.
+fn parse_role_map(map: &str) -> Result<HashMap<String, Role>, AuthError> {
+    map.split(',')
+        .filter(|entry| !entry.trim().is_empty())
+        .map(|entry| {
+            let (name, role) = entry.split_once('=').ok_or_else(|| {
+                AuthError::Config(format!(
+                    "OIDC_ROLE_MAP entries are written as name=role, got '{}'",
+                    entry
+                ))
+            })?;
+            Ok((name.trim().to_string(), role.trim().parse().map_err(AuthError::Config)?))
+        })
+        .collect()
+}
+
+pub fn create_oidc_routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/auth/oidc/login", get(login))
+        .route("/auth/oidc/callback", get(callback))
+}
+
+fn provider(state: &AppState) -> Result<&OidcProvider, HttpAppError> {
+    state.oidc.as_ref().ok_or(HttpAppError::NotFound)
+}
+
+/// Sends the user to the provider, with a fresh state, nonce and PKCE challenge.
+async fn login(State(state): State<Arc<AppState>>) -> Result<Redirect, HttpAppError> {
+    let provider = provider(&state)?;
+    let login_state = Uuid::new_v4().simple().to_string();
+    let login = PendingLogin {
+        nonce: Uuid::new_v4().simple().to_string(),
+        code_verifier: format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple()),
+        started: Instant::now(),
+    };
+    let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(login.code_verifier.as_bytes()));
+    let query = form_urlencoded::Serializer::new(String::new())
+        .append_pair("response_type", "code")
+        .append_pair("client_id", &provider.client_id)
+        .append_pair("redirect_uri", &provider.redirect_url)
+        .append_pair("scope", &provider.scopes)
+        .append_pair("state", &login_state)
+        .append_pair("nonce", &login.nonce)
+        .append_pair("code_challenge", &challenge)
+        .append_pair("code_challenge_method", "S256")
+        .finish();
+
+    let mut pending = provider.pending.write()?;
+    pending.retain(|_, login| login.started.elapsed() < LOGIN_TIMEOUT);
+    pending.insert(login_state, login);
+    let separator = if provider.authorization_endpoint.contains('?') { '&' } else { '?' };
+    Ok(Redirect::to(&format!("{}{}{}", provider.authorization_endpoint, separator, query)))
+}
+
+/// Exchanges the code the provider sent the user back with for an ID token,
+/// which is returned for use as a bearer token.
+async fn callback(
+    State(state): State<Arc<AppState>>,
+    QueryParams(params): QueryParams<CallbackParams>,
+) -> Result<Json<LoginResponse>, HttpAppError> {
+    let provider = provider(&state)?;
+    if let Some(error) = params.error {
+        let description = params.error_description.unwrap_or_default();
+        return Err(HttpAppError::Unauthorized(format!("login failed: {} {}", error, description)));
+    }
+    let (Some(code), Some(login_state)) = (params.code, params.state) else {
+        return Err(HttpAppError::BadRequest("code and state are required".to_string()));
+    };
+    let login = provider
+        .pending
+        .write()?
+        .remove(&login_state)
+        .filter(|login| login.started.elapsed() < LOGIN_TIMEOUT)
+        .ok_or_else(|| HttpAppError::Unauthorized("unknown or expired login".to_string()))?;
+
+    let mut form = vec![
+        ("grant_type", "authorization_code"),
+        ("code", code.as_str()),
+        ("redirect_uri", provider.redirect_url.as_str()),
+        ("client_id", provider.client_id.as_str()),
+        ("code_verifier", login.code_verifier.as_str()),
+    ];
+    if let Some(secret) = &provider.client_secret {
+        form.push(("client_secret", secret.as_str()));
+    }
+    let response = provider.http.post(&provider.token_endpoint).form(&form).send().await?;
+    if !response.status().is_success() {
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        tracing::warn!("Token exchange failed with {}: {}", status, body);
+        return Err(HttpAppError::Unauthorized("the code was not accepted".to_string()));
+    }
+    let tokens: TokenResponse = response.json().await?;
+
+    let claims: IdClaims = provider.verifier.verify(&tokens.id_token)?;
+    if claims.nonce.as_deref() != Some(login.nonce.as_str()) {
+        return Err(HttpAppError::Unauthorized("the ID token nonce does not match".to_string()));
+    }
+    let roles = provider.roles(&claims.other);
+    tracing::info!("{} logged in through OpenID Connect with roles {:?}", claims.sub, roles);
+    Ok(Json(LoginResponse {
+        token_type: "Bearer",
+        access_token: tokens.id_token,
+        expires_in: tokens.expires_in,
+        name: claims.sub,
+        roles,
+    }))
+}
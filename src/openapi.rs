@@ -0,0 +1,79 @@
+use std::sync::Arc;
+use axum::{
+    http::{header, HeaderValue},
+    middleware,
+    response::Response,
+    Router,
+};
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+use utoipa_swagger_ui::SwaggerUi;
+use crate::auth::API_KEY_HEADER;
+use crate::routes::AppState;
+use crate::{bulk, csv_io, routes};
+
+/// Swagger UI loads its own scripts and styles, and sets inline styles.
+const SWAGGER_UI_CSP: &str = "default-src 'none'; script-src 'self'; \
+                              style-src 'self' 'unsafe-inline'; img-src 'self' data:; \
+                              connect-src 'self'; base-uri 'none'; frame-ancestors 'none'";
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Persons API",
+        description = "Persons in JSON, or in MessagePack, Protobuf or XML by `Accept` and \
+                       `Content-Type`. Changes take the version they expect in `If-Match`."
+    ),
+    paths(
+        routes::persons,
+        routes::count_persons,
+        routes::search_persons,
+        routes::single_person,
+        routes::add_person,
+        routes::update_person,
+        routes::patch_person,
+        routes::delete_person,
+        routes::restore_person,
+        routes::person_history,
+        bulk::bulk_create,
+        bulk::bulk_update,
+        bulk::bulk_delete,
+        csv_io::export_csv,
+    ),
+    modifiers(&Credentials),
+    tags((name = "persons", description = "The person API, version 1"))
+)]
+struct ApiDoc;
+
+/// The credentials `auth::authenticate` takes, which changes need once any
+/// are configured.
+struct Credentials;
+
+impl Modify for Credentials {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new(API_KEY_HEADER))),
+        );
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(
+                HttpBuilder::new().scheme(HttpAuthScheme::Bearer).bearer_format("JWT").build(),
+            ),
+        );
+    }
+}
+
+/// The OpenAPI document at `/api-docs/openapi.json` and Swagger UI at `/docs`.
+pub fn create_docs_routes() -> Router<Arc<AppState>> {
+    Router::from(SwaggerUi::new("/docs").url("/api-docs/openapi.json", ApiDoc::openapi()))
+        .layer(middleware::map_response(swagger_ui_csp))
+}
+
+async fn swagger_ui_csp(mut response: Response) -> Response {
+    response
+        .headers_mut()
+        .insert(header::CONTENT_SECURITY_POLICY, HeaderValue::from_static(SWAGGER_UI_CSP));
+    response
+}
@@ -0,0 +1,254 @@
+use std::time::Duration;
+use async_trait::async_trait;
+use axum::{
+    extract::{MatchedPath, Request},
+    http::HeaderMap,
+    response::Response,
+};
+use chrono::{DateTime, Utc};
+use opentelemetry::propagation::{Extractor, TextMapPropagator};
+use opentelemetry::trace::{TraceError, TracerProvider as _};
+use opentelemetry::{global, KeyValue};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{Tracer, TracerProvider};
+use opentelemetry_sdk::{runtime, Resource};
+use sqlx::migrate::MigrateError;
+use tracing::{field, Instrument, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use uuid::Uuid;
+use crate::config::Config;
+use crate::errors::HttpAppError;
+use crate::listing::{ListQuery, PersonFilter};
+use crate::pagination::Page;
+use crate::person::{NewPerson, Person, PersonKey, PersonPatch};
+use crate::proxy::Client;
+use crate::repository::PersonRepository;
+use crate::request_id::REQUEST_ID_HEADER;
+use crate::search::SearchHit;
+
+/// The tracer exporting spans over OTLP/HTTP to `OTEL_EXPORTER_OTLP_ENDPOINT`,
+/// as `OTEL_SERVICE_NAME` (default the crate name); `None` without an endpoint.
+/// The other standard `OTEL_*` variables apply as well.
+pub fn tracer(config: &Config) -> Result<Option<Tracer>, TraceError> {
+    let Some(endpoint) = config.var("OTEL_EXPORTER_OTLP_ENDPOINT") else {
+        return Ok(None);
+    };
+    let endpoint = format!("{}/v1/traces", endpoint.trim_end_matches('/'));
+    let exporter = SpanExporter::builder().with_http().with_endpoint(endpoint).build()?;
+    let service_name =
+        config.var("OTEL_SERVICE_NAME").unwrap_or(env!("CARGO_PKG_NAME")).to_string();
+    let provider = TracerProvider::builder()
+        .with_batch_exporter(exporter, runtime::Tokio)
+        .with_resource(Resource::new_with_defaults([
+            KeyValue::new("service.name", service_name),
+            KeyValue::new("service.version", env!("CARGO_PKG_VERSION")),
+        ]))
+        .build();
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    let tracer = provider.tracer(env!("CARGO_PKG_NAME"));
+    global::set_tracer_provider(provider);
+    Ok(Some(tracer))
+}
+
+/// Sends the spans still buffered, before the process exits.
+pub fn shutdown() {
+    global::shutdown_tracer_provider();
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|name| name.as_str()).collect()
+    }
+}
+
+/// The span of a request for `TraceLayer`, named after its route and a child
+/// of the trace in its `traceparent` header, if any, and tagged with the request
+/// id. Requests no route matched are named after their method alone.
+pub fn make_span(request: &Request) -> Span {
+    let route = request.extensions().get::<MatchedPath>().map(MatchedPath::as_str);
+    let name = match route {
+        Some(route) => format!("{} {}", request.method(), route),
+        None => request.method().to_string(),
+    };
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok());
+    let client = request.extensions().get::<Client>();
+    let span = tracing::info_span!(
+        "request",
+        method = %request.method(),
+        uri = %request.uri(),
+        version = ?request.version(),
+        request_id = request_id,
+        otel.name = name,
+        otel.kind = "server",
+        http.route = route,
+        http.response.status_code = field::Empty,
+        client.address = client.map(|client| field::display(client.ip)),
+        url.scheme = client.map(|client| field::display(client.scheme)),
+    );
+    let parent = TraceContextPropagator::new().extract(&HeaderExtractor(request.headers()));
+    span.set_parent(parent);
+    span
+}
+
+pub fn record_status(response: &Response, _latency: Duration, span: &Span) {
+    span.record("http.response.status_code", response.status().as_u16());
+}
+
+/// Runs every operation of the inner repository in a span of its own.
+pub struct TracedRepository {
+    inner: Box<dyn PersonRepository>,
+    system: &'static str,
+}
+
+impl TracedRepository {
+    pub fn new(inner: Box<dyn PersonRepository>, system: &'static str) -> Self {
+        TracedRepository { inner, system }
+    }
+
+    fn span(&self, operation: &'static str) -> Span {
+        tracing::info_span!(
+            "repository",
+            otel.name = %format!("repository.{}", operation),
+            otel.kind = "client",
+            db.system = self.system,
+            db.operation = operation,
+        )
+    }
+}
+
+#[async_trait]
+impl PersonRepository for TracedRepository {
+    async fn migrate(&self) -> Result<(), MigrateError> {
+        self.inner.migrate().instrument(self.span("migrate")).await
+    }
+
+    async fn pending_migrations(&self) -> Result<usize, MigrateError> {
+        let span = self.span("pending_migrations");
+        self.inner.pending_migrations().instrument(span).await
+    }
+
+    async fn enforce_unique_names(&self, enabled: bool) -> Result<bool, HttpAppError> {
+        let span = self.span("enforce_unique_names");
+        self.inner.enforce_unique_names(enabled).instrument(span).await
+    }
+
+    async fn close(&self) -> Result<(), HttpAppError> {
+        self.inner.close().instrument(self.span("close")).await
+    }
+
+    async fn list(&self) -> Result<Vec<Person>, HttpAppError> {
+        self.inner.list().instrument(self.span("list")).await
+    }
+
+    async fn list_page(&self, query: &ListQuery) -> Result<Page<Person>, HttpAppError> {
+        self.inner.list_page(query).instrument(self.span("list_page")).await
+    }
+
+    async fn count(&self, filter: &PersonFilter) -> Result<u64, HttpAppError> {
+        self.inner.count(filter).instrument(self.span("count")).await
+    }
+
+    async fn list_after(
+        &self,
+        filter: &PersonFilter,
+        after: Option<u32>,
+        limit: u64,
+    ) -> Result<Vec<Person>, HttpAppError> {
+        let span = self.span("list_after");
+        self.inner.list_after(filter, after, limit).instrument(span).await
+    }
+
+    async fn search(
+        &self,
+        query: &str,
+        fuzzy: bool,
+        limit: usize,
+    ) -> Result<Vec<SearchHit>, HttpAppError> {
+        let span = self.span("search");
+        self.inner.search(query, fuzzy, limit).instrument(span).await
+    }
+
+    async fn get(&self, id: u32) -> Result<Person, HttpAppError> {
+        self.inner.get(id).instrument(self.span("get")).await
+    }
+
+    async fn get_by_uuid(&self, uuid: Uuid) -> Result<Person, HttpAppError> {
+        self.inner.get_by_uuid(uuid).instrument(self.span("get_by_uuid")).await
+    }
+
+    async fn get_by_key(&self, key: PersonKey) -> Result<Person, HttpAppError> {
+        self.inner.get_by_key(key).instrument(self.span("get_by_key")).await
+    }
+
+    async fn resolve_id(&self, key: PersonKey) -> Result<u32, HttpAppError> {
+        self.inner.resolve_id(key).instrument(self.span("resolve_id")).await
+    }
+
+    async fn insert(&self, person: Person) -> Result<(), HttpAppError> {
+        self.inner.insert(person).instrument(self.span("insert")).await
+    }
+
+    async fn next_id(&self) -> Result<u32, HttpAppError> {
+        self.inner.next_id().instrument(self.span("next_id")).await
+    }
+
+    async fn create(&self, person: NewPerson) -> Result<Person, HttpAppError> {
+        self.inner.create(person).instrument(self.span("create")).await
+    }
+
+    async fn update(&self, person: Person) -> Result<(), HttpAppError> {
+        self.inner.update(person).instrument(self.span("update")).await
+    }
+
+    async fn upsert(&self, person: Person) -> Result<bool, HttpAppError> {
+        self.inner.upsert(person).instrument(self.span("upsert")).await
+    }
+
+    async fn patch(&self, id: u32, patch: PersonPatch) -> Result<Person, HttpAppError> {
+        self.inner.patch(id, patch).instrument(self.span("patch")).await
+    }
+
+    async fn set_deleted(
+        &self,
+        id: u32,
+        deleted_at: Option<DateTime<Utc>>,
+    ) -> Result<(), HttpAppError> {
+        let span = self.span("set_deleted");
+        self.inner.set_deleted(id, deleted_at).instrument(span).await
+    }
+
+    async fn delete(&self, id: u32, version: u32) -> Result<(), HttpAppError> {
+        self.inner.delete(id, version).instrument(self.span("delete")).await
+    }
+
+    async fn restore(&self, id: u32) -> Result<Person, HttpAppError> {
+        self.inner.restore(id).instrument(self.span("restore")).await
+    }
+
+    async fn purge(&self, id: u32) -> Result<(), HttpAppError> {
+        self.inner.purge(id).instrument(self.span("purge")).await
+    }
+
+    async fn delete_many(&self, ids: &[u32]) -> Result<u64, HttpAppError> {
+        self.inner.delete_many(ids).instrument(self.span("delete_many")).await
+    }
+
+    async fn delete_matching(&self, filter: &PersonFilter) -> Result<u64, HttpAppError> {
+        let span = self.span("delete_matching");
+        self.inner.delete_matching(filter).instrument(span).await
+    }
+
+    async fn replace_all(&self, persons: Vec<Person>) -> Result<(), HttpAppError> {
+        self.inner.replace_all(persons).instrument(self.span("replace_all")).await
+    }
+}
@@ -0,0 +1,178 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use crate::errors::HttpAppError;
+use crate::listing::PersonFilter;
+use crate::person::Person;
+use crate::repository::PersonRepository;
+use crate::validation::FieldError;
+
+pub const DEFAULT_PER_PAGE: u64 = 20;
+pub const MAX_PER_PAGE: u64 = 100;
+/// Keeps offsets far below what the storage backends can represent.
+pub const MAX_PAGE: u64 = 1_000_000;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PageParams {
+    pub page: Option<u64>,
+    pub per_page: Option<u64>,
+    pub after: Option<String>,
+    pub limit: Option<u64>,
+}
+
+impl PageParams {
+    pub fn is_cursor(&self) -> bool {
+        self.after.is_some() || self.limit.is_some()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PageRequest {
+    pub page: u64,
+    pub per_page: u64,
+}
+
+impl PageRequest {
+    pub fn offset(&self) -> u64 {
+        self.page.saturating_sub(1).saturating_mul(self.per_page)
+    }
+}
+
+impl TryFrom<PageParams> for PageRequest {
+    type Error = HttpAppError;
+
+    fn try_from(params: PageParams) -> Result<Self, Self::Error> {
+        let page = params.page.unwrap_or(1);
+        if page == 0 {
+            return Err(HttpAppError::BadRequest("page starts at 1".to_string()));
+        }
+        if page > MAX_PAGE {
+            return Err(HttpAppError::Validation(vec![FieldError::new(
+                "page",
+                format!("must be at most {}", MAX_PAGE),
+            )]));
+        }
+        let per_page = params.per_page.unwrap_or(DEFAULT_PER_PAGE);
+        if per_page == 0 {
+            return Err(HttpAppError::BadRequest("per_page must be positive".to_string()));
+        }
+        Ok(PageRequest {
+            page,
+            per_page: per_page.min(MAX_PER_PAGE),
+        })
+    }
+}
+
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub total: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CursorRequest {
+    pub after: Option<u32>,
+    pub limit: u64,
+}
+
+impl TryFrom<PageParams> for CursorRequest {
+    type Error = HttpAppError;
+
+    fn try_from(params: PageParams) -> Result<Self, Self::Error> {
+        let after = params.after.as_deref().map(decode_cursor).transpose()?;
+        let limit = params.limit.unwrap_or(DEFAULT_PER_PAGE);
+        if limit == 0 {
+            return Err(HttpAppError::BadRequest("limit must be positive".to_string()));
+        }
+        Ok(CursorRequest {
+            after,
+            limit: limit.min(MAX_PER_PAGE),
+        })
+    }
+}
+
+impl CursorRequest {
+    /// The matching persons of the page, with the cursor of the next page when
+    /// more follow.
+    pub async fn fetch(
+        &self,
+        repository: &dyn PersonRepository,
+        filter: &PersonFilter,
+    ) -> Result<CursorPage<Person>, HttpAppError> {
+        let mut items = repository.list_after(filter, self.after, self.limit + 1).await?;
+        let next_cursor = if items.len() as u64 > self.limit {
+            items.truncate(self.limit as usize);
+            items.last().map(|person| encode_cursor(person.id))
+        } else {
+            None
+        };
+        Ok(CursorPage { items, next_cursor })
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct CursorPage<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<String>,
+}
+
+pub fn encode_cursor(id: u32) -> String {
+    URL_SAFE_NO_PAD.encode(format!("id:{}", id))
+}
+
+pub fn decode_cursor(cursor: &str) -> Result<u32, HttpAppError> {
+    URL_SAFE_NO_PAD
+        .decode(cursor)
+        .ok()
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+        .and_then(|value| value.strip_prefix("id:")?.parse().ok())
+        .ok_or_else(|| HttpAppError::BadRequest("Invalid cursor".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(page: Option<u64>, per_page: Option<u64>) -> Result<PageRequest, HttpAppError> {
+        PageRequest::try_from(PageParams { page, per_page, after: None, limit: None })
+    }
+
+    #[test]
+    fn pages_default_and_clamp() {
+        let request = page(None, None).unwrap();
+        assert_eq!((request.page, request.per_page, request.offset()), (1, DEFAULT_PER_PAGE, 0));
+        let request = page(Some(3), Some(1000)).unwrap();
+        assert_eq!((request.per_page, request.offset()), (MAX_PER_PAGE, 2 * MAX_PER_PAGE));
+    }
+
+    #[test]
+    fn rejects_pages_out_of_range() {
+        assert!(matches!(page(Some(0), None), Err(HttpAppError::BadRequest(_))));
+        assert!(matches!(page(None, Some(0)), Err(HttpAppError::BadRequest(_))));
+        assert!(page(Some(MAX_PAGE), None).is_ok());
+        assert!(matches!(page(Some(MAX_PAGE + 1), None), Err(HttpAppError::Validation(_))));
+        assert!(matches!(page(Some(u64::MAX), None), Err(HttpAppError::Validation(_))));
+    }
+
+    #[test]
+    fn offset_saturates() {
+        let request = PageRequest { page: u64::MAX, per_page: u64::MAX };
+        assert_eq!(request.offset(), u64::MAX);
+    }
+
+    #[test]
+    fn cursors_round_trip() {
+        assert_eq!(decode_cursor(&encode_cursor(42)).unwrap(), 42);
+        assert!(decode_cursor("not a cursor").is_err());
+        assert!(decode_cursor(&URL_SAFE_NO_PAD.encode("id:x")).is_err());
+    }
+
+    #[test]
+    fn cursor_limits_default_and_clamp() {
+        let params = |limit| PageParams { page: None, per_page: None, after: None, limit };
+        assert_eq!(CursorRequest::try_from(params(None)).unwrap().limit, DEFAULT_PER_PAGE);
+        assert_eq!(CursorRequest::try_from(params(Some(1000))).unwrap().limit, MAX_PER_PAGE);
+        assert!(CursorRequest::try_from(params(Some(0))).is_err());
+    }
+}
@@ -1,12 +1,123 @@
-use chrono::NaiveDate;
-use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Deserializer, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+use crate::errors::HttpAppError;
 
-#[derive(Clone, Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
 pub struct Person {
     pub id: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uuid: Option<Uuid>,
     pub name: String,
     pub age: u8,
     pub date: NaiveDate,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime<Utc>>,
+    /// Starts at 1 and grows with every change. In an update it is the
+    /// version the change expects, where 0 skips the check.
+    #[serde(default)]
+    pub version: u32,
+}
+
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct NewPerson {
+    #[serde(skip)]
+    pub uuid: Option<Uuid>,
+    pub name: String,
+    pub age: u8,
+    pub date: NaiveDate,
+}
+
+#[derive(Clone, Default, Deserialize, ToSchema)]
+pub struct PersonPatch {
+    pub name: Option<String>,
+    pub age: Option<u8>,
+    pub date: Option<NaiveDate>,
+    pub version: Option<u32>,
+}
+
+impl Person {
+    pub fn check_version(&self, expected: u32) -> Result<(), HttpAppError> {
+        if expected != 0 && expected != self.version {
+            return Err(HttpAppError::PreconditionFailed);
+        }
+        Ok(())
+    }
+}
+
+impl PersonPatch {
+    pub fn apply(self, person: &mut Person) {
+        if let Some(name) = self.name {
+            person.name = name;
+        }
+        if let Some(age) = self.age {
+            person.age = age;
+        }
+        if let Some(date) = self.date {
+            person.date = date;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IdStrategy {
+    Sequential,
+    Uuid,
+}
+
+impl FromStr for IdStrategy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "sequential" => Ok(IdStrategy::Sequential),
+            "uuid" => Ok(IdStrategy::Uuid),
+            other => Err(format!("Unknown ID_STRATEGY '{}', expected sequential or uuid", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum PersonKey {
+    Id(u32),
+    Uuid(Uuid),
+}
+
+impl FromStr for PersonKey {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if let Ok(id) = value.parse() {
+            return Ok(PersonKey::Id(id));
+        }
+        Uuid::parse_str(value)
+            .map(PersonKey::Uuid)
+            .map_err(|_| format!("'{}' is neither a numeric id nor a UUID", value))
+    }
+}
+
+impl<'de> Deserialize<'de> for PersonKey {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        value.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl NewPerson {
+    pub fn with_id(self, id: u32) -> Person {
+        Person {
+            id,
+            uuid: self.uuid,
+            name: self.name,
+            age: self.age,
+            date: self.date,
+            deleted_at: None,
+            version: 1,
+        }
+    }
 }
 
 impl std::fmt::Display for Person {
@@ -19,15 +130,21 @@ pub fn create_person_collection() -> Vec<Person> {
     vec![
         Person {
             id: 1,
+            uuid: None,
             name: "Mario".to_string(),
             age: 43,
             date: NaiveDate::from_ymd_opt(1981, 2, 21).unwrap(),
+            deleted_at: None,
+            version: 1,
         },
         Person {
             id: 2,
+            uuid: None,
             name: "Luigi".to_string(),
             age: 41,
             date: NaiveDate::from_ymd_opt(1983, 3, 25).unwrap(),
+            deleted_at: None,
+            version: 1,
         },
     ]
 }
@@ -0,0 +1,50 @@
+use async_trait::async_trait;
+use sqlx::migrate::Migrator;
+use sqlx::postgres::{PgPool, PgPoolOptions, PgRow, Postgres};
+use sqlx::{Error, FromRow, QueryBuilder};
+use crate::migrations;
+use crate::sql::{Dialect, SqlRepository};
+use crate::storage::PoolSettings;
+
+pub type PostgresRepository = SqlRepository<Postgres>;
+
+#[async_trait]
+impl Dialect for Postgres {
+    fn migrator() -> &'static Migrator {
+        &migrations::POSTGRES
+    }
+
+    async fn fetch_all<T>(pool: &PgPool, mut query: QueryBuilder<'_, Postgres>) -> Result<Vec<T>, Error>
+    where
+        T: for<'r> FromRow<'r, PgRow> + Send + Unpin,
+    {
+        query.build_query_as().fetch_all(pool).await
+    }
+
+    async fn fetch_optional<T>(
+        pool: &PgPool,
+        mut query: QueryBuilder<'_, Postgres>,
+    ) -> Result<Option<T>, Error>
+    where
+        T: for<'r> FromRow<'r, PgRow> + Send + Unpin,
+    {
+        query.build_query_as().fetch_optional(pool).await
+    }
+
+    async fn execute(pool: &PgPool, mut query: QueryBuilder<'_, Postgres>) -> Result<u64, Error> {
+        Ok(query.build().execute(pool).await?.rows_affected())
+    }
+}
+
+impl SqlRepository<Postgres> {
+    pub async fn connect(url: &str, settings: PoolSettings) -> Result<Self, Error> {
+        let pool = PgPoolOptions::new()
+            .max_connections(settings.max_connections)
+            .min_connections(settings.min_connections)
+            .acquire_timeout(settings.acquire_timeout)
+            .idle_timeout(settings.idle_timeout)
+            .connect(url)
+            .await?;
+        Ok(SqlRepository::new(pool))
+    }
+}
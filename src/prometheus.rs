@@ -0,0 +1,106 @@
+use std::sync::Arc;
+use std::time::Instant;
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::header,
+    middleware::Next,
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use metrics::{counter, describe_counter, describe_gauge, describe_histogram, gauge, histogram};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use metrics_process::Collector;
+use crate::config::Config;
+use crate::errors::{ConfigError, HttpAppError};
+use crate::listing::PersonFilter;
+use crate::routes::AppState;
+
+/// Latency buckets in seconds, from a cache hit to a slow database query.
+const DEFAULT_LATENCY_BUCKETS: &[f64] =
+    &[0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0];
+
+/// The route label of requests no route matched, so that scanners probing
+/// random paths do not create a series each.
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+/// The upper bounds in seconds of the latency histogram buckets, from
+/// `HTTP_LATENCY_BUCKETS` as a comma separated ascending list.
+pub fn latency_buckets(config: &Config) -> Result<Vec<f64>, ConfigError> {
+    let Some(value) = config.var("HTTP_LATENCY_BUCKETS") else {
+        return Ok(DEFAULT_LATENCY_BUCKETS.to_vec());
+    };
+    let invalid = || {
+        ConfigError::Invalid(format!(
+            "HTTP_LATENCY_BUCKETS must be ascending positive seconds, got {}",
+            value
+        ))
+    };
+    let buckets = value
+        .split(',')
+        .map(|bucket| bucket.trim().parse::<f64>().map_err(|_| invalid()))
+        .collect::<Result<Vec<f64>, ConfigError>>()?;
+    let positive = buckets.iter().all(|bucket| bucket.is_finite() && *bucket > 0.0);
+    let ascending = buckets.windows(2).all(|pair| pair[0] < pair[1]);
+    if !positive || !ascending {
+        return Err(invalid());
+    }
+    Ok(buckets)
+}
+
+/// Installs the Prometheus recorder behind the `metrics` macros, along with
+/// the process metrics.
+pub fn install(latency_buckets: &[f64]) -> PrometheusHandle {
+    let handle = PrometheusBuilder::new()
+        .set_buckets_for_metric(
+            Matcher::Full("http_request_duration_seconds".to_string()),
+            latency_buckets,
+        )
+        .unwrap()
+        .install_recorder()
+        .unwrap();
+    Collector::default().describe();
+    describe_counter!("http_requests_total", "Requests by method, route and status");
+    describe_histogram!("http_request_duration_seconds", "Time to answer requests");
+    describe_counter!("http_requests_shed_total", "Requests turned away while overloaded");
+    describe_gauge!("persons", "Persons in the collection");
+    describe_counter!("failed_logins_total", "Logins refused for a wrong password");
+    describe_counter!("login_lockouts_total", "Lockouts of an account or address");
+    handle
+}
+
+/// Counts requests and times them by method, route template and status.
+pub async fn track(request: Request, next: Next) -> Response {
+    let started = Instant::now();
+    let method = request.method().to_string();
+    let route = match request.extensions().get::<MatchedPath>() {
+        Some(path) => path.as_str().to_string(),
+        None => UNMATCHED_ROUTE.to_string(),
+    };
+    let response = next.run(request).await;
+    let labels = [
+        ("method", method),
+        ("route", route),
+        ("status", response.status().as_u16().to_string()),
+    ];
+    counter!("http_requests_total", &labels).increment(1);
+    histogram!("http_request_duration_seconds", &labels).record(started.elapsed().as_secs_f64());
+    response
+}
+
+pub fn create_metrics_routes() -> Router<Arc<AppState>> {
+    Router::new().route("/metrics", get(metrics))
+}
+
+/// Samples the gauges that are cheap enough to read on each scrape, then
+/// renders everything in the Prometheus text format.
+async fn metrics(State(state): State<Arc<AppState>>) -> Result<Response, HttpAppError> {
+    let persons = state.person_repository.count(&PersonFilter::default()).await?;
+    gauge!("persons").set(persons as f64);
+    let logins = state.login_guard.stats();
+    counter!("failed_logins_total").absolute(logins.failed_logins_total);
+    counter!("login_lockouts_total").absolute(logins.lockouts_total);
+    Collector::default().collect();
+    let content_type = [(header::CONTENT_TYPE, "text/plain; version=0.0.4")];
+    Ok((content_type, state.metrics.render()).into_response())
+}
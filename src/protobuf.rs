@@ -0,0 +1,170 @@
+use chrono::{DateTime, NaiveDate, SecondsFormat, Utc};
+use prost::Message;
+use uuid::Uuid;
+use crate::bulk::{BulkDeleteRequest, BulkDeleteResponse, BulkResponse};
+use crate::errors::HttpAppError;
+use crate::fields::Sparse;
+use crate::history::Revision;
+use crate::jobs::ImportJob;
+use crate::links::Linked;
+use crate::pagination::CursorPage;
+use crate::person::{NewPerson, Person, PersonPatch};
+use crate::routes::PersonCount;
+use crate::search::SearchHit;
+use crate::validation::FieldError;
+
+/// The messages of `proto/persons.proto`.
+pub mod proto {
+    include!(concat!(env!("OUT_DIR"), "/persons.v1.rs"));
+}
+
+impl From<&Person> for proto::Person {
+    fn from(person: &Person) -> Self {
+        proto::Person {
+            id: person.id,
+            uuid: person.uuid.map(|uuid| uuid.to_string()),
+            name: person.name.clone(),
+            age: u32::from(person.age),
+            date: person.date.to_string(),
+            deleted_at: person
+                .deleted_at
+                .map(|deleted_at| deleted_at.to_rfc3339_opts(SecondsFormat::AutoSi, true)),
+            version: person.version,
+        }
+    }
+}
+
+fn invalid(field: &str, message: impl Into<String>) -> HttpAppError {
+    HttpAppError::Validation(vec![FieldError::new(field, message)])
+}
+
+fn age(age: u32) -> Result<u8, HttpAppError> {
+    u8::try_from(age).map_err(|_| invalid("age", format!("{} is out of range", age)))
+}
+
+fn date(date: &str) -> Result<NaiveDate, HttpAppError> {
+    date.parse().map_err(|e| invalid("date", format!("{}", e)))
+}
+
+impl TryFrom<proto::NewPerson> for NewPerson {
+    type Error = HttpAppError;
+
+    fn try_from(person: proto::NewPerson) -> Result<Self, Self::Error> {
+        Ok(NewPerson {
+            uuid: None,
+            age: age(person.age)?,
+            date: date(&person.date)?,
+            name: person.name,
+        })
+    }
+}
+
+impl TryFrom<proto::Person> for Person {
+    type Error = HttpAppError;
+
+    fn try_from(person: proto::Person) -> Result<Self, Self::Error> {
+        let uuid = match person.uuid {
+            Some(uuid) => Some(Uuid::parse_str(&uuid).map_err(|e| invalid("uuid", e.to_string()))?),
+            None => None,
+        };
+        let deleted_at = match person.deleted_at {
+            Some(deleted_at) => Some(
+                DateTime::parse_from_rfc3339(&deleted_at)
+                    .map_err(|e| invalid("deleted_at", e.to_string()))?
+                    .with_timezone(&Utc),
+            ),
+            None => None,
+        };
+        Ok(Person {
+            id: person.id,
+            uuid,
+            age: age(person.age)?,
+            date: date(&person.date)?,
+            name: person.name,
+            deleted_at,
+            version: person.version,
+        })
+    }
+}
+
+/// A response body with a Protobuf message. The others are answered in JSON
+/// even when a client asks for Protobuf, as HTTP allows.
+pub trait ToProtobuf {
+    fn to_protobuf(&self) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+impl ToProtobuf for Linked<Person> {
+    fn to_protobuf(&self) -> Option<Vec<u8>> {
+        Some(proto::Person::from(&self.item).encode_to_vec())
+    }
+}
+
+/// Protobuf persons always have every field; `?fields=` only narrows the other
+/// formats.
+impl ToProtobuf for Linked<Sparse<'_>> {
+    fn to_protobuf(&self) -> Option<Vec<u8>> {
+        Some(proto::Person::from(&self.item.person).encode_to_vec())
+    }
+}
+
+impl ToProtobuf for Vec<Linked<Sparse<'_>>> {
+    fn to_protobuf(&self) -> Option<Vec<u8>> {
+        let persons = self.iter().map(|person| proto::Person::from(&person.item.person));
+        let list = proto::PersonList { persons: persons.collect(), next_cursor: None };
+        Some(list.encode_to_vec())
+    }
+}
+
+impl ToProtobuf for Linked<CursorPage<Linked<Sparse<'_>>>> {
+    fn to_protobuf(&self) -> Option<Vec<u8>> {
+        let persons = self.item.items.iter().map(|person| proto::Person::from(&person.item.person));
+        let list = proto::PersonList {
+            persons: persons.collect(),
+            next_cursor: self.item.next_cursor.clone(),
+        };
+        Some(list.encode_to_vec())
+    }
+}
+
+impl ToProtobuf for PersonCount {}
+
+impl ToProtobuf for Vec<SearchHit> {}
+
+impl ToProtobuf for Vec<Revision> {}
+
+impl ToProtobuf for BulkResponse {}
+
+impl ToProtobuf for BulkDeleteResponse {}
+
+impl ToProtobuf for ImportJob {}
+
+/// A request body with a Protobuf message; `None` for the others.
+pub trait FromProtobuf: Sized {
+    fn from_protobuf(_bytes: &[u8]) -> Option<Result<Self, HttpAppError>> {
+        None
+    }
+}
+
+fn decode<M: Message + Default>(bytes: &[u8]) -> Result<M, HttpAppError> {
+    M::decode(bytes).map_err(|e| HttpAppError::BadRequest(format!("Invalid Protobuf: {}", e)))
+}
+
+impl FromProtobuf for NewPerson {
+    fn from_protobuf(bytes: &[u8]) -> Option<Result<Self, HttpAppError>> {
+        Some(decode::<proto::NewPerson>(bytes).and_then(NewPerson::try_from))
+    }
+}
+
+impl FromProtobuf for Person {
+    fn from_protobuf(bytes: &[u8]) -> Option<Result<Self, HttpAppError>> {
+        Some(decode::<proto::Person>(bytes).and_then(Person::try_from))
+    }
+}
+
+impl FromProtobuf for PersonPatch {}
+
+impl FromProtobuf for BulkDeleteRequest {}
+
+impl<T> FromProtobuf for Vec<T> {}
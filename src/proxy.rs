@@ -0,0 +1,276 @@
+use std::fmt;
+use std::iter;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{header, HeaderMap},
+    middleware::Next,
+    response::Response,
+};
+use ipnet::IpNet;
+use crate::config::Config;
+use crate::errors::ConfigError;
+use crate::routes::AppState;
+
+pub const FORWARDED_FOR_HEADER: &str = "X-Forwarded-For";
+pub const FORWARDED_PROTO_HEADER: &str = "X-Forwarded-Proto";
+
+/// Parses a comma separated list of CIDR ranges, where a bare address stands
+/// for itself alone.
+pub fn parse_nets(name: &str, value: &str) -> Result<Vec<IpNet>, ConfigError> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|net| !net.is_empty())
+        .map(|net| {
+            net.parse()
+                .or_else(|_| net.parse::<IpAddr>().map(IpNet::from))
+                .map_err(|_| ConfigError::Invalid(format!("{} has an invalid range {}", name, net)))
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scheme {
+    Http,
+    Https,
+}
+
+impl Scheme {
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "http" => Some(Scheme::Http),
+            "https" => Some(Scheme::Https),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Scheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Scheme::Http => "http",
+            Scheme::Https => "https",
+        })
+    }
+}
+
+/// Who sent a request and whether over HTTPS, behind any trusted proxies;
+/// attached to every request as an extension by `resolve`.
+#[derive(Debug, Clone, Copy)]
+pub struct Client {
+    pub ip: IpAddr,
+    pub scheme: Scheme,
+}
+
+/// What a proxy recorded about the connection it received.
+struct Hop {
+    ip: Option<IpAddr>,
+    scheme: Option<Scheme>,
+}
+
+/// The reverse proxies whose `Forwarded`, `X-Forwarded-For` and
+/// `X-Forwarded-Proto` headers are believed.
+#[derive(Default)]
+pub struct TrustedProxies {
+    nets: Vec<IpNet>,
+}
+
+impl TrustedProxies {
+    /// Reads the CIDR ranges of `TRUSTED_PROXIES`; none are trusted by default.
+    pub fn from_config(config: &Config) -> Result<Self, ConfigError> {
+        let nets = match config.var("TRUSTED_PROXIES") {
+            Some(value) => parse_nets("TRUSTED_PROXIES", value)?,
+            None => Vec::new(),
+        };
+        Ok(TrustedProxies { nets })
+    }
+
+    fn trusts(&self, ip: IpAddr) -> bool {
+        self.nets.iter().any(|net| net.contains(&ip))
+    }
+
+    /// The client behind `peer`: the last hop that was not added by a trusted
+    /// proxy, with the scheme the proxy in front of it received. Hops further
+    /// left were written by the client and could be anything.
+    pub fn client(&self, peer: IpAddr, scheme: Scheme, headers: &HeaderMap) -> Client {
+        let mut client = Client { ip: peer.to_canonical(), scheme };
+        if !self.trusts(client.ip) {
+            return client;
+        }
+        for hop in hops(headers) {
+            let Some(ip) = hop.ip else {
+                break;
+            };
+            client = Client {
+                ip: ip.to_canonical(),
+                scheme: hop.scheme.unwrap_or(client.scheme),
+            };
+            if !self.trusts(client.ip) {
+                break;
+            }
+        }
+        client
+    }
+}
+
+/// The hops, nearest first, of the standard `Forwarded` header when there is
+/// one, otherwise those of `X-Forwarded-For`. Each proxy appends to
+/// `X-Forwarded-Proto` as well, if at all, so the two line up from the right.
+fn hops(headers: &HeaderMap) -> Vec<Hop> {
+    let values = |name| {
+        headers
+            .get_all(name)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .rev()
+            .collect::<Vec<_>>()
+    };
+    let forwarded = values(header::FORWARDED.as_str());
+    if !forwarded.is_empty() {
+        return forwarded.into_iter().map(forwarded_hop).collect();
+    }
+    let protos = values(FORWARDED_PROTO_HEADER).into_iter().map(Scheme::parse);
+    values(FORWARDED_FOR_HEADER)
+        .into_iter()
+        .zip(protos.chain(iter::repeat(None)))
+        .map(|(hop, scheme)| Hop { ip: parse_node(hop.trim()), scheme })
+        .collect()
+}
+
+/// One element of `Forwarded`, such as `for="[2001:db8::17]:4711";proto=https`.
+fn forwarded_hop(element: &str) -> Hop {
+    let mut hop = Hop { ip: None, scheme: None };
+    for pair in element.split(';') {
+        let Some((key, value)) = pair.split_once('=') else {
+            continue;
+        };
+        let value = value.trim().trim_matches('"');
+        match key.trim().to_ascii_lowercase().as_str() {
+            "for" => hop.ip = parse_node(value),
+            "proto" => hop.scheme = Scheme::parse(value),
+            _ => {}
+        }
+    }
+    hop
+}
+
+/// An address with or without a port; `unknown` and obfuscated identifiers
+/// are not addresses and end the walk through the hops.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    if let Some(bracketed) = node.strip_prefix('[') {
+        return bracketed.split_once(']')?.0.parse().ok();
+    }
+    node.parse()
+        .ok()
+        .or_else(|| node.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+}
+
+/// Finds the client of each request once, so that the logs, the rate limits,
+/// the IP filter and the login guard agree on it. The TLS listener marks its
+/// requests as HTTPS.
+pub async fn resolve(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let scheme = request.extensions().get::<Scheme>().copied().unwrap_or(Scheme::Http);
+    let client = state.trusted_proxies.client(peer.ip(), scheme, request.headers());
+    request.extensions_mut().insert(client);
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn proxies(nets: &str) -> TrustedProxies {
+        TrustedProxies::from_config(&Config::from_overrides(&[("TRUSTED_PROXIES", nets)])).unwrap()
+    }
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn the_client_is_the_last_untrusted_hop() {
+        let proxies = proxies("10.0.0.0/8");
+        let spoofed = headers(&[(FORWARDED_FOR_HEADER, "6.6.6.6, 203.0.113.7, 10.0.0.2")]);
+        let client = proxies.client(ip("10.0.0.1"), Scheme::Http, &spoofed);
+        assert_eq!(client.ip, ip("203.0.113.7"));
+
+        let appended = headers(&[
+            (FORWARDED_FOR_HEADER, "6.6.6.6"),
+            (FORWARDED_FOR_HEADER, "203.0.113.7"),
+        ]);
+        assert_eq!(proxies.client(ip("10.0.0.1"), Scheme::Http, &appended).ip, ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn untrusted_peers_are_the_client() {
+        let proxies = proxies("10.0.0.0/8");
+        let forged = headers(&[
+            (FORWARDED_FOR_HEADER, "10.0.0.3"),
+            (FORWARDED_PROTO_HEADER, "https"),
+        ]);
+        let client = proxies.client(ip("203.0.113.7"), Scheme::Http, &forged);
+        assert_eq!((client.ip, client.scheme), (ip("203.0.113.7"), Scheme::Http));
+
+        let nobody = TrustedProxies::default();
+        assert_eq!(nobody.client(ip("10.0.0.1"), Scheme::Http, &forged).ip, ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn proxies_tell_the_scheme_they_received() {
+        let proxies = proxies("10.0.0.1, 10.0.0.2");
+        let forwarded = headers(&[
+            (FORWARDED_FOR_HEADER, "203.0.113.7, 10.0.0.2"),
+            (FORWARDED_PROTO_HEADER, "https, http"),
+        ]);
+        let client = proxies.client(ip("10.0.0.1"), Scheme::Http, &forwarded);
+        assert_eq!((client.ip, client.scheme), (ip("203.0.113.7"), Scheme::Https));
+    }
+
+    #[test]
+    fn forwarded_takes_precedence() {
+        let proxies = proxies("10.0.0.0/8");
+        let forwarded = headers(&[
+            ("forwarded", "for=6.6.6.6, for=\"[2001:db8::17]:4711\";proto=https"),
+            (FORWARDED_FOR_HEADER, "198.51.100.1"),
+        ]);
+        let client = proxies.client(ip("10.0.0.1"), Scheme::Http, &forwarded);
+        assert_eq!((client.ip, client.scheme), (ip("2001:db8::17"), Scheme::Https));
+
+        let unknown = headers(&[("forwarded", "for=203.0.113.7, for=unknown")]);
+        assert_eq!(proxies.client(ip("10.0.0.1"), Scheme::Http, &unknown).ip, ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn mapped_addresses_are_canonical() {
+        let proxies = proxies("10.0.0.0/8");
+        let forwarded = headers(&[(FORWARDED_FOR_HEADER, "203.0.113.7:8080")]);
+        let client = proxies.client(ip("::ffff:10.0.0.1"), Scheme::Http, &forwarded);
+        assert_eq!(client.ip, ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn ranges_are_cidrs_or_addresses() {
+        let nets = parse_nets("NETS", "10.0.0.0/8, 192.0.2.1,,2001:db8::/32").unwrap();
+        assert_eq!(nets.len(), 3);
+        assert!(nets[1].contains(&ip("192.0.2.1")) && !nets[1].contains(&ip("192.0.2.2")));
+        assert!(parse_nets("NETS", "10.0.0.0/33").is_err());
+        assert!(parse_nets("NETS", "localhost").is_err());
+    }
+}
@@ -0,0 +1,379 @@
+use std::iter::Peekable;
+use std::str::{Chars, FromStr};
+use chrono::NaiveDate;
+use serde::{de, Deserialize, Deserializer};
+use crate::person::Person;
+
+/// Deepest nesting of `not` and parentheses a filter may use.
+const MAX_DEPTH: usize = 32;
+/// Most comparisons a filter may join with `and` and `or`.
+const MAX_TERMS: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Id,
+    Name,
+    Age,
+    Date,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Eq,
+    Ne,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+    /// Case-insensitive substring match on text.
+    Contains,
+}
+
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub enum Value {
+    Int(i64),
+    Text(String),
+    Date(NaiveDate),
+}
+
+/// A parsed `?filter=` expression such as `age > 30 and name ~ "Ann"`. Text
+/// compares case-insensitively on every backend, as MySQL does by default.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare(Field, Op, Value),
+}
+
+impl Field {
+    pub fn column(self) -> &'static str {
+        match self {
+            Field::Id => "id",
+            Field::Name => "name",
+            Field::Age => "age",
+            Field::Date => "date",
+        }
+    }
+
+    fn value_of(self, person: &Person) -> Value {
+        match self {
+            Field::Id => Value::Int(i64::from(person.id)),
+            Field::Name => Value::Text(person.name.clone()),
+            Field::Age => Value::Int(i64::from(person.age)),
+            Field::Date => Value::Date(person.date),
+        }
+    }
+
+    fn parse_value(self, literal: Token) -> Result<Value, String> {
+        let (Token::Word(text) | Token::Text(text)) = literal else {
+            return Err(format!("expected a value for '{}'", self.column()));
+        };
+        let value = match self {
+            Field::Id => text.parse::<u32>().ok().map(|id| Value::Int(id.into())),
+            Field::Age => text.parse::<u8>().ok().map(|age| Value::Int(age.into())),
+            Field::Date => text.parse().ok().map(Value::Date),
+            Field::Name => Some(Value::Text(text.clone())),
+        };
+        value.ok_or_else(|| format!("'{}' is not a valid {}", text, self.column()))
+    }
+}
+
+impl Op {
+    pub fn sql(self) -> &'static str {
+        match self {
+            Op::Eq => "=",
+            Op::Ne => "<>",
+            Op::Gt => ">",
+            Op::Ge => ">=",
+            Op::Lt => "<",
+            Op::Le => "<=",
+            Op::Contains => "LIKE",
+        }
+    }
+}
+
+impl Expr {
+    pub fn matches(&self, person: &Person) -> bool {
+        match self {
+            Expr::And(left, right) => left.matches(person) && right.matches(person),
+            Expr::Or(left, right) => left.matches(person) || right.matches(person),
+            Expr::Not(expr) => !expr.matches(person),
+            Expr::Compare(field, op, value) => {
+                let (actual, value) = match (field.value_of(person), value) {
+                    (Value::Text(actual), Value::Text(value)) => {
+                        (Value::Text(actual.to_lowercase()), Value::Text(value.to_lowercase()))
+                    }
+                    (actual, value) => (actual, value.clone()),
+                };
+                match (op, &actual, &value) {
+                    (Op::Contains, Value::Text(actual), Value::Text(value)) => {
+                        actual.contains(value.as_str())
+                    }
+                    (Op::Contains, _, _) => false,
+                    (Op::Eq, _, _) => actual == value,
+                    (Op::Ne, _, _) => actual != value,
+                    (Op::Gt, _, _) => actual > value,
+                    (Op::Ge, _, _) => actual >= value,
+                    (Op::Lt, _, _) => actual < value,
+                    (Op::Le, _, _) => actual <= value,
+                }
+            }
+        }
+    }
+}
+
+impl FromStr for Expr {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        parse(input).map_err(|e| format!("invalid filter: {}", e))
+    }
+}
+
+impl<'de> Deserialize<'de> for Expr {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(de::Error::custom)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Text(String),
+    Op(Op),
+    Open,
+    Close,
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' | ')' => {
+                chars.next();
+                tokens.push(if c == '(' { Token::Open } else { Token::Close });
+            }
+            '"' => {
+                chars.next();
+                tokens.push(Token::Text(quoted(&mut chars)?));
+            }
+            '=' | '!' | '<' | '>' | '~' => {
+                chars.next();
+                let or_equal = chars.next_if_eq(&'=').is_some();
+                let op = match (c, or_equal) {
+                    ('=', false) => Op::Eq,
+                    ('!', true) => Op::Ne,
+                    ('<', false) => Op::Lt,
+                    ('<', true) => Op::Le,
+                    ('>', false) => Op::Gt,
+                    ('>', true) => Op::Ge,
+                    ('~', false) => Op::Contains,
+                    _ => return Err(format!("unknown operator near '{}'", c)),
+                };
+                tokens.push(Token::Op(op));
+            }
+            c if is_word_char(c) => {
+                let mut word = String::new();
+                while let Some(c) = chars.next_if(|&c| is_word_char(c)) {
+                    word.push(c);
+                }
+                tokens.push(Token::Word(word));
+            }
+            other => return Err(format!("unexpected character '{}'", other)),
+        }
+    }
+    Ok(tokens)
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '-' | '.')
+}
+
+fn quoted(chars: &mut Peekable<Chars>) -> Result<String, String> {
+    let mut text = String::new();
+    loop {
+        match chars.next() {
+            Some('"') => return Ok(text),
+            Some('\\') => text.push(chars.next().ok_or("unterminated string")?),
+            Some(c) => text.push(c),
+            None => return Err("unterminated string".to_string()),
+        }
+    }
+}
+
+fn parse(input: &str) -> Result<Expr, String> {
+    let mut parser = Parser {
+        tokens: tokenize(input)?.into_iter().peekable(),
+        depth: 0,
+        terms: 0,
+    };
+    let expr = parser.or()?;
+    match parser.tokens.next() {
+        None => Ok(expr),
+        Some(token) => Err(format!("unexpected {:?}", token)),
+    }
+}
+
+struct Parser {
+    tokens: Peekable<std::vec::IntoIter<Token>>,
+    depth: usize,
+    terms: usize,
+}
+
+impl Parser {
+    fn keyword(&mut self, keyword: &str) -> bool {
+        let is_keyword = |token: &Token| {
+            matches!(token, Token::Word(word) if word.eq_ignore_ascii_case(keyword))
+        };
+        self.tokens.next_if(is_keyword).is_some()
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+        while self.keyword("or") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.unary()?;
+        while self.keyword("and") {
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err("filter is nested too deeply".to_string());
+        }
+        let expr = if self.keyword("not") {
+            Expr::Not(Box::new(self.unary()?))
+        } else if self.tokens.next_if_eq(&Token::Open).is_some() {
+            let expr = self.or()?;
+            if self.tokens.next_if_eq(&Token::Close).is_none() {
+                return Err("missing ')'".to_string());
+            }
+            expr
+        } else {
+            self.comparison()?
+        };
+        self.depth -= 1;
+        Ok(expr)
+    }
+
+    fn comparison(&mut self) -> Result<Expr, String> {
+        self.terms += 1;
+        if self.terms > MAX_TERMS {
+            return Err(format!("filter has more than {} comparisons", MAX_TERMS));
+        }
+        let field = match self.tokens.next() {
+            Some(Token::Word(word)) => match word.to_lowercase().as_str() {
+                "id" => Field::Id,
+                "name" => Field::Name,
+                "age" => Field::Age,
+                "date" => Field::Date,
+                _ => return Err(format!("unknown field '{}'", word)),
+            },
+            Some(token) => return Err(format!("expected a field, found {:?}", token)),
+            None => return Err("expected a field".to_string()),
+        };
+        let op = match self.tokens.next() {
+            Some(Token::Op(op)) => op,
+            _ => return Err(format!("expected an operator after '{}'", field.column())),
+        };
+        if op == Op::Contains && field != Field::Name {
+            return Err(format!("'~' does not apply to '{}'", field.column()));
+        }
+        let literal = self.tokens.next().ok_or("expected a value")?;
+        Ok(Expr::Compare(field, op, field.parse_value(literal)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::person::create_person_collection;
+
+    fn compare(field: Field, op: Op, value: Value) -> Expr {
+        Expr::Compare(field, op, value)
+    }
+
+    fn name_is(name: &str) -> Expr {
+        compare(Field::Name, Op::Eq, Value::Text(name.to_string()))
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let expr: Expr = "name = a or name = b and not name = c".parse().unwrap();
+        let and = Expr::And(Box::new(name_is("b")), Box::new(Expr::Not(Box::new(name_is("c")))));
+        assert_eq!(expr, Expr::Or(Box::new(name_is("a")), Box::new(and)));
+
+        let expr: Expr = "(name = a OR name = b) AND age >= 18".parse().unwrap();
+        let or = Expr::Or(Box::new(name_is("a")), Box::new(name_is("b")));
+        let adult = compare(Field::Age, Op::Ge, Value::Int(18));
+        assert_eq!(expr, Expr::And(Box::new(or), Box::new(adult)));
+    }
+
+    #[test]
+    fn quoted_values_keep_spaces_and_escapes() {
+        let expr: Expr = r#"name = "Ann \"the\" Smith""#.parse().unwrap();
+        assert_eq!(expr, name_is(r#"Ann "the" Smith"#));
+        let expr: Expr = r#"name ~ "and or""#.parse().unwrap();
+        assert_eq!(expr, compare(Field::Name, Op::Contains, Value::Text("and or".to_string())));
+    }
+
+    #[test]
+    fn invalid_filters_are_rejected() {
+        for filter in [
+            "",
+            "height > 3",
+            "age > old",
+            "age > 300",
+            "age ~ 3",
+            "date = 2020-13-01",
+            "name = \"open",
+            "(age > 3",
+            "age > 3)",
+            "age >> 3",
+            "age > 3 and",
+            "name == a",
+            "name = a $",
+        ] {
+            assert!(filter.parse::<Expr>().is_err(), "{}", filter);
+        }
+    }
+
+    #[test]
+    fn filters_are_limited() {
+        let nested = format!("{}age > 3{}", "(".repeat(MAX_DEPTH), ")".repeat(MAX_DEPTH));
+        assert!(nested.parse::<Expr>().is_err());
+        let nested = format!("{}age > 3", "not ".repeat(MAX_DEPTH));
+        assert!(nested.parse::<Expr>().is_err());
+
+        let terms = |count| vec!["age > 3"; count].join(" or ");
+        assert!(terms(MAX_TERMS).parse::<Expr>().is_ok());
+        assert!(terms(MAX_TERMS + 1).parse::<Expr>().is_err());
+    }
+
+    #[test]
+    fn text_matches_regardless_of_case() {
+        let persons = create_person_collection();
+        let matching = |filter: &str| {
+            let expr: Expr = filter.parse().unwrap();
+            persons.iter().filter(|person| expr.matches(person)).count()
+        };
+        assert_eq!(matching("name = mario"), 1);
+        assert_eq!(matching("name = MARIO"), 1);
+        assert_eq!(matching("name != mario"), persons.len() - 1);
+        assert_eq!(matching("name ~ ARI"), 1);
+        assert_eq!(matching("name = mario and age > 100"), 0);
+    }
+}
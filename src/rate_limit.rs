@@ -0,0 +1,217 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+    Extension,
+};
+use crate::config::Config;
+use crate::errors::{ConfigError, HttpAppError};
+use crate::grpc;
+use crate::proxy::Client;
+use crate::routes::AppState;
+
+/// Buckets kept at most; past that, those of the clients seen least recently
+/// are dropped.
+const MAX_BUCKETS: usize = 10_000;
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct RateLimits {
+    rate: f64,
+    burst: f64,
+}
+
+impl RateLimits {
+    /// Reads `RATE_LIMIT_PER_SEC` and `RATE_LIMIT_BURST` (default twice the
+    /// rate); `None` when no rate is set.
+    pub fn from_config(config: &Config) -> Result<Option<Self>, ConfigError> {
+        let Some(rate) = config.var("RATE_LIMIT_PER_SEC") else {
+            return Ok(None);
+        };
+        let rate = positive("RATE_LIMIT_PER_SEC", rate)?;
+        let burst = match config.var("RATE_LIMIT_BURST") {
+            Some(burst) => positive("RATE_LIMIT_BURST", burst)?,
+            None => (rate * 2.0).max(1.0),
+        };
+        Ok(Some(RateLimits { rate, burst }))
+    }
+}
+
+fn positive(name: &str, value: &str) -> Result<f64, ConfigError> {
+    match value.parse::<f64>() {
+        Ok(number) if number > 0.0 && number.is_finite() => Ok(number),
+        _ => Err(ConfigError::Invalid(format!(
+            "{} must be a positive number, got {}",
+            name, value
+        ))),
+    }
+}
+
+/// A token bucket per client IP: each request takes a token, and tokens come
+/// back at `rate` per second up to `burst`.
+pub struct RateLimiter {
+    limits: RwLock<Option<RateLimits>>,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: Option<RateLimits>) -> Self {
+        RateLimiter {
+            limits: RwLock::new(limits),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Applies new limits to the buckets as they are; `None` stops limiting.
+    pub fn set_limits(&self, limits: Option<RateLimits>) {
+        let mut current = self.limits.write().unwrap_or_else(|e| e.into_inner());
+        if *current == limits {
+            return;
+        }
+        match limits {
+            Some(limits) => tracing::info!(
+                "Rate limit changed to {} requests per second, bursts of {}",
+                limits.rate,
+                limits.burst
+            ),
+            None => tracing::info!("Rate limit turned off"),
+        }
+        *current = limits;
+    }
+
+    /// Takes a token for `ip`, or tells how many seconds until one is back.
+    fn acquire(&self, ip: IpAddr) -> Result<(), u64> {
+        let Some(limits) = *self.limits.read().unwrap_or_else(|e| e.into_inner()) else {
+            return Ok(());
+        };
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        if buckets.len() >= MAX_BUCKETS && !buckets.contains_key(&ip) {
+            evict(&mut buckets, &limits, now);
+        }
+        let bucket = buckets.entry(ip).or_insert(Bucket {
+            tokens: limits.burst,
+            updated: now,
+        });
+        let tokens = refill(&limits, bucket, now);
+        if tokens >= 1.0 {
+            bucket.tokens = tokens - 1.0;
+            Ok(())
+        } else {
+            Err(((1.0 - tokens) / limits.rate).ceil() as u64)
+        }
+    }
+}
+
+/// Drops the buckets idle long enough to have refilled, which a new bucket
+/// would equal, and then, while more than half the room is taken, those seen
+/// least recently, so that a new client pays for this only once in many.
+fn evict(buckets: &mut HashMap<IpAddr, Bucket>, limits: &RateLimits, now: Instant) {
+    let refilled = Duration::try_from_secs_f64(limits.burst / limits.rate).unwrap_or(Duration::MAX);
+    buckets.retain(|_, bucket| now.duration_since(bucket.updated) < refilled);
+    let excess = buckets.len().saturating_sub(MAX_BUCKETS / 2);
+    if excess == 0 {
+        return;
+    }
+    let mut by_last_seen: Vec<(Instant, IpAddr)> =
+        buckets.iter().map(|(ip, bucket)| (bucket.updated, *ip)).collect();
+    by_last_seen.select_nth_unstable(excess - 1);
+    for (_, ip) in &by_last_seen[..excess] {
+        buckets.remove(ip);
+    }
+}
+
+fn refill(limits: &RateLimits, bucket: &mut Bucket, now: Instant) -> f64 {
+    let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+    bucket.tokens = (bucket.tokens + elapsed * limits.rate).min(limits.burst);
+    bucket.updated = now;
+    bucket.tokens
+}
+
+/// Limits the `/api` routes and the gRPC calls per client IP, leaving `/`,
+/// `/health` and the rest alone so probes keep working under load.
+pub async fn limit(
+    State(state): State<Arc<AppState>>,
+    Extension(client): Extension<Client>,
+    request: Request,
+    next: Next,
+) -> Result<Response, HttpAppError> {
+    if request.uri().path().starts_with("/api") || grpc::is_call(&request) {
+        if let Err(retry_after) = state.rate_limiter.acquire(client.ip) {
+            tracing::warn!("Rate limited {}", client.ip);
+            return Err(HttpAppError::TooManyRequests(retry_after));
+        }
+    }
+    Ok(next.run(request).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    const CLIENT: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+    const OTHER: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+
+    #[test]
+    fn buckets_allow_a_burst_per_client() {
+        let limiter = RateLimiter::new(Some(RateLimits { rate: 0.5, burst: 2.0 }));
+        assert_eq!(limiter.acquire(CLIENT), Ok(()));
+        assert_eq!(limiter.acquire(CLIENT), Ok(()));
+        assert_eq!(limiter.acquire(CLIENT), Err(2));
+        assert_eq!(limiter.acquire(OTHER), Ok(()));
+
+        limiter.set_limits(None);
+        assert_eq!(limiter.acquire(CLIENT), Ok(()));
+    }
+
+    fn client(n: u32) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::from(0x0a00_0000 + n))
+    }
+
+    #[test]
+    fn buckets_are_bounded() {
+        let limiter = RateLimiter::new(Some(RateLimits { rate: 0.001, burst: 1.0 }));
+        for n in 0..MAX_BUCKETS as u32 * 3 {
+            assert_eq!(limiter.acquire(client(n)), Ok(()));
+            assert!(limiter.buckets.lock().unwrap().len() <= MAX_BUCKETS);
+        }
+    }
+
+    #[test]
+    fn recent_clients_keep_their_bucket() {
+        let limiter = RateLimiter::new(Some(RateLimits { rate: 0.001, burst: 1.0 }));
+        assert_eq!(limiter.acquire(CLIENT), Ok(()));
+        for n in 1..MAX_BUCKETS as u32 {
+            limiter.acquire(client(n)).unwrap();
+        }
+        assert!(limiter.acquire(CLIENT).is_err());
+        limiter.acquire(client(0)).unwrap();
+        let buckets = limiter.buckets.lock().unwrap().len();
+        assert_eq!(buckets, MAX_BUCKETS / 2 + 1);
+        assert!(limiter.acquire(CLIENT).is_err(), "the bucket was dropped");
+        assert_eq!(limiter.acquire(client(1)), Ok(()), "the oldest buckets are dropped");
+    }
+
+    #[test]
+    fn limits_must_be_positive() {
+        let limits = |settings: &[(&str, &str)]| {
+            RateLimits::from_config(&Config::from_overrides(settings))
+        };
+        assert!(limits(&[]).unwrap().is_none());
+        let defaults = limits(&[("RATE_LIMIT_PER_SEC", "5")]).unwrap().unwrap();
+        assert_eq!((defaults.rate, defaults.burst), (5.0, 10.0));
+        for rate in ["0", "-1", "fast", "inf"] {
+            assert!(limits(&[("RATE_LIMIT_PER_SEC", rate)]).is_err(), "{}", rate);
+        }
+        assert!(limits(&[("RATE_LIMIT_PER_SEC", "5"), ("RATE_LIMIT_BURST", "0")]).is_err());
+    }
+}
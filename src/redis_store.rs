@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use redis::aio::ConnectionManager;
+use redis::AsyncCommands;
+use crate::errors::{HttpAppError, StorageError};
+use crate::person::Person;
+use crate::repository::{next_after, PersonRepository};
+
+const INDEX_KEY: &str = "persons";
+const UPDATE_FIELDS: [&str; 3] = ["name", "age", "date"];
+const VERSION_FIELD: &str = "version";
+
+/// Checks and bumps the version in one step, so that no other writer slips in
+/// between. Answers the new version, or one of the `UPDATE_` codes below.
+const UPDATE_SCRIPT: &str = r#"
+if redis.call('SISMEMBER', KEYS[2], ARGV[1]) == 0
+    or redis.call('HEXISTS', KEYS[1], 'deleted_at') == 1 then
+    return -1
+end
+local version = tonumber(redis.call('HGET', KEYS[1], 'version') or '1')
+if ARGV[2] ~= '0' and version ~= tonumber(ARGV[2]) then
+    return -2
+end
+redis.call('HSET', KEYS[1], unpack(ARGV, 3))
+return redis.call('HINCRBY', KEYS[1], 'version', 1)
+"#;
+const UPDATE_NOT_FOUND: i64 = -1;
+const UPDATE_OUTDATED: i64 = -2;
+
+/// Claims the id and writes the fields in one step, so that a failed write
+/// leaves no id without a person. Answers 0 when the id is taken.
+const INSERT_SCRIPT: &str = r#"
+if redis.call('SADD', KEYS[2], ARGV[1]) == 0 then
+    return 0
+end
+redis.call('DEL', KEYS[1])
+redis.call('HSET', KEYS[1], unpack(ARGV, 2))
+return 1
+"#;
+
+pub struct RedisRepository {
+    connection: ConnectionManager,
+}
+
+impl RedisRepository {
+    pub async fn connect(url: &str) -> Result<Self, StorageError> {
+        let client = redis::Client::open(url)?;
+        let connection = ConnectionManager::new(client).await?;
+        Ok(RedisRepository { connection })
+    }
+
+    /// Sets `fields` through `UPDATE_SCRIPT`, when the person is at `version`.
+    async fn set_at_version(
+        &self,
+        id: u32,
+        version: u32,
+        fields: &[(String, String)],
+    ) -> Result<(), HttpAppError> {
+        let mut connection = self.connection.clone();
+        let script = redis::Script::new(UPDATE_SCRIPT);
+        let mut invocation = script.prepare_invoke();
+        invocation.key(person_key(id)).key(INDEX_KEY).arg(id).arg(version);
+        for (field, value) in fields {
+            invocation.arg(field).arg(value);
+        }
+        let outcome: i64 = invocation.invoke_async(&mut connection).await?;
+        match outcome {
+            UPDATE_NOT_FOUND => Err(HttpAppError::NotFound),
+            UPDATE_OUTDATED => Err(HttpAppError::PreconditionFailed),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn person_key(id: u32) -> String {
+    format!("person:{}", id)
+}
+
+fn to_fields(person: &Person) -> Result<Vec<(String, String)>, HttpAppError> {
+    let value = serde_json::to_value(person)
+        .map_err(|e| HttpAppError::StorageError(e.to_string()))?;
+    let serde_json::Value::Object(map) = value else {
+        return Err(HttpAppError::StorageError("Person is not a JSON object".to_string()));
+    };
+    Ok(map.into_iter().map(|(field, value)| (field, value.to_string())).collect())
+}
+
+fn from_fields(fields: HashMap<String, String>) -> Result<Person, HttpAppError> {
+    let map = fields
+        .into_iter()
+        .map(|(field, value)| Ok((field, serde_json::from_str(&value)?)))
+        .collect::<Result<serde_json::Map<_, _>, serde_json::Error>>()
+        .map_err(|e| HttpAppError::StorageError(e.to_string()))?;
+    serde_json::from_value(serde_json::Value::Object(map))
+        .map_err(|e| HttpAppError::StorageError(e.to_string()))
+}
+
+#[async_trait]
+impl PersonRepository for RedisRepository {
+    async fn list(&self) -> Result<Vec<Person>, HttpAppError> {
+        let mut connection = self.connection.clone();
+        let mut ids: Vec<u32> = connection.smembers(INDEX_KEY).await?;
+        ids.sort_unstable();
+        let mut pipe = redis::pipe();
+        for id in &ids {
+            pipe.hgetall(person_key(*id));
+        }
+        let rows: Vec<HashMap<String, String>> = pipe.query_async(&mut connection).await?;
+        rows.into_iter()
+            .filter(|fields| !fields.is_empty())
+            .map(from_fields)
+            .collect()
+    }
+
+    async fn get(&self, id: u32) -> Result<Person, HttpAppError> {
+        let mut connection = self.connection.clone();
+        let fields: HashMap<String, String> = connection.hgetall(person_key(id)).await?;
+        if fields.is_empty() {
+            return Err(HttpAppError::NotFound);
+        }
+        from_fields(fields)
+    }
+
+    async fn next_id(&self) -> Result<u32, HttpAppError> {
+        let mut connection = self.connection.clone();
+        let ids: Vec<u32> = connection.smembers(INDEX_KEY).await?;
+        next_after(ids.into_iter().max())
+    }
+
+    async fn insert(&self, person: Person) -> Result<(), HttpAppError> {
+        let mut connection = self.connection.clone();
+        let fields = to_fields(&Person {
+            version: person.version.max(1),
+            ..person.clone()
+        })?;
+        let script = redis::Script::new(INSERT_SCRIPT);
+        let mut invocation = script.prepare_invoke();
+        invocation.key(person_key(person.id)).key(INDEX_KEY).arg(person.id);
+        for (field, value) in &fields {
+            invocation.arg(field).arg(value);
+        }
+        let added: u32 = invocation.invoke_async(&mut connection).await?;
+        if added == 0 {
+            return Err(HttpAppError::Conflict);
+        }
+        Ok(())
+    }
+
+    async fn update(&self, person: Person) -> Result<(), HttpAppError> {
+        let mut fields = to_fields(&person)?;
+        fields.retain(|(field, _)| UPDATE_FIELDS.contains(&field.as_str()));
+        self.set_at_version(person.id, person.version, &fields).await
+    }
+
+    async fn delete(&self, id: u32, version: u32) -> Result<(), HttpAppError> {
+        let deleted_at = serde_json::to_string(&Utc::now())
+            .map_err(|e| HttpAppError::StorageError(e.to_string()))?;
+        self.set_at_version(id, version, &[("deleted_at".to_owned(), deleted_at)]).await
+    }
+
+    async fn set_deleted(
+        &self,
+        id: u32,
+        deleted_at: Option<DateTime<Utc>>,
+    ) -> Result<(), HttpAppError> {
+        let mut connection = self.connection.clone();
+        let exists: bool = connection.sismember(INDEX_KEY, id).await?;
+        if !exists {
+            return Err(HttpAppError::NotFound);
+        }
+        let key = person_key(id);
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        match deleted_at {
+            Some(deleted_at) => {
+                let value = serde_json::to_string(&deleted_at)
+                    .map_err(|e| HttpAppError::StorageError(e.to_string()))?;
+                pipe.hset(&key, "deleted_at", value)
+            }
+            None => pipe.hdel(&key, "deleted_at"),
+        };
+        let _: () = pipe.hincr(&key, VERSION_FIELD, 1).query_async(&mut connection).await?;
+        Ok(())
+    }
+
+    async fn purge(&self, id: u32) -> Result<(), HttpAppError> {
+        let mut connection = self.connection.clone();
+        let removed: u32 = connection.srem(INDEX_KEY, id).await?;
+        if removed == 0 {
+            return Err(HttpAppError::NotFound);
+        }
+        let _: () = connection.del(person_key(id)).await?;
+        Ok(())
+    }
+}
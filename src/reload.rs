@@ -0,0 +1,140 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::mpsc;
+use tokio::time;
+use crate::config::{Config, Settings};
+use crate::cors;
+use crate::errors::ConfigError;
+use crate::flags;
+use crate::logging;
+use crate::rate_limit::RateLimits;
+use crate::routes::AppState;
+
+/// Reloads the settings that can change while requests run: the ones of
+/// `Settings`, the rate limits, the log filter, the CORS policy and the
+/// feature flags.
+pub struct Reloader {
+    config: Config,
+    every: Option<Duration>,
+    log_filter: String,
+}
+
+impl Reloader {
+    /// Reloads on SIGHUP and, with `CONFIG_RELOAD_SECS`, whenever the
+    /// configuration file changed, checked that often. Without a file the
+    /// settings cannot change and nothing is watched.
+    pub fn from_config(config: &Config) -> Result<Self, ConfigError> {
+        let every = match config.var("CONFIG_RELOAD_SECS") {
+            Some(secs) => Some(Duration::from_secs(
+                secs.parse().ok().filter(|secs| *secs > 0).ok_or_else(|| {
+                    ConfigError::Invalid(format!(
+                        "CONFIG_RELOAD_SECS must be a positive number of seconds, got {}",
+                        secs
+                    ))
+                })?,
+            )),
+            None => None,
+        };
+        Ok(Reloader {
+            config: config.clone(),
+            every,
+            log_filter: logging::filter_from_config(config)?.to_string(),
+        })
+    }
+
+    pub fn watch(mut self, state: Arc<AppState>) {
+        let Some(path) = self.config.file().map(Path::to_path_buf) else {
+            return;
+        };
+        let (reloads, mut requested) = mpsc::channel(1);
+        #[cfg(unix)]
+        tokio::spawn(forward_hangups(reloads.clone()));
+        if let Some(every) = self.every {
+            tokio::spawn(poll(path, every, reloads));
+        }
+        tokio::spawn(async move {
+            while let Some(cause) = requested.recv().await {
+                tracing::info!("Reloading the configuration on {}", cause);
+                if let Err(e) = self.reload(&state) {
+                    tracing::error!("Could not reload the configuration, keeping it: {}", e);
+                }
+            }
+        });
+    }
+
+    /// Builds the new configuration and every setting from it before any is
+    /// swapped in, so that a broken file changes nothing.
+    fn reload(&mut self, state: &AppState) -> Result<(), ConfigError> {
+        let config = self.config.reload()?;
+        let settings = Settings::load(&config)?;
+        let rate_limits = RateLimits::from_config(&config)?;
+        let log_filter = logging::filter_from_config(&config)?;
+        let cors = cors::layer_from_config(&config)?;
+        let flags = flags::from_config(&config)?;
+
+        let current = state.settings();
+        if settings.unique_names != current.unique_names
+            || settings.max_body_bytes != current.max_body_bytes
+            || settings.seed_file != current.seed_file
+            || settings.audit_log_file != current.audit_log_file
+        {
+            tracing::warn!(
+                "Changes to UNIQUE_NAMES, MAX_BODY_BYTES, SEED_FILE and AUDIT_LOG_FILE wait for a \
+                 restart"
+            );
+        }
+        *state.settings.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(settings);
+        state.rate_limiter.set_limits(rate_limits);
+        state.cors.set(cors);
+        state.flags.set_configured(flags);
+        // Leaves a filter set through /admin/loglevel alone unless LOG_LEVEL changed.
+        let filter = log_filter.to_string();
+        if filter != self.log_filter {
+            if let Err(e) = state.log_level.replace(log_filter) {
+                tracing::error!("Could not change the log filter: {}", e);
+            }
+            self.log_filter = filter;
+        }
+        self.config = config;
+        tracing::info!("Reloaded the configuration");
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+async fn forward_hangups(reloads: mpsc::Sender<&'static str>) {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => {
+            tracing::error!("Could not listen for SIGHUP: {}", e);
+            return;
+        }
+    };
+    while hangups.recv().await.is_some() {
+        if reloads.send("SIGHUP").await.is_err() {
+            return;
+        }
+    }
+}
+
+async fn poll(path: PathBuf, every: Duration, reloads: mpsc::Sender<&'static str>) {
+    let mut seen = modified(&path).await;
+    let mut interval = time::interval(every);
+    loop {
+        interval.tick().await;
+        let modified = modified(&path).await;
+        if modified == seen {
+            continue;
+        }
+        seen = modified;
+        if reloads.send("a change to the file").await.is_err() {
+            return;
+        }
+    }
+}
+
+async fn modified(path: &Path) -> Option<SystemTime> {
+    tokio::fs::metadata(path).await.and_then(|metadata| metadata.modified()).ok()
+}
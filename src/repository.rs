@@ -0,0 +1,445 @@
+use std::sync::RwLock;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::migrate::MigrateError;
+use uuid::Uuid;
+use crate::errors::HttpAppError;
+use crate::listing::{ListQuery, PersonFilter};
+use crate::pagination::Page;
+use crate::person::{NewPerson, Person, PersonKey, PersonPatch};
+use crate::search::{self, SearchHit};
+
+const CREATE_ATTEMPTS: usize = 3;
+
+#[async_trait]
+pub trait PersonRepository: Send + Sync {
+    async fn migrate(&self) -> Result<(), MigrateError> {
+        Ok(())
+    }
+
+    /// Counts the embedded migrations the database has not applied yet.
+    async fn pending_migrations(&self) -> Result<usize, MigrateError> {
+        Ok(0)
+    }
+
+    /// Creates or drops a unique index on the case-folded name. Returns false
+    /// when the backend has no such index, so `UniqueNameRepository` checks names.
+    async fn enforce_unique_names(&self, _enabled: bool) -> Result<bool, HttpAppError> {
+        Ok(false)
+    }
+
+    /// Writes out whatever is still buffered and closes connections; called
+    /// once on shutdown, after the last request.
+    async fn close(&self) -> Result<(), HttpAppError> {
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<Vec<Person>, HttpAppError>;
+
+    async fn list_page(&self, query: &ListQuery) -> Result<Page<Person>, HttpAppError> {
+        let persons: Vec<Person> = self
+            .list()
+            .await?
+            .into_iter()
+            .filter(|person| query.filter.matches(person))
+            .collect();
+        let total = persons.len() as u64;
+        let items = query.apply(persons);
+        Ok(Page { items, total })
+    }
+
+    async fn count(&self, filter: &PersonFilter) -> Result<u64, HttpAppError> {
+        let persons = self.list().await?;
+        Ok(persons.iter().filter(|person| filter.matches(person)).count() as u64)
+    }
+
+    async fn list_after(
+        &self,
+        filter: &PersonFilter,
+        after: Option<u32>,
+        limit: u64,
+    ) -> Result<Vec<Person>, HttpAppError> {
+        let mut persons: Vec<Person> = self
+            .list()
+            .await?
+            .into_iter()
+            .filter(|person| after.map_or(true, |after| person.id > after))
+            .filter(|person| filter.matches(person))
+            .collect();
+        persons.sort_by_key(|person| person.id);
+        persons.truncate(limit as usize);
+        Ok(persons)
+    }
+
+    async fn search(
+        &self,
+        query: &str,
+        fuzzy: bool,
+        limit: usize,
+    ) -> Result<Vec<SearchHit>, HttpAppError> {
+        let persons = self
+            .list()
+            .await?
+            .into_iter()
+            .filter(|person| person.deleted_at.is_none())
+            .collect();
+        Ok(search::rank(persons, query, fuzzy, limit))
+    }
+
+    async fn get(&self, id: u32) -> Result<Person, HttpAppError>;
+
+    async fn get_by_uuid(&self, uuid: Uuid) -> Result<Person, HttpAppError> {
+        let persons = self.list().await?;
+        persons
+            .into_iter()
+            .find(|person| person.uuid == Some(uuid))
+            .ok_or(HttpAppError::NotFound)
+    }
+
+    async fn get_by_key(&self, key: PersonKey) -> Result<Person, HttpAppError> {
+        match key {
+            PersonKey::Id(id) => self.get(id).await,
+            PersonKey::Uuid(uuid) => self.get_by_uuid(uuid).await,
+        }
+    }
+
+    async fn resolve_id(&self, key: PersonKey) -> Result<u32, HttpAppError> {
+        match key {
+            PersonKey::Id(id) => Ok(id),
+            PersonKey::Uuid(uuid) => Ok(self.get_by_uuid(uuid).await?.id),
+        }
+    }
+
+    async fn insert(&self, person: Person) -> Result<(), HttpAppError>;
+
+    async fn next_id(&self) -> Result<u32, HttpAppError> {
+        let persons = self.list().await?;
+        next_after(persons.iter().map(|p| p.id).max())
+    }
+
+    async fn create(&self, person: NewPerson) -> Result<Person, HttpAppError> {
+        let mut attempt = 1;
+        loop {
+            let created = person.clone().with_id(self.next_id().await?);
+            match self.insert(created.clone()).await {
+                Err(HttpAppError::Conflict) if attempt < CREATE_ATTEMPTS => attempt += 1,
+                result => return result.map(|()| created),
+            }
+        }
+    }
+
+    /// Bumps the version; fails with `NotFound` when the person is deleted and
+    /// with `PreconditionFailed` when `person.version` is neither 0 nor the
+    /// stored version.
+    async fn update(&self, person: Person) -> Result<(), HttpAppError>;
+
+    /// Inserts the person, or updates the one with its id, restored first if
+    /// it was deleted. Returns whether the person was inserted.
+    async fn upsert(&self, person: Person) -> Result<bool, HttpAppError> {
+        match self.get(person.id).await {
+            Err(HttpAppError::NotFound) => {
+                self.insert(Person { version: 0, ..person }).await.map(|()| true)
+            }
+            Err(e) => Err(e),
+            Ok(stored) if stored.deleted_at.is_some() => {
+                // Restoring bumps the version the caller named, so check it first.
+                stored.check_version(person.version)?;
+                self.set_deleted(person.id, None).await?;
+                self.update(Person { version: 0, ..person }).await.map(|()| false)
+            }
+            Ok(_) => self.update(person).await.map(|()| false),
+        }
+    }
+
+    async fn patch(&self, id: u32, patch: PersonPatch) -> Result<Person, HttpAppError> {
+        let mut person = self.get(id).await?;
+        if person.deleted_at.is_some() {
+            return Err(HttpAppError::NotFound);
+        }
+        person.check_version(patch.version.unwrap_or(0))?;
+        patch.apply(&mut person);
+        self.update(person).await?;
+        self.get(id).await
+    }
+
+    async fn set_deleted(
+        &self,
+        id: u32,
+        deleted_at: Option<DateTime<Utc>>,
+    ) -> Result<(), HttpAppError>;
+
+    /// Marks the person as deleted at `version`, or at any version with 0;
+    /// deleted persons read as not found. Like `update`, the version is
+    /// checked in the same step as the write.
+    async fn delete(&self, id: u32, version: u32) -> Result<(), HttpAppError>;
+
+    /// Undoes `delete`; fails with `NotDeleted` when there is nothing to undo.
+    async fn restore(&self, id: u32) -> Result<Person, HttpAppError> {
+        if self.get(id).await?.deleted_at.is_none() {
+            return Err(HttpAppError::NotDeleted);
+        }
+        self.set_deleted(id, None).await?;
+        self.get(id).await
+    }
+
+    /// Removes the person for good.
+    async fn purge(&self, id: u32) -> Result<(), HttpAppError>;
+
+    async fn delete_many(&self, ids: &[u32]) -> Result<u64, HttpAppError> {
+        let mut deleted = 0;
+        for id in ids {
+            match self.delete(*id, 0).await {
+                Ok(()) => deleted += 1,
+                Err(HttpAppError::NotFound) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(deleted)
+    }
+
+    async fn delete_matching(&self, filter: &PersonFilter) -> Result<u64, HttpAppError> {
+        let ids: Vec<u32> = self
+            .list()
+            .await?
+            .into_iter()
+            .filter(|person| filter.matches(person))
+            .map(|person| person.id)
+            .collect();
+        self.delete_many(&ids).await
+    }
+
+    async fn replace_all(&self, persons: Vec<Person>) -> Result<(), HttpAppError> {
+        for person in self.list().await? {
+            self.purge(person.id).await?;
+        }
+        for person in persons {
+            self.insert(person).await?;
+        }
+        Ok(())
+    }
+}
+
+pub fn next_after(max_id: Option<u32>) -> Result<u32, HttpAppError> {
+    max_id
+        .unwrap_or(0)
+        .checked_add(1)
+        .ok_or_else(|| HttpAppError::StorageError("No person ids left".to_string()))
+}
+
+pub struct MemoryRepository {
+    person_collection: RwLock<Vec<Person>>,
+}
+
+impl MemoryRepository {
+    pub fn new(persons: Vec<Person>) -> Self {
+        MemoryRepository {
+            person_collection: RwLock::new(persons),
+        }
+    }
+}
+
+#[async_trait]
+impl PersonRepository for MemoryRepository {
+    async fn list(&self) -> Result<Vec<Person>, HttpAppError> {
+        let persons = self.person_collection.read()?;
+        Ok(persons.clone())
+    }
+
+    async fn get(&self, id: u32) -> Result<Person, HttpAppError> {
+        let persons_guard = self.person_collection.read()?;
+        let filtered = persons_guard.iter().find(|t| t.id == id);
+        match filtered {
+            Some(filtered) => Ok(filtered.clone()),
+            None => Err(HttpAppError::NotFound),
+        }
+    }
+
+    async fn insert(&self, person: Person) -> Result<(), HttpAppError> {
+        let mut persons_guard = self.person_collection.write()?;
+        let filtered = persons_guard.iter().any(|t| t.id == person.id);
+        if !filtered {
+            persons_guard.push(Person {
+                version: person.version.max(1),
+                ..person
+            });
+            Ok(())
+        } else {
+            Err(HttpAppError::Conflict)
+        }
+    }
+
+    async fn create(&self, person: NewPerson) -> Result<Person, HttpAppError> {
+        let mut persons_guard = self.person_collection.write()?;
+        let id = next_after(persons_guard.iter().map(|t| t.id).max())?;
+        let created = person.with_id(id);
+        persons_guard.push(created.clone());
+        Ok(created)
+    }
+
+    async fn update(&self, person: Person) -> Result<(), HttpAppError> {
+        let mut persons_guard = self.person_collection.write()?;
+        let filtered = persons_guard
+            .iter_mut()
+            .find(|t| t.id == person.id && t.deleted_at.is_none());
+        match filtered {
+            Some(p) => {
+                p.check_version(person.version)?;
+                p.age = person.age;
+                p.date = person.date;
+                p.name = person.name;
+                p.version += 1;
+                Ok(())
+            }
+            None => Err(HttpAppError::NotFound),
+        }
+    }
+
+    async fn delete(&self, id: u32, version: u32) -> Result<(), HttpAppError> {
+        let mut persons_guard = self.person_collection.write()?;
+        let filtered = persons_guard
+            .iter_mut()
+            .find(|t| t.id == id && t.deleted_at.is_none());
+        match filtered {
+            Some(p) => {
+                p.check_version(version)?;
+                p.deleted_at = Some(Utc::now());
+                p.version += 1;
+                Ok(())
+            }
+            None => Err(HttpAppError::NotFound),
+        }
+    }
+
+    async fn patch(&self, id: u32, patch: PersonPatch) -> Result<Person, HttpAppError> {
+        let mut persons_guard = self.person_collection.write()?;
+        let filtered = persons_guard
+            .iter_mut()
+            .find(|t| t.id == id && t.deleted_at.is_none());
+        match filtered {
+            Some(p) => {
+                p.check_version(patch.version.unwrap_or(0))?;
+                patch.apply(p);
+                p.version += 1;
+                Ok(p.clone())
+            }
+            None => Err(HttpAppError::NotFound),
+        }
+    }
+
+    async fn set_deleted(
+        &self,
+        id: u32,
+        deleted_at: Option<DateTime<Utc>>,
+    ) -> Result<(), HttpAppError> {
+        let mut persons_guard = self.person_collection.write()?;
+        let filtered = persons_guard.iter_mut().find(|t| t.id == id);
+        match filtered {
+            Some(p) => {
+                p.deleted_at = deleted_at;
+                p.version += 1;
+                Ok(())
+            }
+            None => Err(HttpAppError::NotFound),
+        }
+    }
+
+    async fn purge(&self, id: u32) -> Result<(), HttpAppError> {
+        let mut persons_guard = self.person_collection.write()?;
+        let index = persons_guard.iter().position(|t| t.id == id);
+        match index {
+            Some(index) => {
+                persons_guard.remove(index);
+                Ok(())
+            }
+            None => Err(HttpAppError::NotFound),
+        }
+    }
+
+    async fn replace_all(&self, persons: Vec<Person>) -> Result<(), HttpAppError> {
+        let mut persons_guard = self.person_collection.write()?;
+        *persons_guard = persons
+            .into_iter()
+            .map(|person| Person {
+                version: person.version.max(1),
+                ..person
+            })
+            .collect();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::person::create_person_collection;
+
+    fn repository() -> MemoryRepository {
+        MemoryRepository::new(create_person_collection())
+    }
+
+    #[tokio::test]
+    async fn deleted_persons_cannot_change() {
+        let repository = repository();
+        repository.delete(1, 0).await.unwrap();
+        let person = repository.get(1).await.unwrap();
+        let patch = PersonPatch { age: Some(44), ..PersonPatch::default() };
+        assert!(matches!(repository.update(person.clone()).await, Err(HttpAppError::NotFound)));
+        assert!(matches!(repository.patch(1, patch).await, Err(HttpAppError::NotFound)));
+        assert!(matches!(repository.delete(1, 0).await, Err(HttpAppError::NotFound)));
+        assert_eq!(repository.get(1).await.unwrap().version, person.version);
+    }
+
+    #[tokio::test]
+    async fn deletes_check_the_version() {
+        let repository = repository();
+        let outdated = repository.delete(1, 2).await;
+        assert!(matches!(outdated, Err(HttpAppError::PreconditionFailed)));
+        let person = repository.get(1).await.unwrap();
+        assert_eq!((person.deleted_at, person.version), (None, 1));
+        repository.delete(1, 1).await.unwrap();
+        let person = repository.get(1).await.unwrap();
+        assert!(person.deleted_at.is_some());
+        assert_eq!(person.version, 2);
+    }
+
+    #[tokio::test]
+    async fn restore_needs_a_deleted_person() {
+        let repository = repository();
+        assert!(matches!(repository.restore(1).await, Err(HttpAppError::NotDeleted)));
+        assert_eq!(repository.get(1).await.unwrap().version, 1);
+        repository.delete(1, 0).await.unwrap();
+        let restored = repository.restore(1).await.unwrap();
+        assert_eq!((restored.deleted_at, restored.version), (None, 3));
+        assert!(matches!(repository.restore(3).await, Err(HttpAppError::NotFound)));
+    }
+
+    #[tokio::test]
+    async fn upsert_restores_deleted_persons() {
+        let repository = repository();
+        repository.delete(1, 0).await.unwrap();
+        let mut mario = repository.get(1).await.unwrap();
+        mario.age = 44;
+        mario.version = 0;
+        assert!(!repository.upsert(mario.clone()).await.unwrap());
+        let stored = repository.get(1).await.unwrap();
+        assert_eq!((stored.age, stored.deleted_at), (44, None));
+
+        mario.id = 3;
+        assert!(repository.upsert(mario).await.unwrap());
+        assert_eq!(repository.get(3).await.unwrap().version, 1);
+    }
+
+    #[tokio::test]
+    async fn upsert_checks_the_version_of_deleted_persons() {
+        let repository = repository();
+        repository.delete(1, 0).await.unwrap();
+        let deleted = repository.get(1).await.unwrap();
+        let outdated = Person { version: 1, ..deleted.clone() };
+        let failed = repository.upsert(outdated).await;
+        assert!(matches!(failed, Err(HttpAppError::PreconditionFailed)));
+        assert!(repository.get(1).await.unwrap().deleted_at.is_some());
+
+        assert!(!repository.upsert(Person { deleted_at: None, ..deleted }).await.unwrap());
+        assert_eq!(repository.get(1).await.unwrap().version, 4);
+    }
+}
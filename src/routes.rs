@@ -1,35 +1,206 @@
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
-    response::Html,
-    routing::{delete, get, post, put},
-    Json, Router,
+    extract::{OriginalUri, Path, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    middleware,
+    response::{Html, IntoResponse, Response},
+    routing::get,
+    Router,
 };
+use metrics_exporter_prometheus::PrometheusHandle;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::sync::{Arc, RwLock};
-use crate::errors::HttpAppError;
-use crate::person::Person;
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+use crate::api_keys::ApiKeys;
+use crate::audit::AuditLog;
+use crate::auth::{AdminCredentials, JwtVerifier};
+use crate::config::Settings;
+use crate::cors::Cors;
+use crate::csv_io;
+use crate::errors::{ErrorBody, HttpAppError, ValidationBody};
+use crate::etag;
+use crate::extract::{QueryParams, ValidatedBody};
+use crate::fields::{FieldSet, FieldsParams};
+use crate::flags::{self, Enabled, Flags};
+use crate::graphql;
+use crate::health::Readiness;
+use crate::history::{History, Revision};
+use crate::ip_filter::IpFilter;
+use crate::jobs::ImportJobs;
+use crate::links::{self, Link, Linked};
+use crate::listing::{ListQuery, PersonFilter, Sort};
+use crate::logging::LogLevel;
+use crate::login_guard::LoginGuard;
+use crate::maintenance::Maintenance;
+use crate::negotiation::{Format, ListFormat, Negotiated};
+use crate::pagination::{
+    CursorPage, CursorRequest, PageParams, PageRequest, DEFAULT_PER_PAGE, MAX_PER_PAGE,
+};
+use crate::oidc::OidcProvider;
+use crate::person::{IdStrategy, NewPerson, Person, PersonKey, PersonPatch};
+use crate::proxy::TrustedProxies;
+use crate::rate_limit::RateLimiter;
+use crate::repository::PersonRepository;
+use crate::search::{SearchHit, SearchParams};
+use crate::sessions::Sessions;
+use crate::signatures::RequestSigning;
+use crate::tokens::TokenIssuer;
+use crate::users::UserRepository;
+use crate::validation::Validate;
 
 pub struct AppState {
-    pub person_collection: RwLock<Vec<Person>>,
-    pub greeting_text: String,
+    pub person_repository: Box<dyn PersonRepository>,
+    pub history: Arc<History>,
+    pub audit: AuditLog,
+    pub metrics: PrometheusHandle,
+    pub readiness: Readiness,
+    pub log_level: LogLevel,
+    pub maintenance: Maintenance,
+    pub import_jobs: ImportJobs,
+    pub settings: RwLock<Arc<Settings>>,
+    pub jwt: Option<JwtVerifier>,
+    pub api_keys: Option<ApiKeys>,
+    pub admin: Option<AdminCredentials>,
+    pub oidc: Option<OidcProvider>,
+    pub cors: Cors,
+    pub flags: Flags,
+    pub ip_filter: Option<IpFilter>,
+    pub trusted_proxies: TrustedProxies,
+    pub login_guard: LoginGuard,
+    pub rate_limiter: RateLimiter,
+    pub sessions: Option<Sessions>,
+    pub signing: Option<RequestSigning>,
+    pub tokens: Option<TokenIssuer>,
+    pub users: Box<dyn UserRepository>,
+}
+
+impl AppState {
+    /// The settings in force, which a configuration reload may replace.
+    pub fn settings(&self) -> Arc<Settings> {
+        self.settings.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+#[cfg(test)]
+impl AppState {
+    /// The state of a service with the sample persons in memory and every
+    /// optional feature off, for tests to adjust before sharing it.
+    pub fn for_tests() -> Self {
+        use metrics_exporter_prometheus::PrometheusBuilder;
+        use tracing_subscriber::{reload, EnvFilter};
+        use crate::config::Config;
+        use crate::person::create_person_collection;
+        use crate::repository::MemoryRepository;
+        use crate::users::MemoryUserRepository;
+
+        let config = Config::default();
+        let (_, log_filter) = reload::Layer::new(EnvFilter::new("info"));
+        AppState {
+            person_repository: Box::new(MemoryRepository::new(create_person_collection())),
+            history: Default::default(),
+            audit: Default::default(),
+            metrics: PrometheusBuilder::new().build_recorder().handle(),
+            readiness: Default::default(),
+            log_level: LogLevel::new(log_filter),
+            maintenance: Maintenance::from_config(&config).unwrap(),
+            import_jobs: Default::default(),
+            settings: RwLock::new(Arc::new(Settings::default())),
+            jwt: None,
+            api_keys: None,
+            admin: None,
+            oidc: None,
+            cors: Cors::new(None),
+            flags: Flags::new(BTreeMap::new()),
+            ip_filter: None,
+            trusted_proxies: Default::default(),
+            login_guard: LoginGuard::from_config(&config).unwrap(),
+            rate_limiter: RateLimiter::new(None),
+            sessions: None,
+            signing: None,
+            tokens: None,
+            users: Box::new(MemoryUserRepository::default()),
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct PersonCount {
+    pub count: u64,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DeletedParams {
+    #[serde(default)]
+    pub include_deleted: bool,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PutParams {
+    #[serde(default)]
+    pub upsert: bool,
 }
 
 pub fn create_routes() -> Router<Arc<AppState>> {
     Router::new()
         .route("/", get(landing_page))
         .route("/health", get(health))
-        .route("/api/persons", get(persons))
-        .route("/api/person/:id", get(single_person))
-        .route("/api/person", post(add_person))
-        .route("/api/person", put(update_person))
-        .route("/api/person/:id", delete(delete_person))
+        .nest("/api/v1", v1::create_routes())
+        .nest("/api", v1::create_routes().layer(middleware::map_response(deprecated)))
+        .merge(graphql::create_graphql_routes())
         .fallback(not_found_handler)
 }
 
+/// Version 1 of the person API. A later version gets its own module with
+/// its own schema and is nested next to this one in `create_routes`.
+pub mod v1 {
+    use std::sync::Arc;
+    use axum::{
+        middleware,
+        routing::{delete, get, patch, post, put},
+        Router,
+    };
+    use crate::auth::{self, Role};
+    use crate::{bulk, jobs};
+    use crate::links::{PERSON, PERSONS, PERSON_BY_ID};
+    use super::*;
+
+    pub fn create_routes() -> Router<Arc<AppState>> {
+        let writer = middleware::from_fn_with_state(Role::Writer, auth::authorize);
+        Router::new()
+            .route(PERSONS, get(persons))
+            .route("/persons/count", get(count_persons))
+            .route("/persons/search", get(search_persons))
+            .route(PERSON_BY_ID, get(single_person))
+            .route(PERSON_BY_ID, patch(patch_person).route_layer(writer.clone()))
+            .route(PERSON, post(add_person).route_layer(writer.clone()))
+            .route(PERSON, put(update_person).route_layer(writer.clone()))
+            .route(PERSON_BY_ID, delete(delete_person).route_layer(writer.clone()))
+            .route("/person/:id/restore", post(restore_person).route_layer(writer))
+            .route("/person/:id/history", get(person_history))
+            .merge(bulk::create_bulk_routes())
+            .merge(csv_io::create_csv_routes())
+            .merge(jobs::create_job_routes())
+    }
+}
+
+/// Unversioned `/api` paths keep working but point clients at `/api/v1`.
+async fn deprecated(mut response: Response) -> Response {
+    let headers = response.headers_mut();
+    headers.insert("Deprecation", HeaderValue::from_static("true"));
+    headers.insert(
+        header::LINK,
+        HeaderValue::from_static("</api/v1>; rel=\"successor-version\""),
+    );
+    response
+}
+
 async fn landing_page(State(state): State<Arc<AppState>>) -> Html<String> {
     use chrono::Utc;
     let current_time = Utc::now().to_rfc3339();
-    let response_body = format!("Rust-Axum {} <br> Current UTC time: {}", state.greeting_text, current_time);
+    let response_body = format!("<link rel=\"stylesheet\" href=\"/static/style.css\">Rust-Axum {} <br> Current UTC time: {}", state.settings().greeting_text, current_time);
     Html(response_body)
 }
 
@@ -41,65 +212,441 @@ async fn health() -> &'static str {
     "OK"
 }
 
-async fn persons(State(state): State<Arc<AppState>>) -> Result<Json<Vec<Person>>, HttpAppError> {
-    let persons = state.person_collection.read()?;
-    Ok(Json(persons.clone()))
+#[utoipa::path(
+    get,
+    path = "/api/v1/persons",
+    tag = "persons",
+    summary = "List persons",
+    description = "A page of the matching persons, or a cursor page with `after` or `limit`. \
+                   `Accept: text/csv` streams every match instead.",
+    params(PageParams, Sort, PersonFilter, FieldsParams),
+    responses(
+        (status = 200, body = [Linked<Person>], headers(
+            ("X-Total-Count" = u64), ("X-Page" = u64), ("X-Per-Page" = u64), ("Link" = String)
+        )),
+        (status = 400, body = ErrorBody),
+        (status = 422, body = ValidationBody),
+    )
+)]
+async fn persons(
+    QueryParams(params): QueryParams<PageParams>,
+    QueryParams(sort): QueryParams<Sort>,
+    QueryParams(filter): QueryParams<PersonFilter>,
+    QueryParams(fields): QueryParams<FieldsParams>,
+    OriginalUri(uri): OriginalUri,
+    format: ListFormat,
+    State(state): State<Arc<AppState>>,
+) -> Result<Response, HttpAppError> {
+    filter.validate()?;
+    let fields = FieldSet::try_from(fields)?;
+    let format = match format {
+        ListFormat::Csv => {
+            if params.page.is_some() || params.per_page.is_some() || params.is_cursor() {
+                return Err(HttpAppError::BadRequest(
+                    "CSV lists every match, without page, per_page, after or limit".to_string(),
+                ));
+            }
+            let vary = [(header::VARY, HeaderValue::from_static("accept"))];
+            return Ok((vary, csv_io::stream_csv(state, filter, sort)?).into_response());
+        }
+        ListFormat::Other(format) => format,
+    };
+    if params.is_cursor() {
+        if !sort.is_default() {
+            return Err(HttpAppError::BadRequest(
+                "Cursor pagination only supports the default id order".to_string(),
+            ));
+        }
+        let cursor = CursorRequest::try_from(params)?;
+        let CursorPage { items, next_cursor } =
+            cursor.fetch(state.person_repository.as_ref(), &filter).await?;
+        let mut page_links = BTreeMap::from([(
+            "self",
+            Link { href: links::list_href(uri.query(), &[]), method: None },
+        )]);
+        if let Some(next) = &next_cursor {
+            let href = links::list_href(uri.query(), &[("after", next.clone())]);
+            page_links.insert("next", Link { href, method: None });
+        }
+        let items = links::persons(items, &fields);
+        let page = CursorPage { items, next_cursor };
+        let page = Linked { item: page, links: page_links };
+        return Ok(Negotiated(format, page).into_response());
+    }
+
+    let page = PageRequest::try_from(params)?;
+    let query = ListQuery { filter, sort, page };
+    let persons = state.person_repository.list_page(&query).await?;
+    let headers = [
+        ("X-Total-Count", persons.total.to_string()),
+        ("X-Page", page.page.to_string()),
+        ("X-Per-Page", page.per_page.to_string()),
+        ("Link", links::page_header(uri.query(), page.page, page.per_page, persons.total)),
+    ];
+    Ok((headers, Negotiated(format, links::persons(persons.items, &fields))).into_response())
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/v1/persons/count",
+    tag = "persons",
+    summary = "Count the matching persons",
+    params(PersonFilter),
+    responses((status = 200, body = PersonCount), (status = 422, body = ValidationBody))
+)]
+async fn count_persons(
+    QueryParams(filter): QueryParams<PersonFilter>,
+    format: Format,
+    State(state): State<Arc<AppState>>,
+) -> Result<Negotiated<PersonCount>, HttpAppError> {
+    filter.validate()?;
+    let count = state.person_repository.count(&filter).await?;
+    Ok(Negotiated(format, PersonCount { count }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/persons/search",
+    tag = "persons",
+    summary = "Search persons by name",
+    params(SearchParams),
+    responses((status = 200, body = Vec<SearchHit>), (status = 400, body = ErrorBody))
+)]
+async fn search_persons(
+    _: Enabled<flags::Search>,
+    QueryParams(params): QueryParams<SearchParams>,
+    format: Format,
+    State(state): State<Arc<AppState>>,
+) -> Result<Negotiated<Vec<SearchHit>>, HttpAppError> {
+    let query = params.q.trim();
+    if query.is_empty() {
+        return Err(HttpAppError::BadRequest("q must not be empty".to_string()));
+    }
+    let limit = params.limit.unwrap_or(DEFAULT_PER_PAGE).min(MAX_PER_PAGE) as usize;
+    let hits = state
+        .person_repository
+        .search(query, params.fuzzy, limit)
+        .await?;
+    Ok(Negotiated(format, hits))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/person/{id}",
+    tag = "persons",
+    summary = "Get a person",
+    params(
+        ("id" = String, Path, description = "An id, or a UUID"),
+        FieldsParams,
+        DeletedParams,
+        ("If-None-Match" = Option<String>, Header),
+    ),
+    responses(
+        (status = 200, body = Linked<Person>, headers(("ETag" = String))),
+        (status = 304, description = "The person still has the `If-None-Match` version"),
+        (status = 404, body = ErrorBody),
+    )
+)]
 async fn single_person(
-    Path(id): Path<u32>,
+    Path(key): Path<PersonKey>,
+    QueryParams(fields): QueryParams<FieldsParams>,
+    QueryParams(deleted): QueryParams<DeletedParams>,
+    headers: HeaderMap,
+    format: Format,
     State(state): State<Arc<AppState>>,
-) -> Result<Json<Person>, HttpAppError> {
-    let persons_guard = state.person_collection.read()?;
-    let filtered = persons_guard.iter().find(|t| t.id == id);
-    match filtered {
-        Some(filtered) => Ok(Json(filtered.clone())),
-        None => Err(HttpAppError::NotFound),
+) -> Result<Response, HttpAppError> {
+    let fields = FieldSet::try_from(fields)?;
+    let person = state.person_repository.get_by_key(key).await?;
+    if person.deleted_at.is_some() && !deleted.include_deleted {
+        return Err(HttpAppError::NotFound);
+    }
+    let tag = etag::for_version(person.version, !fields.is_all());
+    let etag_header = [(header::ETAG, etag::header_value(&tag))];
+    if etag::none_match(&headers, &tag) {
+        return Ok((StatusCode::NOT_MODIFIED, etag_header).into_response());
     }
+    Ok((etag_header, Negotiated(format, links::sparse(person, &fields))).into_response())
 }
 
+fn created(format: Format, person: Person) -> Response {
+    let location = [(header::LOCATION, links::href(links::PERSON_BY_ID, Some(person.id)))];
+    let etag = etag_header(person.version);
+    let body = Negotiated(format, links::person(person));
+    (StatusCode::CREATED, location, etag, body).into_response()
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/person",
+    tag = "persons",
+    summary = "Create a person",
+    request_body = NewPerson,
+    security((), ("api_key" = []), ("bearer" = [])),
+    responses(
+        (status = 201, body = Linked<Person>, headers(("Location" = String), ("ETag" = String))),
+        (status = 409, body = ErrorBody),
+        (status = 422, body = ValidationBody),
+    )
+)]
 async fn add_person(
+    format: Format,
     State(state): State<Arc<AppState>>,
-    Json(person): Json<Person>,
-) -> Result<StatusCode, HttpAppError> {
-    let mut persons_guard = state.person_collection.write()?;
-    let filtered = persons_guard.iter().any(|t| t.id == person.id);
-    if !filtered {
-        persons_guard.push(person);
-        Ok(StatusCode::CREATED)
-    } else {
-        Err(HttpAppError::Conflict)
+    ValidatedBody(mut person): ValidatedBody<NewPerson>,
+) -> Result<Response, HttpAppError> {
+    if state.settings().id_strategy == IdStrategy::Uuid {
+        person.uuid = Some(Uuid::new_v4());
     }
+    let person = state.person_repository.create(person).await?;
+    Ok(created(format, person))
 }
 
+/// The version a change expects, from `If-Match` or else the request body.
+fn expected_version(headers: &HeaderMap, body: Option<u32>) -> Result<u32, HttpAppError> {
+    match etag::if_match(headers)? {
+        Some(version) => Ok(version),
+        None => body
+            .filter(|version| *version != 0)
+            .ok_or(HttpAppError::PreconditionRequired),
+    }
+}
+
+fn etag_header(version: u32) -> [(header::HeaderName, HeaderValue); 1] {
+    [(header::ETAG, etag::header_value(&etag::for_version(version, false)))]
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/person",
+    tag = "persons",
+    summary = "Replace a person",
+    description = "Replaces the person at the version of `If-Match` or else of the body.",
+    params(PutParams, ("If-Match" = Option<String>, Header)),
+    request_body = Person,
+    security((), ("api_key" = []), ("bearer" = [])),
+    responses(
+        (status = 204, description = "Replaced", headers(("ETag" = String))),
+        (status = 201, description = "Created with `upsert=true`", body = Linked<Person>),
+        (status = 404, body = ErrorBody),
+        (status = 412, body = ErrorBody),
+        (status = 422, body = ValidationBody),
+        (status = 428, body = ErrorBody),
+    )
+)]
 async fn update_person(
+    QueryParams(params): QueryParams<PutParams>,
+    headers: HeaderMap,
+    format: Format,
     State(state): State<Arc<AppState>>,
-    Json(person): Json<Person>,
-) -> Result<StatusCode, HttpAppError> {
-    let mut persons_guard = state.person_collection.write()?;
-    let filtered = persons_guard.iter_mut().find(|t| t.id == person.id);
-    match filtered {
-        Some(p) => {
-            p.age = person.age;
-            p.date = person.date;
-            p.name = person.name;
-            Ok(StatusCode::NO_CONTENT)
+    ValidatedBody(mut person): ValidatedBody<Person>,
+) -> Result<Response, HttpAppError> {
+    let id = person.id;
+    // There is no version to expect of a person that does not exist yet.
+    let missing = params.upsert
+        && matches!(state.person_repository.get(id).await, Err(HttpAppError::NotFound));
+    if missing {
+        if state.settings().id_strategy == IdStrategy::Uuid && person.uuid.is_none() {
+            person.uuid = Some(Uuid::new_v4());
         }
-        None => Err(HttpAppError::NotFound),
+        state.person_repository.insert(Person { version: 0, ..person }).await?;
+        return Ok(created(format, state.person_repository.get(id).await?));
     }
+    person.version = expected_version(&headers, Some(person.version))?;
+    state.person_repository.update(person).await?;
+    let version = state.person_repository.get(id).await?.version;
+    Ok((StatusCode::NO_CONTENT, etag_header(version)).into_response())
 }
 
+#[utoipa::path(
+    patch,
+    path = "/api/v1/person/{id}",
+    tag = "persons",
+    summary = "Change some fields of a person",
+    params(("id" = String, Path), ("If-Match" = Option<String>, Header)),
+    request_body = PersonPatch,
+    security((), ("api_key" = []), ("bearer" = [])),
+    responses(
+        (status = 200, body = Linked<Person>, headers(("ETag" = String))),
+        (status = 404, body = ErrorBody),
+        (status = 412, body = ErrorBody),
+        (status = 422, body = ValidationBody),
+        (status = 428, body = ErrorBody),
+    )
+)]
+async fn patch_person(
+    Path(key): Path<PersonKey>,
+    headers: HeaderMap,
+    format: Format,
+    State(state): State<Arc<AppState>>,
+    ValidatedBody(mut patch): ValidatedBody<PersonPatch>,
+) -> Result<Response, HttpAppError> {
+    patch.version = Some(expected_version(&headers, patch.version)?);
+    let id = state.person_repository.resolve_id(key).await?;
+    let person = state.person_repository.patch(id, patch).await?;
+    Ok((etag_header(person.version), Negotiated(format, links::person(person))).into_response())
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/person/{id}",
+    tag = "persons",
+    summary = "Delete a person",
+    description = "Soft-deletes the person at the `If-Match` version, so that it can be restored.",
+    params(("id" = String, Path), ("If-Match" = String, Header)),
+    security((), ("api_key" = []), ("bearer" = [])),
+    responses(
+        (status = 204, description = "Deleted"),
+        (status = 404, body = ErrorBody),
+        (status = 412, body = ErrorBody),
+        (status = 428, body = ErrorBody),
+    )
+)]
 async fn delete_person(
-    Path(id): Path<u32>,
+    Path(key): Path<PersonKey>,
+    headers: HeaderMap,
     State(state): State<Arc<AppState>>,
 ) -> Result<StatusCode, HttpAppError> {
-    let mut persons_guard = state.person_collection.write()?;
-    let index = persons_guard.iter().position(|t| t.id == id);
-    match index {
-        Some(index) => {
-            persons_guard.remove(index);
-            Ok(StatusCode::NO_CONTENT)
+    let expected = expected_version(&headers, None)?;
+    let id = state.person_repository.resolve_id(key).await?;
+    state.person_repository.delete(id, expected).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/person/{id}/restore",
+    tag = "persons",
+    summary = "Restore a deleted person",
+    params(("id" = String, Path)),
+    security((), ("api_key" = []), ("bearer" = [])),
+    responses(
+        (status = 200, body = Linked<Person>),
+        (status = 404, body = ErrorBody),
+        (status = 409, description = "The person is not deleted", body = ErrorBody),
+    )
+)]
+async fn restore_person(
+    Path(key): Path<PersonKey>,
+    format: Format,
+    State(state): State<Arc<AppState>>,
+) -> Result<Negotiated<Linked<Person>>, HttpAppError> {
+    let id = state.person_repository.resolve_id(key).await?;
+    let person = state.person_repository.restore(id).await?;
+    Ok(Negotiated(format, links::person(person)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/person/{id}/history",
+    tag = "persons",
+    summary = "List the revisions of a person",
+    description = "The revisions since the last restart, with the authenticated principal as \
+                   the actor, or `anonymous`. The history is kept in memory only.",
+    params(("id" = String, Path)),
+    responses((status = 200, body = Vec<Revision>), (status = 404, body = ErrorBody))
+)]
+async fn person_history(
+    Path(key): Path<PersonKey>,
+    format: Format,
+    State(state): State<Arc<AppState>>,
+) -> Result<Negotiated<Vec<Revision>>, HttpAppError> {
+    let id = state.person_repository.resolve_id(key).await?;
+    let revisions = state.history.revisions(id)?;
+    if revisions.is_empty() {
+        state.person_repository.get(id).await?;
+    }
+    Ok(Negotiated(format, revisions))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::{self, Body};
+    use axum::http::{Method, Request};
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+
+    fn router() -> Router {
+        create_routes().with_state(Arc::new(AppState::for_tests()))
+    }
+
+    async fn send(
+        router: &Router,
+        method: Method,
+        uri: &str,
+        if_match: Option<&str>,
+        body: Option<Value>,
+    ) -> (StatusCode, Option<String>, Value) {
+        let mut request = Request::builder().method(method).uri(uri);
+        if let Some(version) = if_match {
+            request = request.header(header::IF_MATCH, version);
         }
-        None => Err(HttpAppError::NotFound),
+        let request = match body {
+            Some(body) => request
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string())),
+            None => request.body(Body::empty()),
+        };
+        let response = router.clone().oneshot(request.unwrap()).await.unwrap();
+        let status = response.status();
+        let etag = response.headers().get(header::ETAG);
+        let etag = etag.map(|tag| tag.to_str().unwrap().to_string());
+        let bytes = body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, etag, serde_json::from_slice(&bytes).unwrap_or(Value::Null))
+    }
+
+    #[tokio::test]
+    async fn persons_are_created_changed_and_deleted_at_their_version() {
+        let router = router();
+        let ann = json!({"name": "Ann", "age": 30, "date": "2000-01-02"});
+        let (status, _, created) =
+            send(&router, Method::POST, "/api/v1/person", None, Some(ann)).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let id = created["id"].as_u64().unwrap();
+        let uri = format!("/api/v1/person/{}", id);
+
+        let (status, etag, person) = send(&router, Method::GET, &uri, None, None).await;
+        assert_eq!((status, etag.as_deref()), (StatusCode::OK, Some("\"v1\"")));
+        assert_eq!(person["name"], "Ann");
+
+        let older = json!({"id": id, "name": "Ann B", "age": 31, "date": "2000-01-02"});
+        let put = |if_match| {
+            send(&router, Method::PUT, "/api/v1/person", if_match, Some(older.clone()))
+        };
+        assert_eq!(put(None).await.0, StatusCode::PRECONDITION_REQUIRED);
+        assert_eq!(put(Some("\"v1\"")).await.0, StatusCode::NO_CONTENT);
+        assert_eq!(put(Some("\"v1\"")).await.0, StatusCode::PRECONDITION_FAILED);
+
+        let patch = Some(json!({"age": 32}));
+        let (status, _, person) = send(&router, Method::PATCH, &uri, Some("\"v2\""), patch).await;
+        assert_eq!((status, person["age"].as_u64()), (StatusCode::OK, Some(32)));
+
+        let delete = |if_match| send(&router, Method::DELETE, &uri, Some(if_match), None);
+        assert_eq!(delete("\"v2\"").await.0, StatusCode::PRECONDITION_FAILED);
+        assert_eq!(delete("\"v3\"").await.0, StatusCode::NO_CONTENT);
+    }
+
+    #[tokio::test]
+    async fn deleted_persons_are_hidden_until_restored() {
+        let router = router();
+        let uri = "/api/v1/person/1";
+        let (status, _, _) = send(&router, Method::DELETE, uri, Some("\"v1\""), None).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert_eq!(send(&router, Method::GET, uri, None, None).await.0, StatusCode::NOT_FOUND);
+        let (status, _, person) =
+            send(&router, Method::GET, "/api/v1/person/1?include_deleted=true", None, None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(person["deleted_at"].is_string());
+
+        let patch = Some(json!({"age": 50}));
+        let (status, _, _) = send(&router, Method::PATCH, uri, Some("\"v2\""), patch).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (_, _, persons) = send(&router, Method::GET, "/api/v1/persons", None, None).await;
+        assert!(persons.as_array().unwrap().iter().all(|person| person["id"] != 1));
+
+        let restore = "/api/v1/person/1/restore";
+        let (status, _, person) = send(&router, Method::POST, restore, None, None).await;
+        assert_eq!((status, person["version"].as_u64()), (StatusCode::OK, Some(3)));
+        assert_eq!(send(&router, Method::POST, restore, None, None).await.0, StatusCode::CONFLICT);
+        assert_eq!(send(&router, Method::GET, uri, None, None).await.0, StatusCode::OK);
     }
 }
@@ -0,0 +1,56 @@
+use std::io;
+use tokio::runtime::{Builder, Handle, Runtime};
+use crate::config::Config;
+use crate::errors::ConfigError;
+
+/// What tokio allows unless told otherwise.
+pub const DEFAULT_MAX_BLOCKING_THREADS: usize = 512;
+
+/// The threads of the tokio runtime: `WORKER_THREADS` run the requests, one
+/// per CPU core by default, and up to `MAX_BLOCKING_THREADS` (512) run the
+/// blocking work such as file access and password hashing.
+#[derive(Debug, Clone, Copy)]
+pub struct RuntimeSettings {
+    pub worker_threads: Option<usize>,
+    pub max_blocking_threads: usize,
+}
+
+impl RuntimeSettings {
+    pub fn from_config(config: &Config) -> Result<Self, ConfigError> {
+        Ok(RuntimeSettings {
+            worker_threads: positive(config, "WORKER_THREADS")?,
+            max_blocking_threads: positive(config, "MAX_BLOCKING_THREADS")?
+                .unwrap_or(DEFAULT_MAX_BLOCKING_THREADS),
+        })
+    }
+
+    pub fn build(&self) -> io::Result<Runtime> {
+        let mut builder = Builder::new_multi_thread();
+        if let Some(worker_threads) = self.worker_threads {
+            builder.worker_threads(worker_threads);
+        }
+        builder.max_blocking_threads(self.max_blocking_threads).enable_all().build()
+    }
+
+    /// Logs the threads in effect, from within the runtime.
+    pub fn log(&self) {
+        tracing::info!(
+            "Runtime with {} worker threads and up to {} blocking threads",
+            Handle::current().metrics().num_workers(),
+            self.max_blocking_threads
+        );
+    }
+}
+
+fn positive(config: &Config, name: &str) -> Result<Option<usize>, ConfigError> {
+    match config.var(name) {
+        Some(value) => match value.parse() {
+            Ok(0) | Err(_) => Err(ConfigError::Invalid(format!(
+                "{} must be a positive number, got {}",
+                name, value
+            ))),
+            Ok(threads) => Ok(Some(threads)),
+        },
+        None => Ok(None),
+    }
+}
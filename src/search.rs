@@ -0,0 +1,78 @@
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use crate::person::Person;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SearchParams {
+    pub q: String,
+    #[serde(default)]
+    pub fuzzy: bool,
+    pub limit: Option<u64>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct SearchHit {
+    pub score: f64,
+    #[serde(flatten)]
+    pub person: Person,
+}
+
+pub fn score(name: &str, query: &str, fuzzy: bool) -> Option<f64> {
+    let name = name.to_lowercase();
+    let query = query.to_lowercase();
+    if name == query {
+        return Some(1.0);
+    }
+    if name.starts_with(&query) {
+        return Some(0.9);
+    }
+    if name.split_whitespace().any(|word| word.starts_with(&query)) {
+        return Some(0.8);
+    }
+    if name.contains(&query) {
+        return Some(0.6);
+    }
+    if !fuzzy {
+        return None;
+    }
+    let query_len = query.chars().count();
+    let max_distance = (query_len / 4).max(1);
+    name.split_whitespace()
+        .map(|word| levenshtein(word, &query))
+        .min()
+        .filter(|distance| *distance <= max_distance)
+        .map(|distance| 0.5 * (1.0 - distance as f64 / query_len.max(1) as f64))
+}
+
+pub fn rank(persons: Vec<Person>, query: &str, fuzzy: bool, limit: usize) -> Vec<SearchHit> {
+    let mut hits: Vec<SearchHit> = persons
+        .into_iter()
+        .filter_map(|person| {
+            score(&person.name, query, fuzzy).map(|score| SearchHit { score, person })
+        })
+        .collect();
+    hits.sort_by(|a, b| {
+        b.score
+            .total_cmp(&a.score)
+            .then(a.person.id.cmp(&b.person.id))
+    });
+    hits.truncate(limit);
+    hits
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = usize::from(ca != *cb);
+            current[j + 1] = (previous[j] + cost)
+                .min(previous[j + 1] + 1)
+                .min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
@@ -0,0 +1,69 @@
+use std::sync::Arc;
+use axum::{
+    extract::State,
+    http::{header, HeaderValue},
+    response::Response,
+};
+use crate::config::Config;
+use crate::errors::ConfigError;
+
+/// The landing page loads its stylesheet from `/static` and no scripts or
+/// images, so its policy allows nothing else.
+const LANDING_PAGE_CSP: &str = "default-src 'none'; style-src 'self'; base-uri 'none'; \
+                                form-action 'none'; frame-ancestors 'none'";
+
+pub struct SecurityHeaders {
+    hsts: Option<HeaderValue>,
+}
+
+impl SecurityHeaders {
+    /// Sends `Strict-Transport-Security` for `HSTS_MAX_AGE_SECS`, which should
+    /// only be set when clients reach the service over TLS.
+    pub fn from_config(config: &Config) -> Result<Self, ConfigError> {
+        let hsts = match config.var("HSTS_MAX_AGE_SECS") {
+            Some(max_age) => {
+                let max_age: u64 = max_age.parse().map_err(|_| {
+                    ConfigError::Invalid(format!(
+                        "HSTS_MAX_AGE_SECS must be a number of seconds, got {}",
+                        max_age
+                    ))
+                })?;
+                let value = format!("max-age={}; includeSubDomains", max_age);
+                Some(HeaderValue::from_str(&value).expect("digits are a valid header value"))
+            }
+            None => None,
+        };
+        Ok(SecurityHeaders { hsts })
+    }
+}
+
+/// Adds the headers to every response, keeping any a handler set itself.
+pub async fn set_headers(
+    State(security): State<Arc<SecurityHeaders>>,
+    mut response: Response,
+) -> Response {
+    let is_html = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/html"));
+    let headers = response.headers_mut();
+    headers
+        .entry(header::X_CONTENT_TYPE_OPTIONS)
+        .or_insert(HeaderValue::from_static("nosniff"));
+    headers
+        .entry(header::X_FRAME_OPTIONS)
+        .or_insert(HeaderValue::from_static("DENY"));
+    headers
+        .entry(header::REFERRER_POLICY)
+        .or_insert(HeaderValue::from_static("no-referrer"));
+    if is_html {
+        headers
+            .entry(header::CONTENT_SECURITY_POLICY)
+            .or_insert(HeaderValue::from_static(LANDING_PAGE_CSP));
+    }
+    if let Some(hsts) = &security.hsts {
+        headers.entry(header::STRICT_TRANSPORT_SECURITY).or_insert(hsts.clone());
+    }
+    response
+}
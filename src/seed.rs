@@ -0,0 +1,91 @@
+use std::collections::HashSet;
+use std::path::Path;
+use thiserror::Error;
+use crate::errors::HttpAppError;
+use crate::person::Person;
+use crate::repository::PersonRepository;
+use crate::validation::Validate;
+
+#[derive(Debug, Error)]
+pub enum SeedError {
+    #[error("cannot read seed file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("malformed JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("malformed CSV: {0}")]
+    Csv(#[from] csv::Error),
+    #[error("record {record}: {reason}")]
+    Invalid { record: usize, reason: String },
+}
+
+pub fn load(path: &Path) -> Result<Vec<Person>, SeedError> {
+    let is_csv = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("csv"));
+    let persons = if is_csv {
+        csv::Reader::from_path(path)?
+            .deserialize()
+            .collect::<Result<Vec<Person>, _>>()?
+    } else {
+        serde_json::from_slice(&std::fs::read(path)?)?
+    };
+    validate(&persons)?;
+    Ok(persons)
+}
+
+fn validate(persons: &[Person]) -> Result<(), SeedError> {
+    let mut ids = HashSet::new();
+    for (index, person) in persons.iter().enumerate() {
+        let record = index + 1;
+        if let Err(e) = person.validate() {
+            return Err(SeedError::Invalid { record, reason: e.to_string() });
+        }
+        if !ids.insert(person.id) {
+            return Err(SeedError::Invalid {
+                record,
+                reason: format!("duplicate id {}", person.id),
+            });
+        }
+    }
+    Ok(())
+}
+
+pub async fn apply(
+    repository: &dyn PersonRepository,
+    persons: Vec<Person>,
+) -> Result<usize, HttpAppError> {
+    let mut created = 0;
+    for person in persons {
+        let id = person.id;
+        match repository.insert(person).await {
+            Ok(()) => created += 1,
+            Err(HttpAppError::Conflict) => tracing::debug!("Seed person {} already exists", id),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(created)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::person::create_person_collection;
+    use crate::validation::MAX_AGE;
+
+    #[test]
+    fn records_are_validated_like_requests() {
+        let mut persons = create_person_collection();
+        assert!(validate(&persons).is_ok());
+        persons[1].age = MAX_AGE + 1;
+        let Err(SeedError::Invalid { record, reason }) = validate(&persons) else {
+            panic!("an age over the maximum was accepted");
+        };
+        assert_eq!(record, 2);
+        assert!(reason.contains("age"), "{}", reason);
+
+        persons[1].age = 41;
+        persons[1].id = 1;
+        let duplicate = validate(&persons);
+        assert!(matches!(duplicate, Err(SeedError::Invalid { record: 2, .. })));
+    }
+}
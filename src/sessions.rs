@@ -0,0 +1,369 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use async_trait::async_trait;
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    routing::post,
+    Extension, Json, Router,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Duration, Utc};
+use hmac::{Hmac, Mac};
+use redis::aio::ConnectionManager;
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use uuid::Uuid;
+use crate::auth::{self, Principal, Role};
+use crate::config::Config;
+use crate::errors::{AuthError, HttpAppError};
+use crate::extract::JsonBody;
+use crate::proxy::Client;
+use crate::routes::AppState;
+
+pub const SESSION_COOKIE: &str = "session";
+
+const DEFAULT_SESSION_TTL_SECS: i64 = 3600;
+const MAX_SESSION_TTL_SECS: i64 = 366 * 24 * 3600;
+const DEFAULT_REDIS_URL: &str = "redis://127.0.0.1/";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub name: String,
+    pub roles: Vec<Role>,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Where sessions live between requests, by session id.
+#[async_trait]
+pub trait SessionStore: Send + Sync {
+    async fn insert(&self, id: &str, session: &Session) -> Result<(), HttpAppError>;
+
+    /// The session, unless it is unknown or has expired.
+    async fn get(&self, id: &str) -> Result<Option<Session>, HttpAppError>;
+
+    async fn remove(&self, id: &str) -> Result<(), HttpAppError>;
+}
+
+#[derive(Default)]
+pub struct MemorySessionStore {
+    sessions: RwLock<HashMap<String, Session>>,
+}
+
+#[async_trait]
+impl SessionStore for MemorySessionStore {
+    async fn insert(&self, id: &str, session: &Session) -> Result<(), HttpAppError> {
+        let mut sessions = self.sessions.write()?;
+        let now = Utc::now();
+        sessions.retain(|_, session| session.expires_at > now);
+        sessions.insert(id.to_string(), session.clone());
+        Ok(())
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Session>, HttpAppError> {
+        let sessions = self.sessions.read()?;
+        Ok(sessions.get(id).filter(|session| session.expires_at > Utc::now()).cloned())
+    }
+
+    async fn remove(&self, id: &str) -> Result<(), HttpAppError> {
+        self.sessions.write()?.remove(id);
+        Ok(())
+    }
+}
+
+/// Keeps sessions in Redis, which expires them on its own.
+pub struct RedisSessionStore {
+    connection: ConnectionManager,
+}
+
+impl RedisSessionStore {
+    pub async fn connect(url: &str) -> Result<Self, redis::RedisError> {
+        let client = redis::Client::open(url)?;
+        let connection = ConnectionManager::new(client).await?;
+        Ok(RedisSessionStore { connection })
+    }
+}
+
+fn session_key(id: &str) -> String {
+    format!("session:{}", id)
+}
+
+#[async_trait]
+impl SessionStore for RedisSessionStore {
+    async fn insert(&self, id: &str, session: &Session) -> Result<(), HttpAppError> {
+        let value = serde_json::to_string(session)
+            .map_err(|e| HttpAppError::StorageError(e.to_string()))?;
+        let ttl = (session.expires_at - Utc::now()).num_seconds().max(1) as u64;
+        let mut connection = self.connection.clone();
+        let _: () = connection.set_ex(session_key(id), value, ttl).await?;
+        Ok(())
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Session>, HttpAppError> {
+        let mut connection = self.connection.clone();
+        let value: Option<String> = connection.get(session_key(id)).await?;
+        value
+            .map(|value| serde_json::from_str(&value))
+            .transpose()
+            .map_err(|e| HttpAppError::StorageError(e.to_string()))
+    }
+
+    async fn remove(&self, id: &str) -> Result<(), HttpAppError> {
+        let mut connection = self.connection.clone();
+        let _: () = connection.del(session_key(id)).await?;
+        Ok(())
+    }
+}
+
+/// Session cookies signed with `SESSION_SECRET`, so that a session id cannot
+/// be guessed or forged even if the store is shared.
+pub struct Sessions {
+    store: Box<dyn SessionStore>,
+    secret: Vec<u8>,
+    ttl: Duration,
+    secure: bool,
+}
+
+impl Sessions {
+    /// Reads `SESSION_SECRET`, `SESSION_STORE` (`memory` or `redis`),
+    /// `SESSION_TTL_SECS`, at most a year, and `SESSION_COOKIE_SECURE`; `None`
+    /// without a secret.
+    pub async fn from_config(config: &Config) -> Result<Option<Self>, AuthError> {
+        let Some(secret) = config.var("SESSION_SECRET") else {
+            return Ok(None);
+        };
+        let store: Box<dyn SessionStore> = match config.var("SESSION_STORE") {
+            Some("memory") | None => Box::new(MemorySessionStore::default()),
+            Some("redis") => {
+                let url = config.var("REDIS_URL").unwrap_or(DEFAULT_REDIS_URL);
+                Box::new(RedisSessionStore::connect(url).await?)
+            }
+            Some(other) => {
+                return Err(AuthError::Config(format!("Unknown session store {}", other)))
+            }
+        };
+        let ttl = match config.var("SESSION_TTL_SECS") {
+            Some(value) => value
+                .parse()
+                .ok()
+                .filter(|ttl| (1..=MAX_SESSION_TTL_SECS).contains(ttl))
+                .and_then(Duration::try_seconds)
+                .ok_or_else(|| {
+                    AuthError::Config(format!(
+                        "SESSION_TTL_SECS must be a positive number of seconds up to {}, got {}",
+                        MAX_SESSION_TTL_SECS, value
+                    ))
+                })?,
+            None => Duration::seconds(DEFAULT_SESSION_TTL_SECS),
+        };
+        Ok(Some(Sessions {
+            store,
+            secret: secret.as_bytes().to_vec(),
+            ttl,
+            secure: config.enabled("SESSION_COOKIE_SECURE"),
+        }))
+    }
+
+    fn sign(&self, id: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret).unwrap();
+        mac.update(id.as_bytes());
+        URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes())
+    }
+
+    /// The session id of a cookie value, if its signature holds.
+    fn verify<'a>(&self, cookie: &'a str) -> Option<&'a str> {
+        let (id, signature) = cookie.split_once('.')?;
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret).unwrap();
+        mac.update(id.as_bytes());
+        mac.verify_slice(&URL_SAFE_NO_PAD.decode(signature).ok()?).ok()?;
+        Some(id)
+    }
+
+    /// The principal of the session cookie, ignoring cookies that are forged,
+    /// expired or unknown so that stale browsers can still read.
+    pub async fn principal(&self, headers: &HeaderMap) -> Result<Option<Principal>, HttpAppError> {
+        let Some(id) = session_cookie(headers).and_then(|cookie| self.verify(cookie)) else {
+            return Ok(None);
+        };
+        Ok(self.store.get(id).await?.map(|session| Principal {
+            name: session.name,
+            roles: session.roles,
+        }))
+    }
+
+    fn cookie(&self, value: &str, max_age: i64) -> HeaderValue {
+        let secure = if self.secure { "; Secure" } else { "" };
+        let cookie = format!(
+            "{}={}; Path=/; HttpOnly; SameSite=Lax; Max-Age={}{}",
+            SESSION_COOKIE, value, max_age, secure
+        );
+        HeaderValue::from_str(&cookie).unwrap()
+    }
+}
+
+fn session_cookie(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(name, _)| *name == SESSION_COOKIE)
+        .map(|(_, value)| value)
+}
+
+#[derive(Deserialize)]
+pub struct LoginRequest {
+    pub username: String,
+    pub password: String,
+}
+
+pub fn create_session_routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/auth/login", post(login))
+        .route("/auth/logout", post(logout))
+}
+
+fn sessions(state: &AppState) -> Result<&Sessions, HttpAppError> {
+    state.sessions.as_ref().ok_or(HttpAppError::NotFound)
+}
+
+/// Starts a session for the admin credentials or a user account and sets its
+/// cookie.
+async fn login(
+    State(state): State<Arc<AppState>>,
+    Extension(client): Extension<Client>,
+    JsonBody(request): JsonBody<LoginRequest>,
+) -> Result<Response, HttpAppError> {
+    let sessions = sessions(&state)?;
+    let principal =
+        auth::check_login(&state, &request.username, &request.password, client.ip).await?;
+    let expires_at = Utc::now().checked_add_signed(sessions.ttl).ok_or_else(|| {
+        HttpAppError::StorageError("the session would outlast the supported dates".to_string())
+    })?;
+    let session = Session {
+        name: principal.name,
+        roles: principal.roles,
+        expires_at,
+    };
+    let id = Uuid::new_v4().simple().to_string();
+    sessions.store.insert(&id, &session).await?;
+    tracing::info!("{} logged in", session.name);
+
+    let value = format!("{}.{}", id, sessions.sign(&id));
+    let cookie = sessions.cookie(&value, sessions.ttl.num_seconds());
+    Ok(([(header::SET_COOKIE, cookie)], Json(session)).into_response())
+}
+
+/// Ends the session of the cookie, if any, and clears the cookie.
+async fn logout(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<Response, HttpAppError> {
+    let sessions = sessions(&state)?;
+    if let Some(id) = session_cookie(&headers).and_then(|cookie| sessions.verify(cookie)) {
+        sessions.store.remove(id).await?;
+    }
+    let cookie = sessions.cookie("", 0);
+    Ok((StatusCode::NO_CONTENT, [(header::SET_COOKIE, cookie)]).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::IpAddr;
+    use axum::body::Body;
+    use axum::http::Request;
+    use tower::ServiceExt;
+    use crate::auth::AdminCredentials;
+    use crate::proxy::Scheme;
+
+    async fn state() -> Arc<AppState> {
+        let config = Config::from_overrides(&[
+            ("ADMIN_USER", "admin"),
+            ("ADMIN_PASSWORD", "s3cret"),
+            ("SESSION_SECRET", "session s3cret"),
+        ]);
+        let mut state = AppState::for_tests();
+        state.admin = AdminCredentials::from_config(&config).unwrap();
+        state.sessions = Sessions::from_config(&config).await.unwrap();
+        Arc::new(state)
+    }
+
+    async fn post(state: &Arc<AppState>, uri: &str, cookie: Option<&str>, body: &str) -> Response {
+        let mut request = Request::post(uri).header(header::CONTENT_TYPE, "application/json");
+        if let Some(cookie) = cookie {
+            request = request.header(header::COOKIE, cookie);
+        }
+        let client = Client { ip: IpAddr::from([127, 0, 0, 1]), scheme: Scheme::Http };
+        let router = create_session_routes().layer(Extension(client)).with_state(state.clone());
+        router.oneshot(request.body(Body::from(body.to_string())).unwrap()).await.unwrap()
+    }
+
+    fn cookie_headers(cookie: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::COOKIE, HeaderValue::from_str(cookie).unwrap());
+        headers
+    }
+
+    #[tokio::test]
+    async fn logins_start_sessions_until_logout() {
+        let state = state().await;
+        let sessions = state.sessions.as_ref().unwrap();
+        let login = r#"{"username": "admin", "password": "s3cret"}"#;
+        let response = post(&state, "/auth/login", None, login).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let set_cookie = response.headers()[header::SET_COOKIE].to_str().unwrap();
+        assert!(set_cookie.contains("HttpOnly; SameSite=Lax; Max-Age=3600"), "{}", set_cookie);
+        let cookie = set_cookie.split(';').next().unwrap().to_string();
+        let principal = sessions.principal(&cookie_headers(&cookie)).await.unwrap().unwrap();
+        assert_eq!((principal.name.as_str(), principal.roles), ("admin", vec![Role::Admin]));
+
+        let response = post(&state, "/auth/logout", Some(&cookie), "").await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(response.headers()[header::SET_COOKIE].to_str().unwrap().contains("Max-Age=0"));
+        assert!(sessions.principal(&cookie_headers(&cookie)).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn wrong_passwords_start_no_session() {
+        let state = state().await;
+        let login = r#"{"username": "admin", "password": "guess"}"#;
+        let response = post(&state, "/auth/login", None, login).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(response.headers().get(header::SET_COOKIE).is_none());
+    }
+
+    #[tokio::test]
+    async fn expired_and_forged_sessions_are_ignored() {
+        let state = state().await;
+        let sessions = state.sessions.as_ref().unwrap();
+        let session = |expires_at| Session { name: "admin".to_string(), roles: vec![], expires_at };
+        sessions.store.insert("old", &session(Utc::now() - Duration::seconds(1))).await.unwrap();
+        sessions.store.insert("new", &session(Utc::now() + Duration::hours(1))).await.unwrap();
+
+        let old = format!("{}=old.{}", SESSION_COOKIE, sessions.sign("old"));
+        assert!(sessions.principal(&cookie_headers(&old)).await.unwrap().is_none());
+        let new = format!("{}=new.{}", SESSION_COOKIE, sessions.sign("new"));
+        assert!(sessions.principal(&cookie_headers(&new)).await.unwrap().is_some());
+        let forged = format!("{}=new.{}", SESSION_COOKIE, sessions.sign("old"));
+        assert!(sessions.principal(&cookie_headers(&forged)).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn ttls_are_bounded() {
+        let too_long = (MAX_SESSION_TTL_SECS + 1).to_string();
+        for ttl in ["0", "-1", "9223372036854775807", too_long.as_str()] {
+            let config =
+                Config::from_overrides(&[("SESSION_SECRET", "s3cret"), ("SESSION_TTL_SECS", ttl)]);
+            assert!(Sessions::from_config(&config).await.is_err(), "{}", ttl);
+        }
+        let longest = MAX_SESSION_TTL_SECS.to_string();
+        let config =
+            Config::from_overrides(&[("SESSION_SECRET", "s3cret"), ("SESSION_TTL_SECS", &longest)]);
+        let sessions = Sessions::from_config(&config).await.unwrap().unwrap();
+        assert_eq!(sessions.ttl.num_seconds(), MAX_SESSION_TTL_SECS);
+    }
+}
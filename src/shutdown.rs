@@ -0,0 +1,105 @@
+use std::future::{Future, IntoFuture};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::signal;
+use tokio::sync::watch;
+use tokio::time;
+use crate::config::Config;
+use crate::errors::ConfigError;
+use crate::otel;
+use crate::routes::AppState;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub struct Shutdown {
+    delay: Duration,
+    timeout: Duration,
+    draining: watch::Sender<bool>,
+}
+
+impl Shutdown {
+    /// On SIGTERM or SIGINT readiness fails at once, and the listener closes
+    /// `SHUTDOWN_DELAY_SECS` (0) later so that load balancers can notice.
+    /// In-flight requests then get `SHUTDOWN_TIMEOUT_SECS` (30) to finish.
+    pub fn from_config(config: &Config) -> Result<Self, ConfigError> {
+        Ok(Shutdown {
+            delay: secs(config, "SHUTDOWN_DELAY_SECS", Duration::ZERO)?,
+            timeout: secs(config, "SHUTDOWN_TIMEOUT_SECS", DEFAULT_TIMEOUT)?,
+            draining: watch::Sender::new(false),
+        })
+    }
+
+    /// Waits for the signal, then tells every listener to stop taking
+    /// connections.
+    pub async fn listen(self: Arc<Self>, state: Arc<AppState>) {
+        let signal = wait_for_signal().await;
+        state.readiness.shut_down();
+        tracing::info!("Received {}, shutting down", signal);
+        time::sleep(self.delay).await;
+        tracing::info!("Draining requests for up to {}s", self.timeout.as_secs());
+        self.draining.send_replace(true);
+    }
+
+    /// Resolves when the listeners are to stop taking connections.
+    pub fn requested(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut draining = self.draining.subscribe();
+        async move {
+            // Fails only once the `Shutdown` is dropped, on the way out.
+            let _ = draining.wait_for(|draining| *draining).await;
+        }
+    }
+
+    /// Runs the servers until they have drained, or gives up on the requests
+    /// still running once the timeout is over.
+    pub async fn drain<F: IntoFuture>(&self, servers: F) -> Option<F::Output> {
+        let deadline = async {
+            self.requested().await;
+            time::sleep(self.timeout).await;
+        };
+        tokio::select! {
+            output = servers.into_future() => Some(output),
+            () = deadline => {
+                tracing::warn!("Requests still running after the shutdown timeout are dropped");
+                None
+            }
+        }
+    }
+}
+
+/// Writes out what storage still buffers and sends the last spans, once no
+/// request runs anymore.
+pub async fn close(state: &AppState) {
+    if let Err(e) = state.person_repository.close().await {
+        tracing::error!("Could not close the storage: {}", e);
+    }
+    otel::shutdown();
+    tracing::info!("Shut down");
+}
+
+async fn wait_for_signal() -> &'static str {
+    let interrupt = async {
+        signal::ctrl_c().await.expect("Could not listen for SIGINT");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("Could not listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        () = interrupt => "SIGINT",
+        () = terminate => "SIGTERM",
+    }
+}
+
+fn secs(config: &Config, name: &str, default: Duration) -> Result<Duration, ConfigError> {
+    match config.var(name) {
+        Some(value) => value.parse().map(Duration::from_secs).map_err(|_| {
+            ConfigError::Invalid(format!("{} must be a number of seconds, got {}", name, value))
+        }),
+        None => Ok(default),
+    }
+}
@@ -0,0 +1,281 @@
+use std::sync::Arc;
+use axum::{
+    body::{Body, Bytes},
+    extract::{FromRequest, Request, State},
+    http::{HeaderMap, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use crate::config::Config;
+use crate::errors::{ConfigError, HttpAppError};
+use crate::graphql;
+use crate::routes::AppState;
+
+pub const SIGNATURE_HEADER: &str = "X-Signature";
+pub const TIMESTAMP_HEADER: &str = "X-Timestamp";
+
+const DEFAULT_MAX_AGE_SECS: u64 = 300;
+
+/// Shared secrets that `/api` and `/graphql` requests must be signed with, by
+/// label.
+pub struct RequestSigning {
+    secrets: Vec<(String, Vec<u8>)>,
+    max_age: u64,
+}
+
+impl RequestSigning {
+    /// Reads `label:secret` entries from `SIGNING_SECRETS`, separated by commas,
+    /// and `SIGNATURE_MAX_AGE_SECS`, how far a timestamp may be from now;
+    /// `None` when no secret is set. Several secrets allow rotating one.
+    pub fn from_config(config: &Config) -> Result<Option<Self>, ConfigError> {
+        let Some(entries) = config.var("SIGNING_SECRETS") else {
+            return Ok(None);
+        };
+        let secrets = entries
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| match entry.split_once(':') {
+                Some((label, secret)) if !secret.is_empty() => {
+                    Ok((label.trim().to_string(), secret.as_bytes().to_vec()))
+                }
+                _ => Err(ConfigError::Invalid(
+                    "signing secrets are written as label:secret".to_string(),
+                )),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let max_age = match config.var("SIGNATURE_MAX_AGE_SECS") {
+            Some(secs) => secs.parse().map_err(|_| {
+                ConfigError::Invalid(format!(
+                    "SIGNATURE_MAX_AGE_SECS must be a number of seconds, got {}",
+                    secs
+                ))
+            })?,
+            None => DEFAULT_MAX_AGE_SECS,
+        };
+        Ok(Some(RequestSigning { secrets, max_age }))
+    }
+
+    /// The label of the secret that signed the request, checking the timestamp
+    /// first so that a captured request cannot be replayed for long.
+    fn check(&self, headers: &HeaderMap, message: &[u8]) -> Result<&str, HttpAppError> {
+        let invalid = |reason: &str| HttpAppError::InvalidSignature(reason.to_string());
+        let signature = headers
+            .get(SIGNATURE_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(decode_hex)
+            .ok_or_else(|| invalid("an X-Signature header is required"))?;
+        let timestamp: i64 = headers
+            .get(TIMESTAMP_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok())
+            .ok_or_else(|| invalid("an X-Timestamp header is required"))?;
+        if Utc::now().timestamp().abs_diff(timestamp) > self.max_age {
+            return Err(invalid("the timestamp is too far from now"));
+        }
+        self.secrets
+            .iter()
+            .find(|(_, secret)| {
+                let mut mac = Hmac::<Sha256>::new_from_slice(secret).unwrap();
+                mac.update(message);
+                mac.verify_slice(&signature).is_ok()
+            })
+            .map(|(label, _)| label.as_str())
+            .ok_or_else(|| invalid("the signature does not match"))
+    }
+}
+
+fn decode_hex(value: &str) -> Option<Vec<u8>> {
+    let value = value.strip_prefix("sha256=").unwrap_or(value);
+    if value.len() % 2 != 0 {
+        return None;
+    }
+    (0..value.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(value.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// What a client signs: the method, path with query, timestamp and body,
+/// each of the first three followed by a newline.
+fn message(request: &Request, timestamp: &str, body: &[u8]) -> Vec<u8> {
+    let path = request.uri().path_and_query().map_or("/", |path| path.as_str());
+    let mut message = format!("{}\n{}\n{}\n", request.method(), path, timestamp).into_bytes();
+    message.extend_from_slice(body);
+    message
+}
+
+/// Rejects `/api` and `/graphql` requests that are unsigned, stale or tampered with, once
+/// signing secrets are configured. The body is read here and handed on.
+pub async fn verify(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, HttpAppError> {
+    let Some(signing) = &state.signing else {
+        return Ok(next.run(request).await);
+    };
+    if !request.uri().path().starts_with("/api") && !graphql::is_call(&request) {
+        return Ok(next.run(request).await);
+    }
+    let (parts, body) = request.into_parts();
+    let body = Bytes::from_request(Request::from_parts(parts.clone(), body), &state)
+        .await
+        .map_err(|e| match e.status() {
+            StatusCode::PAYLOAD_TOO_LARGE => HttpAppError::PayloadTooLarge,
+            _ => HttpAppError::BadRequest(e.body_text()),
+        })?;
+    let request = Request::from_parts(parts, Body::from(body.clone()));
+    let timestamp = request
+        .headers()
+        .get(TIMESTAMP_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    let message = message(&request, timestamp, &body);
+    match signing.check(request.headers(), &message) {
+        Ok(label) => tracing::debug!("Request signed with {}", label),
+        Err(e) => {
+            tracing::warn!("Rejected {} {}: {}", request.method(), request.uri(), e);
+            return Err(e);
+        }
+    }
+    Ok(next.run(request).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::{header, HeaderValue};
+    use axum::middleware;
+    use tower::ServiceExt;
+
+    const SECRET: &str = "s3cret";
+
+    fn signing() -> RequestSigning {
+        let config = Config::from_overrides(&[("SIGNING_SECRETS", "old:0ld, test:s3cret")]);
+        RequestSigning::from_config(&config).unwrap().unwrap()
+    }
+
+    fn state() -> Arc<AppState> {
+        let mut state = AppState::for_tests();
+        state.signing = Some(signing());
+        Arc::new(state)
+    }
+
+    fn sign(secret: &str, message: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(message);
+        mac.finalize().into_bytes().iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    fn headers(timestamp: &str, signature: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(TIMESTAMP_HEADER, HeaderValue::from_str(timestamp).unwrap());
+        headers.insert(SIGNATURE_HEADER, HeaderValue::from_str(signature).unwrap());
+        headers
+    }
+
+    fn rejected(result: Result<&str, HttpAppError>) -> String {
+        match result {
+            Err(HttpAppError::InvalidSignature(reason)) => reason,
+            other => panic!("expected an invalid signature, got {:?}", other.map(str::to_string)),
+        }
+    }
+
+    #[test]
+    fn signatures_name_their_secret() {
+        let signing = signing();
+        let now = Utc::now().timestamp().to_string();
+        let signature = sign(SECRET, b"message");
+        assert_eq!(signing.check(&headers(&now, &signature), b"message").unwrap(), "test");
+        let prefixed = format!("sha256={}", sign("0ld", b"message"));
+        assert_eq!(signing.check(&headers(&now, &prefixed), b"message").unwrap(), "old");
+    }
+
+    #[test]
+    fn bad_signatures_are_rejected() {
+        let signing = signing();
+        let now = Utc::now().timestamp().to_string();
+        let tampered = headers(&now, &sign(SECRET, b"message"));
+        assert_eq!(rejected(signing.check(&tampered, b"messagE")), "the signature does not match");
+        let other = headers(&now, &sign("guess", b"message"));
+        assert_eq!(rejected(signing.check(&other, b"message")), "the signature does not match");
+        let garbled = headers(&now, "sha256=zz");
+        let reason = rejected(signing.check(&garbled, b"message"));
+        assert_eq!(reason, "an X-Signature header is required");
+    }
+
+    #[test]
+    fn timestamps_must_be_recent() {
+        let signing = signing();
+        let signature = sign(SECRET, b"message");
+        let stale = (Utc::now().timestamp() - 301).to_string();
+        let ahead = (Utc::now().timestamp() + 301).to_string();
+        let extremes = ["-9223372036854775808", "9223372036854775807"];
+        for timestamp in [stale.as_str(), ahead.as_str()].into_iter().chain(extremes) {
+            let result = signing.check(&headers(timestamp, &signature), b"message");
+            assert_eq!(rejected(result), "the timestamp is too far from now", "{}", timestamp);
+        }
+    }
+
+    #[test]
+    fn both_headers_are_required() {
+        let signing = signing();
+        let now = Utc::now().timestamp().to_string();
+        let mut headers = headers(&now, &sign(SECRET, b"message"));
+        headers.remove(TIMESTAMP_HEADER);
+        let reason = rejected(signing.check(&headers, b"message"));
+        assert_eq!(reason, "an X-Timestamp header is required");
+        let reason = rejected(signing.check(&HeaderMap::new(), b"message"));
+        assert_eq!(reason, "an X-Signature header is required");
+    }
+
+    #[test]
+    fn max_age_is_a_number_of_seconds() {
+        for max_age in ["-1", "soon"] {
+            let config = Config::from_overrides(&[
+                ("SIGNING_SECRETS", "test:s3cret"),
+                ("SIGNATURE_MAX_AGE_SECS", max_age),
+            ]);
+            assert!(RequestSigning::from_config(&config).is_err(), "{}", max_age);
+        }
+    }
+
+    #[tokio::test]
+    async fn signed_requests_are_let_through() {
+        let state = state();
+        let router = graphql::create_graphql_routes()
+            .layer(middleware::from_fn_with_state(state.clone(), verify))
+            .with_state(state.clone());
+        let body = r#"{"query": "mutation { deletePerson(id: 1, version: 1) }"}"#;
+        let now = Utc::now().timestamp().to_string();
+        let signature = sign(SECRET, format!("POST\n/graphql\n{}\n{}", now, body).as_bytes());
+        let request = Request::post("/graphql")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(TIMESTAMP_HEADER, &now)
+            .header(SIGNATURE_HEADER, signature)
+            .body(Body::from(body))
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(state.person_repository.get(1).await.unwrap().deleted_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn unsigned_graphql_mutations_are_refused() {
+        let state = state();
+        let router = graphql::create_graphql_routes()
+            .layer(middleware::from_fn_with_state(state.clone(), verify))
+            .with_state(state.clone());
+        let request = Request::post("/graphql")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(r#"{"query": "mutation { deletePerson(id: 1, version: 1) }"}"#))
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(state.person_repository.get(1).await.unwrap().deleted_at.is_none());
+    }
+}
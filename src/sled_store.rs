@@ -0,0 +1,145 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use crate::errors::{HttpAppError, StorageError};
+use crate::person::Person;
+use crate::repository::{next_after, PersonRepository};
+
+pub struct SledRepository {
+    db: sled::Db,
+    tree: sled::Tree,
+}
+
+impl SledRepository {
+    pub fn open(path: &str) -> Result<Self, StorageError> {
+        let db = sled::open(path)?;
+        let tree = db.open_tree("persons")?;
+        Ok(SledRepository { db, tree })
+    }
+
+    async fn flush(&self) -> Result<(), HttpAppError> {
+        self.db.flush_async().await?;
+        Ok(())
+    }
+
+    async fn modify(
+        &self,
+        id: u32,
+        change: impl Fn(&mut Person) -> Result<(), HttpAppError>,
+    ) -> Result<(), HttpAppError> {
+        loop {
+            let Some(old) = self.tree.get(key(id))? else {
+                return Err(HttpAppError::NotFound);
+            };
+            let mut updated = decode(&old)?;
+            change(&mut updated)?;
+            updated.version += 1;
+            let swapped = self
+                .tree
+                .compare_and_swap(key(id), Some(old), Some(encode(&updated)?))?;
+            if swapped.is_ok() {
+                return self.flush().await;
+            }
+        }
+    }
+}
+
+fn key(id: u32) -> [u8; 4] {
+    id.to_be_bytes()
+}
+
+fn encode(person: &Person) -> Result<Vec<u8>, HttpAppError> {
+    serde_json::to_vec(person).map_err(|e| HttpAppError::StorageError(e.to_string()))
+}
+
+fn decode(bytes: &[u8]) -> Result<Person, HttpAppError> {
+    serde_json::from_slice(bytes).map_err(|e| HttpAppError::StorageError(e.to_string()))
+}
+
+#[async_trait]
+impl PersonRepository for SledRepository {
+    async fn close(&self) -> Result<(), HttpAppError> {
+        self.flush().await
+    }
+
+    async fn list(&self) -> Result<Vec<Person>, HttpAppError> {
+        self.tree
+            .iter()
+            .values()
+            .map(|value| decode(&value?))
+            .collect()
+    }
+
+    async fn get(&self, id: u32) -> Result<Person, HttpAppError> {
+        match self.tree.get(key(id))? {
+            Some(value) => decode(&value),
+            None => Err(HttpAppError::NotFound),
+        }
+    }
+
+    async fn next_id(&self) -> Result<u32, HttpAppError> {
+        let max_id = match self.tree.last()? {
+            Some((_, value)) => Some(decode(&value)?.id),
+            None => None,
+        };
+        next_after(max_id)
+    }
+
+    async fn insert(&self, person: Person) -> Result<(), HttpAppError> {
+        let value = encode(&Person {
+            version: person.version.max(1),
+            ..person.clone()
+        })?;
+        let swapped = self
+            .tree
+            .compare_and_swap(key(person.id), None as Option<&[u8]>, Some(value))?;
+        if swapped.is_err() {
+            return Err(HttpAppError::Conflict);
+        }
+        self.flush().await
+    }
+
+    async fn update(&self, person: Person) -> Result<(), HttpAppError> {
+        self.modify(person.id, |updated| {
+            if updated.deleted_at.is_some() {
+                return Err(HttpAppError::NotFound);
+            }
+            updated.check_version(person.version)?;
+            updated.name = person.name.clone();
+            updated.age = person.age;
+            updated.date = person.date;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn delete(&self, id: u32, version: u32) -> Result<(), HttpAppError> {
+        self.modify(id, |person| {
+            if person.deleted_at.is_some() {
+                return Err(HttpAppError::NotFound);
+            }
+            person.check_version(version)?;
+            person.deleted_at = Some(Utc::now());
+            Ok(())
+        })
+        .await
+    }
+
+    async fn set_deleted(
+        &self,
+        id: u32,
+        deleted_at: Option<DateTime<Utc>>,
+    ) -> Result<(), HttpAppError> {
+        self.modify(id, |person| {
+            person.deleted_at = deleted_at;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn purge(&self, id: u32) -> Result<(), HttpAppError> {
+        if self.tree.remove(key(id))?.is_none() {
+            return Err(HttpAppError::NotFound);
+        }
+        self.flush().await
+    }
+}
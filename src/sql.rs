@@ -0,0 +1,455 @@
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDate, Utc};
+use sqlx::migrate::{Migrate, MigrateError, Migrator};
+use sqlx::{Database, Encode, Error, FromRow, Pool, QueryBuilder, Type};
+use uuid::Uuid;
+use crate::errors::HttpAppError;
+use crate::listing::{ListQuery, PersonFilter, Sort, SortField, SortOrder};
+use crate::migrations;
+use crate::pagination::Page;
+use crate::person::Person;
+use crate::query::{Expr, Op, Value};
+use crate::repository::{next_after, PersonRepository};
+use crate::users::{self, User, UserRepository, UserRow};
+
+pub const SELECT_PERSONS: &str =
+    "SELECT id, uuid, name, age, date, deleted_at, version FROM persons";
+pub const COUNT_PERSONS: &str = "SELECT COUNT(*) FROM persons";
+pub const NAME_INDEX: &str = "persons_name_unique";
+pub const CREATE_NAME_INDEX: &str =
+    "CREATE UNIQUE INDEX IF NOT EXISTS persons_name_unique ON persons (LOWER(name))";
+pub const DROP_NAME_INDEX: &str = "DROP INDEX IF EXISTS persons_name_unique";
+
+/// What differs between the SQL backends. The queries of `SqlRepository` are
+/// the same for all of them, `QueryBuilder` writing the placeholders of each;
+/// the backends only run them, which generic code cannot do with a builder
+/// that borrows its arguments.
+#[async_trait]
+pub trait Dialect: Database {
+    fn migrator() -> &'static Migrator;
+
+    /// How to add a user without failing when the username is taken: the
+    /// start of the statement, and what follows its values.
+    const INSERT_USER: (&'static str, &'static str) =
+        ("INSERT INTO", " ON CONFLICT (username) DO NOTHING");
+
+    async fn fetch_all<T>(pool: &Pool<Self>, query: QueryBuilder<'_, Self>) -> Result<Vec<T>, Error>
+    where
+        T: for<'r> FromRow<'r, Self::Row> + Send + Unpin;
+
+    async fn fetch_optional<T>(
+        pool: &Pool<Self>,
+        query: QueryBuilder<'_, Self>,
+    ) -> Result<Option<T>, Error>
+    where
+        T: for<'r> FromRow<'r, Self::Row> + Send + Unpin;
+
+    /// Runs the statement and returns the number of rows it changed.
+    async fn execute(pool: &Pool<Self>, query: QueryBuilder<'_, Self>) -> Result<u64, Error>;
+
+    async fn enforce_unique_names(pool: &Pool<Self>, enabled: bool) -> Result<(), Error> {
+        let statement = if enabled { CREATE_NAME_INDEX } else { DROP_NAME_INDEX };
+        Self::execute(pool, QueryBuilder::new(statement)).await.map(|_| ())
+    }
+}
+
+#[derive(sqlx::FromRow)]
+pub struct PersonRow {
+    id: i64,
+    uuid: Option<Uuid>,
+    name: String,
+    age: i16,
+    date: NaiveDate,
+    deleted_at: Option<DateTime<Utc>>,
+    version: i32,
+}
+
+impl From<PersonRow> for Person {
+    fn from(row: PersonRow) -> Self {
+        Person {
+            id: row.id as u32,
+            uuid: row.uuid,
+            name: row.name,
+            age: row.age as u8,
+            date: row.date,
+            deleted_at: row.deleted_at,
+            version: row.version as u32,
+        }
+    }
+}
+
+/// The persons and users of a SQL database.
+pub struct SqlRepository<DB: Database> {
+    pool: Pool<DB>,
+}
+
+impl<DB: Database> SqlRepository<DB> {
+    pub fn new(pool: Pool<DB>) -> Self {
+        SqlRepository { pool }
+    }
+}
+
+impl<DB: Database> Clone for SqlRepository<DB> {
+    fn clone(&self) -> Self {
+        SqlRepository::new(self.pool.clone())
+    }
+}
+
+#[async_trait]
+impl<DB> PersonRepository for SqlRepository<DB>
+where
+    DB: Dialect,
+    DB::Connection: Migrate,
+    for<'q> i16: Encode<'q, DB> + Type<DB>,
+    for<'q> i32: Encode<'q, DB> + Type<DB>,
+    for<'q> i64: Encode<'q, DB> + Type<DB>,
+    for<'q> String: Encode<'q, DB> + Type<DB>,
+    for<'q> NaiveDate: Encode<'q, DB> + Type<DB>,
+    for<'q> Option<DateTime<Utc>>: Encode<'q, DB> + Type<DB>,
+    for<'q> Option<Uuid>: Encode<'q, DB> + Type<DB>,
+    for<'q> Uuid: Encode<'q, DB> + Type<DB>,
+    for<'r> PersonRow: FromRow<'r, DB::Row>,
+    for<'r> (i64,): FromRow<'r, DB::Row>,
+    for<'r> (Option<i64>,): FromRow<'r, DB::Row>,
+{
+    async fn migrate(&self) -> Result<(), MigrateError> {
+        migrations::run(DB::migrator(), &self.pool).await
+    }
+
+    async fn pending_migrations(&self) -> Result<usize, MigrateError> {
+        migrations::pending(DB::migrator(), &self.pool).await
+    }
+
+    async fn close(&self) -> Result<(), HttpAppError> {
+        self.pool.close().await;
+        Ok(())
+    }
+
+    async fn enforce_unique_names(&self, enabled: bool) -> Result<bool, HttpAppError> {
+        DB::enforce_unique_names(&self.pool, enabled).await?;
+        Ok(true)
+    }
+
+    async fn list(&self) -> Result<Vec<Person>, HttpAppError> {
+        let select = QueryBuilder::new(format!("{} ORDER BY id", SELECT_PERSONS));
+        let rows: Vec<PersonRow> = DB::fetch_all(&self.pool, select).await?;
+        Ok(rows.into_iter().map(Person::from).collect())
+    }
+
+    async fn list_page(&self, query: &ListQuery) -> Result<Page<Person>, HttpAppError> {
+        let total = self.count(&query.filter).await?;
+
+        let mut select = QueryBuilder::new(SELECT_PERSONS);
+        push_filter(&mut select, &query.filter, None);
+        push_order(&mut select, &query.sort);
+        push_limit(&mut select, query.page.per_page, query.page.offset());
+        let rows: Vec<PersonRow> = DB::fetch_all(&self.pool, select).await?;
+        Ok(Page {
+            items: rows.into_iter().map(Person::from).collect(),
+            total,
+        })
+    }
+
+    async fn count(&self, filter: &PersonFilter) -> Result<u64, HttpAppError> {
+        let mut count = QueryBuilder::new(COUNT_PERSONS);
+        push_filter(&mut count, filter, None);
+        let total: Option<(i64,)> = DB::fetch_optional(&self.pool, count).await?;
+        Ok(total.map_or(0, |(total,)| total as u64))
+    }
+
+    async fn list_after(
+        &self,
+        filter: &PersonFilter,
+        after: Option<u32>,
+        limit: u64,
+    ) -> Result<Vec<Person>, HttpAppError> {
+        let mut select = QueryBuilder::new(SELECT_PERSONS);
+        push_filter(&mut select, filter, after);
+        push_order(&mut select, &Sort::default());
+        push_limit(&mut select, limit, 0);
+        let rows: Vec<PersonRow> = DB::fetch_all(&self.pool, select).await?;
+        Ok(rows.into_iter().map(Person::from).collect())
+    }
+
+    async fn get(&self, id: u32) -> Result<Person, HttpAppError> {
+        let mut select = QueryBuilder::new(SELECT_PERSONS);
+        select.push(" WHERE id = ").push_bind(i64::from(id));
+        let row: Option<PersonRow> = DB::fetch_optional(&self.pool, select).await?;
+        row.map(Person::from).ok_or(HttpAppError::NotFound)
+    }
+
+    async fn get_by_uuid(&self, uuid: Uuid) -> Result<Person, HttpAppError> {
+        let mut select = QueryBuilder::new(SELECT_PERSONS);
+        select.push(" WHERE uuid = ").push_bind(uuid);
+        let row: Option<PersonRow> = DB::fetch_optional(&self.pool, select).await?;
+        row.map(Person::from).ok_or(HttpAppError::NotFound)
+    }
+
+    async fn next_id(&self) -> Result<u32, HttpAppError> {
+        let select = QueryBuilder::new("SELECT MAX(id) FROM persons");
+        let max_id: Option<(Option<i64>,)> = DB::fetch_optional(&self.pool, select).await?;
+        next_after(max_id.and_then(|(max_id,)| max_id).map(|id| id as u32))
+    }
+
+    async fn insert(&self, person: Person) -> Result<(), HttpAppError> {
+        let mut insert = QueryBuilder::new(
+            "INSERT INTO persons (id, uuid, name, age, date, deleted_at, version) VALUES (",
+        );
+        insert
+            .separated(", ")
+            .push_bind(i64::from(person.id))
+            .push_bind(person.uuid)
+            .push_bind(person.name)
+            .push_bind(i16::from(person.age))
+            .push_bind(person.date)
+            .push_bind(person.deleted_at)
+            .push_bind(person.version.max(1) as i32);
+        insert.push(")");
+        DB::execute(&self.pool, insert).await?;
+        Ok(())
+    }
+
+    async fn update(&self, person: Person) -> Result<(), HttpAppError> {
+        let version = person.version as i32;
+        let mut update = QueryBuilder::new("UPDATE persons SET name = ");
+        update
+            .push_bind(person.name)
+            .push(", age = ")
+            .push_bind(i16::from(person.age))
+            .push(", date = ")
+            .push_bind(person.date)
+            .push(", version = version + 1 WHERE id = ")
+            .push_bind(i64::from(person.id))
+            .push(" AND (")
+            .push_bind(version)
+            .push(" = 0 OR version = ")
+            .push_bind(version)
+            .push(") AND deleted_at IS NULL");
+        if DB::execute(&self.pool, update).await? == 0 {
+            if self.get(person.id).await?.deleted_at.is_some() {
+                return Err(HttpAppError::NotFound);
+            }
+            return Err(HttpAppError::PreconditionFailed);
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, id: u32, version: u32) -> Result<(), HttpAppError> {
+        let version = version as i32;
+        let mut delete = QueryBuilder::new("UPDATE persons SET deleted_at = ");
+        delete
+            .push_bind(Some(Utc::now()))
+            .push(", version = version + 1 WHERE id = ")
+            .push_bind(i64::from(id))
+            .push(" AND (")
+            .push_bind(version)
+            .push(" = 0 OR version = ")
+            .push_bind(version)
+            .push(") AND deleted_at IS NULL");
+        if DB::execute(&self.pool, delete).await? == 0 {
+            if self.get(id).await?.deleted_at.is_some() {
+                return Err(HttpAppError::NotFound);
+            }
+            return Err(HttpAppError::PreconditionFailed);
+        }
+        Ok(())
+    }
+
+    async fn set_deleted(
+        &self,
+        id: u32,
+        deleted_at: Option<DateTime<Utc>>,
+    ) -> Result<(), HttpAppError> {
+        let mut update = QueryBuilder::new("UPDATE persons SET deleted_at = ");
+        update
+            .push_bind(deleted_at)
+            .push(", version = version + 1 WHERE id = ")
+            .push_bind(i64::from(id));
+        if DB::execute(&self.pool, update).await? == 0 {
+            return Err(HttpAppError::NotFound);
+        }
+        Ok(())
+    }
+
+    async fn purge(&self, id: u32) -> Result<(), HttpAppError> {
+        let mut delete = QueryBuilder::new("DELETE FROM persons WHERE id = ");
+        delete.push_bind(i64::from(id));
+        if DB::execute(&self.pool, delete).await? == 0 {
+            return Err(HttpAppError::NotFound);
+        }
+        Ok(())
+    }
+
+    async fn delete_matching(&self, filter: &PersonFilter) -> Result<u64, HttpAppError> {
+        let mut delete = QueryBuilder::new("UPDATE persons SET deleted_at = ");
+        delete.push_bind(Some(Utc::now())).push(", version = version + 1");
+        push_filter(&mut delete, filter, None);
+        delete.push(" AND deleted_at IS NULL");
+        Ok(DB::execute(&self.pool, delete).await?)
+    }
+}
+
+#[async_trait]
+impl<DB> UserRepository for SqlRepository<DB>
+where
+    DB: Dialect,
+    for<'q> String: Encode<'q, DB> + Type<DB>,
+    for<'q> DateTime<Utc>: Encode<'q, DB> + Type<DB>,
+    for<'r> UserRow: FromRow<'r, DB::Row>,
+{
+    async fn create_user(&self, user: User) -> Result<(), HttpAppError> {
+        let (insert, on_conflict) = DB::INSERT_USER;
+        let mut create = QueryBuilder::new(insert);
+        create.push(" users (username, password_hash, roles, created_at) VALUES (");
+        create
+            .separated(", ")
+            .push_bind(user.username)
+            .push_bind(user.password_hash)
+            .push_bind(users::roles_column(&user.roles))
+            .push_bind(user.created_at);
+        create.push(")").push(on_conflict);
+        if DB::execute(&self.pool, create).await? == 0 {
+            return Err(HttpAppError::UsernameTaken);
+        }
+        Ok(())
+    }
+
+    async fn get_user(&self, username: &str) -> Result<Option<User>, HttpAppError> {
+        let mut select =
+            QueryBuilder::new("SELECT username, password_hash, roles, created_at FROM users");
+        select.push(" WHERE username = ").push_bind(username.to_string());
+        let row: Option<UserRow> = DB::fetch_optional(&self.pool, select).await?;
+        row.map(User::try_from).transpose()
+    }
+}
+
+pub fn push_filter<'args, DB>(
+    builder: &mut QueryBuilder<'args, DB>,
+    filter: &PersonFilter,
+    after: Option<u32>,
+) where
+    DB: Database,
+    i64: 'args + Encode<'args, DB> + Type<DB>,
+    String: 'args + Encode<'args, DB> + Type<DB>,
+    NaiveDate: 'args + Encode<'args, DB> + Type<DB>,
+{
+    builder.push(" WHERE 1 = 1");
+    if !filter.include_deleted {
+        builder.push(" AND deleted_at IS NULL");
+    }
+    if let Some(after) = after {
+        builder.push(" AND id > ").push_bind(i64::from(after));
+    }
+    if let Some(name) = &filter.name {
+        builder
+            .push(" AND LOWER(name) LIKE ")
+            .push_bind(like_pattern(name))
+            .push(" ESCAPE '!'");
+    }
+    if let Some(min_age) = filter.min_age {
+        builder.push(" AND age >= ").push_bind(i64::from(min_age));
+    }
+    if let Some(max_age) = filter.max_age {
+        builder.push(" AND age <= ").push_bind(i64::from(max_age));
+    }
+    if let Some(date_from) = filter.date_from {
+        builder.push(" AND date >= ").push_bind(date_from);
+    }
+    if let Some(date_to) = filter.date_to {
+        builder.push(" AND date <= ").push_bind(date_to);
+    }
+    match filter.year_range() {
+        Some((first, last)) => {
+            builder.push(" AND date >= ").push_bind(first);
+            builder.push(" AND date <= ").push_bind(last);
+        }
+        None if filter.year.is_some() => {
+            builder.push(" AND 1 = 0");
+        }
+        None => {}
+    }
+    if let Some(expr) = &filter.expr {
+        builder.push(" AND ");
+        push_expr(builder, expr);
+    }
+}
+
+fn push_expr<'args, DB>(builder: &mut QueryBuilder<'args, DB>, expr: &Expr)
+where
+    DB: Database,
+    i64: 'args + Encode<'args, DB> + Type<DB>,
+    String: 'args + Encode<'args, DB> + Type<DB>,
+    NaiveDate: 'args + Encode<'args, DB> + Type<DB>,
+{
+    match expr {
+        Expr::And(left, right) | Expr::Or(left, right) => {
+            let joiner = if matches!(expr, Expr::And(..)) { " AND " } else { " OR " };
+            builder.push("(");
+            push_expr(builder, left);
+            builder.push(joiner);
+            push_expr(builder, right);
+            builder.push(")");
+        }
+        Expr::Not(expr) => {
+            builder.push("NOT (");
+            push_expr(builder, expr);
+            builder.push(")");
+        }
+        Expr::Compare(field, Op::Contains, Value::Text(text)) => {
+            builder
+                .push(format!("LOWER({}) LIKE ", field.column()))
+                .push_bind(like_pattern(text))
+                .push(" ESCAPE '!'");
+        }
+        // Text compares in lower case, so that every database matches as
+        // MySQL does with its case-insensitive default collation.
+        Expr::Compare(field, op, Value::Text(text)) => {
+            builder
+                .push(format!("LOWER({}) {} ", field.column(), op.sql()))
+                .push_bind(text.to_lowercase());
+        }
+        Expr::Compare(field, op, value) => {
+            builder.push(format!("{} {} ", field.column(), op.sql()));
+            match value {
+                Value::Int(number) => builder.push_bind(*number),
+                Value::Text(text) => builder.push_bind(text.clone()),
+                Value::Date(date) => builder.push_bind(*date),
+            };
+        }
+    }
+}
+
+pub fn push_order<DB: Database>(builder: &mut QueryBuilder<'_, DB>, sort: &Sort) {
+    let column = match sort.sort {
+        SortField::Id => "id",
+        SortField::Name => "name",
+        SortField::Age => "age",
+        SortField::Date => "date",
+    };
+    let direction = match sort.order {
+        SortOrder::Asc => "ASC",
+        SortOrder::Desc => "DESC",
+    };
+    builder.push(format!(" ORDER BY {column} {direction}, id {direction}"));
+}
+
+pub fn push_limit<'args, DB>(builder: &mut QueryBuilder<'args, DB>, limit: u64, offset: u64)
+where
+    DB: Database,
+    i64: 'args + Encode<'args, DB> + Type<DB>,
+{
+    builder.push(" LIMIT ").push_bind(limit as i64);
+    builder.push(" OFFSET ").push_bind(offset as i64);
+}
+
+fn like_pattern(value: &str) -> String {
+    let mut pattern = String::from("%");
+    for c in value.to_lowercase().chars() {
+        if matches!(c, '!' | '%' | '_') {
+            pattern.push('!');
+        }
+        pattern.push(c);
+    }
+    pattern.push('%');
+    pattern
+}
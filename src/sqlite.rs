@@ -0,0 +1,45 @@
+use std::str::FromStr;
+use async_trait::async_trait;
+use sqlx::migrate::Migrator;
+use sqlx::sqlite::{Sqlite, SqliteConnectOptions, SqlitePool, SqlitePoolOptions, SqliteRow};
+use sqlx::{Error, FromRow, QueryBuilder};
+use crate::migrations;
+use crate::sql::{Dialect, SqlRepository};
+
+pub type SqliteRepository = SqlRepository<Sqlite>;
+
+#[async_trait]
+impl Dialect for Sqlite {
+    fn migrator() -> &'static Migrator {
+        &migrations::SQLITE
+    }
+
+    async fn fetch_all<T>(pool: &SqlitePool, mut query: QueryBuilder<'_, Sqlite>) -> Result<Vec<T>, Error>
+    where
+        T: for<'r> FromRow<'r, SqliteRow> + Send + Unpin,
+    {
+        query.build_query_as().fetch_all(pool).await
+    }
+
+    async fn fetch_optional<T>(
+        pool: &SqlitePool,
+        mut query: QueryBuilder<'_, Sqlite>,
+    ) -> Result<Option<T>, Error>
+    where
+        T: for<'r> FromRow<'r, SqliteRow> + Send + Unpin,
+    {
+        query.build_query_as().fetch_optional(pool).await
+    }
+
+    async fn execute(pool: &SqlitePool, mut query: QueryBuilder<'_, Sqlite>) -> Result<u64, Error> {
+        Ok(query.build().execute(pool).await?.rows_affected())
+    }
+}
+
+impl SqlRepository<Sqlite> {
+    pub async fn connect(url: &str) -> Result<Self, Error> {
+        let options = SqliteConnectOptions::from_str(url)?.create_if_missing(true);
+        let pool = SqlitePoolOptions::new().connect_with(options).await?;
+        Ok(SqlRepository::new(pool))
+    }
+}
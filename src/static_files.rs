@@ -0,0 +1,60 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use axum::{
+    extract::State,
+    http::{header, HeaderValue},
+    middleware,
+    response::Response,
+    Router,
+};
+use tower_http::services::ServeDir;
+use crate::config::Config;
+use crate::errors::ConfigError;
+use crate::routes::AppState;
+
+pub const DEFAULT_STATIC_DIR: &str = "static";
+pub const DEFAULT_MAX_AGE_SECS: u64 = 3600;
+
+/// The stylesheets, scripts and images of the landing page, served under
+/// `/static` from `STATIC_DIR` and cached for `STATIC_MAX_AGE_SECS` (3600).
+pub struct StaticFiles {
+    root: PathBuf,
+    cache_control: HeaderValue,
+}
+
+impl StaticFiles {
+    pub fn from_config(config: &Config) -> Result<Self, ConfigError> {
+        let root = config.var("STATIC_DIR").unwrap_or(DEFAULT_STATIC_DIR).to_string();
+        let max_age: u64 = match config.var("STATIC_MAX_AGE_SECS") {
+            Some(value) => value.parse().map_err(|_| {
+                ConfigError::Invalid(format!("STATIC_MAX_AGE_SECS must be a number, got {}", value))
+            })?,
+            None => DEFAULT_MAX_AGE_SECS,
+        };
+        let root = PathBuf::from(root);
+        if !root.is_dir() {
+            tracing::warn!("STATIC_DIR {} is not a directory, /static is empty", root.display());
+        }
+        Ok(StaticFiles {
+            root,
+            cache_control: HeaderValue::from_str(&format!("public, max-age={}", max_age)).unwrap(),
+        })
+    }
+}
+
+pub fn create_static_routes(files: StaticFiles) -> Router<Arc<AppState>> {
+    Router::new()
+        .nest_service("/static", ServeDir::new(&files.root))
+        .layer(middleware::map_response_with_state(Arc::new(files), cache))
+}
+
+/// Only files found are cached, so that one added later shows up at once.
+async fn cache(State(files): State<Arc<StaticFiles>>, mut response: Response) -> Response {
+    if response.status().is_success() || response.status().is_redirection() {
+        response
+            .headers_mut()
+            .entry(header::CACHE_CONTROL)
+            .or_insert(files.cache_control.clone());
+    }
+    response
+}
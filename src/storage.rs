@@ -0,0 +1,207 @@
+use std::str::FromStr;
+use std::time::Duration;
+use crate::config::Config;
+use crate::errors::StorageError;
+use crate::json_file::JsonFileRepository;
+use crate::mongo::MongoRepository;
+use crate::mysql::MySqlRepository;
+use crate::person;
+use crate::postgres::PostgresRepository;
+use crate::redis_store::RedisRepository;
+use crate::repository::{MemoryRepository, PersonRepository};
+use crate::sled_store::SledRepository;
+use crate::sqlite::SqliteRepository;
+use crate::users::{MemoryUserRepository, UserRepository};
+use crate::wal::WalRepository;
+
+const DEFAULT_SQLITE_URL: &str = "sqlite://persons.db";
+const DEFAULT_JSON_FILE_PATH: &str = "persons.json";
+const DEFAULT_SLED_PATH: &str = "persons.sled";
+const DEFAULT_REDIS_URL: &str = "redis://127.0.0.1/";
+const DEFAULT_MONGODB_URL: &str = "mongodb://localhost:27017";
+const DEFAULT_MONGODB_DATABASE: &str = "persons";
+
+pub struct PoolSettings {
+    pub max_connections: u32,
+    pub min_connections: u32,
+    pub acquire_timeout: Duration,
+    pub idle_timeout: Duration,
+}
+
+impl PoolSettings {
+    pub fn from_config(config: &Config) -> Result<Self, StorageError> {
+        Ok(PoolSettings {
+            max_connections: parse_or(config, "DATABASE_MAX_CONNECTIONS", 10)?,
+            min_connections: parse_or(config, "DATABASE_MIN_CONNECTIONS", 0)?,
+            acquire_timeout: Duration::from_secs(parse_or(config, "DATABASE_ACQUIRE_TIMEOUT_SECS", 30)?),
+            idle_timeout: Duration::from_secs(parse_or(config, "DATABASE_IDLE_TIMEOUT_SECS", 600)?),
+        })
+    }
+}
+
+fn parse_or<T: FromStr>(config: &Config, name: &str, default: T) -> Result<T, StorageError> {
+    match config.var(name) {
+        Some(value) => value.parse().map_err(|_| {
+            StorageError::Config(format!("{} must be a number, got {}", name, value))
+        }),
+        None => Ok(default),
+    }
+}
+
+/// The repositories of one backend.
+pub struct Repositories {
+    pub persons: Box<dyn PersonRepository>,
+    pub users: Box<dyn UserRepository>,
+}
+
+impl Repositories {
+    /// Both repositories on the one connection pool of `repository`.
+    fn shared<R>(repository: R) -> Self
+    where
+        R: PersonRepository + UserRepository + Clone + 'static,
+    {
+        Repositories {
+            persons: Box::new(repository.clone()),
+            users: Box::new(repository),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageBackend {
+    Memory,
+    Sqlite,
+    Postgres,
+    MySql,
+    JsonFile,
+    Sled,
+    Redis,
+    Mongo,
+}
+
+impl FromStr for StorageBackend {
+    type Err = StorageError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "memory" => Ok(StorageBackend::Memory),
+            "sqlite" => Ok(StorageBackend::Sqlite),
+            "postgres" | "postgresql" => Ok(StorageBackend::Postgres),
+            "mysql" | "mariadb" => Ok(StorageBackend::MySql),
+            "json" => Ok(StorageBackend::JsonFile),
+            "sled" => Ok(StorageBackend::Sled),
+            "redis" => Ok(StorageBackend::Redis),
+            "mongodb" | "mongo" => Ok(StorageBackend::Mongo),
+            other => Err(StorageError::Config(format!(
+                "Unknown STORAGE_BACKEND '{}', expected one of {}",
+                other, "memory, sqlite, postgres, mysql, json, sled, redis or mongodb"
+            ))),
+        }
+    }
+}
+
+impl StorageBackend {
+    pub fn from_config(config: &Config) -> Result<Self, StorageError> {
+        match config.var("STORAGE_BACKEND") {
+            Some(value) => value.parse(),
+            None => Ok(match config.var("DATABASE_URL") {
+                Some(url) if url.starts_with("postgres") => StorageBackend::Postgres,
+                Some(url) if url.starts_with("mysql") || url.starts_with("mariadb") => {
+                    StorageBackend::MySql
+                }
+                Some(_) => StorageBackend::Sqlite,
+                None => StorageBackend::Memory,
+            }),
+        }
+    }
+
+    /// The `db.system` name OpenTelemetry uses for the backend.
+    pub fn name(self) -> &'static str {
+        match self {
+            StorageBackend::Memory => "memory",
+            StorageBackend::Sqlite => "sqlite",
+            StorageBackend::Postgres => "postgresql",
+            StorageBackend::MySql => "mysql",
+            StorageBackend::JsonFile => "json",
+            StorageBackend::Sled => "sled",
+            StorageBackend::Redis => "redis",
+            StorageBackend::Mongo => "mongodb",
+        }
+    }
+
+    /// Opens the persons and the user accounts. These live in the `users`
+    /// table of the SQL backends, on the same connections as the persons and
+    /// migrated with them, and only in memory for the others.
+    pub async fn connect(self, config: &Config) -> Result<Repositories, StorageError> {
+        let database_url = config.var("DATABASE_URL");
+        let persons: Box<dyn PersonRepository> = match self {
+            StorageBackend::Memory => {
+                let persons = match config.var("SEED_FILE") {
+                    Some(_) => Vec::new(),
+                    None => person::create_person_collection(),
+                };
+                match config.var("WAL_PATH") {
+                    Some(path) => {
+                        tracing::info!("Using in-memory storage with WAL at {}", path);
+                        Box::new(WalRepository::open(path, persons).await?)
+                    }
+                    None => {
+                        tracing::info!("Using in-memory storage");
+                        Box::new(MemoryRepository::new(persons))
+                    }
+                }
+            }
+            StorageBackend::Sqlite => {
+                let url = database_url.unwrap_or(DEFAULT_SQLITE_URL);
+                tracing::info!("Using SQLite storage at {}", url);
+                return Ok(Repositories::shared(SqliteRepository::connect(url).await?));
+            }
+            StorageBackend::Postgres => {
+                let url = database_url.ok_or_else(|| {
+                    StorageError::Config("DATABASE_URL is required for postgres".to_string())
+                })?;
+                tracing::info!("Using PostgreSQL storage");
+                let pool = PoolSettings::from_config(config)?;
+                let repository = PostgresRepository::connect(url, pool).await?;
+                return Ok(Repositories::shared(repository));
+            }
+            StorageBackend::MySql => {
+                let url = database_url.ok_or_else(|| {
+                    StorageError::Config("DATABASE_URL is required for mysql".to_string())
+                })?;
+                tracing::info!("Using MySQL storage");
+                let pool = PoolSettings::from_config(config)?;
+                let repository = MySqlRepository::connect(url, pool).await?;
+                return Ok(Repositories::shared(repository));
+            }
+            StorageBackend::JsonFile => {
+                let path = config.var("JSON_FILE_PATH").unwrap_or(DEFAULT_JSON_FILE_PATH);
+                tracing::info!("Using JSON file storage at {}", path);
+                Box::new(JsonFileRepository::open(path).await?)
+            }
+            StorageBackend::Sled => {
+                let path = config.var("SLED_PATH").unwrap_or(DEFAULT_SLED_PATH);
+                tracing::info!("Using sled storage at {}", path);
+                Box::new(SledRepository::open(path)?)
+            }
+            StorageBackend::Redis => {
+                let url = config.var("REDIS_URL").unwrap_or(DEFAULT_REDIS_URL);
+                tracing::info!("Using Redis storage");
+                Box::new(RedisRepository::connect(url).await?)
+            }
+            StorageBackend::Mongo => {
+                let url = config.var("MONGODB_URL").unwrap_or(DEFAULT_MONGODB_URL);
+                let database = config.var("MONGODB_DATABASE").unwrap_or(DEFAULT_MONGODB_DATABASE);
+                tracing::info!("Using MongoDB storage, database {}", database);
+                Box::new(MongoRepository::connect(url, database).await?)
+            }
+        };
+        if self != StorageBackend::Memory {
+            tracing::warn!("{:?} storage keeps user accounts in memory only", self);
+        }
+        Ok(Repositories {
+            persons,
+            users: Box::new(MemoryUserRepository::default()),
+        })
+    }
+}
@@ -0,0 +1,207 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::de::{DeserializeOwned, IgnoredAny};
+use serde::Deserialize;
+use uuid::Uuid;
+use crate::admin::{Backup, LogFilter, RestoreRequest};
+use crate::bulk::{BulkDeleteRequest, BulkPerson};
+use crate::flags::Flag;
+use crate::maintenance::MaintenanceRequest;
+use crate::person::{NewPerson, Person, PersonPatch};
+use crate::sessions::LoginRequest;
+use crate::tokens::RefreshRequest;
+use crate::users::RegisterRequest;
+
+/// A request body with a `deny_unknown_fields` twin, used when `STRICT_JSON`
+/// is on so that a typo such as `nmae` is rejected instead of dropped.
+pub trait StrictBody: DeserializeOwned {
+    type Strict: DeserializeOwned;
+
+    /// Whether the body is a list, whose items XML wraps in a root element.
+    const LIST: bool = false;
+
+    fn from_strict(strict: Self::Strict) -> Self;
+}
+
+impl<T: StrictBody> StrictBody for Vec<T> {
+    type Strict = Vec<T::Strict>;
+
+    const LIST: bool = true;
+
+    fn from_strict(strict: Self::Strict) -> Self {
+        strict.into_iter().map(T::from_strict).collect()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StrictNewPerson {
+    name: String,
+    age: u8,
+    date: NaiveDate,
+}
+
+impl StrictBody for NewPerson {
+    type Strict = StrictNewPerson;
+
+    fn from_strict(strict: StrictNewPerson) -> Self {
+        NewPerson {
+            uuid: None,
+            name: strict.name,
+            age: strict.age,
+            date: strict.date,
+        }
+    }
+}
+
+/// Also takes the `_links` of a representation sent back unchanged.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StrictPerson {
+    id: u32,
+    #[serde(default)]
+    uuid: Option<Uuid>,
+    name: String,
+    age: u8,
+    date: NaiveDate,
+    #[serde(default)]
+    deleted_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    version: u32,
+    #[serde(default, rename = "_links")]
+    _links: Option<IgnoredAny>,
+}
+
+impl StrictBody for Person {
+    type Strict = StrictPerson;
+
+    fn from_strict(strict: StrictPerson) -> Self {
+        Person {
+            id: strict.id,
+            uuid: strict.uuid,
+            name: strict.name,
+            age: strict.age,
+            date: strict.date,
+            deleted_at: strict.deleted_at,
+            version: strict.version,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StrictPersonPatch {
+    name: Option<String>,
+    age: Option<u8>,
+    date: Option<NaiveDate>,
+    version: Option<u32>,
+}
+
+impl StrictBody for PersonPatch {
+    type Strict = StrictPersonPatch;
+
+    fn from_strict(strict: StrictPersonPatch) -> Self {
+        PersonPatch {
+            name: strict.name,
+            age: strict.age,
+            date: strict.date,
+            version: strict.version,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StrictBulkPerson {
+    id: Option<u32>,
+    name: String,
+    age: u8,
+    date: NaiveDate,
+}
+
+impl StrictBody for BulkPerson {
+    type Strict = StrictBulkPerson;
+
+    fn from_strict(strict: StrictBulkPerson) -> Self {
+        BulkPerson {
+            id: strict.id,
+            person: NewPerson::from_strict(StrictNewPerson {
+                name: strict.name,
+                age: strict.age,
+                date: strict.date,
+            }),
+        }
+    }
+}
+
+impl StrictBody for BulkDeleteRequest {
+    type Strict = Self;
+
+    fn from_strict(strict: Self) -> Self {
+        strict
+    }
+}
+
+impl StrictBody for RestoreRequest {
+    type Strict = Self;
+
+    fn from_strict(strict: Self) -> Self {
+        strict
+    }
+}
+
+impl StrictBody for Flag {
+    type Strict = Self;
+
+    fn from_strict(strict: Self) -> Self {
+        strict
+    }
+}
+
+impl StrictBody for LogFilter {
+    type Strict = Self;
+
+    fn from_strict(strict: Self) -> Self {
+        strict
+    }
+}
+
+impl StrictBody for LoginRequest {
+    type Strict = Self;
+
+    fn from_strict(strict: Self) -> Self {
+        strict
+    }
+}
+
+impl StrictBody for RegisterRequest {
+    type Strict = Self;
+
+    fn from_strict(strict: Self) -> Self {
+        strict
+    }
+}
+
+impl StrictBody for MaintenanceRequest {
+    type Strict = Self;
+
+    fn from_strict(strict: Self) -> Self {
+        strict
+    }
+}
+
+impl StrictBody for RefreshRequest {
+    type Strict = Self;
+
+    fn from_strict(strict: Self) -> Self {
+        strict
+    }
+}
+
+/// Backups stay lenient so that newer exports can still be imported.
+impl StrictBody for Backup {
+    type Strict = Self;
+
+    fn from_strict(strict: Self) -> Self {
+        strict
+    }
+}
@@ -0,0 +1,19 @@
+use std::time::Duration;
+use crate::config::Config;
+use crate::errors::ConfigError;
+
+pub const DEFAULT_REQUEST_TIMEOUT_SECS: u64 = 30;
+
+/// How long a handler may take to answer, `REQUEST_TIMEOUT_SECS` (30), or no
+/// limit when it is 0. Streaming a response body, as exports do, is not
+/// counted once the headers are sent.
+pub fn from_config(config: &Config) -> Result<Option<Duration>, ConfigError> {
+    let secs = match config.var("REQUEST_TIMEOUT_SECS") {
+        Some(value) => value.parse().map_err(|_| {
+            ConfigError::Invalid(format!("REQUEST_TIMEOUT_SECS must be a number, got {}", value))
+        })?,
+        None => DEFAULT_REQUEST_TIMEOUT_SECS,
+    };
+    Ok((secs > 0).then(|| Duration::from_secs(secs)))
+}
+
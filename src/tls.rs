@@ -0,0 +1,408 @@
+use std::future::Future;
+use std::io::{self, IoSlice};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime};
+use axum::{extract::connect_info::IntoMakeServiceWithConnectInfo, middleware::AddExtension};
+use axum::{Extension, Router};
+use axum_server::accept::Accept;
+use axum_server::tls_rustls::{RustlsAcceptor, RustlsConfig};
+use axum_server::Handle;
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig};
+use sha2::{Digest, Sha256};
+use tokio::fs;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpListener;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::{self, Sleep};
+use tokio_rustls::server::TlsStream;
+use tower::{Layer, Service};
+use x509_parser::prelude::{FromDer, X509Certificate};
+use crate::auth::Role;
+use crate::config::{Config, ServerSettings};
+use crate::errors::ConfigError;
+use crate::listener;
+
+/// Where the HTTPS listener finds its certificate chain and private key, and
+/// the CA bundle that client certificates must chain to, if any.
+#[derive(Clone)]
+pub struct TlsSettings {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    pub client_ca: Option<PathBuf>,
+    pub client_roles: Vec<Role>,
+    pub reload_every: Option<Duration>,
+    pub http2: bool,
+    pub port: Option<u16>,
+}
+
+/// The verified certificate a client presented, in the request extensions
+/// when `TLS_CLIENT_CA_FILE` is set.
+#[derive(Debug, Clone)]
+pub struct ClientIdentity {
+    /// Such as `CN=billing, O=Example`.
+    pub subject: String,
+    pub common_name: Option<String>,
+    /// The SHA-256 of the certificate, in hex, for pinning one client.
+    pub fingerprint: String,
+    pub roles: Vec<Role>,
+}
+
+impl ClientIdentity {
+    fn from_der(der: &[u8], roles: Vec<Role>) -> Option<Self> {
+        let (_, cert) = X509Certificate::from_der(der).ok()?;
+        let subject = cert.subject();
+        let common_name = subject
+            .iter_common_name()
+            .next()
+            .and_then(|cn| cn.as_str().ok())
+            .map(str::to_string);
+        let fingerprint = Sha256::digest(der).iter().map(|b| format!("{:02x}", b)).collect();
+        Some(ClientIdentity {
+            subject: subject.to_string(),
+            common_name,
+            fingerprint,
+            roles,
+        })
+    }
+
+    /// The common name, or the whole subject when the certificate has none.
+    pub fn name(&self) -> &str {
+        self.common_name.as_deref().unwrap_or(&self.subject)
+    }
+}
+
+impl TlsSettings {
+    /// Reads `TLS_CERT_FILE` and `TLS_KEY_FILE`, PEM encoded, `TLS_CLIENT_CA_FILE`
+    /// to require client certificates, `TLS_CLIENT_ROLES` (`+` separated, default
+    /// `writer`) to grant their holders, `TLS_RELOAD_SECS`, how often to check
+    /// the files for changes, `HTTP2=false` to offer HTTP/1.1 only and
+    /// `TLS_PORT` to serve HTTPS there, next to plain HTTP on the usual port;
+    /// `None` when no certificate is set and the listener speaks plain HTTP.
+    pub fn from_config(config: &Config) -> Result<Option<Self>, ConfigError> {
+        let (cert, key) = match (config.var("TLS_CERT_FILE"), config.var("TLS_KEY_FILE")) {
+            (Some(cert), Some(key)) => (cert, key),
+            (None, None) => return Ok(None),
+            _ => {
+                return Err(ConfigError::Invalid(
+                    "TLS_CERT_FILE and TLS_KEY_FILE must be set together".to_string(),
+                ))
+            }
+        };
+        let client_roles = match config.var("TLS_CLIENT_ROLES") {
+            Some(roles) => roles
+                .split('+')
+                .map(|role| role.trim().parse())
+                .collect::<Result<_, _>>()
+                .map_err(ConfigError::Invalid)?,
+            None => vec![Role::Writer],
+        };
+        let reload_every = match config.var("TLS_RELOAD_SECS") {
+            Some(secs) => Some(Duration::from_secs(secs.parse().map_err(|_| {
+                ConfigError::Invalid(format!("TLS_RELOAD_SECS must be a number, got {}", secs))
+            })?)),
+            None => None,
+        };
+        let port = match config.var("TLS_PORT") {
+            Some(port) => Some(port.parse().map_err(|_| {
+                ConfigError::Invalid(format!("TLS_PORT must be a port number, got {}", port))
+            })?),
+            None => None,
+        };
+        Ok(Some(TlsSettings {
+            cert: cert.into(),
+            key: key.into(),
+            client_ca: config.var("TLS_CLIENT_CA_FILE").map(PathBuf::from),
+            client_roles,
+            reload_every,
+            http2: config.var("HTTP2") != Some("false"),
+            port,
+        }))
+    }
+
+    async fn server_config(&self) -> io::Result<Arc<ServerConfig>> {
+        let invalid = |e: rustls::Error| io::Error::new(io::ErrorKind::InvalidData, e);
+        let certs = rustls_pemfile::certs(&mut &*fs::read(&self.cert).await?)
+            .collect::<Result<Vec<_>, _>>()?;
+        let key = rustls_pemfile::private_key(&mut &*fs::read(&self.key).await?)?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no private key found"))?;
+
+        let builder = ServerConfig::builder();
+        let builder = match &self.client_ca {
+            Some(path) => {
+                let mut roots = RootCertStore::empty();
+                for cert in rustls_pemfile::certs(&mut &*fs::read(path).await?) {
+                    roots.add(cert?).map_err(invalid)?;
+                }
+                let verifier = WebPkiClientVerifier::builder(Arc::new(roots))
+                    .build()
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                builder.with_client_cert_verifier(verifier)
+            }
+            None => builder.with_no_client_auth(),
+        };
+        let mut config = builder.with_single_cert(certs, key).map_err(invalid)?;
+        config.alpn_protocols = if self.http2 {
+            vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+        } else {
+            vec![b"http/1.1".to_vec()]
+        };
+        Ok(Arc::new(config))
+    }
+
+    async fn load(&self) -> io::Result<RustlsConfig> {
+        rustls::crypto::ring::default_provider().install_default().ok();
+        Ok(RustlsConfig::from_config(self.server_config().await?))
+    }
+
+    /// Reloads `config` whenever the certificate, key or CA bundle changes, so
+    /// that renewed certificates are picked up without a restart. A broken set
+    /// is logged and the previous one kept.
+    fn watch(&self, config: RustlsConfig) {
+        let Some(every) = self.reload_every else {
+            return;
+        };
+        let settings = self.clone();
+        tokio::spawn(async move {
+            let mut seen = settings.modified().await;
+            let mut interval = tokio::time::interval(every);
+            loop {
+                interval.tick().await;
+                let modified = settings.modified().await;
+                if modified == seen {
+                    continue;
+                }
+                match settings.server_config().await {
+                    Ok(server_config) => {
+                        config.reload_from_config(server_config);
+                        tracing::info!("Reloaded TLS certificate {}", settings.cert.display());
+                        seen = modified;
+                    }
+                    Err(e) => tracing::error!("Could not reload TLS certificate: {}", e),
+                }
+            }
+        });
+    }
+
+    async fn modified(&self) -> [Option<SystemTime>; 3] {
+        let client_ca = match &self.client_ca {
+            Some(path) => modified(path).await,
+            None => None,
+        };
+        [modified(&self.cert).await, modified(&self.key).await, client_ca]
+    }
+
+    /// Serves `app` over TLS, with the `ClientIdentity` of each connection when
+    /// client certificates are required, until `shutdown` resolves and the open
+    /// connections are done.
+    pub async fn serve(
+        self,
+        listener: TcpListener,
+        app: IntoMakeServiceWithConnectInfo<Router, SocketAddr>,
+        settings: ServerSettings,
+        shutdown: impl Future<Output = ()> + Send + 'static,
+    ) -> io::Result<()> {
+        let listener = listener.into_std()?;
+        let config = self.load().await?;
+        self.watch(config.clone());
+        let handle = Handle::new();
+        tokio::spawn({
+            let handle = handle.clone();
+            async move {
+                shutdown.await;
+                handle.graceful_shutdown(None);
+            }
+        });
+        let permits = settings.max_connections.map(|max| Arc::new(Semaphore::new(max)));
+        let first_read = settings.header_read_timeout();
+        if self.client_ca.is_none() {
+            let inner = RustlsAcceptor::new(config);
+            let acceptor = Connections { inner, permits, first_read };
+            let mut server = axum_server::from_tcp(listener).acceptor(acceptor).handle(handle);
+            listener::configure(server.http_builder(), &settings);
+            return server.serve(app).await;
+        }
+        let acceptor = ClientCertAcceptor {
+            inner: RustlsAcceptor::new(config),
+            roles: self.client_roles,
+        };
+        let acceptor = Connections { inner: acceptor, permits, first_read };
+        let mut server = axum_server::from_tcp(listener).acceptor(acceptor).handle(handle);
+        listener::configure(server.http_builder(), &settings);
+        server.serve(app).await
+    }
+}
+
+async fn modified(path: &PathBuf) -> Option<SystemTime> {
+    fs::metadata(path).await.and_then(|metadata| metadata.modified()).ok()
+}
+
+/// Completes the handshake, then hands the connection's `ClientIdentity` to
+/// every request on it.
+#[derive(Clone)]
+struct ClientCertAcceptor {
+    inner: RustlsAcceptor,
+    roles: Vec<Role>,
+}
+
+impl<I, S> Accept<I, S> for ClientCertAcceptor
+where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    S: Send + 'static,
+{
+    type Stream = TlsStream<I>;
+    type Service = AddExtension<S, ClientIdentity>;
+    type Future = Pin<Box<dyn Future<Output = io::Result<(Self::Stream, Self::Service)>> + Send>>;
+
+    fn accept(&self, stream: I, service: S) -> Self::Future {
+        let acceptor = self.inner.clone();
+        let roles = self.roles.clone();
+        Box::pin(async move {
+            let (stream, service) = acceptor.accept(stream, service).await?;
+            let identity = stream
+                .get_ref()
+                .1
+                .peer_certificates()
+                .and_then(|certs| certs.first())
+                .and_then(|cert| ClientIdentity::from_der(cert, roles))
+                .ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidData, "unreadable client certificate")
+                })?;
+            tracing::debug!("TLS client {} ({})", identity.subject, identity.fingerprint);
+            Ok((stream, Extension(identity).layer(service)))
+        })
+    }
+}
+
+/// Holds back the handshake of connections past `MAX_CONNECTIONS` until one
+/// closes; the permit lives as long as the service of the connection. hyper
+/// waits for the first bytes to tell HTTP/1 from HTTP/2 before its header read
+/// timeout starts, so the stream enforces that timeout until then.
+#[derive(Clone)]
+struct Connections<A> {
+    inner: A,
+    permits: Option<Arc<Semaphore>>,
+    first_read: Option<Duration>,
+}
+
+impl<A, I, S> Accept<I, S> for Connections<A>
+where
+    A: Accept<I, S> + Clone + Send + 'static,
+    A::Stream: Unpin,
+    A::Future: Send,
+    I: Send + 'static,
+    S: Send + 'static,
+{
+    type Stream = FirstRead<A::Stream>;
+    type Service = Permitted<A::Service>;
+    type Future = Pin<Box<dyn Future<Output = io::Result<(Self::Stream, Self::Service)>> + Send>>;
+
+    fn accept(&self, stream: I, service: S) -> Self::Future {
+        let acceptor = self.inner.clone();
+        let permits = self.permits.clone();
+        let first_read = self.first_read;
+        Box::pin(async move {
+            let permit = match permits {
+                Some(permits) => {
+                    let permit = permits.acquire_owned().await.map_err(io::Error::other)?;
+                    Some(Arc::new(permit))
+                }
+                None => None,
+            };
+            let (stream, service) = acceptor.accept(stream, service).await?;
+            let deadline = first_read.map(|timeout| Box::pin(time::sleep(timeout)));
+            let service = Permitted { inner: service, _permit: permit };
+            Ok((FirstRead { inner: stream, deadline }, service))
+        })
+    }
+}
+
+/// A stream that fails with `TimedOut` if nothing arrives before `deadline`;
+/// once some bytes have, it reads without one.
+struct FirstRead<S> {
+    inner: S,
+    deadline: Option<Pin<Box<Sleep>>>,
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for FirstRead<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        let filled = buf.filled().len();
+        match Pin::new(&mut this.inner).poll_read(cx, buf) {
+            Poll::Ready(result) => {
+                if buf.filled().len() > filled {
+                    this.deadline = None;
+                }
+                Poll::Ready(result)
+            }
+            Poll::Pending => {
+                let expired = this
+                    .deadline
+                    .as_mut()
+                    .is_some_and(|deadline| deadline.as_mut().poll(cx).is_ready());
+                if expired {
+                    return Poll::Ready(Err(io::ErrorKind::TimedOut.into()));
+                }
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for FirstRead<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[derive(Clone)]
+struct Permitted<S> {
+    inner: S,
+    _permit: Option<Arc<OwnedSemaphorePermit>>,
+}
+
+impl<S: Service<R>, R> Service<R> for Permitted<S> {
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: R) -> Self::Future {
+        self.inner.call(request)
+    }
+}
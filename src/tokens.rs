@@ -0,0 +1,310 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+use axum::{
+    extract::State,
+    http::StatusCode,
+    routing::post,
+    Extension, Json, Router,
+};
+use chrono::{DateTime, Duration, Utc};
+use jsonwebtoken::{encode, EncodingKey, Header};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use crate::api_keys;
+use crate::auth::{self, Principal, Role};
+use crate::config::Config;
+use crate::errors::{ConfigError, HttpAppError};
+use crate::extract::JsonBody;
+use crate::proxy::Client;
+use crate::routes::AppState;
+use crate::sessions::LoginRequest;
+
+const DEFAULT_ACCESS_TOKEN_TTL_SECS: i64 = 900;
+const DEFAULT_REFRESH_TOKEN_TTL_SECS: i64 = 14 * 24 * 3600;
+const MAX_TOKEN_TTL_SECS: i64 = 366 * 24 * 3600;
+
+#[derive(Serialize)]
+struct AccessClaims<'a> {
+    sub: &'a str,
+    roles: &'a [Role],
+    iat: i64,
+    exp: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    iss: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    aud: Option<&'a str>,
+}
+
+/// A refresh token, known by its digest. Each one is good for a single
+/// refresh; the tokens descended from one login share a family.
+struct RefreshToken {
+    family: Uuid,
+    principal: Principal,
+    expires_at: DateTime<Utc>,
+    used: bool,
+}
+
+#[derive(Default)]
+struct RefreshTokens {
+    tokens: HashMap<[u8; 32], RefreshToken>,
+    revoked: HashSet<Uuid>,
+}
+
+/// Issues short-lived HS256 access tokens signed with `JWT_SECRET`, which
+/// `authenticate` accepts, and rotating refresh tokens kept in memory.
+pub struct TokenIssuer {
+    key: EncodingKey,
+    issuer: Option<String>,
+    audience: Option<String>,
+    access_ttl: Duration,
+    refresh_ttl: Duration,
+    refresh: RwLock<RefreshTokens>,
+}
+
+#[derive(Serialize)]
+pub struct TokenResponse {
+    pub token_type: &'static str,
+    pub access_token: String,
+    pub expires_in: i64,
+    pub refresh_token: String,
+    pub refresh_expires_in: i64,
+}
+
+#[derive(Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+impl TokenIssuer {
+    /// Reads `JWT_SECRET`, `ACCESS_TOKEN_TTL_SECS` and `REFRESH_TOKEN_TTL_SECS`,
+    /// each at most a year; `None` without a secret to sign with.
+    pub fn from_config(config: &Config) -> Result<Option<Self>, ConfigError> {
+        let Some(secret) = config.var("JWT_SECRET") else {
+            return Ok(None);
+        };
+        Ok(Some(TokenIssuer {
+            key: EncodingKey::from_secret(secret.as_bytes()),
+            issuer: config.var("JWT_ISSUER").map(str::to_string),
+            audience: config.var("JWT_AUDIENCE").map(str::to_string),
+            access_ttl: ttl(config, "ACCESS_TOKEN_TTL_SECS", DEFAULT_ACCESS_TOKEN_TTL_SECS)?,
+            refresh_ttl: ttl(config, "REFRESH_TOKEN_TTL_SECS", DEFAULT_REFRESH_TOKEN_TTL_SECS)?,
+            refresh: RwLock::new(RefreshTokens::default()),
+        }))
+    }
+
+    fn issue(&self, principal: Principal, family: Uuid) -> Result<TokenResponse, HttpAppError> {
+        let now = Utc::now();
+        let expiry = |ttl| {
+            let outlasting = "the token would outlast the supported dates";
+            now.checked_add_signed(ttl)
+                .ok_or_else(|| HttpAppError::StorageError(outlasting.to_string()))
+        };
+        let claims = AccessClaims {
+            sub: &principal.name,
+            roles: &principal.roles,
+            iat: now.timestamp(),
+            exp: expiry(self.access_ttl)?.timestamp(),
+            iss: self.issuer.as_deref(),
+            aud: self.audience.as_deref(),
+        };
+        let access_token = encode(&Header::default(), &claims, &self.key)
+            .map_err(|e| HttpAppError::StorageError(e.to_string()))?;
+
+        let refresh_token = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+        let expires_at = expiry(self.refresh_ttl)?;
+        let mut refresh = self.refresh.write()?;
+        refresh.tokens.retain(|_, token| token.expires_at > now);
+        let live: HashSet<Uuid> = refresh.tokens.values().map(|token| token.family).collect();
+        refresh.revoked.retain(|family| live.contains(family));
+        refresh.tokens.insert(
+            api_keys::digest(&refresh_token),
+            RefreshToken {
+                family,
+                principal,
+                expires_at,
+                used: false,
+            },
+        );
+        Ok(TokenResponse {
+            token_type: "Bearer",
+            access_token,
+            expires_in: self.access_ttl.num_seconds(),
+            refresh_token,
+            refresh_expires_in: self.refresh_ttl.num_seconds(),
+        })
+    }
+
+    /// Trades a refresh token for a new pair. A token that was already used
+    /// means it leaked, so every token of its family is revoked.
+    fn rotate(&self, refresh_token: &str) -> Result<TokenResponse, HttpAppError> {
+        let invalid = || HttpAppError::Unauthorized("invalid refresh token".to_string());
+        let (family, principal) = {
+            let mut refresh = self.refresh.write()?;
+            let RefreshTokens { tokens, revoked } = &mut *refresh;
+            let token = tokens
+                .get_mut(&api_keys::digest(refresh_token))
+                .filter(|token| token.expires_at > Utc::now())
+                .ok_or_else(invalid)?;
+            if revoked.contains(&token.family) {
+                return Err(invalid());
+            }
+            if token.used {
+                tracing::warn!(
+                    "Refresh token of {} was used twice, revoking its family",
+                    token.principal.name
+                );
+                revoked.insert(token.family);
+                return Err(invalid());
+            }
+            token.used = true;
+            (token.family, token.principal.clone())
+        };
+        self.issue(principal, family)
+    }
+
+    fn revoke(&self, refresh_token: &str) -> Result<(), HttpAppError> {
+        let mut refresh = self.refresh.write()?;
+        let digest = api_keys::digest(refresh_token);
+        if let Some(family) = refresh.tokens.get(&digest).map(|token| token.family) {
+            refresh.revoked.insert(family);
+        }
+        Ok(())
+    }
+}
+
+pub fn create_token_routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/auth/token", post(token))
+        .route("/auth/refresh", post(refresh))
+        .route("/auth/revoke", post(revoke))
+}
+
+fn issuer(state: &AppState) -> Result<&TokenIssuer, HttpAppError> {
+    state.tokens.as_ref().ok_or(HttpAppError::NotFound)
+}
+
+/// Starts a token family for a username and password.
+async fn token(
+    State(state): State<Arc<AppState>>,
+    Extension(client): Extension<Client>,
+    JsonBody(request): JsonBody<LoginRequest>,
+) -> Result<Json<TokenResponse>, HttpAppError> {
+    let issuer = issuer(&state)?;
+    let principal =
+        auth::check_login(&state, &request.username, &request.password, client.ip).await?;
+    tracing::info!("Issued tokens to {}", principal.name);
+    Ok(Json(issuer.issue(principal, Uuid::new_v4())?))
+}
+
+async fn refresh(
+    State(state): State<Arc<AppState>>,
+    JsonBody(request): JsonBody<RefreshRequest>,
+) -> Result<Json<TokenResponse>, HttpAppError> {
+    Ok(Json(issuer(&state)?.rotate(&request.refresh_token)?))
+}
+
+/// Revokes the family of a refresh token, as on logout. Access tokens already
+/// issued stay valid until they expire.
+async fn revoke(
+    State(state): State<Arc<AppState>>,
+    JsonBody(request): JsonBody<RefreshRequest>,
+) -> Result<StatusCode, HttpAppError> {
+    issuer(&state)?.revoke(&request.refresh_token)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+fn ttl(config: &Config, name: &str, default: i64) -> Result<Duration, ConfigError> {
+    match config.var(name) {
+        Some(value) => value
+            .parse()
+            .ok()
+            .filter(|seconds| (1..=MAX_TOKEN_TTL_SECS).contains(seconds))
+            .and_then(Duration::try_seconds)
+            .ok_or_else(|| {
+                ConfigError::Invalid(format!(
+                    "{} must be a positive number of seconds up to {}, got {}",
+                    name, MAX_TOKEN_TTL_SECS, value
+                ))
+            }),
+        None => Ok(Duration::seconds(default)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::{Claims, JwtVerifier};
+
+    const SETTINGS: [(&str, &str); 1] = [("JWT_SECRET", "s3cret")];
+
+    fn issuer() -> TokenIssuer {
+        TokenIssuer::from_config(&Config::from_overrides(&SETTINGS)).unwrap().unwrap()
+    }
+
+    fn ann() -> Principal {
+        Principal { name: "ann".to_string(), roles: vec![Role::Writer] }
+    }
+
+    #[test]
+    fn access_tokens_are_accepted() {
+        let tokens = issuer().issue(ann(), Uuid::new_v4()).unwrap();
+        assert_eq!((tokens.expires_in, tokens.refresh_expires_in), (900, 14 * 24 * 3600));
+        let verifier = JwtVerifier::from_config(&Config::from_overrides(&SETTINGS)).unwrap();
+        let claims: Claims = verifier.unwrap().verify(&tokens.access_token).unwrap();
+        assert_eq!((claims.sub.as_str(), claims.roles), ("ann", vec!["writer".to_string()]));
+    }
+
+    #[test]
+    fn refresh_tokens_rotate() {
+        let issuer = issuer();
+        let first = issuer.issue(ann(), Uuid::new_v4()).unwrap();
+        let second = issuer.rotate(&first.refresh_token).unwrap();
+        assert_ne!(second.refresh_token, first.refresh_token);
+        let third = issuer.rotate(&second.refresh_token).unwrap();
+        assert!(issuer.rotate(&third.refresh_token).is_ok());
+        assert!(issuer.rotate("unknown").is_err());
+    }
+
+    #[test]
+    fn reused_refresh_tokens_revoke_their_family() {
+        let issuer = issuer();
+        let first = issuer.issue(ann(), Uuid::new_v4()).unwrap();
+        let other = issuer.issue(ann(), Uuid::new_v4()).unwrap();
+        let second = issuer.rotate(&first.refresh_token).unwrap();
+        assert!(issuer.rotate(&first.refresh_token).is_err());
+        assert!(issuer.rotate(&second.refresh_token).is_err());
+        assert!(issuer.rotate(&other.refresh_token).is_ok());
+
+        let revoked = issuer.issue(ann(), Uuid::new_v4()).unwrap();
+        issuer.revoke(&revoked.refresh_token).unwrap();
+        assert!(issuer.rotate(&revoked.refresh_token).is_err());
+    }
+
+    #[test]
+    fn expired_refresh_tokens_are_refused() {
+        let issuer = issuer();
+        let tokens = issuer.issue(ann(), Uuid::new_v4()).unwrap();
+        for token in issuer.refresh.write().unwrap().tokens.values_mut() {
+            token.expires_at = Utc::now() - Duration::seconds(1);
+        }
+        assert!(issuer.rotate(&tokens.refresh_token).is_err());
+        issuer.issue(ann(), Uuid::new_v4()).unwrap();
+        assert_eq!(issuer.refresh.read().unwrap().tokens.len(), 1);
+    }
+
+    #[test]
+    fn ttls_are_bounded() {
+        let too_long = (MAX_TOKEN_TTL_SECS + 1).to_string();
+        for name in ["ACCESS_TOKEN_TTL_SECS", "REFRESH_TOKEN_TTL_SECS"] {
+            for ttl in ["0", "-1", "9223372036854775807", too_long.as_str()] {
+                let config = Config::from_overrides(&[SETTINGS[0], (name, ttl)]);
+                assert!(TokenIssuer::from_config(&config).is_err(), "{}={}", name, ttl);
+            }
+        }
+        let longest = MAX_TOKEN_TTL_SECS.to_string();
+        let config = Config::from_overrides(&[SETTINGS[0], ("REFRESH_TOKEN_TTL_SECS", &longest)]);
+        let tokens = TokenIssuer::from_config(&config).unwrap().unwrap();
+        let tokens = tokens.issue(ann(), Uuid::new_v4()).unwrap();
+        assert_eq!(tokens.refresh_expires_in, MAX_TOKEN_TTL_SECS);
+    }
+}
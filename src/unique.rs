@@ -0,0 +1,143 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::migrate::MigrateError;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+use crate::errors::HttpAppError;
+use crate::listing::{ListQuery, PersonFilter};
+use crate::pagination::Page;
+use crate::person::{NewPerson, Person};
+use crate::repository::PersonRepository;
+use crate::search::SearchHit;
+
+/// Keeps names unique, ignoring case, for backends without a unique index.
+/// Writes are serialized so that a check and its write cannot interleave.
+pub struct UniqueNameRepository {
+    inner: Box<dyn PersonRepository>,
+    writes: Mutex<()>,
+}
+
+impl UniqueNameRepository {
+    pub fn new(inner: Box<dyn PersonRepository>) -> Self {
+        UniqueNameRepository {
+            inner,
+            writes: Mutex::new(()),
+        }
+    }
+
+    async fn check_name(&self, id: Option<u32>, name: &str) -> Result<(), HttpAppError> {
+        let name = name.to_lowercase();
+        let taken = self
+            .inner
+            .list()
+            .await?
+            .iter()
+            .any(|person| Some(person.id) != id && person.name.to_lowercase() == name);
+        if taken {
+            return Err(HttpAppError::DuplicateName);
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl PersonRepository for UniqueNameRepository {
+    async fn migrate(&self) -> Result<(), MigrateError> {
+        self.inner.migrate().await
+    }
+
+    async fn pending_migrations(&self) -> Result<usize, MigrateError> {
+        self.inner.pending_migrations().await
+    }
+
+    async fn close(&self) -> Result<(), HttpAppError> {
+        self.inner.close().await
+    }
+
+    async fn list(&self) -> Result<Vec<Person>, HttpAppError> {
+        self.inner.list().await
+    }
+
+    async fn list_page(&self, query: &ListQuery) -> Result<Page<Person>, HttpAppError> {
+        self.inner.list_page(query).await
+    }
+
+    async fn count(&self, filter: &PersonFilter) -> Result<u64, HttpAppError> {
+        self.inner.count(filter).await
+    }
+
+    async fn list_after(
+        &self,
+        filter: &PersonFilter,
+        after: Option<u32>,
+        limit: u64,
+    ) -> Result<Vec<Person>, HttpAppError> {
+        self.inner.list_after(filter, after, limit).await
+    }
+
+    async fn search(
+        &self,
+        query: &str,
+        fuzzy: bool,
+        limit: usize,
+    ) -> Result<Vec<SearchHit>, HttpAppError> {
+        self.inner.search(query, fuzzy, limit).await
+    }
+
+    async fn get(&self, id: u32) -> Result<Person, HttpAppError> {
+        self.inner.get(id).await
+    }
+
+    async fn get_by_uuid(&self, uuid: Uuid) -> Result<Person, HttpAppError> {
+        self.inner.get_by_uuid(uuid).await
+    }
+
+    async fn insert(&self, person: Person) -> Result<(), HttpAppError> {
+        let _write = self.writes.lock().await;
+        self.check_name(Some(person.id), &person.name).await?;
+        self.inner.insert(person).await
+    }
+
+    async fn next_id(&self) -> Result<u32, HttpAppError> {
+        self.inner.next_id().await
+    }
+
+    async fn create(&self, person: NewPerson) -> Result<Person, HttpAppError> {
+        let _write = self.writes.lock().await;
+        self.check_name(None, &person.name).await?;
+        self.inner.create(person).await
+    }
+
+    async fn update(&self, person: Person) -> Result<(), HttpAppError> {
+        let _write = self.writes.lock().await;
+        self.check_name(Some(person.id), &person.name).await?;
+        self.inner.update(person).await
+    }
+
+    async fn delete(&self, id: u32, version: u32) -> Result<(), HttpAppError> {
+        self.inner.delete(id, version).await
+    }
+
+    async fn set_deleted(
+        &self,
+        id: u32,
+        deleted_at: Option<DateTime<Utc>>,
+    ) -> Result<(), HttpAppError> {
+        self.inner.set_deleted(id, deleted_at).await
+    }
+
+    async fn purge(&self, id: u32) -> Result<(), HttpAppError> {
+        self.inner.purge(id).await
+    }
+
+    async fn replace_all(&self, persons: Vec<Person>) -> Result<(), HttpAppError> {
+        let mut names: Vec<String> =
+            persons.iter().map(|person| person.name.to_lowercase()).collect();
+        names.sort();
+        if names.windows(2).any(|pair| pair[0] == pair[1]) {
+            return Err(HttpAppError::DuplicateName);
+        }
+        let _write = self.writes.lock().await;
+        self.inner.replace_all(persons).await
+    }
+}
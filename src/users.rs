@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock, RwLock};
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use async_trait::async_trait;
+use axum::{extract::State, http::StatusCode, routing::post, Json, Router};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use crate::auth::Role;
+use crate::errors::HttpAppError;
+use crate::extract::ValidatedJson;
+use crate::routes::AppState;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct User {
+    pub username: String,
+    #[serde(skip_serializing)]
+    pub password_hash: String,
+    pub roles: Vec<Role>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A `users` table row, shared by the SQL backends.
+#[derive(sqlx::FromRow)]
+pub struct UserRow {
+    pub username: String,
+    pub password_hash: String,
+    pub roles: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl TryFrom<UserRow> for User {
+    type Error = HttpAppError;
+
+    fn try_from(row: UserRow) -> Result<Self, Self::Error> {
+        let roles = row
+            .roles
+            .split(',')
+            .filter(|role| !role.is_empty())
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .map_err(HttpAppError::StorageError)?;
+        Ok(User {
+            username: row.username,
+            password_hash: row.password_hash,
+            roles,
+            created_at: row.created_at,
+        })
+    }
+}
+
+/// The `roles` column of a user, such as `reader,writer`.
+pub fn roles_column(roles: &[Role]) -> String {
+    roles.iter().map(Role::to_string).collect::<Vec<_>>().join(",")
+}
+
+/// Stores user accounts next to the persons of the same backend.
+#[async_trait]
+pub trait UserRepository: Send + Sync {
+    /// Adds the user, failing with `UsernameTaken` when the name is in use.
+    async fn create_user(&self, user: User) -> Result<(), HttpAppError>;
+
+    async fn get_user(&self, username: &str) -> Result<Option<User>, HttpAppError>;
+}
+
+#[derive(Default)]
+pub struct MemoryUserRepository {
+    users: RwLock<HashMap<String, User>>,
+}
+
+#[async_trait]
+impl UserRepository for MemoryUserRepository {
+    async fn create_user(&self, user: User) -> Result<(), HttpAppError> {
+        let mut users = self.users.write()?;
+        if users.contains_key(&user.username) {
+            return Err(HttpAppError::UsernameTaken);
+        }
+        users.insert(user.username.clone(), user);
+        Ok(())
+    }
+
+    async fn get_user(&self, username: &str) -> Result<Option<User>, HttpAppError> {
+        Ok(self.users.read()?.get(username).cloned())
+    }
+}
+
+/// Hashes with Argon2id off the async threads, as it is slow on purpose.
+pub async fn hash_password(password: String) -> Result<String, HttpAppError> {
+    tokio::task::spawn_blocking(move || {
+        let salt = SaltString::generate(&mut OsRng);
+        Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+            .map_err(|e| HttpAppError::StorageError(e.to_string()))
+    })
+    .await
+    .map_err(|e| HttpAppError::StorageError(e.to_string()))?
+}
+
+/// A hash to check unknown usernames against, so that they take as long as
+/// a wrong password and do not reveal which accounts exist.
+fn dummy_hash() -> &'static str {
+    static DUMMY: OnceLock<String> = OnceLock::new();
+    DUMMY.get_or_init(|| {
+        let salt = SaltString::generate(&mut OsRng);
+        Argon2::default().hash_password(b"dummy password", &salt).unwrap().to_string()
+    })
+}
+
+/// The user, if the username exists and the password matches.
+pub async fn check_credentials(
+    users: &dyn UserRepository,
+    username: &str,
+    password: &str,
+) -> Result<Option<User>, HttpAppError> {
+    let user = users.get_user(username).await?;
+    let hash = match &user {
+        Some(user) => user.password_hash.clone(),
+        None => dummy_hash().to_string(),
+    };
+    let password = password.to_string();
+    let matches = tokio::task::spawn_blocking(move || {
+        PasswordHash::new(&hash).is_ok_and(|hash| {
+            Argon2::default().verify_password(password.as_bytes(), &hash).is_ok()
+        })
+    })
+    .await
+    .map_err(|e| HttpAppError::StorageError(e.to_string()))?;
+    Ok(user.filter(|_| matches))
+}
+
+#[derive(Deserialize)]
+pub struct RegisterRequest {
+    pub username: String,
+    pub password: String,
+}
+
+pub fn create_user_routes() -> Router<Arc<AppState>> {
+    Router::new().route("/auth/register", post(register))
+}
+
+/// Creates an account with the `USER_REGISTRATION_ROLE`, when registration is on.
+async fn register(
+    State(state): State<Arc<AppState>>,
+    ValidatedJson(request): ValidatedJson<RegisterRequest>,
+) -> Result<(StatusCode, Json<User>), HttpAppError> {
+    let role = state.settings().registration_role().ok_or(HttpAppError::NotFound)?;
+    let user = User {
+        username: request.username,
+        password_hash: hash_password(request.password).await?,
+        roles: vec![role],
+        created_at: Utc::now(),
+    };
+    state.users.create_user(user.clone()).await?;
+    tracing::info!("Registered user {} with role {}", user.username, role);
+    Ok((StatusCode::CREATED, Json(user)))
+}
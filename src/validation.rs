@@ -0,0 +1,131 @@
+use chrono::{NaiveDate, Utc};
+use serde::Serialize;
+use utoipa::ToSchema;
+use crate::errors::HttpAppError;
+use crate::listing::PersonFilter;
+use crate::person::{NewPerson, Person, PersonPatch};
+use crate::users::RegisterRequest;
+
+pub const MAX_NAME_LENGTH: usize = 100;
+pub const MAX_AGE: u8 = 150;
+pub const MAX_USERNAME_LENGTH: usize = 64;
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+/// Keeps hashing cheap enough that long passwords cannot tie up the server.
+pub const MAX_PASSWORD_LENGTH: usize = 128;
+pub const EARLIEST_DATE: NaiveDate = match NaiveDate::from_ymd_opt(1900, 1, 1) {
+    Some(date) => date,
+    None => panic!("invalid earliest date"),
+};
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: impl Into<String>, message: impl Into<String>) -> Self {
+        FieldError { field: field.into(), message: message.into() }
+    }
+}
+
+/// Checks request data before it reaches a repository, answering 422 with every
+/// failing field.
+pub trait Validate {
+    fn validate(&self) -> Result<(), HttpAppError>;
+}
+
+impl Validate for NewPerson {
+    fn validate(&self) -> Result<(), HttpAppError> {
+        check(Some(&self.name), Some(self.age), Some(self.date))
+    }
+}
+
+impl Validate for Person {
+    fn validate(&self) -> Result<(), HttpAppError> {
+        check(Some(&self.name), Some(self.age), Some(self.date))
+    }
+}
+
+impl Validate for PersonPatch {
+    fn validate(&self) -> Result<(), HttpAppError> {
+        check(self.name.as_deref(), self.age, self.date)
+    }
+}
+
+impl Validate for PersonFilter {
+    fn validate(&self) -> Result<(), HttpAppError> {
+        let mut errors = Vec::new();
+        if let (Some(date_from), Some(date_to)) = (self.date_from, self.date_to) {
+            if date_from > date_to {
+                errors.push(FieldError::new("date_from", "must not be after date_to"));
+            }
+        }
+        if self.year.is_some() && self.year_range().is_none() {
+            errors.push(FieldError::new("year", "is out of range"));
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(HttpAppError::Validation(errors))
+        }
+    }
+}
+
+impl Validate for RegisterRequest {
+    fn validate(&self) -> Result<(), HttpAppError> {
+        let mut errors = Vec::new();
+        let username_chars = |c: char| c.is_ascii_alphanumeric() || "._-".contains(c);
+        if self.username.is_empty() || self.username.len() > MAX_USERNAME_LENGTH {
+            errors.push(FieldError::new(
+                "username",
+                format!("must be 1 to {} characters", MAX_USERNAME_LENGTH),
+            ));
+        } else if !self.username.chars().all(username_chars) {
+            errors.push(FieldError::new("username", "may only contain letters, digits, . _ and -"));
+        }
+        let length = self.password.chars().count();
+        if !(MIN_PASSWORD_LENGTH..=MAX_PASSWORD_LENGTH).contains(&length) {
+            errors.push(FieldError::new(
+                "password",
+                format!(
+                    "must be {} to {} characters",
+                    MIN_PASSWORD_LENGTH, MAX_PASSWORD_LENGTH
+                ),
+            ));
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(HttpAppError::Validation(errors))
+        }
+    }
+}
+
+fn check(name: Option<&str>, age: Option<u8>, date: Option<NaiveDate>) -> Result<(), HttpAppError> {
+    let mut errors = Vec::new();
+    let mut fail = |field, message| errors.push(FieldError::new(field, message));
+    if let Some(name) = name {
+        if name.trim().is_empty() {
+            fail("name", "must not be empty".to_string());
+        } else if name.chars().count() > MAX_NAME_LENGTH {
+            fail("name", format!("must be at most {} characters", MAX_NAME_LENGTH));
+        } else if name.chars().any(char::is_control) {
+            fail("name", "must not contain control characters".to_string());
+        }
+    }
+    if let Some(age) = age.filter(|&age| age > MAX_AGE) {
+        fail("age", format!("must be at most {}, got {}", MAX_AGE, age));
+    }
+    if let Some(date) = date {
+        let today = Utc::now().date_naive();
+        if date < EARLIEST_DATE || date > today {
+            fail("date", format!("must be between {} and {}", EARLIEST_DATE, today));
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(HttpAppError::Validation(errors))
+    }
+}
@@ -0,0 +1,37 @@
+use std::sync::Arc;
+use axum::{routing::get, Json, Router};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use crate::routes::AppState;
+
+/// What `build.rs` captured about the build of the running binary.
+#[derive(Serialize)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub git_sha: &'static str,
+    pub built_at: Option<DateTime<Utc>>,
+    pub rustc: &'static str,
+}
+
+impl BuildInfo {
+    pub fn current() -> Self {
+        let built_at = env!("BUILD_TIMESTAMP")
+            .parse()
+            .ok()
+            .and_then(|secs| DateTime::from_timestamp(secs, 0));
+        BuildInfo {
+            version: env!("CARGO_PKG_VERSION"),
+            git_sha: env!("BUILD_GIT_SHA"),
+            built_at,
+            rustc: env!("BUILD_RUSTC_VERSION"),
+        }
+    }
+}
+
+pub fn create_version_routes() -> Router<Arc<AppState>> {
+    Router::new().route("/version", get(version))
+}
+
+async fn version() -> Json<BuildInfo> {
+    Json(BuildInfo::current())
+}
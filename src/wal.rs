@@ -0,0 +1,208 @@
+use std::io;
+use std::path::Path;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use crate::errors::{HttpAppError, StorageError};
+use crate::person::Person;
+use crate::repository::{MemoryRepository, PersonRepository};
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum WalEntry {
+    Create { person: Person },
+    Update { person: Person },
+    Delete { id: u32 },
+    MarkDeleted { id: u32, deleted_at: Option<DateTime<Utc>> },
+    Replace { persons: Vec<Person> },
+}
+
+pub struct WalRepository {
+    memory: MemoryRepository,
+    log: Mutex<File>,
+}
+
+impl WalRepository {
+    pub async fn open(path: impl AsRef<Path>, persons: Vec<Person>) -> Result<Self, StorageError> {
+        let path = path.as_ref();
+        let memory = MemoryRepository::new(persons);
+        let (replayed, complete) = replay(path, &memory).await?;
+        tracing::info!("Replayed {} WAL entries from {}", replayed, path.display());
+        let log = OpenOptions::new().create(true).append(true).open(path).await?;
+        if log.metadata().await?.len() > complete {
+            tracing::warn!("Dropping the torn last WAL entry of {}", path.display());
+            log.set_len(complete).await?;
+        }
+        Ok(WalRepository {
+            memory,
+            log: Mutex::new(log),
+        })
+    }
+}
+
+/// Applies the logged entries to `memory`, answering how many were applied and
+/// how many bytes of the log end in a complete line; a crash in the middle of
+/// an append leaves a torn line behind, which later appends must not extend.
+async fn replay(path: &Path, memory: &MemoryRepository) -> Result<(usize, u64), StorageError> {
+    let contents = match fs::read_to_string(path).await {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok((0, 0)),
+        Err(e) => return Err(e.into()),
+    };
+    let complete = contents.rfind('\n').map_or(0, |end| end + 1) as u64;
+    let mut replayed = 0;
+    for (number, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: WalEntry = match serde_json::from_str(line) {
+            Ok(entry) => entry,
+            Err(e) => {
+                tracing::warn!("Skipping unreadable WAL entry at line {}: {}", number + 1, e);
+                continue;
+            }
+        };
+        let result = match entry {
+            WalEntry::Create { person } => memory.insert(person).await,
+            WalEntry::Update { person } => memory.update(person).await,
+            WalEntry::Delete { id } => memory.purge(id).await,
+            WalEntry::MarkDeleted { id, deleted_at } => memory.set_deleted(id, deleted_at).await,
+            WalEntry::Replace { persons } => memory.replace_all(persons).await,
+        };
+        match result {
+            Ok(()) => replayed += 1,
+            Err(e) => tracing::warn!("Skipping WAL entry at line {}: {}", number + 1, e),
+        }
+    }
+    Ok((replayed, complete))
+}
+
+async fn append(log: &mut File, entry: &WalEntry) -> Result<(), HttpAppError> {
+    let mut line =
+        serde_json::to_vec(entry).map_err(|e| HttpAppError::StorageError(e.to_string()))?;
+    line.push(b'\n');
+    log.write_all(&line).await?;
+    log.sync_data().await?;
+    Ok(())
+}
+
+#[async_trait]
+impl PersonRepository for WalRepository {
+    async fn close(&self) -> Result<(), HttpAppError> {
+        self.log.lock().await.sync_all().await?;
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<Vec<Person>, HttpAppError> {
+        self.memory.list().await
+    }
+
+    async fn get(&self, id: u32) -> Result<Person, HttpAppError> {
+        self.memory.get(id).await
+    }
+
+    async fn insert(&self, person: Person) -> Result<(), HttpAppError> {
+        let mut log = self.log.lock().await;
+        self.memory.insert(person.clone()).await?;
+        append(&mut log, &WalEntry::Create { person }).await
+    }
+
+    async fn update(&self, person: Person) -> Result<(), HttpAppError> {
+        let mut log = self.log.lock().await;
+        self.memory.update(person.clone()).await?;
+        append(&mut log, &WalEntry::Update { person }).await
+    }
+
+    async fn delete(&self, id: u32, version: u32) -> Result<(), HttpAppError> {
+        let mut log = self.log.lock().await;
+        self.memory.delete(id, version).await?;
+        let deleted_at = self.memory.get(id).await?.deleted_at;
+        append(&mut log, &WalEntry::MarkDeleted { id, deleted_at }).await
+    }
+
+    async fn set_deleted(
+        &self,
+        id: u32,
+        deleted_at: Option<DateTime<Utc>>,
+    ) -> Result<(), HttpAppError> {
+        let mut log = self.log.lock().await;
+        self.memory.set_deleted(id, deleted_at).await?;
+        append(&mut log, &WalEntry::MarkDeleted { id, deleted_at }).await
+    }
+
+    async fn purge(&self, id: u32) -> Result<(), HttpAppError> {
+        let mut log = self.log.lock().await;
+        self.memory.purge(id).await?;
+        append(&mut log, &WalEntry::Delete { id }).await
+    }
+
+    async fn replace_all(&self, persons: Vec<Person>) -> Result<(), HttpAppError> {
+        let mut log = self.log.lock().await;
+        self.memory.replace_all(persons.clone()).await?;
+        append(&mut log, &WalEntry::Replace { persons }).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::person::create_person_collection;
+
+    fn log_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("wal-{}-{}.log", name, std::process::id()))
+    }
+
+    async fn reopen(path: &Path) -> Vec<Person> {
+        let repository = WalRepository::open(path, create_person_collection()).await.unwrap();
+        repository.list().await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn reopening_replays_the_changes() {
+        let path = log_path("replay");
+        let _ = fs::remove_file(&path).await;
+        let repository = WalRepository::open(&path, create_person_collection()).await.unwrap();
+        let mut peach = create_person_collection().remove(0);
+        peach.id = 3;
+        peach.name = "Peach".to_string();
+        repository.insert(peach).await.unwrap();
+        let mut mario = repository.get(1).await.unwrap();
+        mario.age = 44;
+        repository.update(mario).await.unwrap();
+        repository.delete(2, 1).await.unwrap();
+        repository.purge(3).await.unwrap();
+        let expected = repository.list().await.unwrap();
+        drop(repository);
+
+        let persons = reopen(&path).await;
+        fs::remove_file(&path).await.unwrap();
+        assert_eq!(persons.len(), 2);
+        assert_eq!((persons[0].age, persons[0].version), (44, 2));
+        assert_eq!(persons[1].deleted_at, expected[1].deleted_at);
+        assert_eq!(persons[1].version, 2);
+    }
+
+    #[tokio::test]
+    async fn torn_entries_are_cut_off() {
+        let path = log_path("torn");
+        let _ = fs::remove_file(&path).await;
+        let repository = WalRepository::open(&path, create_person_collection()).await.unwrap();
+        repository.delete(1, 1).await.unwrap();
+        drop(repository);
+        let mut log = OpenOptions::new().append(true).open(&path).await.unwrap();
+        log.write_all(br#"{"op":"delete","id""#).await.unwrap();
+        drop(log);
+
+        let repository = WalRepository::open(&path, create_person_collection()).await.unwrap();
+        repository.delete(2, 1).await.unwrap();
+        drop(repository);
+        let persons = reopen(&path).await;
+        let contents = fs::read_to_string(&path).await.unwrap();
+        fs::remove_file(&path).await.unwrap();
+        assert_eq!(contents.lines().count(), 2);
+        assert!(persons.iter().all(|person| person.deleted_at.is_some()));
+    }
+}